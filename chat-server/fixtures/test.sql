@@ -0,0 +1,32 @@
+-- test data, all users have the password 123456
+INSERT INTO users(fullname, email, password_hash)
+  VALUES ('Alice', 'alice@acme.org', '$argon2id$v=19$m=19456,t=2,p=1$yUL4/Mfp/wtrWvAAIUYNmA$omHYP5cE0SLUtYmPY6u6lzPzuGGbOt7VfGiLjPBXMZQ'),
+('Bob', 'bob@acme.org', '$argon2id$v=19$m=19456,t=2,p=1$yUL4/Mfp/wtrWvAAIUYNmA$omHYP5cE0SLUtYmPY6u6lzPzuGGbOt7VfGiLjPBXMZQ'),
+('Charlie', 'charlie@acme.org', '$argon2id$v=19$m=19456,t=2,p=1$yUL4/Mfp/wtrWvAAIUYNmA$omHYP5cE0SLUtYmPY6u6lzPzuGGbOt7VfGiLjPBXMZQ'),
+('Dave', 'dave@acme.org', '$argon2id$v=19$m=19456,t=2,p=1$yUL4/Mfp/wtrWvAAIUYNmA$omHYP5cE0SLUtYmPY6u6lzPzuGGbOt7VfGiLjPBXMZQ');
+
+UPDATE
+  workspaces
+SET
+  owner_id = 1
+WHERE
+  id = 1;
+
+UPDATE
+  users
+SET
+  role = 'owner'
+WHERE
+  id = 1;
+
+-- chat 1: alice is the admin of the general group
+-- chat 2: bob and charlie talk in private
+INSERT INTO chats(name, type, members, admins)
+  VALUES ('general', 'group', '{1, 2, 3}', '{1}'),
+('bob-charlie', 'single', '{2, 3}', '{}');
+
+INSERT INTO messages(chat_id, sender_id, content)
+  VALUES (1, 1, 'Hello, world!'),
+(1, 2, 'Hi, Alice!'),
+(1, 3, 'Good morning'),
+(2, 2, 'Lunch?');
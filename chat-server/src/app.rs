@@ -1,20 +1,60 @@
-use std::{ops::Deref, sync::Arc};
+use std::{ops::Deref, sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
 use axum::{
+    extract::DefaultBodyLimit,
     middleware::from_fn_with_state,
-    routing::{get, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use sqlx::PgPool;
 
 use crate::{
-    handlers::{signin_handler, signup_handler},
+    handlers::{
+        add_member_handler, add_reaction_handler, block_user_handler, cancel_scheduled_handler,
+        chat_presence_handler, complete_upload_handler, create_bot_handler,
+        create_bot_token_handler, create_command_handler, create_direct_chat_handler,
+        create_incoming_webhook_handler, create_legal_hold_handler, create_webhook_handler,
+        delete_command_handler, delete_draft_handler, delete_incoming_webhook_handler,
+        delete_message_handler, delete_passkey_handler, delete_webhook_handler,
+        export_audit_events_handler, file_handler, finish_passkey_registration_handler,
+        finish_passkey_signin_handler, follow_thread_handler, get_chat_retention_handler,
+        get_content_filters_handler, get_draft_handler, get_me_handler, heartbeat_handler,
+        list_audit_events_handler, list_blocks_handler, list_bot_tokens_handler, list_bots_handler,
+        list_chats_handler, list_commands_handler, list_dead_letters_handler,
+        list_deliveries_handler, list_incoming_webhooks_handler, list_legal_holds_handler,
+        list_mentions_handler, list_message_edits_handler, list_messages_handler,
+        list_passkeys_handler, list_pins_handler, list_reports_handler, list_saved_handler,
+        list_scheduled_handler, list_thread_handler, list_typing_handler, list_users_handler,
+        list_warnings_handler, list_webhooks_handler, mark_read_handler, oidc_callback_handler,
+        oidc_start_handler, pin_message_handler, post_incoming_webhook_handler,
+        presign_upload_handler, read_by_handler, release_legal_hold_handler, remove_member_handler,
+        remove_reaction_handler, report_message_handler, resolve_report_handler,
+        retry_dead_letter_handler, revoke_bot_token_handler, save_draft_handler,
+        save_message_handler, search_handler, send_message_handler, signin_handler, signup_handler,
+        start_passkey_registration_handler, start_passkey_signin_handler, stop_typing_handler,
+        storage_usage_handler, typing_handler, unblock_user_handler, unfollow_thread_handler,
+        unpin_message_handler, unsave_message_handler, unsuspend_user_handler,
+        update_chat_retention_handler, update_content_filters_handler, update_me_handler,
+        update_message_handler, update_pin_limit_handler, update_retention_handler,
+        update_role_handler, update_scheduled_handler, update_search_config_handler,
+        update_second_factor_handler, upload_handler, user_presence_handler, verify_email_handler,
+    },
+    mail::{self, Mailer},
+    media::MediaPool,
     middlewares::{set_layer, verify_token},
-    utils::{DecodingKey, EncodingKey},
+    presence::PresenceTracker,
+    storage::{self, Storage},
+    utils::{outbound_client, DecodingKey, EncodingKey},
+    workers::{spawn_retention_worker, spawn_schedule_worker, spawn_webhook_worker},
     AppConfig, AppError,
 };
 
+/// Bots answer slash commands while the user waits.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+/// The size of a whole upload request, it can hold several files.
+const MAX_UPLOAD_SIZE: usize = 50 * 1024 * 1024;
+
 #[derive(Debug, Clone)]
 pub(crate) struct AppState {
     inner: Arc<AppStateInner>,
@@ -27,16 +67,188 @@ pub(crate) struct AppStateInner {
     pub(crate) pk: DecodingKey,
     pub(crate) sk: EncodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) presence: PresenceTracker,
+    /// calls the identity providers configured for sign in
+    pub(crate) http: reqwest::Client,
+    /// calls the endpoints of bots, e.g. for slash commands, see `check_outbound_url`
+    pub(crate) outbound: reqwest::Client,
+    /// decodes and thumbnails uploaded images
+    pub(crate) media: MediaPool,
+    pub(crate) storage: Arc<dyn Storage>,
+    /// sends mails like email verifications
+    pub(crate) mailer: Arc<dyn Mailer>,
 }
 
 pub async fn get_router(config: AppConfig) -> Result<Router, AppError> {
     let state = AppState::new(config).await?;
+    spawn_schedule_worker(state.pool.clone());
+    spawn_webhook_worker(state.pool.clone(), state.config.server.allow_private_urls);
+    spawn_retention_worker(state.pool.clone(), state.storage.clone());
 
     let api = Router::new()
-        .route("/chat", get(|| async { "chat" }))
+        .route(
+            "/workspace/search_config",
+            put(update_search_config_handler),
+        )
+        .route(
+            "/workspace/content_filters",
+            get(get_content_filters_handler).put(update_content_filters_handler),
+        )
+        .route("/workspace/retention", put(update_retention_handler))
+        .route("/admin/storage", get(storage_usage_handler))
+        .route("/admin/users/:id/role", put(update_role_handler))
+        .route(
+            "/admin/users/:id/suspension",
+            delete(unsuspend_user_handler),
+        )
+        .route("/admin/reports", get(list_reports_handler))
+        .route("/admin/reports/:id/resolve", post(resolve_report_handler))
+        .route("/admin/audit", get(list_audit_events_handler))
+        .route("/admin/audit/export", get(export_audit_events_handler))
+        .route(
+            "/admin/legal_holds",
+            get(list_legal_holds_handler).post(create_legal_hold_handler),
+        )
+        .route("/admin/legal_holds/:id", delete(release_legal_hold_handler))
+        .route("/search", get(search_handler))
+        .route("/me", get(get_me_handler).patch(update_me_handler))
+        .route("/me/email/verify", post(verify_email_handler))
+        .route("/me/mentions", get(list_mentions_handler))
+        .route("/me/warnings", get(list_warnings_handler))
+        .route("/me/passkeys", get(list_passkeys_handler))
+        .route(
+            "/me/passkeys/register/start",
+            post(start_passkey_registration_handler),
+        )
+        .route(
+            "/me/passkeys/register/finish",
+            post(finish_passkey_registration_handler),
+        )
+        .route("/me/passkeys/:id", delete(delete_passkey_handler))
+        .route("/me/second_factor", put(update_second_factor_handler))
+        .route("/me/blocks", get(list_blocks_handler))
+        .route(
+            "/me/blocks/:user_id",
+            put(block_user_handler).delete(unblock_user_handler),
+        )
+        .route("/me/scheduled", get(list_scheduled_handler))
+        .route(
+            "/scheduled/:id",
+            patch(update_scheduled_handler).delete(cancel_scheduled_handler),
+        )
+        .route("/me/saved", get(list_saved_handler))
+        .route(
+            "/me/saved/:message_id",
+            put(save_message_handler).delete(unsave_message_handler),
+        )
+        .route("/users", get(list_users_handler))
+        .route(
+            "/upload",
+            post(upload_handler).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route("/files/presign", post(presign_upload_handler))
+        .route("/files/presign/complete", post(complete_upload_handler))
+        .route("/files/:ws_id/*path", get(file_handler))
+        .route("/bots", get(list_bots_handler).post(create_bot_handler))
+        .route(
+            "/bots/:id/tokens",
+            get(list_bot_tokens_handler).post(create_bot_token_handler),
+        )
+        .route(
+            "/bots/:id/tokens/:token_id",
+            delete(revoke_bot_token_handler),
+        )
+        .route(
+            "/commands",
+            get(list_commands_handler).post(create_command_handler),
+        )
+        .route("/commands/:id", delete(delete_command_handler))
+        .route("/chats", get(list_chats_handler))
+        .route("/chats/direct", post(create_direct_chat_handler))
+        .route("/chats/:id/members", post(add_member_handler))
+        .route("/chats/:id/members/:user_id", delete(remove_member_handler))
+        .route("/chats/:id/read", post(mark_read_handler))
+        .route(
+            "/chats/:id/retention",
+            get(get_chat_retention_handler).put(update_chat_retention_handler),
+        )
+        .route(
+            "/chats/:id/draft",
+            get(get_draft_handler)
+                .put(save_draft_handler)
+                .delete(delete_draft_handler),
+        )
+        .route(
+            "/chats/:id/webhooks",
+            get(list_webhooks_handler).post(create_webhook_handler),
+        )
+        .route(
+            "/chats/:id/webhooks/:webhook_id",
+            delete(delete_webhook_handler),
+        )
+        .route(
+            "/chats/:id/webhooks/:webhook_id/deliveries",
+            get(list_deliveries_handler),
+        )
+        .route(
+            "/chats/:id/webhooks/:webhook_id/dead_letters",
+            get(list_dead_letters_handler),
+        )
+        .route(
+            "/chats/:id/webhooks/:webhook_id/dead_letters/:dead_letter_id/retry",
+            post(retry_dead_letter_handler),
+        )
+        .route(
+            "/chats/:id/incoming_webhooks",
+            get(list_incoming_webhooks_handler).post(create_incoming_webhook_handler),
+        )
+        .route(
+            "/chats/:id/incoming_webhooks/:webhook_id",
+            delete(delete_incoming_webhook_handler),
+        )
+        .route(
+            "/chats/:id/typing",
+            get(list_typing_handler)
+                .post(typing_handler)
+                .delete(stop_typing_handler),
+        )
+        .route("/chats/:id/presence", get(chat_presence_handler))
+        .route("/chats/:id/pins", get(list_pins_handler))
+        .route(
+            "/chats/:id/pins/:message_id",
+            put(pin_message_handler).delete(unpin_message_handler),
+        )
+        .route("/chats/:id/pin_limit", put(update_pin_limit_handler))
+        .route("/presence", post(heartbeat_handler))
+        .route("/users/:id/presence", get(user_presence_handler))
+        .route(
+            "/chats/:id/messages",
+            get(list_messages_handler).post(send_message_handler),
+        )
+        .route(
+            "/messages/:id",
+            patch(update_message_handler).delete(delete_message_handler),
+        )
+        .route("/messages/:id/edits", get(list_message_edits_handler))
+        .route("/messages/:id/report", post(report_message_handler))
+        .route("/messages/:id/thread", get(list_thread_handler))
+        .route("/messages/:id/read_by", get(read_by_handler))
+        .route(
+            "/messages/:id/follow",
+            put(follow_thread_handler).delete(unfollow_thread_handler),
+        )
+        .route(
+            "/messages/:id/reactions/:emoji",
+            put(add_reaction_handler).delete(remove_reaction_handler),
+        )
         .layer(from_fn_with_state(state.clone(), verify_token))
         .route("/signup", post(signup_handler))
-        .route("/signin", post(signin_handler));
+        .route("/signin", post(signin_handler))
+        .route("/auth/passkey/start", post(start_passkey_signin_handler))
+        .route("/auth/passkey/finish", post(finish_passkey_signin_handler))
+        .route("/auth/oidc/:provider/start", get(oidc_start_handler))
+        .route("/auth/oidc/:provider/callback", get(oidc_callback_handler))
+        .route("/hooks/:token", post(post_incoming_webhook_handler));
 
     let app = Router::new().nest("/api", api).with_state(state);
     Ok(set_layer(app))
@@ -57,17 +269,36 @@ impl AppState {
         let pool = PgPool::connect(&config.server.db_url)
             .await
             .context("connect to db failed")?;
+        let http = http_client()?;
+        let outbound = outbound_client(HTTP_TIMEOUT, config.server.allow_private_urls)
+            .context("build outbound client failed")?;
+        let storage = storage::from_config(&config.storage)?;
+        let mailer = mail::from_config(&config.mail)?;
         Ok(Self {
             inner: Arc::new(AppStateInner {
                 config,
                 pk,
                 sk,
                 pool,
+                presence: Default::default(),
+                http,
+                outbound,
+                media: Default::default(),
+                storage,
+                mailer,
             }),
         })
     }
 }
 
+fn http_client() -> Result<reqwest::Client, AppError> {
+    let client = reqwest::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .build()
+        .context("build http client failed")?;
+    Ok(client)
+}
+
 #[cfg(test)]
 impl AppState {
     pub(crate) async fn new_for_test(
@@ -80,12 +311,26 @@ impl AppState {
             std::path::Path::new("../migrations"),
         );
         let pool = tdb.get_pool().await;
+        let http = http_client()?;
+        let outbound = outbound_client(HTTP_TIMEOUT, config.server.allow_private_urls)
+            .context("build outbound client failed")?;
+        let storage = storage::from_config(&config.storage)?;
+        let mailer = mail::from_config(&config.mail)?;
+        crate::utils::load_fixtures(&pool)
+            .await
+            .context("load fixtures failed")?;
         let state = Self {
             inner: Arc::new(AppStateInner {
                 config,
                 pk,
                 sk,
                 pool,
+                presence: Default::default(),
+                http,
+                outbound,
+                media: Default::default(),
+                storage,
+                mailer,
             }),
         };
         Ok((tdb, state))
@@ -0,0 +1,206 @@
+use chrono::{Duration, Utc};
+
+use crate::{
+    models::{Chat, CreateMessage, ScheduledMessage, SlashCommand, User},
+    AppError,
+};
+
+use super::{CommandContext, CommandOutput};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BuiltinCommand {
+    Help,
+    Invite,
+    Leave,
+    Topic,
+    Remind,
+}
+
+const BUILTIN_COMMANDS: [BuiltinCommand; 5] = [
+    BuiltinCommand::Help,
+    BuiltinCommand::Invite,
+    BuiltinCommand::Leave,
+    BuiltinCommand::Topic,
+    BuiltinCommand::Remind,
+];
+
+/// Names of built-in commands cannot be registered by bots.
+pub(crate) fn is_builtin(name: &str) -> bool {
+    BuiltinCommand::from_name(name).is_some()
+}
+
+impl BuiltinCommand {
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        BUILTIN_COMMANDS.into_iter().find(|c| c.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Help => "help",
+            Self::Invite => "invite",
+            Self::Leave => "leave",
+            Self::Topic => "topic",
+            Self::Remind => "remind",
+        }
+    }
+
+    fn usage(self) -> &'static str {
+        match self {
+            Self::Help => "/help - list the commands",
+            Self::Invite => "/invite @user... - add users to the chat",
+            Self::Leave => "/leave - leave the chat",
+            Self::Topic => "/topic [text] - show or set the topic of the chat",
+            Self::Remind => "/remind <30s|10m|2h|1d> <text> - post a reminder into the chat",
+        }
+    }
+
+    pub(super) async fn run(
+        self,
+        ctx: &CommandContext<'_>,
+        args: &str,
+    ) -> Result<CommandOutput, AppError> {
+        let pool = &ctx.state.pool;
+        let chat = &ctx.chat;
+        match self {
+            Self::Help => {
+                let mut lines: Vec<String> = BUILTIN_COMMANDS
+                    .iter()
+                    .map(|c| c.usage().to_string())
+                    .collect();
+                for command in SlashCommand::list(chat.ws_id, pool).await? {
+                    lines.push(format!("/{} - {}", command.name, command.description));
+                }
+                Ok(ctx.reply(lines.join("\n")))
+            }
+            Self::Invite => {
+                let handles: Vec<&str> = args.split_whitespace().collect();
+                if handles.is_empty() {
+                    return Err(AppError::InvalidInput(self.usage().to_string()));
+                }
+                // nobody is added when one of the handles is wrong
+                let mut users = Vec::new();
+                for handle in &handles {
+                    let Some(user) = User::find_by_handle(chat.ws_id, handle, pool).await? else {
+                        return Err(AppError::NotFound(format!("user {}", handle)));
+                    };
+                    users.push(user);
+                }
+                for user in users {
+                    Chat::add_member(chat.id, user.id, ctx.user.id, pool).await?;
+                }
+                let invited: Vec<String> = handles
+                    .iter()
+                    .map(|handle| format!("@{}", handle.trim_start_matches('@')))
+                    .collect();
+                Ok(ctx.reply(format!("Added {} to {}", invited.join(", "), chat.name)))
+            }
+            Self::Leave => {
+                Chat::remove_member(chat.id, ctx.user.id, ctx.user.id, pool).await?;
+                Ok(ctx.reply(format!("You left {}", chat.name)))
+            }
+            Self::Topic if args.is_empty() => match &chat.topic {
+                Some(topic) => Ok(ctx.reply(format!("Topic: {}", topic))),
+                None => Ok(ctx.reply("No topic is set")),
+            },
+            Self::Topic => {
+                let chat = Chat::set_topic(chat.id, args, ctx.user.id, pool).await?;
+                Ok(ctx.reply(format!("Topic set to: {}", chat.topic.unwrap_or_default())))
+            }
+            Self::Remind => {
+                let args = args.strip_prefix("in ").unwrap_or(args);
+                let (delay, text) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                let (Some(delay), false) = (parse_delay(delay), text.trim().is_empty()) else {
+                    return Err(AppError::InvalidInput(self.usage().to_string()));
+                };
+                let input = CreateMessage {
+                    content: format!("Reminder: {}", text.trim()),
+                    parent_id: ctx.parent_id,
+                    ..Default::default()
+                };
+                let send_at = Utc::now() + delay;
+                ScheduledMessage::create(&input, send_at, chat.id, ctx.user.id, pool).await?;
+                Ok(ctx.reply(format!(
+                    "I will post the reminder at {}",
+                    send_at.format("%Y-%m-%d %H:%M:%S UTC")
+                )))
+            }
+        }
+    }
+}
+
+/// `30s`, `10m`, `2h` or `1d`.
+fn parse_delay(s: &str) -> Option<Duration> {
+    let unit = s.chars().last()?;
+    let n: i64 = s[..s.len() - unit.len_utf8()]
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)?;
+    match unit {
+        's' => Duration::try_seconds(n),
+        'm' => Duration::try_minutes(n),
+        'h' => Duration::try_hours(n),
+        'd' => Duration::try_days(n),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::{commands::run, AppConfig, AppState};
+
+    fn command(content: &str) -> CreateMessage {
+        CreateMessage {
+            content: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_delay_should_work() {
+        assert_eq!(parse_delay("30s"), Some(Duration::seconds(30)));
+        assert_eq!(parse_delay("2h"), Some(Duration::hours(2)));
+        assert_eq!(parse_delay("0m"), None);
+        assert_eq!(parse_delay("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn builtin_commands_should_reply_to_the_caller() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+
+        let ret = run(&state, &alice, 1, &command("/invite @dave @nobody")).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        assert!(Chat::get_for_member(1, 4, &state.pool).await.is_err());
+
+        let ret = run(&state, &alice, 1, &command("/invite @dave")).await?;
+        let Some(CommandOutput::Ephemeral(reply)) = ret else {
+            panic!("expected an ephemeral reply");
+        };
+        assert_eq!(reply.content, "Added @dave to general");
+        assert!(reply.ephemeral);
+        assert!(Chat::get_for_member(1, 4, &state.pool).await.is_ok());
+
+        run(&state, &alice, 1, &command("/topic release planning")).await?;
+        let chat = Chat::get_by_id(1, &state.pool).await?.unwrap();
+        assert_eq!(chat.topic.as_deref(), Some("release planning"));
+
+        run(&state, &alice, 1, &command("/remind in 10m ship it")).await?;
+        let scheduled = ScheduledMessage::list(1, &state.pool).await?;
+        assert_eq!(scheduled[0].content, "Reminder: ship it");
+
+        let dave = User::new(4, "Dave", "dave@acme.org");
+        run(&state, &dave, 1, &command("/leave")).await?;
+        assert!(Chat::get_for_member(1, 4, &state.pool).await.is_err());
+
+        let ret = run(&state, &alice, 1, &command("/unknown")).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        assert!(run(&state, &alice, 1, &command("/usr/bin"))
+            .await?
+            .is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{CreateMessage, Message, SlashCommand},
+    utils::{check_outbound_url, sign},
+    AppError,
+};
+
+use super::{CommandContext, CommandOutput};
+
+/// Sent to the endpoint of the bot, signed like outgoing webhooks.
+#[derive(Debug, Serialize, Deserialize)]
+struct CommandRequest<'a> {
+    command: &'a str,
+    text: &'a str,
+    chat_id: i64,
+    parent_id: Option<i64>,
+    user_id: i64,
+    user_name: &'a str,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ResponseType {
+    #[default]
+    Ephemeral,
+    InChannel,
+}
+
+/// What the bot answered, an empty body means there is nothing to show.
+#[derive(Debug, Deserialize)]
+struct CommandReply {
+    text: String,
+    #[serde(default)]
+    response_type: ResponseType,
+}
+
+/// Forward the command to its bot and post the reply, as the bot for `in_channel` replies.
+pub(super) async fn run(
+    ctx: &CommandContext<'_>,
+    command: &SlashCommand,
+    args: &str,
+) -> Result<CommandOutput, AppError> {
+    if !ctx.chat.is_member(command.bot_id) {
+        return Err(AppError::PermissionDenied(format!(
+            "the bot of /{} is not a member of chat {}",
+            command.name, ctx.chat.id
+        )));
+    }
+
+    // the host may point somewhere else since the command was registered
+    let allow_private = ctx.state.config.server.allow_private_urls;
+    check_outbound_url(&command.url, allow_private).await?;
+    let request = CommandRequest {
+        command: &command.name,
+        text: args,
+        chat_id: ctx.chat.id,
+        parent_id: ctx.parent_id,
+        user_id: ctx.user.id,
+        user_name: &ctx.user.fullname,
+    };
+    let body = serde_json::to_string(&request)
+        .map_err(|e| AppError::InvalidInput(format!("invalid command: {}", e)))?;
+    let timestamp = Utc::now().timestamp().to_string();
+    let signature = sign(
+        command.secret.as_deref().unwrap_or_default(),
+        &timestamp,
+        &body,
+    );
+    let res = ctx
+        .state
+        .outbound
+        .post(&command.url)
+        .header("content-type", "application/json")
+        .header("x-command-timestamp", &timestamp)
+        .header("x-command-signature", format!("sha256={}", signature))
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| AppError::UpstreamError(format!("/{} failed: {}", command.name, e)))?;
+    if !res.status().is_success() {
+        return Err(AppError::UpstreamError(format!(
+            "/{} failed with status {}",
+            command.name,
+            res.status()
+        )));
+    }
+    let body = res
+        .bytes()
+        .await
+        .map_err(|e| AppError::UpstreamError(format!("/{} failed: {}", command.name, e)))?;
+    if body.is_empty() {
+        return Ok(CommandOutput::Empty);
+    }
+    let reply: CommandReply = serde_json::from_slice(&body).map_err(|e| {
+        AppError::UpstreamError(format!(
+            "/{} replied with invalid json: {}",
+            command.name, e
+        ))
+    })?;
+
+    match reply.response_type {
+        ResponseType::Ephemeral => Ok(CommandOutput::Ephemeral(Message::ephemeral(
+            ctx.chat.id,
+            ctx.parent_id,
+            command.bot_id,
+            reply.text,
+        ))),
+        ResponseType::InChannel => {
+            let input = CreateMessage {
+                content: reply.text,
+                parent_id: ctx.parent_id,
+                ..Default::default()
+            };
+            let message =
+                Message::create(&input, ctx.chat.id, command.bot_id, &ctx.state.pool).await?;
+            Ok(CommandOutput::Message(message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{http::HeaderMap, routing::post, Json, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        commands::run,
+        models::{Bot, Chat, CreateBot, CreateSlashCommand, User},
+        AppConfig, AppState,
+    };
+
+    /// Echoes the text back, in the channel when it starts with `!`.
+    async fn echo(headers: HeaderMap, body: String) -> Json<serde_json::Value> {
+        let request: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let text = request["text"].as_str().unwrap();
+        let response_type = match text.strip_prefix('!') {
+            Some(_) => "in_channel",
+            None => "ephemeral",
+        };
+        let signed = headers.contains_key("x-command-signature");
+        Json(serde_json::json!({
+            "text": format!("{} (signed: {})", text, signed),
+            "response_type": response_type,
+        }))
+    }
+
+    #[tokio::test]
+    async fn external_command_should_post_the_bot_reply() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        config.server.allow_private_urls = true;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/echo", listener.local_addr()?);
+        let app = Router::new().route("/echo", post(echo));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateBot {
+            fullname: "Echo".to_string(),
+        };
+        let bot = Bot::create(&input, &alice, &state.pool).await?;
+        let input = CreateSlashCommand {
+            name: "echo".to_string(),
+            description: "repeat".to_string(),
+            bot_id: bot.id,
+            url,
+        };
+        SlashCommand::create(&input, &alice, true, &state.pool).await?;
+        let input = CreateMessage {
+            content: "/echo hi".to_string(),
+            ..Default::default()
+        };
+        let ret = run(&state, &alice, 1, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        Chat::add_member(1, bot.id, 1, &state.pool).await?;
+        let Some(CommandOutput::Ephemeral(reply)) = run(&state, &alice, 1, &input).await? else {
+            panic!("expected an ephemeral reply");
+        };
+        assert_eq!(reply.content, "hi (signed: true)");
+        assert_eq!(reply.sender_id, bot.id);
+
+        let input = CreateMessage {
+            content: "/echo !hi".to_string(),
+            ..Default::default()
+        };
+        let Some(CommandOutput::Message(message)) = run(&state, &alice, 1, &input).await? else {
+            panic!("expected a message");
+        };
+        assert_eq!(message.sender_id, bot.id);
+        assert!(message.id > 0);
+        Ok(())
+    }
+}
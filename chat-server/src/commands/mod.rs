@@ -0,0 +1,122 @@
+mod builtin;
+mod external;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{
+    models::{Chat, CreateMessage, Message, SlashCommand, User},
+    AppError, AppState,
+};
+
+pub(crate) use builtin::is_builtin;
+use builtin::BuiltinCommand;
+
+/// What a command answered with.
+#[derive(Debug)]
+pub(crate) enum CommandOutput {
+    /// a message posted into the chat
+    Message(Message),
+    /// a reply only the caller sees
+    Ephemeral(Message),
+    Empty,
+}
+
+/// Where a command was sent from.
+struct CommandContext<'a> {
+    state: &'a AppState,
+    user: &'a User,
+    chat: Chat,
+    parent_id: Option<i64>,
+}
+
+impl CommandContext<'_> {
+    /// An ephemeral reply from the server.
+    fn reply(&self, content: impl Into<String>) -> CommandOutput {
+        CommandOutput::Ephemeral(Message::ephemeral(
+            self.chat.id,
+            self.parent_id,
+            0,
+            content.into(),
+        ))
+    }
+}
+
+impl IntoResponse for CommandOutput {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Message(message) => (StatusCode::CREATED, Json(message)).into_response(),
+            Self::Ephemeral(message) => (StatusCode::OK, Json(message)).into_response(),
+            Self::Empty => StatusCode::NO_CONTENT.into_response(),
+        }
+    }
+}
+
+/// Run the command if the message is one, `/name args`. Messages like `/usr/bin` which do not
+/// start with a command name are not commands.
+pub(crate) async fn run(
+    state: &AppState,
+    user: &User,
+    chat_id: i64,
+    input: &CreateMessage,
+) -> Result<Option<CommandOutput>, AppError> {
+    let Some((name, args)) = parse(&input.content) else {
+        return Ok(None);
+    };
+    if input.send_at.is_some() {
+        return Err(AppError::InvalidInput(
+            "commands cannot be scheduled".to_string(),
+        ));
+    }
+
+    let chat = Chat::get_for_member(chat_id, user.id, &state.pool).await?;
+    let ctx = CommandContext {
+        state,
+        user,
+        chat,
+        parent_id: input.parent_id,
+    };
+    if let Some(command) = BuiltinCommand::from_name(&name) {
+        return command.run(&ctx, args).await.map(Some);
+    }
+    match SlashCommand::get_by_name(ctx.chat.ws_id, &name, &state.pool).await? {
+        Some(command) => external::run(&ctx, &command, args).await.map(Some),
+        None => Err(AppError::InvalidInput(format!("unknown command /{}", name))),
+    }
+}
+
+/// The lowercase command name and the rest of the message.
+fn parse(content: &str) -> Option<(String, &str)> {
+    let rest = content.trim_start().strip_prefix('/')?;
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let name = rest[..end].to_lowercase();
+    is_command_name(&name).then(|| (name, rest[end..].trim()))
+}
+
+/// Lowercase ascii letters, digits, `-` and `_`, starting with a letter.
+pub(crate) fn is_command_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && name.len() <= 32
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_should_split_name_and_args() {
+        assert_eq!(
+            parse("/remind 10m stand up"),
+            Some(("remind".to_string(), "10m stand up"))
+        );
+        assert_eq!(parse("/LEAVE"), Some(("leave".to_string(), "")));
+        assert_eq!(parse("/usr/bin/env is missing"), None);
+        assert_eq!(parse("hello /leave"), None);
+        assert_eq!(parse("/ leave"), None);
+    }
+}
@@ -1,4 +1,9 @@
-use std::{env, fs::File, path::Path};
+use std::{
+    collections::HashMap,
+    env,
+    fs::File,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
@@ -7,18 +12,122 @@ use serde::{Deserialize, Serialize};
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    pub storage: StorageConfig,
+    pub upload: UploadConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub sk: String,
     pub pk: String,
+    /// identity providers users can sign in with, by the name used in their urls
+    #[serde(default)]
+    pub oidc: HashMap<String, OidcProviderConfig>,
+    pub webauthn: WebauthnConfig,
+}
+
+/// The relying party passkeys are registered with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebauthnConfig {
+    /// the domain of the web app, e.g. `chat.acme.org`
+    pub rp_id: String,
+    /// shown by the browser when creating a passkey
+    pub rp_name: String,
+    /// where the web app is served from, e.g. `https://chat.acme.org`
+    pub origin: String,
+}
+
+/// An OpenID Connect provider, its endpoints are discovered from the issuer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcProviderConfig {
+    pub issuer: String,
+    pub client_id: String,
+    /// public clients only rely on PKCE
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// the callback of the provider, e.g. `https://chat.acme.org/api/auth/oidc/acme/callback`
+    pub redirect_uri: String,
+    /// only users with a verified email of these domains can sign in, any domain when empty
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// existing accounts are linked by their email, only for providers which own the emails
+    /// of the workspace like its single sign-on
+    #[serde(default)]
+    pub trusted: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
+    /// let webhooks and slash commands call hosts on private networks, e.g. a local receiver
+    /// in development
+    #[serde(default)]
+    pub allow_private_urls: bool,
+}
+
+/// Where uploaded files are stored, picked by the `driver` key.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "driver", rename_all = "snake_case")]
+pub enum StorageConfig {
+    Local { base_dir: PathBuf },
+    S3(S3Config),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// the url of a S3 compatible service like MinIO, AWS is used when absent
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+/// How mails are sent, picked by the `driver` key. The `log` driver only logs who a mail
+/// is for, users never get it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "driver", rename_all = "snake_case")]
+pub enum MailConfig {
+    #[default]
+    Log,
+    Http(HttpMailConfig),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HttpMailConfig {
+    /// where mails are posted as `{"from", "to", "subject", "text"}`
+    pub url: String,
+    pub api_key: String,
+    /// the sender, e.g. `Crablink <no-reply@chat.acme.org>`
+    pub from: String,
+}
+
+/// Limits of uploaded files, sizes are in bytes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadConfig {
+    pub max_file_size: u64,
+    /// storage of a user, unless the user has its own quota
+    pub user_quota: u64,
+    /// storage of all users of a workspace, unless the workspace has its own quota
+    pub workspace_quota: u64,
+    /// content types sniffed from the uploaded bytes which are accepted, `image/*` accepts
+    /// all images
+    pub allowed_mime_types: Vec<String>,
+}
+
+impl UploadConfig {
+    pub fn allows(&self, mime: &str) -> bool {
+        self.allowed_mime_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(kind) => mime.split_once('/').is_some_and(|(k, _)| k == kind),
+                None => allowed == mime,
+            })
+    }
 }
 
 impl AppConfig {
@@ -18,6 +18,33 @@ pub enum AppError {
 
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+
+    #[error("upstream error: {0}")]
+    UpstreamError(String),
+
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("media error: {0}")]
+    MediaError(String),
+
+    #[error("storage error: {0}")]
+    StorageError(String),
+
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,6 +67,15 @@ impl IntoResponse for AppError {
             Self::HashPasswordError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::JwtError(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            Self::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            Self::UpstreamError(_) => StatusCode::BAD_GATEWAY,
+            Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::MediaError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
         };
 
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
@@ -0,0 +1,100 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap},
+};
+use tower_http::request_id::RequestId;
+use uuid::Uuid;
+
+use crate::models::AuditContext;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        // the server listens on localhost, the proxy in front of it tells the client address
+        let ip = match peer {
+            Some(ip) if ip.is_loopback() => forwarded_for(&parts.headers).or(Some(ip)),
+            ip => ip,
+        };
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        // an incoming x-request-id is kept by the layer, only the uuids it makes are recorded
+        let request_id = parts
+            .extensions
+            .get::<RequestId>()
+            .and_then(|id| id.header_value().to_str().ok())
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .map(|id| id.to_string());
+        Ok(Self {
+            ip: ip.map(|ip| ip.to_string()),
+            user_agent,
+            request_id,
+        })
+    }
+}
+
+/// The address our proxy appended, the entries before it are sent by the client.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    let value = headers.get(X_FORWARDED_FOR)?.to_str().ok()?;
+    value.rsplit(',').next()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{body::Body, http::Request};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn audit_context_should_be_read_from_the_request() -> Result<()> {
+        let mut req = Request::builder()
+            .header(header::USER_AGENT, "curl/8.5")
+            .header(X_FORWARDED_FOR, "10.0.0.2, 203.0.113.7")
+            .body(Body::empty())?;
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+        let id = "0193dbd2-6a3e-7c41-9a4b-1f1d7e4c2b10";
+        req.extensions_mut().insert(RequestId::new(id.parse()?));
+        let (mut parts, _) = req.into_parts();
+        let ctx = AuditContext::from_request_parts(&mut parts, &()).await?;
+        // the client sent the first entry, the proxy appended the last one
+        assert_eq!(ctx.ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(ctx.user_agent.as_deref(), Some("curl/8.5"));
+        assert_eq!(ctx.request_id.as_deref(), Some(id));
+
+        // values sent by the client must fit the columns of audit_events
+        let long = "a".repeat(100);
+        parts
+            .headers
+            .insert(X_FORWARDED_FOR, format!("203.0.113.7, {}", long).parse()?);
+        parts.extensions.insert(RequestId::new(long.parse()?));
+        let ctx = AuditContext::from_request_parts(&mut parts, &()).await?;
+        assert_eq!(ctx.ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(ctx.request_id, None);
+
+        // only a local proxy is trusted
+        parts
+            .extensions
+            .insert(ConnectInfo(SocketAddr::from(([198, 51, 100, 1], 4000))));
+        let ctx = AuditContext::from_request_parts(&mut parts, &()).await?;
+        assert_eq!(ctx.ip.as_deref(), Some("198.51.100.1"));
+        Ok(())
+    }
+}
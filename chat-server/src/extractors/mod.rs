@@ -0,0 +1,4 @@
+mod audit;
+mod role;
+
+pub(crate) use role::{Admin, Member, RequireRole};
@@ -0,0 +1,112 @@
+use std::marker::PhantomData;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+
+use crate::{
+    models::{User, WorkspaceRole},
+    AppError, AppState,
+};
+
+/// The lowest role allowed to use an endpoint.
+pub(crate) trait MinRole {
+    const ROLE: WorkspaceRole;
+}
+
+/// Members and up, i.e. no guests.
+pub(crate) struct Member;
+
+pub(crate) struct Admin;
+
+impl MinRole for Member {
+    const ROLE: WorkspaceRole = WorkspaceRole::Member;
+}
+
+impl MinRole for Admin {
+    const ROLE: WorkspaceRole = WorkspaceRole::Admin;
+}
+
+/// The user of the request, if it has at least the role `R`, e.g. `RequireRole<Admin>`.
+/// The role in the jwt may be outdated, so the current one is looked up.
+pub(crate) struct RequireRole<R>(pub User, pub PhantomData<R>);
+
+#[cfg(test)]
+impl<R> RequireRole<R> {
+    pub(crate) fn new(user: User) -> Self {
+        Self(user, PhantomData)
+    }
+}
+
+#[async_trait]
+impl<R: MinRole> FromRequestParts<AppState> for RequireRole<R> {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // set by the verify_token middleware
+        let mut user = parts
+            .extensions
+            .get::<User>()
+            .cloned()
+            .ok_or_else(|| AppError::PermissionDenied("not signed in".to_string()))?;
+        match User::get_role(user.id, user.ws_id, &state.pool).await? {
+            Some(role) if role >= R::ROLE => {
+                user.role = role;
+                Ok(Self(user, PhantomData))
+            }
+            _ => Err(AppError::PermissionDenied(format!(
+                "user {} is not allowed to do that",
+                user.id
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        middleware::from_fn_with_state,
+        routing::get,
+        Json, Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{middlewares::verify_token, AppConfig};
+
+    async fn admin_only(RequireRole(user, _): RequireRole<Admin>) -> Json<User> {
+        Json(user)
+    }
+
+    #[tokio::test]
+    async fn require_role_should_check_the_current_role() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let token = state.sk.sign(bob)?;
+        let app = Router::new()
+            .route("/admin", get(admin_only))
+            .layer(from_fn_with_state(state.clone(), verify_token))
+            .with_state(state.clone());
+        let call = || {
+            Request::get("/admin")
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let ret = app.clone().oneshot(call()).await?;
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        // the jwt still says member
+        sqlx::query("UPDATE users SET role = 'admin' WHERE id = 2")
+            .execute(&state.pool)
+            .await?;
+        let ret = app.oneshot(call()).await?;
+        assert_eq!(ret.status(), StatusCode::OK);
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+
+use crate::AppError;
+
+use super::{
+    check_action, ContentFilter, FilterAction, FilterContext, LengthFilterConfig, Matched,
+};
+
+const MAX_CHARS: usize = 100_000;
+
+/// Messages longer than a number of characters, redacting cuts them.
+pub(crate) struct LengthFilter {
+    max_chars: usize,
+    action: FilterAction,
+}
+
+impl LengthFilter {
+    pub(crate) fn new(config: &LengthFilterConfig) -> Result<Self, AppError> {
+        check_action("length", config.action, true)?;
+        if !(1..=MAX_CHARS).contains(&config.max_chars) {
+            return Err(AppError::InvalidInput(format!(
+                "max_chars must be between 1 and {}",
+                MAX_CHARS
+            )));
+        }
+        Ok(Self {
+            max_chars: config.max_chars,
+            action: config.action,
+        })
+    }
+}
+
+#[async_trait]
+impl ContentFilter for LengthFilter {
+    fn name(&self) -> &'static str {
+        "length"
+    }
+
+    fn action(&self) -> FilterAction {
+        self.action
+    }
+
+    async fn check(
+        &self,
+        content: &str,
+        _ctx: &FilterContext<'_>,
+    ) -> Result<Option<Matched>, AppError> {
+        let len = content.chars().count();
+        if len <= self.max_chars {
+            return Ok(None);
+        }
+        Ok(Some(Matched {
+            reason: format!("{} characters, at most {}", len, self.max_chars),
+            redacted: Some(content.chars().take(self.max_chars).collect()),
+        }))
+    }
+}
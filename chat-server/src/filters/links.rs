@@ -0,0 +1,164 @@
+use std::sync::LazyLock;
+
+use async_trait::async_trait;
+use regex::Regex;
+use url::Url;
+
+use crate::AppError;
+
+use super::{check_action, ContentFilter, FilterAction, FilterContext, LinkFilterConfig, Matched};
+
+const MAX_DOMAINS: usize = 500;
+const REDACTED_LINK: &str = "[link removed]";
+
+/// Urls in plain text and markdown, `)` and `]` end them so markdown links are found too.
+static LINK_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\bhttps?://[^\s<>"'()\[\]]+"#).expect("a valid regex"));
+
+/// Links to denied domains, or to domains missing from the allow list when there is one.
+/// Redacting replaces the links.
+pub(crate) struct LinkFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+    action: FilterAction,
+}
+
+impl LinkFilter {
+    pub(crate) fn new(config: &LinkFilterConfig) -> Result<Self, AppError> {
+        check_action("links", config.action, true)?;
+        if config.allow.len() + config.deny.len() > MAX_DOMAINS {
+            return Err(AppError::InvalidInput(format!(
+                "at most {} link domains are allowed",
+                MAX_DOMAINS
+            )));
+        }
+        let normalize = |domains: &[String]| {
+            domains
+                .iter()
+                .map(|d| d.trim().trim_start_matches("*.").to_lowercase())
+                .filter(|d| !d.is_empty())
+                .collect()
+        };
+        Ok(Self {
+            allow: normalize(&config.allow),
+            deny: normalize(&config.deny),
+            action: config.action,
+        })
+    }
+
+    fn is_blocked(&self, link: &str) -> bool {
+        let Some(host) = Url::parse(link)
+            .ok()
+            .and_then(|url| url.host_str().map(|h| h.to_lowercase()))
+        else {
+            return false;
+        };
+        let matches = |domains: &[String]| domains.iter().any(|d| is_within(&host, d));
+        matches(&self.deny) || (!self.allow.is_empty() && !matches(&self.allow))
+    }
+}
+
+/// The host is the domain or one of its subdomains.
+fn is_within(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.'))
+}
+
+#[async_trait]
+impl ContentFilter for LinkFilter {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn action(&self) -> FilterAction {
+        self.action
+    }
+
+    async fn check(
+        &self,
+        content: &str,
+        ctx: &FilterContext<'_>,
+    ) -> Result<Option<Matched>, AppError> {
+        let blocked: Vec<&str> = LINK_RE
+            .find_iter(content)
+            .map(|m| m.as_str())
+            .chain(ctx.urls.iter().copied())
+            .filter(|link| self.is_blocked(link))
+            .collect();
+        if blocked.is_empty() {
+            return Ok(None);
+        }
+        let reason = format!("links to {}", blocked.join(", "));
+        // attachments cannot be redacted
+        if ctx.urls.iter().any(|url| self.is_blocked(url)) {
+            return Ok(Some(Matched {
+                reason,
+                redacted: None,
+            }));
+        }
+        let redacted = LINK_RE.replace_all(content, |caps: &regex::Captures| {
+            match self.is_blocked(&caps[0]) {
+                true => REDACTED_LINK.to_string(),
+                false => caps[0].to_string(),
+            }
+        });
+        Ok(Some(Matched {
+            reason,
+            redacted: Some(redacted.into_owned()),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn links_should_be_checked_against_the_lists() {
+        let config = LinkFilterConfig {
+            allow: vec!["acme.org".to_string()],
+            deny: vec!["*.internal.acme.org".to_string()],
+            action: FilterAction::Redact,
+        };
+        let filter = LinkFilter::new(&config).unwrap();
+        assert!(!filter.is_blocked("https://acme.org/docs"));
+        assert!(!filter.is_blocked("https://wiki.acme.org"));
+        assert!(filter.is_blocked("https://vpn.internal.acme.org/login"));
+        assert!(filter.is_blocked("http://notacme.org"));
+        assert!(filter.is_blocked("https://example.com"));
+    }
+
+    #[tokio::test]
+    async fn attachment_urls_should_be_checked() -> Result<()> {
+        let db = create_test_pool().await?;
+        let config = LinkFilterConfig {
+            allow: vec![],
+            deny: vec!["evil.com".to_string()],
+            action: FilterAction::Redact,
+        };
+        let filter = LinkFilter::new(&config)?;
+        let mut ctx = FilterContext {
+            ws_id: 1,
+            sender_id: 1,
+            // uploaded files have a path, they are not links to check
+            urls: vec!["/files/1/abc/def/a.png", "https://acme.org/logo.png"],
+            pool: &db,
+        };
+        assert_eq!(filter.check("see this", &ctx).await?, None);
+        ctx.urls.push("https://cdn.evil.com/x.png");
+        let matched = filter.check("see https://evil.com", &ctx).await?;
+        assert_eq!(
+            matched,
+            Some(Matched {
+                reason: "links to https://evil.com, https://cdn.evil.com/x.png".to_string(),
+                redacted: None,
+            })
+        );
+        Ok(())
+    }
+}
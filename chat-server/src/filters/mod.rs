@@ -0,0 +1,309 @@
+mod length;
+mod links;
+mod spam;
+mod words;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use length::LengthFilter;
+use links::LinkFilter;
+use spam::SpamFilter;
+use words::WordFilter;
+
+/// What happens to a message matched by a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// the message is not sent
+    Reject,
+    /// the matched parts are removed before the message is sent
+    Redact,
+    /// the message is sent and reported to the admins
+    Flag,
+}
+
+/// The filters of a workspace, each one is off when absent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContentFilterConfig {
+    pub words: Option<WordFilterConfig>,
+    pub links: Option<LinkFilterConfig>,
+    pub length: Option<LengthFilterConfig>,
+    pub spam: Option<SpamFilterConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WordFilterConfig {
+    /// case insensitive regular expressions, e.g. `\bfoo(bar)?\b`
+    pub patterns: Vec<String>,
+    pub action: FilterAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkFilterConfig {
+    /// when not empty only links to these domains and their subdomains are allowed
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    pub action: FilterAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LengthFilterConfig {
+    pub max_chars: usize,
+    pub action: FilterAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpamFilterConfig {
+    /// how many identical messages a user can send within the window
+    pub max_repeats: i64,
+    pub window_secs: i64,
+    pub action: FilterAction,
+}
+
+/// Who sends the message being filtered, and the urls of its attachments.
+pub(crate) struct FilterContext<'a> {
+    pub(crate) ws_id: i64,
+    pub(crate) sender_id: i64,
+    pub(crate) urls: Vec<&'a str>,
+    pub(crate) pool: &'a PgPool,
+}
+
+/// Why a filter matched, with the content without the matched parts if it can redact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Matched {
+    pub(crate) reason: String,
+    pub(crate) redacted: Option<String>,
+}
+
+#[async_trait]
+pub(crate) trait ContentFilter: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn action(&self) -> FilterAction;
+
+    async fn check(
+        &self,
+        content: &str,
+        ctx: &FilterContext<'_>,
+    ) -> Result<Option<Matched>, AppError>;
+}
+
+/// A filter which matched a message and what was done about it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct FilterHit {
+    pub(crate) filter: &'static str,
+    pub(crate) action: FilterAction,
+    pub(crate) reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FilterOutcome {
+    /// the content to send, redacted by the filters
+    pub(crate) content: String,
+    pub(crate) hits: Vec<FilterHit>,
+}
+
+impl FilterOutcome {
+    pub(crate) fn rejection(&self) -> Option<&FilterHit> {
+        self.hits.iter().find(|h| h.action == FilterAction::Reject)
+    }
+
+    pub(crate) fn flags(&self) -> impl Iterator<Item = &FilterHit> {
+        self.hits.iter().filter(|h| h.action == FilterAction::Flag)
+    }
+
+    /// Record a hit of the filter and return what is done about it.
+    fn push(&mut self, filter: &dyn ContentFilter, reason: String, redacted: bool) -> FilterAction {
+        let action = match filter.action() {
+            FilterAction::Redact if !redacted => FilterAction::Reject,
+            action => action,
+        };
+        self.hits.push(FilterHit {
+            filter: filter.name(),
+            action,
+            reason,
+        });
+        action
+    }
+}
+
+/// The filters of a workspace, run in order on every message before it is stored.
+pub(crate) struct FilterChain {
+    filters: Vec<Box<dyn ContentFilter>>,
+}
+
+impl FilterChain {
+    /// Build the chain, invalid settings like a bad regular expression are rejected.
+    pub(crate) fn from_config(config: &ContentFilterConfig) -> Result<Self, AppError> {
+        let mut filters: Vec<Box<dyn ContentFilter>> = vec![];
+        if let Some(config) = &config.length {
+            filters.push(Box::new(LengthFilter::new(config)?));
+        }
+        if let Some(config) = &config.words {
+            filters.push(Box::new(WordFilter::new(config)?));
+        }
+        if let Some(config) = &config.links {
+            filters.push(Box::new(LinkFilter::new(config)?));
+        }
+        if let Some(config) = &config.spam {
+            filters.push(Box::new(SpamFilter::new(config)?));
+        }
+        Ok(Self { filters })
+    }
+
+    /// Run the filters, each one sees the content redacted by the previous ones. It stops at
+    /// the first filter rejecting the message, what a filter cannot redact, like an
+    /// attachment, is rejected.
+    pub(crate) async fn run(
+        &self,
+        content: &str,
+        ctx: &FilterContext<'_>,
+    ) -> Result<FilterOutcome, AppError> {
+        let mut outcome = FilterOutcome {
+            content: content.to_string(),
+            hits: vec![],
+        };
+        for filter in &self.filters {
+            let Some(matched) = filter.check(&outcome.content, ctx).await? else {
+                continue;
+            };
+            let hit = outcome.push(filter.as_ref(), matched.reason, matched.redacted.is_some());
+            match (hit, matched.redacted) {
+                (FilterAction::Reject, _) => break,
+                (FilterAction::Redact, Some(redacted)) => outcome.content = redacted,
+                _ => {}
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Run the filters again on the plain text the redacted content renders to, markup like
+    /// `**d**arn` must not get a message past them. The text cannot be redacted, any match
+    /// in it rejects the message, only a flag raised already is not raised twice.
+    pub(crate) async fn recheck(
+        &self,
+        outcome: &mut FilterOutcome,
+        text: &str,
+        ctx: &FilterContext<'_>,
+    ) -> Result<(), AppError> {
+        if outcome.rejection().is_some() {
+            return Ok(());
+        }
+        for filter in &self.filters {
+            let Some(matched) = filter.check(text, ctx).await? else {
+                continue;
+            };
+            let flagged = outcome.flags().any(|h| h.filter == filter.name());
+            if filter.action() == FilterAction::Flag && flagged {
+                continue;
+            }
+            if outcome.push(filter.as_ref(), matched.reason, false) == FilterAction::Reject {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Only some filters can redact what they matched.
+fn check_action(filter: &str, action: FilterAction, can_redact: bool) -> Result<(), AppError> {
+    match action == FilterAction::Redact && !can_redact {
+        true => Err(AppError::InvalidInput(format!(
+            "the {} filter cannot redact",
+            filter
+        ))),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn filter_chain_should_redact_flag_and_reject() -> Result<()> {
+        let db = create_test_pool().await?;
+        let ctx = FilterContext {
+            ws_id: 1,
+            sender_id: 1,
+            urls: vec![],
+            pool: &db,
+        };
+        let config: ContentFilterConfig = serde_json::from_value(serde_json::json!({
+            "words": { "patterns": ["darn"], "action": "redact" },
+            "links": { "deny": ["evil.com"], "action": "flag" },
+            "length": { "max_chars": 40, "action": "reject" },
+        }))?;
+        let chain = FilterChain::from_config(&config)?;
+
+        let outcome = chain.run("darn, see https://www.evil.com/x", &ctx).await?;
+        assert_eq!(outcome.content, "****, see https://www.evil.com/x");
+        let filters: Vec<_> = outcome.hits.iter().map(|h| h.filter).collect();
+        assert_eq!(filters, ["words", "links"]);
+        assert_eq!(outcome.flags().count(), 1);
+        assert!(outcome.rejection().is_none());
+
+        let outcome = chain.run(&"darn ".repeat(10), &ctx).await?;
+        assert_eq!(outcome.rejection().map(|h| h.filter), Some("length"));
+        assert_eq!(outcome.hits.len(), 1);
+
+        let outcome = chain.run("all good", &ctx).await?;
+        assert!(outcome.hits.is_empty());
+
+        // the rendered text cannot be redacted
+        let mut outcome = chain.run("**d**arn", &ctx).await?;
+        assert!(outcome.hits.is_empty());
+        chain.recheck(&mut outcome, "darn", &ctx).await?;
+        assert_eq!(outcome.rejection().map(|h| h.filter), Some("words"));
+        // a filter which redacted the content still checks the text
+        let mut outcome = chain.run("darn **d**arn", &ctx).await?;
+        assert_eq!(outcome.content, "**** **d**arn");
+        chain.recheck(&mut outcome, "**** darn", &ctx).await?;
+        assert_eq!(outcome.rejection().map(|h| h.filter), Some("words"));
+        // a link flagged in the content is not flagged again
+        let mut outcome = chain.run("see **https://evil.com**", &ctx).await?;
+        chain
+            .recheck(&mut outcome, "see https://evil.com", &ctx)
+            .await?;
+        assert_eq!(outcome.flags().count(), 1);
+        assert!(outcome.rejection().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config_should_be_rejected() {
+        let config = ContentFilterConfig {
+            words: Some(WordFilterConfig {
+                patterns: vec!["(unclosed".to_string()],
+                action: FilterAction::Reject,
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            FilterChain::from_config(&config),
+            Err(AppError::InvalidInput(_))
+        ));
+        let config = ContentFilterConfig {
+            spam: Some(SpamFilterConfig {
+                max_repeats: 3,
+                window_secs: 60,
+                action: FilterAction::Redact,
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            FilterChain::from_config(&config),
+            Err(AppError::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,77 @@
+use async_trait::async_trait;
+
+use crate::AppError;
+
+use super::{check_action, ContentFilter, FilterAction, FilterContext, Matched, SpamFilterConfig};
+
+const MAX_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+/// The same message sent again and again by a user, in any chat of the workspace. The
+/// rendered text is compared too, so changing the markup does not make a message new.
+pub(crate) struct SpamFilter {
+    max_repeats: i64,
+    window_secs: i64,
+    action: FilterAction,
+}
+
+impl SpamFilter {
+    pub(crate) fn new(config: &SpamFilterConfig) -> Result<Self, AppError> {
+        check_action("spam", config.action, false)?;
+        if config.max_repeats < 1 || !(1..=MAX_WINDOW_SECS).contains(&config.window_secs) {
+            return Err(AppError::InvalidInput(format!(
+                "max_repeats must be positive and window_secs between 1 and {}",
+                MAX_WINDOW_SECS
+            )));
+        }
+        Ok(Self {
+            max_repeats: config.max_repeats,
+            window_secs: config.window_secs,
+            action: config.action,
+        })
+    }
+}
+
+#[async_trait]
+impl ContentFilter for SpamFilter {
+    fn name(&self) -> &'static str {
+        "spam"
+    }
+
+    fn action(&self) -> FilterAction {
+        self.action
+    }
+
+    async fn check(
+        &self,
+        content: &str,
+        ctx: &FilterContext<'_>,
+    ) -> Result<Option<Matched>, AppError> {
+        // goes through the sender_id_index
+        let sent: i64 = sqlx::query_scalar(
+            r#"
+            SELECT count(*)
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id
+            WHERE m.sender_id = $1 AND m.created_at > now() - make_interval(secs => $3)
+                AND c.ws_id = $2 AND (m.content = $4 OR m.content_text = $4)
+            "#,
+        )
+        .bind(ctx.sender_id)
+        .bind(ctx.ws_id)
+        .bind(self.window_secs as f64)
+        .bind(content)
+        .fetch_one(ctx.pool)
+        .await?;
+        if sent < self.max_repeats {
+            return Ok(None);
+        }
+        Ok(Some(Matched {
+            reason: format!(
+                "sent {} times in the last {} seconds",
+                sent + 1,
+                self.window_secs
+            ),
+            redacted: None,
+        }))
+    }
+}
@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+use regex::{Regex, RegexBuilder};
+
+use crate::AppError;
+
+use super::{check_action, ContentFilter, FilterAction, FilterContext, Matched, WordFilterConfig};
+
+const MAX_PATTERNS: usize = 200;
+/// The compiled size of a pattern, the regex crate runs in linear time but big patterns are
+/// still slow to build.
+const MAX_PATTERN_SIZE: usize = 1 << 20;
+
+/// Words or phrases matching a list of regular expressions, redacting masks them with `*`.
+pub(crate) struct WordFilter {
+    patterns: Vec<Regex>,
+    action: FilterAction,
+}
+
+impl WordFilter {
+    pub(crate) fn new(config: &WordFilterConfig) -> Result<Self, AppError> {
+        check_action("words", config.action, true)?;
+        if config.patterns.len() > MAX_PATTERNS {
+            return Err(AppError::InvalidInput(format!(
+                "at most {} word patterns are allowed",
+                MAX_PATTERNS
+            )));
+        }
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|p| {
+                RegexBuilder::new(p)
+                    .case_insensitive(true)
+                    .size_limit(MAX_PATTERN_SIZE)
+                    .build()
+                    .map_err(|e| AppError::InvalidInput(format!("invalid pattern {}: {}", p, e)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            patterns,
+            action: config.action,
+        })
+    }
+}
+
+#[async_trait]
+impl ContentFilter for WordFilter {
+    fn name(&self) -> &'static str {
+        "words"
+    }
+
+    fn action(&self) -> FilterAction {
+        self.action
+    }
+
+    async fn check(
+        &self,
+        content: &str,
+        _ctx: &FilterContext<'_>,
+    ) -> Result<Option<Matched>, AppError> {
+        let matched: Vec<&Regex> = self
+            .patterns
+            .iter()
+            .filter(|p| p.is_match(content))
+            .collect();
+        if matched.is_empty() {
+            return Ok(None);
+        }
+        let mut redacted = content.to_string();
+        for pattern in &matched {
+            redacted = pattern
+                .replace_all(&redacted, |caps: &regex::Captures| {
+                    "*".repeat(caps[0].chars().count())
+                })
+                .into_owned();
+        }
+        let patterns: Vec<&str> = matched.iter().map(|p| p.as_str()).collect();
+        Ok(Some(Matched {
+            reason: format!("matched {}", patterns.join(", ")),
+            redacted: Some(redacted),
+        }))
+    }
+}
@@ -0,0 +1,103 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use serde_json::json;
+
+use crate::{
+    extractors::{Admin, RequireRole},
+    models::{AuditAction, AuditContext, AuditEvent, ListAuditEvents, NewAuditEvent},
+    AppError, AppState,
+};
+
+pub(crate) async fn list_audit_events_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    Query(input): Query<ListAuditEvents>,
+) -> Result<impl IntoResponse, AppError> {
+    let events = AuditEvent::list(user.ws_id, user.id, &input, &state.pool).await?;
+    Ok(Json(events))
+}
+
+/// Stream the matching events as NDJSON, the export itself is recorded too.
+pub(crate) async fn export_audit_events_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Query(input): Query<ListAuditEvents>,
+) -> Result<impl IntoResponse, AppError> {
+    let filters = json!(input);
+    let events = AuditEvent::export(user.ws_id, user.id, input, state.pool.clone()).await?;
+    let event = NewAuditEvent::new(AuditAction::AuditExport, &user).metadata(filters);
+    audit.record(event, &state.pool).await;
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(events),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{body::to_bytes, http::StatusCode};
+
+    use super::*;
+    use crate::{models::User, AppConfig};
+
+    #[tokio::test]
+    async fn test_export_audit_events() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let event = NewAuditEvent::new(AuditAction::ChatMemberAdd, &alice).target("chat", 1);
+        AuditContext::default().record(event, &state.pool).await;
+
+        let ret = export_audit_events_handler(
+            RequireRole::new(bob),
+            State(state.clone()),
+            AuditContext::default(),
+            Query(ListAuditEvents::default()),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let input = ListAuditEvents {
+            action: Some("chat.member_add".to_string()),
+            ..Default::default()
+        };
+        let ret = export_audit_events_handler(
+            RequireRole::new(alice.clone()),
+            State(state.clone()),
+            AuditContext::default(),
+            Query(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.headers()[header::CONTENT_TYPE], "application/x-ndjson");
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        let lines: Vec<&[u8]> = body
+            .split(|b| *b == b'\n')
+            .filter(|l| !l.is_empty())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        let event: AuditEvent = serde_json::from_slice(lines[0])?;
+        assert_eq!(event.target_id, Some(1));
+
+        // the export is in the log
+        let ret = list_audit_events_handler(
+            RequireRole::new(alice),
+            State(state),
+            Query(ListAuditEvents::default()),
+        )
+        .await?
+        .into_response();
+        let events = crate::utils::parser_response::<Vec<AuditEvent>>(ret).await?;
+        assert_eq!(events[0].action, "audit.export");
+        Ok(())
+    }
+}
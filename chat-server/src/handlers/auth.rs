@@ -1,10 +1,25 @@
 use anyhow::Result;
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::{Path, Query, State},
+    http::{
+        header::{COOKIE, SET_COOKIE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
 
 use crate::{
+    config::OidcProviderConfig,
     error::ErrorOutput,
-    models::{CreateUser, User, VerifyUser},
+    models::{
+        AuditAction, AuditContext, AuthenticatePasskey, CreateUser, NewAuditEvent, OidcLogin, User,
+        VerifyUser, WebauthnCredential,
+    },
+    utils::OidcClient,
     AppError, AppState,
 };
 
@@ -15,15 +30,27 @@ pub struct AuthOutput {
 
 pub(crate) async fn signin_handler(
     State(state): State<AppState>,
+    audit: AuditContext,
     Json(input): Json<VerifyUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = User::verify(&input, &state.pool).await?;
     match user {
         Some(user) => {
-            let token = state.sk.sign(user)?;
-            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+            let event = NewAuditEvent::new(AuditAction::UserSignin, &user).target("user", user.id);
+            sign_in(&state, user, event, &audit).await
         }
         None => {
+            // the workspace of an existing account sees the attempts on it
+            let account = User::find_account(&input.email, &state.pool).await?;
+            let mut event = NewAuditEvent::anonymous(
+                AuditAction::UserSigninFailed,
+                account.map(|(_, ws_id)| ws_id),
+            )
+            .metadata(json!({ "email": input.email }));
+            if let Some((id, _)) = account {
+                event = event.target("user", id);
+            }
+            audit.record(event, &state.pool).await;
             let body = Json(ErrorOutput::new("Invalid email or password"));
             Ok((StatusCode::FORBIDDEN, body).into_response())
         }
@@ -32,25 +59,515 @@ pub(crate) async fn signin_handler(
 
 pub(crate) async fn signup_handler(
     State(state): State<AppState>,
+    audit: AuditContext,
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = User::create(&input, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::UserSignup, &user).target("user", user.id);
+    audit.record(event, &state.pool).await;
     let token = state.sk.sign(user.clone())?;
     Ok((StatusCode::CREATED, Json(AuthOutput { token })).into_response())
 }
 
+/// The options to sign in with any passkey in the browser.
+pub(crate) async fn start_passkey_signin_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = &state.config.auth.webauthn;
+    let options = WebauthnCredential::start_authentication(None, config, &state.pool).await?;
+    Ok(Json(options))
+}
+
+/// Sign in with a passkey, alone or after the password when the user requires it.
+pub(crate) async fn finish_passkey_signin_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(input): Json<AuthenticatePasskey>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = &state.config.auth.webauthn;
+    let (user, second_factor) =
+        match WebauthnCredential::authenticate(&input, config, &state.pool).await {
+            Ok(ret) => ret,
+            Err(e) => {
+                let event = NewAuditEvent::anonymous(AuditAction::UserSigninFailed, None)
+                    .metadata(json!({ "method": "passkey", "error": e.to_string() }));
+                audit.record(event, &state.pool).await;
+                return Err(e);
+            }
+        };
+    let event = NewAuditEvent::new(AuditAction::UserSignin, &user)
+        .target("user", user.id)
+        .metadata(json!({ "method": "passkey", "second_factor": second_factor }));
+    audit.record(event, &state.pool).await;
+    let token = state.sk.sign(user)?;
+    Ok(Json(AuthOutput { token }))
+}
+
+/// What the identity provider redirects back with.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OidcCallback {
+    pub state: String,
+    #[serde(default)]
+    pub code: Option<String>,
+    /// set when the user or the provider refused the login
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// The cookie binding a login to the browser which started it, so that nobody can finish
+/// their own login in the browser of someone else.
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+const OIDC_STATE_MAX_AGE: i64 = 600;
+
+/// Send the user to sign in at the identity provider, it redirects back to the callback.
+pub(crate) async fn oidc_start_handler(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = oidc_provider(&state, &provider)?;
+    let client = OidcClient::new(config, &state.http);
+    let discovery = client.discover().await?;
+    let login = OidcLogin::create(&provider, &state.pool).await?;
+    let url = client.authorize_url(&discovery, &login.state, &login.nonce, &login.code_verifier)?;
+    let cookie = state_cookie(config, &login.state, OIDC_STATE_MAX_AGE)?;
+    Ok(([(SET_COOKIE, cookie)], Redirect::to(url.as_str())))
+}
+
+/// Finish the login at the identity provider, users are provisioned on their first login.
+/// Users who require a passkey get the options to confirm the login with it.
+pub(crate) async fn oidc_callback_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    Query(input): Query<OidcCallback>,
+) -> Result<Response, AppError> {
+    let config = oidc_provider(&state, &provider)?;
+    if cookie(&headers, OIDC_STATE_COOKIE) != Some(input.state.as_str()) {
+        return Err(AppError::PermissionDenied(
+            "the login was not started in this browser".to_string(),
+        ));
+    }
+    // the login is used up even when the provider refused it
+    let login = OidcLogin::take(&input.state, &provider, &state.pool).await?;
+    let code = match (input.code, input.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            return Err(AppError::PermissionDenied(format!(
+                "the identity provider refused the login: {}",
+                error.unwrap_or_default()
+            )))
+        }
+    };
+    let client = OidcClient::new(config, &state.http);
+    let discovery = client.discover().await?;
+    let identity = client
+        .exchange(&discovery, &code, &login.code_verifier, &login.nonce)
+        .await?;
+
+    let ret = User::from_identity(&provider, &identity, config, &state.pool).await;
+    let (user, created) = match ret {
+        Ok(ret) => ret,
+        Err(e) => {
+            let event = NewAuditEvent::anonymous(AuditAction::UserSigninFailed, None).metadata(
+                json!({ "provider": provider, "email": identity.profile.email, "error": e.to_string() }),
+            );
+            audit.record(event, &state.pool).await;
+            return Err(e);
+        }
+    };
+    if created {
+        let event = NewAuditEvent::new(AuditAction::UserSignup, &user)
+            .target("user", user.id)
+            .metadata(json!({ "provider": provider }));
+        audit.record(event, &state.pool).await;
+    }
+    let event = NewAuditEvent::new(AuditAction::UserSignin, &user)
+        .target("user", user.id)
+        .metadata(json!({ "provider": provider }));
+    let mut ret = sign_in(&state, user, event, &audit).await?;
+    ret.headers_mut()
+        .insert(SET_COOKIE, state_cookie(config, "", 0)?);
+    Ok(ret)
+}
+
+/// Give the user a token, or the options to confirm the sign in with a passkey when the user
+/// requires one. Every way to sign in but a passkey goes through here.
+async fn sign_in(
+    state: &AppState,
+    user: User,
+    event: NewAuditEvent,
+    audit: &AuditContext,
+) -> Result<Response, AppError> {
+    if User::passkey_required(user.id, &state.pool).await? {
+        let config = &state.config.auth.webauthn;
+        let options =
+            WebauthnCredential::start_authentication(Some(user.id), config, &state.pool).await?;
+        return Ok((StatusCode::ACCEPTED, Json(options)).into_response());
+    }
+    audit.record(event, &state.pool).await;
+    let token = state.sk.sign(user)?;
+    Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+}
+
+/// The state cookie is only sent to the callback of the provider, and only over https when
+/// the callback is.
+fn state_cookie(
+    config: &OidcProviderConfig,
+    value: &str,
+    max_age: i64,
+) -> Result<HeaderValue, AppError> {
+    let redirect_uri = Url::parse(&config.redirect_uri)
+        .map_err(|e| AppError::InvalidInput(format!("invalid redirect uri: {}", e)))?;
+    let secure = if redirect_uri.scheme() == "https" {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie = format!(
+        "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        OIDC_STATE_COOKIE,
+        value,
+        redirect_uri.path(),
+        max_age,
+        secure
+    );
+    HeaderValue::from_str(&cookie).map_err(|e| AppError::InvalidInput(e.to_string()))
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+fn oidc_provider<'a>(
+    state: &'a AppState,
+    provider: &str,
+) -> Result<&'a OidcProviderConfig, AppError> {
+    state
+        .config
+        .auth
+        .oidc
+        .get(provider)
+        .ok_or_else(|| AppError::NotFound(format!("identity provider {}", provider)))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{utils::parser_response, AppConfig};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        http::header::LOCATION,
+        routing::{get, post},
+        Form, Router,
+    };
+    use jwt_simple::prelude::*;
+    use tokio::net::TcpListener;
+
+    use crate::{
+        models::{RegisterPasskey, UpdateSecondFactor},
+        utils::{code_challenge, encode_b64, parser_response, OidcProfile, SoftAuthenticator},
+        AppConfig,
+    };
 
     use super::*;
 
+    /// A local identity provider, a user signing in there is simulated with `authorize`.
+    struct MockIdp {
+        issuer: String,
+        key: ES256KeyPair,
+        /// codes given to users, with the nonce and the code challenge of their login
+        codes: Mutex<HashMap<String, (String, String, String, OidcProfile)>>,
+    }
+
+    impl MockIdp {
+        async fn start() -> Result<Arc<Self>> {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let idp = Arc::new(Self {
+                issuer: format!("http://{}", listener.local_addr()?),
+                key: ES256KeyPair::generate().with_key_id("k1"),
+                codes: Default::default(),
+            });
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(idp.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            Ok(idp)
+        }
+
+        fn authorize(&self, authorize_url: &str, subject: &str, email: &str) -> (String, String) {
+            let url = reqwest::Url::parse(authorize_url).unwrap();
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            assert_eq!(params["code_challenge_method"], "S256");
+            let code = format!("code-{}", subject);
+            let profile = OidcProfile {
+                email: Some(email.to_string()),
+                email_verified: Some(true),
+                name: Some("Erin Example".to_string()),
+            };
+            self.codes.lock().unwrap().insert(
+                code.clone(),
+                (
+                    params["nonce"].clone(),
+                    params["code_challenge"].clone(),
+                    subject.to_string(),
+                    profile,
+                ),
+            );
+            (code, params["state"].clone())
+        }
+    }
+
+    async fn discovery(State(idp): State<Arc<MockIdp>>) -> Json<serde_json::Value> {
+        Json(json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+        }))
+    }
+
+    async fn jwks(State(idp): State<Arc<MockIdp>>) -> Json<serde_json::Value> {
+        let point = idp.key.public_key().public_key().to_bytes_uncompressed();
+        let b64 = |bytes: &[u8]| Base64UrlSafeNoPadding::encode_to_string(bytes).unwrap();
+        Json(json!({ "keys": [{
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": "k1",
+            "x": b64(&point[1..33]),
+            "y": b64(&point[33..]),
+        }]}))
+    }
+
+    async fn token(
+        State(idp): State<Arc<MockIdp>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<serde_json::Value>, StatusCode> {
+        let (nonce, challenge, subject, profile) = idp
+            .codes
+            .lock()
+            .unwrap()
+            .remove(&form["code"])
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        if code_challenge(&form["code_verifier"]) != challenge {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let claims = Claims::with_custom_claims(profile, Duration::from_mins(5))
+            .with_issuer(&idp.issuer)
+            .with_audience(&form["client_id"])
+            .with_subject(subject)
+            .with_nonce(nonce);
+        let id_token = idp.key.sign(claims).unwrap();
+        Ok(Json(
+            json!({ "access_token": "opaque", "token_type": "Bearer", "id_token": id_token }),
+        ))
+    }
+
+    /// Start a login, returns where the user is sent and the cookie the browser keeps.
+    async fn start_login(state: &AppState) -> Result<(String, String)> {
+        let ret = oidc_start_handler(State(state.clone()), Path("mock".to_string()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::SEE_OTHER);
+        let cookie = ret.headers()[SET_COOKIE].to_str()?;
+        assert!(cookie.contains("; HttpOnly; SameSite=Lax"));
+        let cookie = cookie.split(';').next().unwrap_or_default().to_string();
+        Ok((ret.headers()[LOCATION].to_str()?.to_string(), cookie))
+    }
+
+    async fn callback(
+        state: &AppState,
+        code: String,
+        login_state: String,
+        cookie: &str,
+    ) -> Result<Response, AppError> {
+        let input = OidcCallback {
+            state: login_state,
+            code: Some(code),
+            error: None,
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_str(&format!("theme=dark; {}", cookie)).unwrap(),
+        );
+        oidc_callback_handler(
+            State(state.clone()),
+            AuditContext::default(),
+            Path("mock".to_string()),
+            headers,
+            Query(input),
+        )
+        .await
+    }
+
+    async fn finish_login(
+        state: &AppState,
+        code: String,
+        login_state: String,
+        cookie: &str,
+    ) -> Result<AuthOutput, AppError> {
+        let ret = callback(state, code, login_state, cookie).await?;
+        assert_eq!(ret.status(), StatusCode::OK);
+        assert!(ret.headers()[SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .contains("Max-Age=0"));
+        Ok(parser_response::<AuthOutput>(ret).await.unwrap())
+    }
+
+    fn mock_provider(idp: &MockIdp) -> OidcProviderConfig {
+        OidcProviderConfig {
+            issuer: idp.issuer.clone(),
+            client_id: "crablink".to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost:7070/api/auth/oidc/mock/callback".to_string(),
+            allowed_domains: vec!["acme.org".to_string()],
+            trusted: true,
+        }
+    }
+
+    /// Give the user a passkey it has to sign in with.
+    async fn require_passkey(user: &User, state: &AppState) -> Result<SoftAuthenticator> {
+        let webauthn = &state.config.auth.webauthn;
+        let authenticator = SoftAuthenticator::new(webauthn);
+        let options = WebauthnCredential::start_registration(user, webauthn, &state.pool).await?;
+        let input: RegisterPasskey =
+            serde_json::from_value(authenticator.register(&options.challenge))?;
+        WebauthnCredential::register(&input, user, webauthn, &state.pool).await?;
+        let input = UpdateSecondFactor { enabled: true };
+        User::update_second_factor(user.id, &input, &state.pool).await?;
+        Ok(authenticator)
+    }
+
+    #[tokio::test]
+    async fn oidc_login_should_provision_users() -> Result<()> {
+        let idp = MockIdp::start().await?;
+        let mut config = AppConfig::load()?;
+        config
+            .auth
+            .oidc
+            .insert("mock".to_string(), mock_provider(&idp));
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let (url, cookie) = start_login(&state).await?;
+        assert!(url.starts_with(&format!("{}/authorize?", idp.issuer)));
+        let (code, login_state) = idp.authorize(&url, "erin-1", "erin@acme.org");
+        let ret = finish_login(&state, code.clone(), login_state.clone(), &cookie).await?;
+        let erin = state.pk.verify(&ret.token)?;
+        assert_eq!(erin.email, "erin@acme.org");
+
+        // a login can only be finished once
+        let ret = finish_login(&state, code, login_state, &cookie).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        // the user is found again by its subject
+        let (url, cookie) = start_login(&state).await?;
+        let (code, login_state) = idp.authorize(&url, "erin-1", "erin@acme.org");
+        let ret = finish_login(&state, code, login_state, &cookie).await?;
+        assert_eq!(state.pk.verify(&ret.token)?.id, erin.id);
+
+        // the code only works with the verifier of its login
+        let (first, _) = start_login(&state).await?;
+        let (second, cookie) = start_login(&state).await?;
+        let (code, _) = idp.authorize(&first, "erin-1", "erin@acme.org");
+        let (_, login_state) = idp.authorize(&second, "erin-2", "erin@acme.org");
+        let ret = finish_login(&state, code, login_state, &cookie).await;
+        assert!(matches!(ret, Err(AppError::UpstreamError(_))));
+
+        let (url, cookie) = start_login(&state).await?;
+        let (code, login_state) = idp.authorize(&url, "m-1", "mallory@evil.com");
+        let ret = finish_login(&state, code, login_state, &cookie).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let actions: Vec<String> =
+            sqlx::query_scalar("SELECT action FROM audit_events ORDER BY id")
+                .fetch_all(&state.pool)
+                .await?;
+        assert_eq!(
+            actions,
+            [
+                "user.signup",
+                "user.signin",
+                "user.signin",
+                "user.signin_failed"
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_login_should_be_finished_in_its_browser() -> Result<()> {
+        let idp = MockIdp::start().await?;
+        let mut config = AppConfig::load()?;
+        config
+            .auth
+            .oidc
+            .insert("mock".to_string(), mock_provider(&idp));
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        // a victim is sent to the callback with the login of the attacker
+        let (url, cookie) = start_login(&state).await?;
+        let (code, login_state) = idp.authorize(&url, "m-1", "mallory@acme.org");
+        let (_, victim) = start_login(&state).await?;
+        let ret = callback(&state, code.clone(), login_state.clone(), &victim).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = callback(&state, code.clone(), login_state.clone(), "").await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // the login is still there for the browser which started it
+        finish_login(&state, code, login_state, &cookie).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_login_should_ask_for_passkey() -> Result<()> {
+        let idp = MockIdp::start().await?;
+        let mut config = AppConfig::load()?;
+        config
+            .auth
+            .oidc
+            .insert("mock".to_string(), mock_provider(&idp));
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let input = CreateUser::new("Erin", "erin@acme.org", "password");
+        let user = User::create(&input, &state.pool).await?;
+        let authenticator = require_passkey(&user, &state).await?;
+
+        // the provider does not replace the passkey
+        let (url, cookie) = start_login(&state).await?;
+        let (code, login_state) = idp.authorize(&url, "erin-1", "erin@acme.org");
+        let ret = callback(&state, code, login_state, &cookie).await?;
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let options = parser_response::<serde_json::Value>(ret).await?;
+        assert!(options.get("token").is_none());
+        assert_eq!(
+            options["allowCredentials"][0]["id"],
+            encode_b64(&authenticator.credential_id)
+        );
+        let signins: i64 =
+            sqlx::query_scalar("SELECT count(*) FROM audit_events WHERE action = 'user.signin'")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(signins, 0);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_signup_success() -> Result<()> {
         let config = AppConfig::load()?;
         let (_tdb, state) = AppState::new_for_test(config).await?;
         let input = CreateUser::new("hildxd", "hildxd@qq.com", "password");
-        let ret = signup_handler(State(state), Json(input))
+        let ret = signup_handler(State(state), AuditContext::default(), Json(input))
             .await?
             .into_response();
 
@@ -68,12 +585,56 @@ mod tests {
         User::create(&input, &state.pool).await?;
 
         let input = VerifyUser::new("hildxd@qq.com", "password");
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), AuditContext::default(), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let ret = parser_response::<AuthOutput>(ret).await?;
+        assert_ne!(ret.token, "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_signin_should_ask_for_passkey() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let input = CreateUser::new("hildxd", "hildxd@qq.com", "password");
+        let user = User::create(&input, &state.pool).await?;
+        let mut authenticator = require_passkey(&user, &state).await?;
+
+        // the password alone gives the options to confirm it with the passkey
+        let input = VerifyUser::new("hildxd@qq.com", "password");
+        let ret = signin_handler(State(state.clone()), AuditContext::default(), Json(input))
             .await?
             .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let options = parser_response::<serde_json::Value>(ret).await?;
+        assert_eq!(
+            options["allowCredentials"][0]["id"],
+            encode_b64(&authenticator.credential_id)
+        );
+        let challenge = options["challenge"].as_str().unwrap();
+
+        let input = serde_json::from_value(authenticator.authenticate(challenge, false))?;
+        let ret = finish_passkey_signin_handler(
+            State(state.clone()),
+            AuditContext::default(),
+            Json(input),
+        )
+        .await?
+        .into_response();
         assert_eq!(ret.status(), StatusCode::OK);
         let ret = parser_response::<AuthOutput>(ret).await?;
         assert_ne!(ret.token, "");
+
+        let metadata: serde_json::Value =
+            sqlx::query_scalar("SELECT metadata FROM audit_events WHERE action = 'user.signin'")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(
+            metadata,
+            json!({ "method": "passkey", "second_factor": true })
+        );
         Ok(())
     }
 
@@ -82,13 +643,25 @@ mod tests {
         let config = AppConfig::load()?;
         let (_tdb, state) = AppState::new_for_test(config).await?;
         let input = CreateUser::new("hildxd", "hildxd@qq.com", "password");
-        User::create(&input, &state.pool).await?;
+        let user = User::create(&input, &state.pool).await?;
 
         let input = VerifyUser::new("hildxd@qq.com", "wrong_password");
-        let ret = signin_handler(State(state), Json(input))
+        let audit = AuditContext {
+            ip: Some("203.0.113.7".to_string()),
+            ..Default::default()
+        };
+        let ret = signin_handler(State(state.clone()), audit, Json(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let (action, target_id, ip): (String, Option<i64>, Option<String>) =
+            sqlx::query_as("SELECT action, target_id, ip FROM audit_events")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(action, "user.signin_failed");
+        assert_eq!(target_id, Some(user.id));
+        assert_eq!(ip.as_deref(), Some("203.0.113.7"));
         Ok(())
     }
 
@@ -97,9 +670,14 @@ mod tests {
         let config = AppConfig::load()?;
         let (_tdb, state) = AppState::new_for_test(config).await?;
         let input = CreateUser::new("hildxd", "email@qq.com", "password");
-        signup_handler(State(state.clone()), Json(input.clone())).await?;
+        signup_handler(
+            State(state.clone()),
+            AuditContext::default(),
+            Json(input.clone()),
+        )
+        .await?;
 
-        let ret = signup_handler(State(state), Json(input))
+        let ret = signup_handler(State(state), AuditContext::default(), Json(input))
             .await
             .into_response();
         assert_eq!(ret.status(), StatusCode::CONFLICT);
@@ -113,10 +691,10 @@ mod tests {
         let config = AppConfig::load()?;
         let (_tdb, state) = AppState::new_for_test(config).await?;
         let input = CreateUser::new("hildxd", "email@qq.com", "password");
-        signup_handler(State(state.clone()), Json(input)).await?;
+        signup_handler(State(state.clone()), AuditContext::default(), Json(input)).await?;
 
         let input = VerifyUser::new("xxemail@qq.com", "password");
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), AuditContext::default(), Json(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::FORBIDDEN);
@@ -0,0 +1,152 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::json;
+
+use crate::{
+    extractors::{Member, RequireRole},
+    models::{
+        AuditAction, AuditContext, Bot, BotToken, CreateBot, CreateBotToken, CreateSlashCommand,
+        NewAuditEvent, SlashCommand, User,
+    },
+    AppError, AppState,
+};
+
+pub(crate) async fn create_bot_handler(
+    RequireRole(user, _): RequireRole<Member>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(input): Json<CreateBot>,
+) -> Result<impl IntoResponse, AppError> {
+    let bot = Bot::create(&input, &user, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::BotCreate, &user).target("user", bot.id);
+    audit.record(event, &state.pool).await;
+    Ok((StatusCode::CREATED, Json(bot)))
+}
+
+pub(crate) async fn list_bots_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let bots = Bot::list(&user, &state.pool).await?;
+    Ok(Json(bots))
+}
+
+/// Issue an api token, it is only shown in this response.
+pub(crate) async fn create_bot_token_handler(
+    RequireRole(user, _): RequireRole<Member>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(bot_id): Path<i64>,
+    Json(input): Json<CreateBotToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = BotToken::create(bot_id, &input, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::BotTokenCreate, &user)
+        .target("bot_token", token.id)
+        .metadata(json!({ "bot_id": bot_id, "name": token.name }));
+    audit.record(event, &state.pool).await;
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+pub(crate) async fn list_bot_tokens_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(bot_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let tokens = BotToken::list(bot_id, user.id, &state.pool).await?;
+    Ok(Json(tokens))
+}
+
+pub(crate) async fn revoke_bot_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path((bot_id, id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    BotToken::delete(bot_id, id, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::BotTokenRevoke, &user)
+        .target("bot_token", id)
+        .metadata(json!({ "bot_id": bot_id }));
+    audit.record(event, &state.pool).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Register a slash command answered by a bot of the user.
+pub(crate) async fn create_command_handler(
+    RequireRole(user, _): RequireRole<Member>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(input): Json<CreateSlashCommand>,
+) -> Result<impl IntoResponse, AppError> {
+    let command = SlashCommand::create(
+        &input,
+        &user,
+        state.config.server.allow_private_urls,
+        &state.pool,
+    )
+    .await?;
+    let event = NewAuditEvent::new(AuditAction::CommandCreate, &user)
+        .target("slash_command", command.id)
+        .metadata(json!({ "name": command.name, "bot_id": command.bot_id }));
+    audit.record(event, &state.pool).await;
+    Ok((StatusCode::CREATED, Json(command)))
+}
+
+pub(crate) async fn list_commands_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let commands = SlashCommand::list(user.ws_id, &state.pool).await?;
+    Ok(Json(commands))
+}
+
+pub(crate) async fn delete_command_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    SlashCommand::delete(id, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::CommandDelete, &user).target("slash_command", id);
+    audit.record(event, &state.pool).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::{utils::parser_response, AppConfig};
+
+    #[tokio::test]
+    async fn test_create_bot_token() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateBot {
+            fullname: "Deploy Bot".to_string(),
+        };
+        let bot = Bot::create(&input, &user, &state.pool).await?;
+        let input = CreateBotToken {
+            name: "ci".to_string(),
+        };
+        let ret = create_bot_token_handler(
+            RequireRole::new(user),
+            State(state),
+            AuditContext::default(),
+            Path(bot.id),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let token = parser_response::<BotToken>(ret).await?;
+        assert_eq!(token.bot_id, bot.id);
+        assert!(token.token.is_some());
+        Ok(())
+    }
+}
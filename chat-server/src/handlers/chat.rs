@@ -0,0 +1,232 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::json;
+
+use crate::{
+    extractors::{Admin, RequireRole},
+    models::{
+        AddMember, AuditAction, AuditContext, Chat, CreateDirectChat, DeleteDraft, Draft, GetDraft,
+        MarkRead, Message, NewAuditEvent, SaveDraft, UpdateChatRetention, UpdatePinLimit, User,
+    },
+    AppError, AppState,
+};
+
+pub(crate) async fn list_chats_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chats = Chat::list_for_user(user.id, &state.pool).await?;
+    Ok(Json(chats))
+}
+
+pub(crate) async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    Message::mark_read(input.message_id, chat_id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_pins_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = Chat::list_pins(chat_id, user.id, &state.pool).await?;
+    Ok(Json(messages))
+}
+
+pub(crate) async fn pin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    Chat::pin(chat_id, message_id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn unpin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    Chat::unpin(chat_id, message_id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn update_pin_limit_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<UpdatePinLimit>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = Chat::update_pin_limit(chat_id, &input, user.id, &state.pool).await?;
+    Ok(Json(chat))
+}
+
+pub(crate) async fn get_chat_retention_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let retention = Chat::get_retention(chat_id, user.id, &state.pool).await?;
+    Ok(Json(retention))
+}
+
+pub(crate) async fn update_chat_retention_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<UpdateChatRetention>,
+) -> Result<impl IntoResponse, AppError> {
+    let retention =
+        Chat::update_retention(chat_id, &input, user.id, user.ws_id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::ChatRetentionUpdate, &user)
+        .target("chat", chat_id)
+        .metadata(json!({ "retention_days": input.retention_days }));
+    audit.record(event, &state.pool).await;
+    Ok(Json(retention))
+}
+
+/// Open the direct chat with another user, it is created the first time.
+pub(crate) async fn create_direct_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateDirectChat>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = Chat::open_direct(&input, &user, &state.pool).await?;
+    Ok(Json(chat))
+}
+
+pub(crate) async fn add_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<AddMember>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = Chat::add_member(chat_id, input.user_id, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::ChatMemberAdd, &user)
+        .target("chat", chat_id)
+        .metadata(json!({ "user_id": input.user_id }));
+    audit.record(event, &state.pool).await;
+    Ok(Json(chat))
+}
+
+pub(crate) async fn remove_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path((chat_id, member_id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = Chat::remove_member(chat_id, member_id, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::ChatMemberRemove, &user)
+        .target("chat", chat_id)
+        .metadata(json!({ "user_id": member_id }));
+    audit.record(event, &state.pool).await;
+    Ok(Json(chat))
+}
+
+pub(crate) async fn get_draft_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Query(input): Query<GetDraft>,
+) -> Result<impl IntoResponse, AppError> {
+    match Draft::get(chat_id, &input, user.id, &state.pool).await? {
+        Some(draft) => Ok(Json(draft).into_response()),
+        None => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+pub(crate) async fn save_draft_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<SaveDraft>,
+) -> Result<impl IntoResponse, AppError> {
+    let draft = Draft::save(chat_id, &input, user.id, &state.pool).await?;
+    Ok(Json(draft))
+}
+
+pub(crate) async fn delete_draft_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Query(input): Query<DeleteDraft>,
+) -> Result<impl IntoResponse, AppError> {
+    Draft::delete(chat_id, &input, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{models::ChatOverview, utils::parser_response, AppConfig};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mark_read_clears_unread_count() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(3, "Charlie", "charlie@acme.org");
+        let ret = mark_read_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path(2),
+            Json(MarkRead { message_id: 4 }),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        let ret = list_chats_handler(Extension(user), State(state))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let chats = parser_response::<Vec<ChatOverview>>(ret).await?;
+        assert_eq!(chats.len(), 2);
+        assert_eq!(chats[0].unread_count, 2);
+        assert_eq!(chats[1].unread_count, 0);
+        assert_eq!(chats[1].last_read_message_id, Some(4));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_draft_without_draft() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(2, "Bob", "bob@acme.org");
+        let ret = get_draft_handler(
+            Extension(user),
+            State(state),
+            Path(1),
+            Query(GetDraft::default()),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pin_message_fails_for_non_admin() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(2, "Bob", "bob@acme.org");
+        let ret = pin_message_handler(Extension(user), State(state), Path((1, 1)))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
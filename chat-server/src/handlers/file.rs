@@ -0,0 +1,399 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Extension, Json,
+};
+use futures_util::TryStreamExt;
+
+use crate::{
+    models::{ChatFile, CompleteUpload, GetFile, PresignUpload, User},
+    storage::{parse_range, ByteRange},
+    AppError, AppState,
+};
+
+/// How long a presigned download url is valid.
+const PRESIGNED_URL_EXPIRY: Duration = Duration::from_secs(300);
+
+/// Upload files as multipart form fields, the attachments to send them are returned.
+pub(crate) async fn upload_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    let mut attachments = vec![];
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::InvalidInput(e.to_string()))?
+    {
+        let Some(name) = field.file_name().map(|name| name.to_string()) else {
+            continue;
+        };
+        let mut body = field.map_err(|e| AppError::InvalidInput(e.to_string()));
+        let file = ChatFile::upload(
+            &name,
+            &mut body,
+            &user,
+            &state.config.upload,
+            &state.media,
+            state.storage.as_ref(),
+            &state.pool,
+        )
+        .await?;
+        attachments.push(file.attachment());
+    }
+    Ok((StatusCode::CREATED, Json(attachments)))
+}
+
+/// Get a url to upload a file straight to the storage, for files too large to go through
+/// `/upload`. Once the file was put to the url the upload is completed with its key.
+pub(crate) async fn presign_upload_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<PresignUpload>,
+) -> Result<impl IntoResponse, AppError> {
+    let upload = ChatFile::presign_upload(
+        &input,
+        &user,
+        &state.config.upload,
+        state.storage.as_ref(),
+        &state.pool,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(upload)))
+}
+
+/// Register a file uploaded to a presigned url, the attachment to send it is returned.
+pub(crate) async fn complete_upload_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CompleteUpload>,
+) -> Result<impl IntoResponse, AppError> {
+    let file = ChatFile::complete_upload(
+        &input,
+        &user,
+        &state.config.upload,
+        state.storage.as_ref(),
+        &state.pool,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(file.attachment())))
+}
+
+/// Download a file of the user's workspace, `?size=thumb` sends a thumbnail of an image.
+/// Storages which sign urls get the download directly, otherwise the file is streamed and
+/// a `Range` header is honored.
+pub(crate) async fn file_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((ws_id, path)): Path<(i64, String)>,
+    Query(input): Query<GetFile>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if user.ws_id != ws_id {
+        return Err(AppError::PermissionDenied(format!(
+            "user {} cannot access files of workspace {}",
+            user.id, ws_id
+        )));
+    }
+    let file = ChatFile::find(ws_id, &path, &state.pool).await?;
+    if !file.can_access(user.id, &state.pool).await? {
+        return Err(AppError::NotFound(format!("file {}", path)));
+    }
+    let (key, mime) = file.location(input.size);
+    if let Some(url) = state
+        .storage
+        .presigned_url(&key, PRESIGNED_URL_EXPIRY)
+        .await?
+    {
+        return Ok(Redirect::temporary(&url).into_response());
+    }
+
+    let size = state
+        .storage
+        .head(&key)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("file {}", path)))?;
+    let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    let (status, range) = match parse_range(range, size) {
+        ByteRange::Full => (StatusCode::OK, 0..size),
+        ByteRange::Partial(range) => (StatusCode::PARTIAL_CONTENT, range),
+        ByteRange::Unsatisfiable => {
+            let content_range = format!("bytes */{}", size);
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, content_range)],
+            )
+                .into_response());
+        }
+    };
+    let body = state
+        .storage
+        .get(
+            &key,
+            (status == StatusCode::PARTIAL_CONTENT).then_some(range.clone()),
+        )
+        .await?;
+
+    let mut res = Response::builder()
+        .status(status)
+        .header(header::CONTENT_DISPOSITION, disposition(&mime))
+        .header(header::CONTENT_TYPE, mime)
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CONTENT_LENGTH, range.end - range.start)
+        .header(header::ACCEPT_RANGES, "bytes")
+        // the url changes with the content
+        .header(
+            header::CACHE_CONTROL,
+            "private, max-age=31536000, immutable",
+        );
+    if status == StatusCode::PARTIAL_CONTENT {
+        res = res.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+        );
+    }
+    res.body(Body::from_stream(body))
+        .map_err(|e| AppError::StorageError(e.to_string()))
+}
+
+/// Only media is shown in the browser, anything else like html or svg could run scripts
+/// with the origin of the api and is downloaded instead.
+fn disposition(mime: &str) -> &'static str {
+    let inline = matches!(
+        mime,
+        "image/png" | "image/jpeg" | "image/gif" | "image/webp"
+    ) || mime.starts_with("audio/")
+        || mime.starts_with("video/");
+    match inline {
+        true => "inline",
+        false => "attachment",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use anyhow::Result;
+    use axum::{
+        body::{to_bytes, Body},
+        extract::{FromRequest, Request},
+    };
+    use image::{DynamicImage, ImageFormat, RgbImage};
+
+    use crate::{
+        media::ThumbnailSize,
+        models::{Attachment, CreateMessage, Message},
+        utils::parser_response,
+        AppConfig,
+    };
+
+    use super::*;
+
+    const BOUNDARY: &str = "crablink-boundary";
+
+    async fn multipart(name: &str, data: &[u8]) -> Result<Multipart> {
+        let mut body = format!(
+            "--{}\r\ncontent-disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n",
+            BOUNDARY, name
+        )
+        .into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        let req = Request::builder()
+            .method("POST")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(Body::from(body))?;
+        Ok(Multipart::from_request(req, &()).await?)
+    }
+
+    #[tokio::test]
+    async fn test_upload_image_and_download_thumbnail() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let img = RgbImage::from_fn(600, 300, |x, y| image::Rgb([x as u8, y as u8, 7]));
+        let mut png = Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(img).write_to(&mut png, ImageFormat::Png)?;
+
+        let form = multipart("cat.png", png.get_ref()).await?;
+        let ret = upload_handler(Extension(user.clone()), State(state.clone()), form)
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let attachments = parser_response::<Vec<Attachment>>(ret).await?;
+        let Attachment::Image {
+            url,
+            width,
+            height,
+            blurhash,
+            ..
+        } = &attachments[0]
+        else {
+            panic!("expected an image attachment");
+        };
+        assert_eq!((*width, *height), (Some(600), Some(300)));
+        assert!(blurhash.is_some());
+
+        let path = url.trim_start_matches("/files/1/").to_string();
+        let input = GetFile {
+            size: Some(ThumbnailSize::Thumb),
+        };
+        let ret = file_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path((1, path.clone())),
+            Query(input),
+            HeaderMap::new(),
+        )
+        .await?;
+        assert_eq!(ret.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(ret.headers()[header::CONTENT_DISPOSITION], "inline");
+        assert_eq!(ret.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        let thumb = image::load_from_memory(&body)?;
+        assert_eq!((thumb.width(), thumb.height()), (256, 128));
+
+        // images smaller than the size are sent as they are
+        let input = GetFile {
+            size: Some(ThumbnailSize::Medium),
+        };
+        let ret = file_handler(
+            Extension(user),
+            State(state.clone()),
+            Path((1, path.clone())),
+            Query(input),
+            HeaderMap::new(),
+        )
+        .await?;
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        assert_eq!(image::load_from_memory(&body)?.width(), 600);
+
+        let other = User {
+            ws_id: 2,
+            ..User::new(5, "Eve", "eve@other.org")
+        };
+        let ret = file_handler(
+            Extension(other),
+            State(state),
+            Path((1, path)),
+            Query(GetFile::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_file_range() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let form = multipart("notes.txt", b"hello range requests").await?;
+        let ret = upload_handler(Extension(user.clone()), State(state.clone()), form)
+            .await?
+            .into_response();
+        let attachments = parser_response::<Vec<Attachment>>(ret).await?;
+        let Attachment::File { url, size, .. } = &attachments[0] else {
+            panic!("expected a file attachment");
+        };
+        assert_eq!(*size, 20);
+        let path = url.trim_start_matches("/files/1/").to_string();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, "bytes=6-10".parse()?);
+        let ret = file_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path((1, path.clone())),
+            Query(GetFile::default()),
+            headers,
+        )
+        .await?;
+        assert_eq!(ret.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(ret.headers()[header::CONTENT_RANGE], "bytes 6-10/20");
+        assert_eq!(ret.headers()[header::CONTENT_LENGTH], "5");
+        assert_eq!(ret.headers()[header::CONTENT_DISPOSITION], "attachment");
+        assert_eq!(ret.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        assert_eq!(&body[..], b"range");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, "bytes=20-".parse()?);
+        let ret = file_handler(
+            Extension(user),
+            State(state),
+            Path((1, path)),
+            Query(GetFile::default()),
+            headers,
+        )
+        .await?;
+        assert_eq!(ret.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(ret.headers()[header::CONTENT_RANGE], "bytes */20");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_guests_only_download_files_of_their_chats() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        sqlx::query("UPDATE users SET role = 'guest' WHERE id = 3")
+            .execute(&state.pool)
+            .await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let charlie = User::new(3, "Charlie", "charlie@acme.org");
+        let form = multipart("notes.txt", b"for the group").await?;
+        let ret = upload_handler(Extension(alice.clone()), State(state.clone()), form)
+            .await?
+            .into_response();
+        let attachments = parser_response::<Vec<Attachment>>(ret).await?;
+        let Attachment::File { url, .. } = &attachments[0] else {
+            panic!("expected a file attachment");
+        };
+        let path = url.trim_start_matches("/files/1/").to_string();
+        let download = || {
+            file_handler(
+                Extension(charlie.clone()),
+                State(state.clone()),
+                Path((1, path.clone())),
+                Query(GetFile::default()),
+                HeaderMap::new(),
+            )
+        };
+
+        let ret = download().await.into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+        let input = CreateMessage {
+            content: "the notes".to_string(),
+            attachments,
+            ..Default::default()
+        };
+        Message::create(&input, 1, alice.id, &state.pool).await?;
+        let ret = download().await?;
+        assert_eq!(ret.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_fails_for_disallowed_type() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let form = multipart("setup.txt", b"MZ\x90\0\x03\0\0\0\x04\0").await?;
+        let ret = upload_handler(Extension(user), State(state), form)
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        Ok(())
+    }
+}
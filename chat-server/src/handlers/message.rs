@@ -0,0 +1,367 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::json;
+
+use crate::{
+    commands,
+    models::{
+        AuditAction, AuditContext, CreateMessage, ListMessages, Message, NewAuditEvent,
+        ScheduledMessage, UpdateMessage, UpdateScheduledMessage, User,
+    },
+    AppError, AppState,
+};
+
+pub(crate) async fn send_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<CreateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(output) = commands::run(&state, &user, chat_id, &input).await? {
+        return Ok(output.into_response());
+    }
+    if let Some(send_at) = input.send_at {
+        let scheduled =
+            ScheduledMessage::create(&input, send_at, chat_id, user.id, &state.pool).await?;
+        return Ok((StatusCode::ACCEPTED, Json(scheduled)).into_response());
+    }
+    let message = Message::create(&input, chat_id, user.id, &state.pool).await?;
+    Ok((StatusCode::CREATED, Json(message)).into_response())
+}
+
+pub(crate) async fn list_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = Message::list(&input, chat_id, user.id, &state.pool).await?;
+    Ok(Json(messages))
+}
+
+pub(crate) async fn update_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = Message::update(id, &input, user.id, &state.pool).await?;
+    Ok(Json(message))
+}
+
+pub(crate) async fn delete_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = Message::delete(id, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::MessageDelete, &user)
+        .target("message", id)
+        .metadata(json!({ "chat_id": message.chat_id }));
+    audit.record(event, &state.pool).await;
+    Ok(Json(message))
+}
+
+pub(crate) async fn list_message_edits_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let edits = Message::list_edits(id, user.id, &state.pool).await?;
+    Ok(Json(edits))
+}
+
+pub(crate) async fn list_thread_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = Message::list_thread(id, &input, user.id, &state.pool).await?;
+    Ok(Json(messages))
+}
+
+pub(crate) async fn follow_thread_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Message::follow(id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn unfollow_thread_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Message::unfollow(id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, emoji)): Path<(i64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    Message::add_reaction(id, &emoji, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, emoji)): Path<(i64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    Message::remove_reaction(id, &emoji, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn read_by_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let users = Message::read_by(id, user.id, &state.pool).await?;
+    Ok(Json(users))
+}
+
+pub(crate) async fn list_mentions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = Message::list_mentions(&input, user.id, &state.pool).await?;
+    Ok(Json(messages))
+}
+
+pub(crate) async fn list_saved_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = Message::list_saved(&input, user.id, &state.pool).await?;
+    Ok(Json(messages))
+}
+
+pub(crate) async fn save_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Message::save(id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn unsave_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Message::unsave(id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_scheduled_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = ScheduledMessage::list(user.id, &state.pool).await?;
+    Ok(Json(scheduled))
+}
+
+pub(crate) async fn update_scheduled_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = ScheduledMessage::update(id, &input, user.id, &state.pool).await?;
+    Ok(Json(scheduled))
+}
+
+pub(crate) async fn cancel_scheduled_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    ScheduledMessage::cancel(id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{error::ErrorOutput, utils::parser_response, AppConfig};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_send_message_success() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateMessage {
+            content: "hi there".to_string(),
+            ..Default::default()
+        };
+        let ret = send_message_handler(Extension(user), State(state), Path(1), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let ret = parser_response::<Message>(ret).await?;
+        assert_eq!(ret.content, "hi there");
+        assert_eq!(ret.sender_id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_command_replies_ephemerally() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateMessage {
+            content: "/topic".to_string(),
+            ..Default::default()
+        };
+        let ret = send_message_handler(Extension(user), State(state), Path(1), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let ret = parser_response::<Message>(ret).await?;
+        assert_eq!(ret.content, "No topic is set");
+        assert!(ret.ephemeral);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_message_later_is_scheduled() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateMessage {
+            content: "see you tomorrow".to_string(),
+            send_at: Some(chrono::Utc::now() + chrono::Duration::days(1)),
+            ..Default::default()
+        };
+        let ret = send_message_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path(1),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let ret = parser_response::<ScheduledMessage>(ret).await?;
+        assert_eq!(ret.content, "see you tomorrow");
+
+        let ret = list_scheduled_handler(Extension(user), State(state))
+            .await?
+            .into_response();
+        let scheduled = parser_response::<Vec<ScheduledMessage>>(ret).await?;
+        assert_eq!(scheduled.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_message_fails_for_other_user() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let input = UpdateMessage {
+            content: "hacked".to_string(),
+            ..Default::default()
+        };
+        let ret = update_message_handler(Extension(user), State(state), Path(2), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        let ret = parser_response::<ErrorOutput>(ret).await?;
+        assert_eq!(ret.error, "permission denied: user 1 cannot edit message 2");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_message_keeps_tombstone_in_history() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(2, "Bob", "bob@acme.org");
+        let ret = delete_message_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            AuditContext::default(),
+            Path(2),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let ret = list_messages_handler(
+            Extension(user),
+            State(state),
+            Path(1),
+            Query(ListMessages::default()),
+        )
+        .await?
+        .into_response();
+        let messages = parser_response::<Vec<Message>>(ret).await?;
+        assert_eq!(messages.len(), 3);
+        let tombstone = messages.iter().find(|m| m.id == 2).expect("tombstone");
+        assert!(tombstone.deleted_at.is_some());
+        assert_eq!(tombstone.content, "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_thread_fails_for_non_member() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(4, "Dave", "dave@acme.org");
+        let ret = list_thread_handler(
+            Extension(user),
+            State(state),
+            Path(1),
+            Query(ListMessages::default()),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_reaction_success() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(2, "Bob", "bob@acme.org");
+        let ret = add_reaction_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path((1, "👍".to_string())),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        let ret = list_messages_handler(
+            Extension(user),
+            State(state),
+            Path(1),
+            Query(ListMessages::default()),
+        )
+        .await?
+        .into_response();
+        let messages = parser_response::<Vec<Message>>(ret).await?;
+        let message = messages.iter().find(|m| m.id == 1).expect("message exists");
+        assert_eq!(message.reactions.len(), 1);
+        assert!(message.reactions[0].reacted);
+        Ok(())
+    }
+}
@@ -1,3 +1,25 @@
+mod audit;
 mod auth;
+mod bot;
+mod chat;
+mod file;
+mod message;
+mod moderation;
+mod presence;
+mod search;
+mod user;
+mod webhook;
+mod workspace;
 
+pub(crate) use audit::*;
 pub use auth::*;
+pub(crate) use bot::*;
+pub(crate) use chat::*;
+pub(crate) use file::*;
+pub(crate) use message::*;
+pub(crate) use moderation::*;
+pub(crate) use presence::*;
+pub(crate) use search::*;
+pub(crate) use user::*;
+pub(crate) use webhook::*;
+pub(crate) use workspace::*;
@@ -0,0 +1,125 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::json;
+
+use crate::{
+    extractors::{Admin, RequireRole},
+    models::{
+        AuditAction, AuditContext, CreateReport, ListReports, MessageReport, NewAuditEvent,
+        ResolveReport, User,
+    },
+    AppError, AppState,
+};
+
+pub(crate) async fn report_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<CreateReport>,
+) -> Result<impl IntoResponse, AppError> {
+    let report = MessageReport::create(id, &input, &user, &state.pool).await?;
+    Ok((StatusCode::CREATED, Json(report)))
+}
+
+/// The moderation queue, open reports unless resolved ones are asked for.
+pub(crate) async fn list_reports_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    Query(input): Query<ListReports>,
+) -> Result<impl IntoResponse, AppError> {
+    let reports = MessageReport::list(&input, &user, &state.pool).await?;
+    Ok(Json(reports))
+}
+
+pub(crate) async fn resolve_report_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<i64>,
+    Json(input): Json<ResolveReport>,
+) -> Result<impl IntoResponse, AppError> {
+    let report = MessageReport::resolve(id, &input, &user, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::ReportResolve, &user)
+        .target("report", id)
+        .metadata(json!({
+            "action": report.action,
+            "message_id": report.message_id,
+            "sender_id": report.sender_id,
+            "suspend_days": input.suspend_days,
+        }));
+    audit.record(event, &state.pool).await;
+    Ok(Json(report))
+}
+
+pub(crate) async fn unsuspend_user_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    User::unsuspend(id, &user, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::UserUnsuspend, &user).target("user", id);
+    audit.record(event, &state.pool).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::{
+        extractors::RequireRole,
+        models::{ModerationAction, UserWarning},
+        utils::parser_response,
+        AppConfig,
+    };
+
+    #[tokio::test]
+    async fn test_report_and_warn() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let charlie = User::new(3, "Charlie", "charlie@acme.org");
+        let input = CreateReport {
+            reason: "off topic".to_string(),
+        };
+        let ret = report_message_handler(
+            Extension(charlie),
+            State(state.clone()),
+            Path(2),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let report = parser_response::<MessageReport>(ret).await?;
+
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = ResolveReport {
+            action: ModerationAction::Warn,
+            note: None,
+            suspend_days: None,
+        };
+        resolve_report_handler(
+            RequireRole::new(alice),
+            State(state.clone()),
+            AuditContext::default(),
+            Path(report.id),
+            Json(input),
+        )
+        .await?;
+        let warnings = UserWarning::list(2, &state.pool).await?;
+        assert_eq!(warnings[0].reason, "off topic");
+        let action: String =
+            sqlx::query_scalar("SELECT metadata->>'action' FROM audit_events WHERE action = $1")
+                .bind(AuditAction::ReportResolve.as_str())
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(action, "warn");
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{Chat, User, UserBlock, WorkspaceRole},
+    presence::PresenceStatus,
+    AppError, AppState,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub status: PresenceStatus,
+}
+
+pub(crate) async fn heartbeat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<Heartbeat>,
+) -> Result<impl IntoResponse, AppError> {
+    state.presence.heartbeat(user.id, input.status);
+    Ok(Json(state.presence.get(user.id)))
+}
+
+/// Presence of a user in the same workspace, unless that user blocked the current one.
+/// Guests only see the members of their chats.
+pub(crate) async fn user_presence_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let not_found = || AppError::NotFound(format!("user {}", id));
+    let blocked_by = UserBlock::blocked_by(user.id, &state.pool).await?;
+    match User::find_by_id(id, &state.pool).await? {
+        Some(other) if other.ws_id == user.ws_id && !blocked_by.contains(&id) => {}
+        _ => return Err(not_found()),
+    }
+    let role = User::get_role(user.id, user.ws_id, &state.pool).await?;
+    if role == Some(WorkspaceRole::Guest)
+        && id != user.id
+        && !Chat::shared_by(user.id, id, &state.pool).await?
+    {
+        return Err(not_found());
+    }
+    Ok(Json(state.presence.get(id)))
+}
+
+/// Presence of the members of a chat, members who blocked the current user are left out.
+pub(crate) async fn chat_presence_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut chat = Chat::get_for_member(chat_id, user.id, &state.pool).await?;
+    let blocked_by = UserBlock::blocked_by(user.id, &state.pool).await?;
+    chat.members.retain(|id| !blocked_by.contains(id));
+    Ok(Json(state.presence.get_many(&chat.members)))
+}
+
+pub(crate) async fn typing_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Chat::get_for_member(chat_id, user.id, &state.pool).await?;
+    state.presence.typing(chat_id, user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn stop_typing_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.presence.stop_typing(chat_id, user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Other members typing in the chat.
+pub(crate) async fn list_typing_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Chat::get_for_member(chat_id, user.id, &state.pool).await?;
+    let mut users = state.presence.typing_users(chat_id);
+    users.retain(|id| *id != user.id);
+    Ok(Json(users))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{presence::UserPresence, utils::parser_response, AppConfig};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_chat_presence_success() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let input = Heartbeat {
+            status: PresenceStatus::Away,
+        };
+        heartbeat_handler(Extension(bob), State(state.clone()), Json(input)).await?;
+
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let ret = chat_presence_handler(Extension(alice), State(state), Path(1))
+            .await?
+            .into_response();
+        let ret = parser_response::<Vec<UserPresence>>(ret).await?;
+        let status: Vec<_> = ret.iter().map(|p| (p.user_id, p.status)).collect();
+        assert_eq!(
+            status,
+            vec![
+                (1, PresenceStatus::Offline),
+                (2, PresenceStatus::Away),
+                (3, PresenceStatus::Offline)
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_presence_is_hidden_from_blocked_users() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        UserBlock::create(&bob, 1, &state.pool).await?;
+
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let ret = user_presence_handler(Extension(alice.clone()), State(state.clone()), Path(2))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+        let ret = chat_presence_handler(Extension(alice), State(state), Path(1))
+            .await?
+            .into_response();
+        let ret = parser_response::<Vec<UserPresence>>(ret).await?;
+        let users: Vec<_> = ret.iter().map(|p| p.user_id).collect();
+        assert_eq!(users, vec![1, 3]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_guests_only_see_presence_of_their_chats() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        sqlx::query("UPDATE users SET role = 'guest' WHERE id = 3")
+            .execute(&state.pool)
+            .await?;
+
+        // dave is in none of the chats of charlie
+        let charlie = User::new(3, "Charlie", "charlie@acme.org");
+        let ret = user_presence_handler(Extension(charlie.clone()), State(state.clone()), Path(4))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+        let ret = user_presence_handler(Extension(charlie), State(state.clone()), Path(2))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let ret = user_presence_handler(Extension(alice), State(state), Path(4))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_typing_is_visible_to_other_members() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let ret = typing_handler(Extension(bob.clone()), State(state.clone()), Path(2))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        let ret = list_typing_handler(Extension(bob), State(state.clone()), Path(2))
+            .await?
+            .into_response();
+        assert!(parser_response::<Vec<i64>>(ret).await?.is_empty());
+
+        let charlie = User::new(3, "Charlie", "charlie@acme.org");
+        let ret = list_typing_handler(Extension(charlie), State(state.clone()), Path(2))
+            .await?
+            .into_response();
+        assert_eq!(parser_response::<Vec<i64>>(ret).await?, vec![2]);
+
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let ret = list_typing_handler(Extension(alice), State(state), Path(2))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
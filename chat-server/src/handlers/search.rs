@@ -0,0 +1,61 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{
+    models::{Message, SearchMessages, User},
+    AppError, AppState,
+};
+
+pub(crate) async fn search_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let hits = Message::search(&input, &user, &state.pool).await?;
+    Ok(Json(hits))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::http::StatusCode;
+
+    use crate::{models::SearchHit, utils::parser_response, AppConfig};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_search_success() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(2, "Bob", "bob@acme.org");
+        let input = SearchMessages {
+            q: "alice".to_string(),
+            chat_id: Some(1),
+            ..Default::default()
+        };
+        let ret = search_handler(Extension(user), State(state), Query(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let hits = parser_response::<Vec<SearchHit>>(ret).await?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "<mark>Alice</mark>");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_fails_with_empty_query() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(2, "Bob", "bob@acme.org");
+        let ret = search_handler(Extension(user), State(state), Query(Default::default()))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+}
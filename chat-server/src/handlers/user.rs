@@ -0,0 +1,182 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::json;
+
+use crate::{
+    models::{
+        AuditAction, AuditContext, ListUsers, NewAuditEvent, PublicUser, RegisterPasskey,
+        UpdateProfile, UpdateSecondFactor, User, UserBlock, UserProfile, UserWarning, VerifyEmail,
+        WebauthnCredential,
+    },
+    AppError, AppState,
+};
+
+pub(crate) async fn get_me_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = UserProfile::get(user.id, &state.pool).await?;
+    Ok(Json(profile))
+}
+
+pub(crate) async fn update_me_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = UserProfile::update(user.id, &input, state.mailer.as_ref(), &state.pool).await?;
+    Ok(Json(profile))
+}
+
+pub(crate) async fn verify_email_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = UserProfile::verify_email(user.id, &input, &state.pool).await?;
+    Ok(Json(profile))
+}
+
+pub(crate) async fn list_users_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListUsers>,
+) -> Result<impl IntoResponse, AppError> {
+    let users = PublicUser::list(&input, &user, &state.pool).await?;
+    Ok(Json(users))
+}
+
+pub(crate) async fn list_blocks_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let blocks = UserBlock::list(user.id, &state.pool).await?;
+    Ok(Json(blocks))
+}
+
+pub(crate) async fn block_user_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(blocked_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let block = UserBlock::create(&user, blocked_id, &state.pool).await?;
+    Ok(Json(block))
+}
+
+pub(crate) async fn unblock_user_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(blocked_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    UserBlock::delete(user.id, blocked_id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Warnings the admins sent to the current user.
+pub(crate) async fn list_warnings_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let warnings = UserWarning::list(user.id, &state.pool).await?;
+    Ok(Json(warnings))
+}
+
+pub(crate) async fn list_passkeys_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let passkeys = WebauthnCredential::list(user.id, &state.pool).await?;
+    Ok(Json(passkeys))
+}
+
+/// The options to create a passkey with in the browser.
+pub(crate) async fn start_passkey_registration_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = &state.config.auth.webauthn;
+    let options = WebauthnCredential::start_registration(&user, config, &state.pool).await?;
+    Ok(Json(options))
+}
+
+pub(crate) async fn finish_passkey_registration_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(input): Json<RegisterPasskey>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = &state.config.auth.webauthn;
+    let passkey = WebauthnCredential::register(&input, &user, config, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::PasskeyRegister, &user)
+        .target("passkey", passkey.id)
+        .metadata(json!({ "name": passkey.name }));
+    audit.record(event, &state.pool).await;
+    Ok((StatusCode::CREATED, Json(passkey)))
+}
+
+pub(crate) async fn delete_passkey_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let passkey = WebauthnCredential::delete(id, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::PasskeyDelete, &user)
+        .target("passkey", passkey.id)
+        .metadata(json!({ "name": passkey.name }));
+    audit.record(event, &state.pool).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Require a passkey after the password when signing in.
+pub(crate) async fn update_second_factor_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(input): Json<UpdateSecondFactor>,
+) -> Result<impl IntoResponse, AppError> {
+    User::update_second_factor(user.id, &input, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::SecondFactorUpdate, &user)
+        .target("user", user.id)
+        .metadata(json!({ "enabled": input.enabled }));
+    audit.record(event, &state.pool).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::http::StatusCode;
+
+    use crate::{utils::parser_response, AppConfig};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_update_me_success() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(3, "Charlie", "charlie@acme.org");
+        let input = UpdateProfile {
+            title: Some("Engineer".to_string()),
+            status_text: Some("on vacation".to_string()),
+            ..Default::default()
+        };
+        let ret = update_me_handler(Extension(user.clone()), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let ret = get_me_handler(Extension(user), State(state))
+            .await?
+            .into_response();
+        let profile = parser_response::<UserProfile>(ret).await?;
+        assert_eq!(profile.title.as_deref(), Some("Engineer"));
+        assert_eq!(profile.status_text.as_deref(), Some("on vacation"));
+        Ok(())
+    }
+}
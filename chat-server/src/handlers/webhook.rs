@@ -0,0 +1,173 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::json;
+
+use crate::{
+    extractors::{Member, RequireRole},
+    models::{
+        AuditAction, AuditContext, CreateIncomingWebhook, CreateMessage, CreateWebhook,
+        IncomingWebhook, ListMessages, NewAuditEvent, User, Webhook, WebhookDeadLetter,
+        WebhookDelivery,
+    },
+    AppError, AppState,
+};
+
+pub(crate) async fn create_webhook_handler(
+    RequireRole(user, _): RequireRole<Member>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<CreateWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = Webhook::create(
+        chat_id,
+        &input,
+        user.id,
+        state.config.server.allow_private_urls,
+        &state.pool,
+    )
+    .await?;
+    let event = NewAuditEvent::new(AuditAction::WebhookCreate, &user)
+        .target("webhook", webhook.id)
+        .metadata(json!({ "chat_id": chat_id, "url": webhook.url }));
+    audit.record(event, &state.pool).await;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+pub(crate) async fn list_webhooks_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhooks = Webhook::list(chat_id, user.id, &state.pool).await?;
+    Ok(Json(webhooks))
+}
+
+pub(crate) async fn delete_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path((chat_id, id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    Webhook::delete(chat_id, id, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::WebhookDelete, &user)
+        .target("webhook", id)
+        .metadata(json!({ "chat_id": chat_id }));
+    audit.record(event, &state.pool).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The delivery log of a webhook.
+pub(crate) async fn list_deliveries_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, id)): Path<(i64, i64)>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let deliveries = WebhookDelivery::list(chat_id, id, &input, user.id, &state.pool).await?;
+    Ok(Json(deliveries))
+}
+
+pub(crate) async fn list_dead_letters_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let dead_letters = WebhookDeadLetter::list(chat_id, id, user.id, &state.pool).await?;
+    Ok(Json(dead_letters))
+}
+
+pub(crate) async fn retry_dead_letter_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, id, dead_letter_id)): Path<(i64, i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let delivery =
+        WebhookDeadLetter::retry(chat_id, id, dead_letter_id, user.id, &state.pool).await?;
+    Ok(Json(delivery))
+}
+
+pub(crate) async fn create_incoming_webhook_handler(
+    RequireRole(user, _): RequireRole<Member>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<CreateIncomingWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = IncomingWebhook::create(chat_id, &input, &user, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::IncomingWebhookCreate, &user)
+        .target("incoming_webhook", webhook.id)
+        .metadata(json!({ "chat_id": chat_id, "bot_id": webhook.bot_id }));
+    audit.record(event, &state.pool).await;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+pub(crate) async fn list_incoming_webhooks_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhooks = IncomingWebhook::list(chat_id, user.id, &state.pool).await?;
+    Ok(Json(webhooks))
+}
+
+pub(crate) async fn delete_incoming_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path((chat_id, id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    IncomingWebhook::delete(chat_id, id, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::IncomingWebhookDelete, &user)
+        .target("incoming_webhook", id)
+        .metadata(json!({ "chat_id": chat_id }));
+    audit.record(event, &state.pool).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Public, the token in the url is the credential.
+pub(crate) async fn post_incoming_webhook_handler(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Json(input): Json<CreateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = IncomingWebhook::post(&token, &input, &state.pool).await?;
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::{utils::parser_response, AppConfig};
+
+    #[tokio::test]
+    async fn test_create_webhook() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateWebhook {
+            url: "https://example.com/hook".to_string(),
+            events: vec![],
+        };
+        let ret = create_webhook_handler(
+            RequireRole::new(user),
+            State(state),
+            AuditContext::default(),
+            Path(1),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let webhook = parser_response::<Webhook>(ret).await?;
+        assert_eq!(webhook.chat_id, 1);
+        assert_eq!(webhook.created_by, 1);
+        Ok(())
+    }
+}
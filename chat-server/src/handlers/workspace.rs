@@ -0,0 +1,127 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde_json::json;
+
+use crate::{
+    extractors::{Admin, RequireRole},
+    filters::ContentFilterConfig,
+    models::{
+        AuditAction, AuditContext, CreateLegalHold, LegalHold, NewAuditEvent, UpdateRetention,
+        UpdateRole, UpdateSearchConfig, User, Workspace, WorkspaceStorage,
+    },
+    AppError, AppState,
+};
+
+pub(crate) async fn update_search_config_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(input): Json<UpdateSearchConfig>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = Workspace::update_search_config(user.ws_id, &input, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::SearchConfigUpdate, &user)
+        .target("workspace", ws.id)
+        .metadata(json!({ "search_config": ws.search_config }));
+    audit.record(event, &state.pool).await;
+    Ok(Json(ws))
+}
+
+pub(crate) async fn get_content_filters_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = Workspace::get_content_filters(user.ws_id, &state.pool).await?;
+    Ok(Json(config))
+}
+
+pub(crate) async fn update_content_filters_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(input): Json<ContentFilterConfig>,
+) -> Result<impl IntoResponse, AppError> {
+    let config =
+        Workspace::update_content_filters(user.ws_id, &input, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::ContentFiltersUpdate, &user)
+        .target("workspace", user.ws_id)
+        .metadata(json!(config));
+    audit.record(event, &state.pool).await;
+    Ok(Json(config))
+}
+
+/// Storage used by each user of the workspace, for its admins.
+pub(crate) async fn storage_usage_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let usage =
+        WorkspaceStorage::get(user.ws_id, user.id, &state.config.upload, &state.pool).await?;
+    Ok(Json(usage))
+}
+
+pub(crate) async fn update_retention_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(input): Json<UpdateRetention>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = Workspace::update_retention(user.ws_id, &input, user.id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::RetentionUpdate, &user)
+        .target("workspace", ws.id)
+        .metadata(json!(input));
+    audit.record(event, &state.pool).await;
+    Ok(Json(ws))
+}
+
+pub(crate) async fn list_legal_holds_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let holds = LegalHold::list(user.ws_id, user.id, &state.pool).await?;
+    Ok(Json(holds))
+}
+
+pub(crate) async fn create_legal_hold_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(input): Json<CreateLegalHold>,
+) -> Result<impl IntoResponse, AppError> {
+    let hold = LegalHold::create(&input, user.id, user.ws_id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::LegalHoldCreate, &user)
+        .target("legal_hold", hold.id)
+        .metadata(json!(input));
+    audit.record(event, &state.pool).await;
+    Ok((StatusCode::CREATED, Json(hold)))
+}
+
+pub(crate) async fn release_legal_hold_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let hold = LegalHold::release(id, user.id, user.ws_id, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::LegalHoldRelease, &user).target("legal_hold", id);
+    audit.record(event, &state.pool).await;
+    Ok(Json(hold))
+}
+
+pub(crate) async fn update_role_handler(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateRole>,
+) -> Result<impl IntoResponse, AppError> {
+    let (member, previous) = User::update_role(id, &input, &user, &state.pool).await?;
+    let event = NewAuditEvent::new(AuditAction::RoleUpdate, &user)
+        .target("user", id)
+        .metadata(json!({ "from": previous, "to": member.role }));
+    audit.record(event, &state.pool).await;
+    Ok(Json(member))
+}
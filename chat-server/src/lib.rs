@@ -2,10 +2,18 @@ mod app;
 mod config;
 mod middlewares;
 
+mod commands;
 mod error;
+mod extractors;
+mod filters;
 mod handlers;
+mod mail;
+mod media;
 mod models;
+mod presence;
+mod storage;
 mod utils;
+mod workers;
 
 pub use app::*;
 pub use config::AppConfig;
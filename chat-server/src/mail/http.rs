@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::{config::HttpMailConfig, AppError};
+
+use super::{Mail, Mailer};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Mails sent through the JSON api of a mail service, like the ones of Resend or Postmark.
+#[derive(Debug)]
+pub(crate) struct HttpMailer {
+    client: reqwest::Client,
+    url: String,
+    api_key: String,
+    from: String,
+}
+
+impl HttpMailer {
+    pub fn new(config: &HttpMailConfig) -> Result<Self, AppError> {
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(|e| AppError::UpstreamError(e.to_string()))?;
+        Ok(Self {
+            client,
+            url: config.url.clone(),
+            api_key: config.api_key.clone(),
+            from: config.from.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for HttpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+        let body = json!({
+            "from": self.from,
+            "to": mail.to,
+            "subject": mail.subject,
+            "text": mail.text,
+        });
+        self.client
+            .post(&self.url)
+            .bearer_auth(&self.api_key)
+            .header("content-type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| AppError::UpstreamError(format!("failed to send mail: {}", e)))?;
+        Ok(())
+    }
+}
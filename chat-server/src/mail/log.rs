@@ -0,0 +1,46 @@
+use async_trait::async_trait;
+use tracing::info;
+
+use crate::AppError;
+
+use super::{Mail, Mailer};
+
+/// Mails are not sent, only who they are for is logged. Their text often holds a secret like
+/// a token and stays out of the logs.
+#[derive(Debug)]
+pub(crate) struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+        info!(
+            to = mail.to,
+            subject = mail.subject,
+            "mail not sent by the log driver"
+        );
+        Ok(())
+    }
+}
+
+/// Keeps the mails for tests to read them.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct MemoryMailer {
+    sent: std::sync::Mutex<Vec<Mail>>,
+}
+
+#[cfg(test)]
+impl MemoryMailer {
+    pub(crate) fn sent(&self) -> Vec<Mail> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+        self.sent.lock().unwrap().push(mail.clone());
+        Ok(())
+    }
+}
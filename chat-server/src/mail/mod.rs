@@ -0,0 +1,35 @@
+mod http;
+mod log;
+
+use std::{fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{config::MailConfig, AppError};
+
+pub(crate) use http::HttpMailer;
+pub(crate) use log::LogMailer;
+#[cfg(test)]
+pub(crate) use log::MemoryMailer;
+
+/// A plain text mail to a single address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+}
+
+/// How mails like email verifications reach users.
+#[async_trait]
+pub(crate) trait Mailer: Debug + Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError>;
+}
+
+pub(crate) fn from_config(config: &MailConfig) -> Result<Arc<dyn Mailer>, AppError> {
+    let mailer: Arc<dyn Mailer> = match config {
+        MailConfig::Log => Arc::new(LogMailer),
+        MailConfig::Http(config) => Arc::new(HttpMailer::new(config)?),
+    };
+    Ok(mailer)
+}
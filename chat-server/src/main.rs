@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use anyhow::Result;
 use chat_server::{get_router, AppConfig};
 use tokio::net::TcpListener;
@@ -15,7 +17,11 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind(&addr).await?;
 
     info!("Listening on http://{}", addr);
-    axum::serve(listener, app.into_make_service()).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
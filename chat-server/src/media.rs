@@ -0,0 +1,254 @@
+use std::{io::Cursor, sync::Arc, thread};
+
+use axum::body::Bytes;
+use image::{
+    codecs::jpeg::JpegEncoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::AppError;
+
+/// Larger images are rejected before they are decoded.
+const MAX_IMAGE_SIDE: u32 = 16_384;
+const MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
+const JPEG_QUALITY: u8 = 85;
+/// The blurhash is computed from a tiny version of the image.
+const BLURHASH_SOURCE_SIDE: u32 = 32;
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Sizes of the thumbnails generated for uploaded images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailSize {
+    Thumb,
+    Small,
+    Medium,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [Self; 3] = [Self::Thumb, Self::Small, Self::Medium];
+
+    /// The longest side of the thumbnail.
+    pub fn max_side(self) -> u32 {
+        match self {
+            Self::Thumb => 256,
+            Self::Small => 640,
+            Self::Medium => 1280,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Thumb => "thumb",
+            Self::Small => "small",
+            Self::Medium => "medium",
+        }
+    }
+}
+
+/// An uploaded image, re-encoded without its metadata.
+#[derive(Debug)]
+pub(crate) struct ProcessedImage {
+    pub data: Vec<u8>,
+    pub ext: &'static str,
+    pub mime: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    pub thumbnails: Vec<(ThumbnailSize, Vec<u8>)>,
+}
+
+/// Decodes images on the blocking threads, at most `size` of them at a time so that large
+/// uploads queue up instead of taking all the threads.
+#[derive(Debug, Clone)]
+pub(crate) struct MediaPool {
+    permits: Arc<Semaphore>,
+}
+
+impl Default for MediaPool {
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(2, |n| n.get()))
+    }
+}
+
+impl MediaPool {
+    pub fn new(size: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(size)),
+        }
+    }
+
+    /// Process the uploaded data if it is an image, other files are returned as `None`.
+    pub async fn process(&self, data: Bytes) -> Result<Option<ProcessedImage>, AppError> {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|e| AppError::MediaError(e.to_string()))?;
+        tokio::task::spawn_blocking(move || process_image(&data))
+            .await
+            .map_err(|e| AppError::MediaError(e.to_string()))?
+    }
+}
+
+/// The extension of the thumbnails of an image stored with `ext`.
+pub(crate) fn thumbnail_ext(ext: &str) -> &'static str {
+    match ext {
+        "jpg" => "jpg",
+        _ => "png",
+    }
+}
+
+pub(crate) fn image_mime(ext: &str) -> &'static str {
+    match ext {
+        "jpg" => "image/jpeg",
+        "gif" => "image/gif",
+        _ => "image/png",
+    }
+}
+
+/// Whether the data starts like an image we can process, the first bytes are enough.
+pub(crate) fn is_image(data: &[u8]) -> bool {
+    image_format(data).is_some()
+}
+
+fn image_format(data: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(data) {
+        Ok(
+            format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Gif | ImageFormat::WebP),
+        ) => Some(format),
+        _ => None,
+    }
+}
+
+/// Decode an image, apply its EXIF orientation and encode it again so that no metadata, e.g.
+/// the GPS position, is kept. Animated GIFs are stored as they are, they have no EXIF.
+fn process_image(data: &[u8]) -> Result<Option<ProcessedImage>, AppError> {
+    let Some(format) = image_format(data) else {
+        return Ok(None);
+    };
+
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SIDE);
+    limits.max_image_height = Some(MAX_IMAGE_SIDE);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder().map_err(invalid_image)?;
+    let orientation = decoder.orientation().map_err(invalid_image)?;
+    let mut img = DynamicImage::from_decoder(decoder).map_err(invalid_image)?;
+    img.apply_orientation(orientation);
+
+    let (data, ext) = match format {
+        ImageFormat::Gif => (data.to_vec(), "gif"),
+        ImageFormat::Jpeg => (encode(&img, "jpg")?, "jpg"),
+        _ => (encode(&img, "png")?, "png"),
+    };
+
+    let mut thumbnails = vec![];
+    for size in ThumbnailSize::ALL {
+        let side = size.max_side();
+        if img.width() > side || img.height() > side {
+            let thumbnail = img.thumbnail(side, side);
+            thumbnails.push((size, encode(&thumbnail, thumbnail_ext(ext))?));
+        }
+    }
+
+    let tiny = img
+        .thumbnail(BLURHASH_SOURCE_SIDE, BLURHASH_SOURCE_SIDE)
+        .to_rgba8();
+    let (x, y) = BLURHASH_COMPONENTS;
+    let blurhash = blurhash::encode(x, y, tiny.width(), tiny.height(), tiny.as_raw())
+        .map_err(|e| AppError::MediaError(e.to_string()))?;
+
+    Ok(Some(ProcessedImage {
+        data,
+        ext,
+        mime: image_mime(ext),
+        width: img.width(),
+        height: img.height(),
+        blurhash,
+        thumbnails,
+    }))
+}
+
+fn encode(img: &DynamicImage, ext: &str) -> Result<Vec<u8>, AppError> {
+    let mut buf = Cursor::new(vec![]);
+    let ret = match ext {
+        "jpg" => {
+            let encoder = JpegEncoder::new_with_quality(&mut buf, JPEG_QUALITY);
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)
+        }
+        _ => img.write_to(&mut buf, ImageFormat::Png),
+    };
+    ret.map_err(|e| AppError::MediaError(e.to_string()))?;
+    Ok(buf.into_inner())
+}
+
+fn invalid_image(e: image::ImageError) -> AppError {
+    AppError::InvalidInput(format!("invalid image: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    /// A jpeg with an EXIF segment holding a fake GPS tag.
+    fn jpeg_with_exif(width: u32, height: u32) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+        }));
+        let jpeg = encode(&img, "jpg").unwrap();
+        let tiff = b"MM\x00\x2a\x00\x00\x00\x08\x00\x00\x00\x00\x00\x00GPS 52.52N 13.40E";
+        let mut segment = b"Exif\x00\x00".to_vec();
+        segment.extend_from_slice(tiff);
+        let len = (segment.len() + 2) as u16;
+        let mut data = jpeg[..2].to_vec();
+        data.extend_from_slice(&[0xff, 0xe1]);
+        data.extend_from_slice(&len.to_be_bytes());
+        data.extend_from_slice(&segment);
+        data.extend_from_slice(&jpeg[2..]);
+        data
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[tokio::test]
+    async fn image_should_be_stripped_and_thumbnailed() {
+        let data = jpeg_with_exif(800, 400);
+        assert!(contains(&data, b"GPS 52.52N"));
+
+        let pool = MediaPool::new(1);
+        let img = pool
+            .process(Bytes::from(data))
+            .await
+            .unwrap()
+            .expect("an image");
+        assert!(!contains(&img.data, b"Exif"));
+        assert!(!contains(&img.data, b"GPS 52.52N"));
+        assert_eq!((img.width, img.height, img.ext), (800, 400, "jpg"));
+        assert!(!img.blurhash.is_empty());
+
+        let sizes: Vec<_> = img.thumbnails.iter().map(|(size, _)| *size).collect();
+        assert_eq!(sizes, [ThumbnailSize::Thumb, ThumbnailSize::Small]);
+        let thumb = image::load_from_memory(&img.thumbnails[0].1).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (256, 128));
+    }
+
+    #[tokio::test]
+    async fn other_files_should_not_be_processed() {
+        let pool = MediaPool::default();
+        let ret = pool.process(Bytes::from_static(b"%PDF-1.7")).await.unwrap();
+        assert!(ret.is_none());
+
+        let ret = pool
+            .process(Bytes::from_static(b"\x89PNG\r\n\x1a\nbroken"))
+            .await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+    }
+}
@@ -1,6 +1,6 @@
 use axum::{
     extract::{FromRequestParts, Request, State},
-    http::StatusCode,
+    http::{Method, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
@@ -10,7 +10,10 @@ use axum_extra::{
 };
 use tracing::warn;
 
-use crate::AppState;
+use crate::{
+    models::{User, BOT_TOKEN_PREFIX},
+    AppError, AppState,
+};
 
 pub async fn verify_token(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
@@ -18,7 +21,12 @@ pub async fn verify_token(State(state): State<AppState>, req: Request, next: Nex
         match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
             Ok(TypedHeader(Authorization(bearer))) => {
                 let token = bearer.token();
-                match state.pk.verify(token) {
+                match verify(token, &state).await {
+                    Ok(user) if user.is_bot && !bot_can_access(&parts.method, parts.uri.path()) => {
+                        let msg = format!("Bot {} cannot access {}", user.id, parts.uri.path());
+                        warn!(%msg);
+                        return (StatusCode::FORBIDDEN, msg).into_response();
+                    }
                     Ok(user) => {
                         let mut req = Request::from_parts(parts, body);
                         req.extensions_mut().insert(user);
@@ -40,3 +48,96 @@ pub async fn verify_token(State(state): State<AppState>, req: Request, next: Nex
 
     next.run(req).await
 }
+
+/// Users carry a jwt, bots an api token looked up in the database. Tokens of suspended
+/// users stop working until the suspension is lifted.
+async fn verify(token: &str, state: &AppState) -> Result<User, AppError> {
+    let user = match token.starts_with(BOT_TOKEN_PREFIX) {
+        false => state.pk.verify(token)?,
+        true => User::find_by_bot_token(token, &state.pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound("bot token".to_string()))?,
+    };
+    if User::is_suspended(user.id, &state.pool).await? {
+        return Err(AppError::PermissionDenied(format!(
+            "user {} is suspended",
+            user.id
+        )));
+    }
+    Ok(user)
+}
+
+/// Bots only work with the chats they were added to, the membership checks of the chat and
+/// message endpoints do the rest.
+fn bot_can_access(method: &Method, path: &str) -> bool {
+    match path {
+        "/me" => method == Method::GET,
+        "/chats" => true,
+        _ => path.starts_with("/chats/") || path.starts_with("/messages/"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{body::Body, middleware::from_fn_with_state, routing::get, Extension, Json, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        models::{Bot, BotToken, CreateBot, CreateBotToken},
+        AppConfig,
+    };
+
+    async fn whoami(Extension(user): Extension<User>) -> Json<User> {
+        Json(user)
+    }
+
+    #[tokio::test]
+    async fn verify_token_should_accept_users_and_bots() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateBot {
+            fullname: "Deploy Bot".to_string(),
+        };
+        let bot = Bot::create(&input, &alice, &state.pool).await?;
+        let input = CreateBotToken {
+            name: "ci".to_string(),
+        };
+        let bot_token = BotToken::create(bot.id, &input, alice.id, &state.pool).await?;
+        let user_token = state.sk.sign(alice)?;
+
+        let api = Router::new()
+            .route("/chats", get(whoami))
+            .route("/users", get(whoami))
+            .layer(from_fn_with_state(state.clone(), verify_token));
+        let app = Router::new().nest("/api", api).with_state(state.clone());
+        let call = |path: &str, token: &str| {
+            Request::get(path)
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let ret = app.clone().oneshot(call("/api/users", &user_token)).await?;
+        assert_eq!(ret.status(), StatusCode::OK);
+        let token = bot_token.token.unwrap();
+        let ret = app.clone().oneshot(call("/api/chats", &token)).await?;
+        assert_eq!(ret.status(), StatusCode::OK);
+        let ret = app.clone().oneshot(call("/api/users", &token)).await?;
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        let ret = app
+            .clone()
+            .oneshot(call("/api/chats", "bot_unknown"))
+            .await?;
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        sqlx::query("UPDATE users SET suspended_until = 'infinity' WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let ret = app.oneshot(call("/api/users", &user_token)).await?;
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
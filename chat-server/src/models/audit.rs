@@ -0,0 +1,343 @@
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use tracing::warn;
+
+use crate::AppError;
+
+use super::{AuditEvent, User, Workspace};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+/// Events are exported in pages of this size.
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    UserSignup,
+    UserSignin,
+    UserSigninFailed,
+    PasskeyRegister,
+    PasskeyDelete,
+    SecondFactorUpdate,
+    RoleUpdate,
+    BotCreate,
+    BotTokenCreate,
+    BotTokenRevoke,
+    ChatMemberAdd,
+    ChatMemberRemove,
+    ChatRetentionUpdate,
+    MessageDelete,
+    MessageFilter,
+    ReportResolve,
+    UserUnsuspend,
+    WebhookCreate,
+    WebhookDelete,
+    IncomingWebhookCreate,
+    IncomingWebhookDelete,
+    CommandCreate,
+    CommandDelete,
+    SearchConfigUpdate,
+    ContentFiltersUpdate,
+    RetentionUpdate,
+    LegalHoldCreate,
+    LegalHoldRelease,
+    AuditExport,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UserSignup => "user.signup",
+            Self::UserSignin => "user.signin",
+            Self::UserSigninFailed => "user.signin_failed",
+            Self::PasskeyRegister => "passkey.register",
+            Self::PasskeyDelete => "passkey.delete",
+            Self::SecondFactorUpdate => "user.second_factor_update",
+            Self::RoleUpdate => "user.role_update",
+            Self::BotCreate => "bot.create",
+            Self::BotTokenCreate => "bot_token.create",
+            Self::BotTokenRevoke => "bot_token.revoke",
+            Self::ChatMemberAdd => "chat.member_add",
+            Self::ChatMemberRemove => "chat.member_remove",
+            Self::ChatRetentionUpdate => "chat.retention_update",
+            Self::MessageDelete => "message.delete",
+            Self::MessageFilter => "message.filter",
+            Self::ReportResolve => "report.resolve",
+            Self::UserUnsuspend => "user.unsuspend",
+            Self::WebhookCreate => "webhook.create",
+            Self::WebhookDelete => "webhook.delete",
+            Self::IncomingWebhookCreate => "incoming_webhook.create",
+            Self::IncomingWebhookDelete => "incoming_webhook.delete",
+            Self::CommandCreate => "command.create",
+            Self::CommandDelete => "command.delete",
+            Self::SearchConfigUpdate => "workspace.search_config_update",
+            Self::ContentFiltersUpdate => "workspace.content_filters_update",
+            Self::RetentionUpdate => "workspace.retention_update",
+            Self::LegalHoldCreate => "legal_hold.create",
+            Self::LegalHoldRelease => "legal_hold.release",
+            Self::AuditExport => "audit.export",
+        }
+    }
+}
+
+/// Where a request came from, taken from its headers and connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditContext {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// An action to record, usually done by the user of the request.
+#[derive(Debug, Clone)]
+pub struct NewAuditEvent {
+    pub ws_id: Option<i64>,
+    pub actor_id: Option<i64>,
+    pub action: AuditAction,
+    pub target: Option<(&'static str, i64)>,
+    pub metadata: Value,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListAuditEvents {
+    pub actor_id: Option<i64>,
+    /// e.g. `user.signin_failed`
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<i64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// only events older than this id are returned
+    pub last_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl NewAuditEvent {
+    pub fn new(action: AuditAction, actor: &User) -> Self {
+        Self {
+            ws_id: Some(actor.ws_id),
+            actor_id: Some(actor.id),
+            action,
+            target: None,
+            metadata: Value::Object(Default::default()),
+        }
+    }
+
+    /// An action without a signed in user, e.g. a failed signin.
+    pub fn anonymous(action: AuditAction, ws_id: Option<i64>) -> Self {
+        Self {
+            ws_id,
+            actor_id: None,
+            action,
+            target: None,
+            metadata: Value::Object(Default::default()),
+        }
+    }
+
+    pub fn target(mut self, target_type: &'static str, target_id: i64) -> Self {
+        self.target = Some((target_type, target_id));
+        self
+    }
+
+    pub fn metadata(mut self, metadata: Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+impl AuditContext {
+    /// Record an event of the request. The action already happened, so a failure is logged
+    /// instead of failing the request.
+    pub async fn record(&self, event: NewAuditEvent, pool: &PgPool) {
+        if let Err(e) = AuditEvent::create(&event, self, pool).await {
+            warn!(
+                "failed to record audit event {}: {}",
+                event.action.as_str(),
+                e
+            );
+        }
+    }
+}
+
+impl AuditEvent {
+    pub async fn create(
+        event: &NewAuditEvent,
+        ctx: &AuditContext,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let (target_type, target_id) = event.target.unzip();
+        let event = sqlx::query_as(
+            r#"
+            INSERT INTO audit_events (ws_id, actor_id, action, target_type, target_id, metadata,
+                ip, user_agent, request_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id, ws_id, actor_id, action, target_type, target_id, metadata, ip,
+                user_agent, request_id, created_at
+            "#,
+        )
+        .bind(event.ws_id)
+        .bind(event.actor_id)
+        .bind(event.action.as_str())
+        .bind(target_type)
+        .bind(target_id)
+        .bind(&event.metadata)
+        .bind(&ctx.ip)
+        .bind(&ctx.user_agent)
+        .bind(&ctx.request_id)
+        .fetch_one(pool)
+        .await?;
+        Ok(event)
+    }
+
+    /// Events of the workspace matching the filters, newest first, for its admins.
+    pub async fn list(
+        ws_id: i64,
+        user_id: i64,
+        input: &ListAuditEvents,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        Workspace::ensure_admin(ws_id, user_id, pool).await?;
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        Self::page(ws_id, input, input.last_id.unwrap_or(i64::MAX), limit, pool).await
+    }
+
+    /// All events of the workspace matching the filters as NDJSON, one event per line. The
+    /// events are read page by page while the response is sent.
+    pub async fn export(
+        ws_id: i64,
+        user_id: i64,
+        input: ListAuditEvents,
+        pool: PgPool,
+    ) -> Result<impl Stream<Item = Result<Bytes, AppError>>, AppError> {
+        Workspace::ensure_admin(ws_id, user_id, &pool).await?;
+        let last_id = input.last_id.unwrap_or(i64::MAX);
+        let pages = stream::try_unfold(Some(last_id), move |last_id| {
+            let (input, pool) = (input.clone(), pool.clone());
+            async move {
+                let Some(last_id) = last_id else {
+                    return Ok(None);
+                };
+                let events = Self::page(ws_id, &input, last_id, EXPORT_PAGE_SIZE, &pool).await?;
+                if events.is_empty() {
+                    return Ok(None);
+                }
+                let next = match events.len() as i64 == EXPORT_PAGE_SIZE {
+                    true => events.last().map(|e| e.id),
+                    false => None,
+                };
+                let mut buf = Vec::new();
+                for event in &events {
+                    // ids, strings and json values always serialize
+                    serde_json::to_writer(&mut buf, event).expect("an audit event is json");
+                    buf.push(b'\n');
+                }
+                Ok(Some((Bytes::from(buf), next)))
+            }
+        });
+        Ok(pages.into_stream())
+    }
+
+    async fn page(
+        ws_id: i64,
+        input: &ListAuditEvents,
+        last_id: i64,
+        limit: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        let events = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, actor_id, action, target_type, target_id, metadata, ip, user_agent,
+                request_id, created_at
+            FROM audit_events
+            WHERE ws_id = $1 AND id < $2
+                AND ($3::bigint IS NULL OR actor_id = $3)
+                AND ($4::text IS NULL OR action = $4)
+                AND ($5::text IS NULL OR target_type = $5)
+                AND ($6::bigint IS NULL OR target_id = $6)
+                AND ($7::timestamptz IS NULL OR created_at >= $7)
+                AND ($8::timestamptz IS NULL OR created_at < $8)
+            ORDER BY id DESC
+            LIMIT $9
+            "#,
+        )
+        .bind(ws_id)
+        .bind(last_id)
+        .bind(input.actor_id)
+        .bind(&input.action)
+        .bind(&input.target_type)
+        .bind(input.target_id)
+        .bind(input.since)
+        .bind(input.until)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn audit_events_should_be_filtered_and_exported() -> Result<()> {
+        let db = create_test_pool().await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let ctx = AuditContext {
+            ip: Some("10.0.0.1".to_string()),
+            user_agent: Some("curl/8.5".to_string()),
+            request_id: Some("0193a0b4-0000-7000-8000-000000000000".to_string()),
+        };
+        let removed = NewAuditEvent::new(AuditAction::ChatMemberRemove, &alice).target("user", 3);
+        ctx.record(removed, &db).await;
+        let failed = NewAuditEvent::anonymous(AuditAction::UserSigninFailed, Some(1))
+            .target("user", 2)
+            .metadata(serde_json::json!({ "email": "bob@acme.org" }));
+        ctx.record(failed, &db).await;
+        let other = NewAuditEvent::anonymous(AuditAction::UserSigninFailed, None);
+        ctx.record(other, &db).await;
+
+        let ret = AuditEvent::list(1, 2, &ListAuditEvents::default(), &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let events = AuditEvent::list(1, 1, &ListAuditEvents::default(), &db).await?;
+        let actions: Vec<_> = events.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, ["user.signin_failed", "chat.member_remove"]);
+        assert_eq!(events[0].metadata["email"], "bob@acme.org");
+        assert_eq!(events[1].request_id, ctx.request_id);
+
+        let input = ListAuditEvents {
+            actor_id: Some(1),
+            ..Default::default()
+        };
+        let events = AuditEvent::list(1, 1, &input, &db).await?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].target_id, Some(3));
+
+        let chunks: Vec<Bytes> =
+            AuditEvent::export(1, 1, ListAuditEvents::default(), (*db).clone())
+                .await?
+                .try_collect()
+                .await?;
+        let lines: Vec<AuditEvent> = chunks
+            .concat()
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 2);
+
+        let ret = sqlx::query("DELETE FROM audit_events").execute(&*db).await;
+        assert!(ret.is_err());
+        Ok(())
+    }
+}
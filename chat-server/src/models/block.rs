@@ -0,0 +1,111 @@
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use super::{User, UserBlock};
+
+impl UserBlock {
+    /// The users blocked by the user, newest first.
+    pub async fn list(user_id: i64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        let blocks = sqlx::query_as(
+            r#"
+            SELECT user_id, blocked_id, created_at
+            FROM user_blocks
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(blocks)
+    }
+
+    /// Block another user of the workspace, blocking it again changes nothing.
+    pub async fn create(user: &User, blocked_id: i64, pool: &PgPool) -> Result<Self, AppError> {
+        if blocked_id == user.id {
+            return Err(AppError::InvalidInput("cannot block yourself".to_string()));
+        }
+        match User::find_by_id(blocked_id, pool).await? {
+            Some(blocked) if blocked.ws_id == user.ws_id => {}
+            _ => return Err(AppError::NotFound(format!("user {}", blocked_id))),
+        }
+
+        let block = sqlx::query_as(
+            r#"
+            INSERT INTO user_blocks (user_id, blocked_id)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id, blocked_id) DO UPDATE SET user_id = EXCLUDED.user_id
+            RETURNING user_id, blocked_id, created_at
+            "#,
+        )
+        .bind(user.id)
+        .bind(blocked_id)
+        .fetch_one(pool)
+        .await?;
+        Ok(block)
+    }
+
+    pub async fn delete(user_id: i64, blocked_id: i64, pool: &PgPool) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM user_blocks WHERE user_id = $1 AND blocked_id = $2")
+            .bind(user_id)
+            .bind(blocked_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Whether one of the users blocked the other.
+    pub async fn between(a: i64, b: i64, pool: &PgPool) -> Result<bool, AppError> {
+        let blocked = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM user_blocks
+                WHERE (user_id = $1 AND blocked_id = $2) OR (user_id = $2 AND blocked_id = $1)
+            )
+            "#,
+        )
+        .bind(a)
+        .bind(b)
+        .fetch_one(pool)
+        .await?;
+        Ok(blocked)
+    }
+
+    /// The users who blocked the user.
+    pub async fn blocked_by(user_id: i64, pool: &PgPool) -> Result<Vec<i64>, AppError> {
+        let users = sqlx::query_scalar("SELECT user_id FROM user_blocks WHERE blocked_id = $1")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+        Ok(users)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn block_and_unblock_should_work() -> Result<()> {
+        let db = create_test_pool().await?;
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let ret = UserBlock::create(&bob, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let ret = UserBlock::create(&bob, 42, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        UserBlock::create(&bob, 3, &db).await?;
+        UserBlock::create(&bob, 3, &db).await?;
+        assert_eq!(UserBlock::list(2, &db).await?.len(), 1);
+        assert!(UserBlock::between(3, 2, &db).await?);
+        assert_eq!(UserBlock::blocked_by(3, &db).await?, vec![2]);
+
+        UserBlock::delete(2, 3, &db).await?;
+        assert!(!UserBlock::between(2, 3, &db).await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,381 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::AppError;
+
+use super::{Bot, BotToken, Chat, CreateMessage, IncomingWebhook, Message, User};
+
+/// Bearer tokens starting with this are looked up as bot tokens instead of verified as jwt.
+pub const BOT_TOKEN_PREFIX: &str = "bot_";
+const INCOMING_WEBHOOK_TOKEN_PREFIX: &str = "whk_";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateBot {
+    pub fullname: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateBotToken {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateIncomingWebhook {
+    /// also the name of the bot posting the messages
+    pub name: String,
+}
+
+impl Bot {
+    /// Create a bot in the workspace of the user, bots cannot create other bots.
+    pub async fn create(input: &CreateBot, user: &User, pool: &PgPool) -> Result<Self, AppError> {
+        check_not_bot(user)?;
+        let mut conn = pool.acquire().await?;
+        insert_bot(&input.fullname, user, &mut conn).await
+    }
+
+    /// Bots of the workspace of the user.
+    pub async fn list(user: &User, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        let bots = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, fullname, bot_owner_id AS owner_id, created_at
+            FROM users
+            WHERE ws_id = $1 AND is_bot
+            ORDER BY id
+            "#,
+        )
+        .bind(user.ws_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(bots)
+    }
+
+    pub(super) async fn get_for_owner(
+        id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let bot: Option<Self> = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, fullname, bot_owner_id AS owner_id, created_at
+            FROM users
+            WHERE id = $1 AND is_bot
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+        match bot {
+            Some(bot) if bot.owner_id == user_id => Ok(bot),
+            Some(_) => Err(AppError::PermissionDenied(format!(
+                "user {} does not own bot {}",
+                user_id, id
+            ))),
+            None => Err(AppError::NotFound(format!("bot {}", id))),
+        }
+    }
+}
+
+impl BotToken {
+    /// Issue a token for a bot of the user, the token is only returned here.
+    pub async fn create(
+        bot_id: i64,
+        input: &CreateBotToken,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        Bot::get_for_owner(bot_id, user_id, pool).await?;
+        let token = generate_token(BOT_TOKEN_PREFIX);
+        let mut bot_token: Self = sqlx::query_as(
+            r#"
+            INSERT INTO bot_tokens (bot_id, name, token_hash)
+            VALUES ($1, $2, $3)
+            RETURNING id, bot_id, name, created_at, last_used_at
+            "#,
+        )
+        .bind(bot_id)
+        .bind(&input.name)
+        .bind(hash_token(&token))
+        .fetch_one(pool)
+        .await?;
+        bot_token.token = Some(token);
+        Ok(bot_token)
+    }
+
+    pub async fn list(bot_id: i64, user_id: i64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        Bot::get_for_owner(bot_id, user_id, pool).await?;
+        let tokens = sqlx::query_as(
+            r#"
+            SELECT id, bot_id, name, created_at, last_used_at
+            FROM bot_tokens
+            WHERE bot_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(bot_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(tokens)
+    }
+
+    /// Revoke a token, requests using it are rejected right away.
+    pub async fn delete(bot_id: i64, id: i64, user_id: i64, pool: &PgPool) -> Result<(), AppError> {
+        Bot::get_for_owner(bot_id, user_id, pool).await?;
+        let ret = sqlx::query("DELETE FROM bot_tokens WHERE id = $1 AND bot_id = $2")
+            .bind(id)
+            .bind(bot_id)
+            .execute(pool)
+            .await?;
+        match ret.rows_affected() {
+            0 => Err(AppError::NotFound(format!("bot token {}", id))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl User {
+    /// The bot a token was issued for.
+    pub async fn find_by_bot_token(token: &str, pool: &PgPool) -> Result<Option<Self>, AppError> {
+        let user = sqlx::query_as(
+            r#"
+            WITH t AS (
+                UPDATE bot_tokens
+                SET last_used_at = now()
+                WHERE token_hash = $1
+                RETURNING bot_id
+            )
+            SELECT u.id, u.ws_id, u.fullname, u.email, u.is_bot, u.role, u.created_at
+            FROM users u
+            JOIN t ON t.bot_id = u.id
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(pool)
+        .await?;
+        Ok(user)
+    }
+}
+
+impl IncomingWebhook {
+    /// Create a webhook posting into the chat, only admins can do that. A bot named after the
+    /// webhook is created and added to the chat.
+    pub async fn create(
+        chat_id: i64,
+        input: &CreateIncomingWebhook,
+        user: &User,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        check_not_bot(user)?;
+        Chat::get_for_admin(chat_id, user.id, pool).await?;
+
+        let token = generate_token(INCOMING_WEBHOOK_TOKEN_PREFIX);
+        let mut tx = pool.begin().await?;
+        let bot = insert_bot(&input.name, user, &mut tx).await?;
+        sqlx::query("UPDATE chats SET members = array_append(members, $2) WHERE id = $1")
+            .bind(chat_id)
+            .bind(bot.id)
+            .execute(&mut *tx)
+            .await?;
+        let mut webhook: Self = sqlx::query_as(
+            r#"
+            INSERT INTO incoming_webhooks (chat_id, bot_id, name, token_hash, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, chat_id, bot_id, name, created_by, created_at
+            "#,
+        )
+        .bind(chat_id)
+        .bind(bot.id)
+        .bind(&input.name)
+        .bind(hash_token(&token))
+        .bind(user.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        webhook.token = Some(token);
+        Ok(webhook)
+    }
+
+    pub async fn list(chat_id: i64, user_id: i64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        Chat::get_for_admin(chat_id, user_id, pool).await?;
+        let webhooks = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, bot_id, name, created_by, created_at
+            FROM incoming_webhooks
+            WHERE chat_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(chat_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(webhooks)
+    }
+
+    /// Disable the url, the bot stays in the chat so its messages keep their sender.
+    pub async fn delete(
+        chat_id: i64,
+        id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        Chat::get_for_admin(chat_id, user_id, pool).await?;
+        let ret = sqlx::query("DELETE FROM incoming_webhooks WHERE id = $1 AND chat_id = $2")
+            .bind(id)
+            .bind(chat_id)
+            .execute(pool)
+            .await?;
+        match ret.rows_affected() {
+            0 => Err(AppError::NotFound(format!("incoming webhook {}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Post a message through the webhook, it goes through the same checks as one sent by
+    /// the bot itself, so removing the bot from the chat stops the webhook too.
+    pub async fn post(
+        token: &str,
+        input: &CreateMessage,
+        pool: &PgPool,
+    ) -> Result<Message, AppError> {
+        if input.send_at.is_some() {
+            return Err(AppError::InvalidInput(
+                "incoming webhooks cannot schedule messages".to_string(),
+            ));
+        }
+        let webhook: Option<(i64, i64)> =
+            sqlx::query_as("SELECT chat_id, bot_id FROM incoming_webhooks WHERE token_hash = $1")
+                .bind(hash_token(token))
+                .fetch_optional(pool)
+                .await?;
+        let Some((chat_id, bot_id)) = webhook else {
+            return Err(AppError::NotFound("incoming webhook".to_string()));
+        };
+        Message::create(input, chat_id, bot_id, pool).await
+    }
+}
+
+fn check_not_bot(user: &User) -> Result<(), AppError> {
+    match user.is_bot {
+        true => Err(AppError::PermissionDenied(format!(
+            "bot {} cannot create bots",
+            user.id
+        ))),
+        false => Ok(()),
+    }
+}
+
+async fn insert_bot(
+    fullname: &str,
+    owner: &User,
+    conn: &mut PgConnection,
+) -> Result<Bot, AppError> {
+    if fullname.trim().is_empty() {
+        return Err(AppError::InvalidInput(
+            "fullname cannot be empty".to_string(),
+        ));
+    }
+    // bots have no mailbox, the address only has to be unique
+    let email = format!("bot-{}@bots.invalid", Uuid::new_v4().simple());
+    let bot = sqlx::query_as(
+        r#"
+        INSERT INTO users (ws_id, fullname, email, is_bot, bot_owner_id)
+        VALUES ($1, $2, $3, TRUE, $4)
+        RETURNING id, ws_id, fullname, bot_owner_id AS owner_id, created_at
+        "#,
+    )
+    .bind(owner.ws_id)
+    .bind(fullname)
+    .bind(email)
+    .bind(owner.id)
+    .fetch_one(conn)
+    .await?;
+    Ok(bot)
+}
+
+fn generate_token(prefix: &str) -> String {
+    format!(
+        "{}{}{}",
+        prefix,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn bot_token_should_authenticate_the_bot() -> Result<()> {
+        let db = create_test_pool().await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateBot {
+            fullname: "Deploy Bot".to_string(),
+        };
+        let bot = Bot::create(&input, &alice, &db).await?;
+        assert_eq!(bot.owner_id, 1);
+        assert_eq!(Bot::list(&alice, &db).await?, vec![bot.clone()]);
+
+        let input = CreateBotToken {
+            name: "ci".to_string(),
+        };
+        let ret = BotToken::create(bot.id, &input, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let token = BotToken::create(bot.id, &input, 1, &db).await?;
+        let secret = token.token.clone().unwrap();
+        assert!(secret.starts_with(BOT_TOKEN_PREFIX));
+
+        let user = User::find_by_bot_token(&secret, &db).await?.unwrap();
+        assert_eq!(user.id, bot.id);
+        assert!(user.is_bot);
+        let ret = Bot::create(&CreateBot::default(), &user, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let tokens = BotToken::list(bot.id, 1, &db).await?;
+        assert!(tokens[0].token.is_none());
+        assert!(tokens[0].last_used_at.is_some());
+
+        BotToken::delete(bot.id, token.id, 1, &db).await?;
+        assert!(User::find_by_bot_token(&secret, &db).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incoming_webhook_should_post_as_its_bot() -> Result<()> {
+        let db = create_test_pool().await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateIncomingWebhook {
+            name: "CI".to_string(),
+        };
+        let ret =
+            IncomingWebhook::create(1, &input, &User::new(2, "Bob", "bob@acme.org"), &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let webhook = IncomingWebhook::create(1, &input, &alice, &db).await?;
+        let token = webhook.token.clone().unwrap();
+
+        let input = CreateMessage {
+            content: "build #42 passed".to_string(),
+            ..Default::default()
+        };
+        let message = IncomingWebhook::post(&token, &input, &db).await?;
+        assert_eq!(message.chat_id, 1);
+        assert_eq!(message.sender_id, webhook.bot_id);
+
+        // the bot only posts into the chats it is a member of
+        Chat::remove_member(1, webhook.bot_id, 1, &db).await?;
+        let ret = IncomingWebhook::post(&token, &input, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        IncomingWebhook::delete(1, webhook.id, 1, &db).await?;
+        let ret = IncomingWebhook::post(&token, &input, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use sqlx::{FromRow, PgPool};
+
+use crate::AppError;
+
+use super::{Chat, ChatOverview, Draft, Message};
+
+#[derive(Debug, FromRow)]
+struct ChatOverviewRow {
+    #[sqlx(flatten)]
+    chat: Chat,
+    unread_count: i64,
+    last_read_message_id: Option<i64>,
+    last_message_id: Option<i64>,
+}
+
+impl Chat {
+    pub async fn get_by_id(id: i64, pool: &PgPool) -> Result<Option<Self>, AppError> {
+        sqlx::query_as(
+            r#"
+            SELECT id, ws_id, name, type, members, admins, pin_limit, topic, created_at
+            FROM chats
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Load a chat the user belongs to, messages of other chats are invisible to them.
+    pub async fn get_for_member(id: i64, user_id: i64, pool: &PgPool) -> Result<Self, AppError> {
+        match Self::get_by_id(id, pool).await? {
+            Some(chat) if chat.is_member(user_id) => Ok(chat),
+            Some(_) => Err(AppError::PermissionDenied(format!(
+                "user {} is not a member of chat {}",
+                user_id, id
+            ))),
+            None => Err(AppError::NotFound(format!("chat {}", id))),
+        }
+    }
+
+    /// Whether both users are members of a chat.
+    pub async fn shared_by(a: i64, b: i64, pool: &PgPool) -> Result<bool, AppError> {
+        let shared = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM chats WHERE members @> ARRAY[$1, $2]::bigint[])",
+        )
+        .bind(a)
+        .bind(b)
+        .fetch_one(pool)
+        .await?;
+        Ok(shared)
+    }
+
+    /// List the chats of a user. Unread messages are the top level messages of others after
+    /// the last read one, both lookups go through the chat_id_created_at_index.
+    pub async fn list_for_user(user_id: i64, pool: &PgPool) -> Result<Vec<ChatOverview>, AppError> {
+        let rows: Vec<ChatOverviewRow> = sqlx::query_as(
+            r#"
+            SELECT c.id, c.ws_id, c.name, c.type, c.members, c.admins, c.pin_limit, c.topic,
+                c.created_at, r.last_read_message_id, lm.id AS last_message_id, uc.unread_count
+            FROM chats c
+            LEFT JOIN chat_reads r ON r.chat_id = c.id AND r.user_id = $1
+            LEFT JOIN messages lr ON lr.id = r.last_read_message_id
+            LEFT JOIN LATERAL (
+                SELECT m.id
+                FROM messages m
+                WHERE m.chat_id = c.id AND m.parent_id IS NULL
+                ORDER BY m.created_at DESC, m.id DESC
+                LIMIT 1
+            ) lm ON true
+            LEFT JOIN LATERAL (
+                SELECT count(*) AS unread_count
+                FROM messages m
+                WHERE m.chat_id = c.id
+                    AND (lr.id IS NULL OR (m.created_at >= lr.created_at AND m.id > lr.id))
+                    AND m.parent_id IS NULL
+                    AND m.deleted_at IS NULL
+                    AND m.sender_id <> $1
+            ) uc ON true
+            WHERE $1 = ANY(c.members)
+            ORDER BY c.id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        let ids: Vec<i64> = rows.iter().filter_map(|row| row.last_message_id).collect();
+        let messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, parent_id, format, content, content_text, attachments,
+                created_at, updated_at, deleted_at
+            FROM messages
+            WHERE id = ANY($1)
+            "#,
+        )
+        .bind(&ids)
+        .fetch_all(pool)
+        .await?;
+        let mut messages: HashMap<i64, Message> = messages.into_iter().map(|m| (m.id, m)).collect();
+
+        let drafts: Vec<Draft> = sqlx::query_as(
+            r#"
+            SELECT chat_id, parent_id, content, version, updated_at
+            FROM message_drafts
+            WHERE user_id = $1 AND parent_id IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        let mut drafts: HashMap<i64, Draft> = drafts.into_iter().map(|d| (d.chat_id, d)).collect();
+
+        let chats = rows
+            .into_iter()
+            .map(|row| ChatOverview {
+                draft: drafts.remove(&row.chat.id),
+                chat: row.chat,
+                unread_count: row.unread_count,
+                last_read_message_id: row.last_read_message_id,
+                last_message: row.last_message_id.and_then(|id| messages.remove(&id)),
+            })
+            .collect();
+        Ok(chats)
+    }
+
+    pub fn is_member(&self, user_id: i64) -> bool {
+        self.members.contains(&user_id)
+    }
+
+    pub fn is_admin(&self, user_id: i64) -> bool {
+        self.admins.contains(&user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn get_for_member_should_check_membership() -> Result<()> {
+        let db = create_test_pool().await?;
+        let chat = Chat::get_for_member(1, 1, &db).await?;
+        assert_eq!(chat.name, "general");
+        assert!(chat.is_admin(1));
+        assert!(!chat.is_admin(2));
+
+        let ret = Chat::get_for_member(2, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = Chat::get_for_member(100, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_for_user_should_count_unread_messages() -> Result<()> {
+        let db = create_test_pool().await?;
+        let chats = Chat::list_for_user(2, &db).await?;
+        assert_eq!(chats.len(), 2);
+        // bob sent message 2 and 4 himself
+        assert_eq!(chats[0].unread_count, 2);
+        assert_eq!(chats[0].last_message.as_ref().map(|m| m.id), Some(3));
+        assert_eq!(chats[1].unread_count, 0);
+
+        Message::mark_read(1, 1, 2, &db).await?;
+        let chats = Chat::list_for_user(2, &db).await?;
+        assert_eq!(chats[0].unread_count, 1);
+        assert_eq!(chats[0].last_read_message_id, Some(1));
+
+        let chats = Chat::list_for_user(4, &db).await?;
+        assert!(chats.is_empty());
+        Ok(())
+    }
+}
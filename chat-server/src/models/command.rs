@@ -0,0 +1,258 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    commands::{is_builtin, is_command_name},
+    utils::check_outbound_url,
+    AppError,
+};
+
+use super::{Bot, Chat, Message, MessageFormat, SlashCommand, User};
+
+const MAX_TOPIC_LEN: usize = 250;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateSlashCommand {
+    /// without the leading slash
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub bot_id: i64,
+    pub url: String,
+}
+
+impl SlashCommand {
+    /// Register a command answered by a bot of the user. Built-in commands cannot be replaced.
+    /// Urls of private hosts are rejected unless `allow_private` is set.
+    pub async fn create(
+        input: &CreateSlashCommand,
+        user: &User,
+        allow_private: bool,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let name = input.name.trim_start_matches('/').to_lowercase();
+        if !is_command_name(&name) || is_builtin(&name) {
+            return Err(AppError::InvalidInput(format!(
+                "invalid command name: {}",
+                input.name
+            )));
+        }
+        check_outbound_url(&input.url, allow_private).await?;
+        let bot = Bot::get_for_owner(input.bot_id, user.id, pool).await?;
+
+        let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let command = sqlx::query_as(
+            r#"
+            INSERT INTO slash_commands (ws_id, name, description, bot_id, url, secret, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (ws_id, name) DO NOTHING
+            RETURNING id, ws_id, name, description, bot_id, url, secret, created_by, created_at
+            "#,
+        )
+        .bind(bot.ws_id)
+        .bind(&name)
+        .bind(&input.description)
+        .bind(bot.id)
+        .bind(&input.url)
+        .bind(secret)
+        .bind(user.id)
+        .fetch_optional(pool)
+        .await?;
+        command.ok_or_else(|| AppError::InvalidInput(format!("command /{} already exists", name)))
+    }
+
+    /// Commands registered in the workspace, without their secrets.
+    pub async fn list(ws_id: i64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        let commands = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, name, description, bot_id, url, created_by, created_at
+            FROM slash_commands
+            WHERE ws_id = $1
+            ORDER BY name
+            "#,
+        )
+        .bind(ws_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(commands)
+    }
+
+    pub async fn get_by_name(
+        ws_id: i64,
+        name: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, AppError> {
+        let command = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, name, description, bot_id, url, secret, created_by, created_at
+            FROM slash_commands
+            WHERE ws_id = $1 AND name = $2
+            "#,
+        )
+        .bind(ws_id)
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+        Ok(command)
+    }
+
+    /// Unregister a command, only its creator can do that.
+    pub async fn delete(id: i64, user_id: i64, pool: &PgPool) -> Result<(), AppError> {
+        let ret = sqlx::query("DELETE FROM slash_commands WHERE id = $1 AND created_by = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        match ret.rows_affected() {
+            0 => Err(AppError::NotFound(format!("command {}", id))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Chat {
+    /// Set or clear the topic, any member can do that.
+    pub async fn set_topic(
+        id: i64,
+        topic: &str,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        Self::get_for_member(id, user_id, pool).await?;
+        let topic = topic.trim();
+        if topic.chars().count() > MAX_TOPIC_LEN {
+            return Err(AppError::InvalidInput(format!(
+                "topic cannot be longer than {} characters",
+                MAX_TOPIC_LEN
+            )));
+        }
+
+        let chat = sqlx::query_as(
+            r#"
+            UPDATE chats
+            SET topic = NULLIF($2, '')
+            WHERE id = $1
+            RETURNING id, ws_id, name, type, members, admins, pin_limit, topic, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(topic)
+        .fetch_one(pool)
+        .await?;
+        Ok(chat)
+    }
+}
+
+impl User {
+    /// A user of the workspace by the handle used in mentions, e.g. `alice` or `@alice`.
+    pub async fn find_by_handle(
+        ws_id: i64,
+        handle: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, AppError> {
+        let handle = handle.trim_start_matches('@').to_lowercase();
+        let user = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, fullname, email, is_bot, created_at
+            FROM users
+            WHERE ws_id = $1 AND lower(regexp_replace(fullname, '\s', '', 'g')) = $2
+            ORDER BY id
+            LIMIT 1
+            "#,
+        )
+        .bind(ws_id)
+        .bind(handle)
+        .fetch_optional(pool)
+        .await?;
+        Ok(user)
+    }
+}
+
+impl Message {
+    /// A reply to a command which is only returned to the caller, `sender_id` is 0 for the
+    /// server itself.
+    pub fn ephemeral(
+        chat_id: i64,
+        parent_id: Option<i64>,
+        sender_id: i64,
+        content: String,
+    ) -> Self {
+        Self {
+            id: 0,
+            chat_id,
+            sender_id,
+            parent_id,
+            format: MessageFormat::Plain,
+            content_text: content.clone(),
+            content,
+            attachments: vec![],
+            created_at: Utc::now(),
+            updated_at: None,
+            deleted_at: None,
+            reply_count: 0,
+            last_reply_at: None,
+            reactions: vec![],
+            ephemeral: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::{Bot, CreateBot},
+        utils::create_test_pool,
+    };
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn slash_command_should_be_registered_for_own_bots() -> Result<()> {
+        let db = create_test_pool().await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateBot {
+            fullname: "Deploy Bot".to_string(),
+        };
+        let bot = Bot::create(&input, &alice, &db).await?;
+
+        let mut input = CreateSlashCommand {
+            name: "/Deploy".to_string(),
+            description: "deploy a branch".to_string(),
+            bot_id: bot.id,
+            url: "https://example.com/deploy".to_string(),
+        };
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let ret = SlashCommand::create(&input, &bob, false, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let command = SlashCommand::create(&input, &alice, false, &db).await?;
+        assert_eq!(command.name, "deploy");
+        assert!(command.secret.is_some());
+        let ret = SlashCommand::create(&input, &alice, false, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        input.name = "invite".to_string();
+        let ret = SlashCommand::create(&input, &alice, false, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        // the network of the server is off limits
+        let private = CreateSlashCommand {
+            name: "metadata".to_string(),
+            url: "http://169.254.169.254/latest/meta-data".to_string(),
+            ..input.clone()
+        };
+        let ret = SlashCommand::create(&private, &alice, false, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        let commands = SlashCommand::list(1, &db).await?;
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0].secret.is_none());
+        let command = SlashCommand::get_by_name(1, "deploy", &db).await?.unwrap();
+        assert!(command.secret.is_some());
+
+        SlashCommand::delete(command.id, 1, &db).await?;
+        assert!(SlashCommand::list(1, &db).await?.is_empty());
+        Ok(())
+    }
+}
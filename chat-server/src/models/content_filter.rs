@@ -0,0 +1,210 @@
+use serde_json::json;
+use sqlx::{types::Json, PgPool};
+
+use crate::{
+    filters::{ContentFilterConfig, FilterChain, FilterContext, FilterOutcome},
+    AppError,
+};
+
+use super::{
+    format::{prepare, Rendered},
+    Attachment, AuditAction, AuditContext, Chat, Message, MessageFormat, MessageReport,
+    NewAuditEvent, Workspace,
+};
+
+impl Workspace {
+    pub async fn get_content_filters(
+        id: i64,
+        pool: &PgPool,
+    ) -> Result<ContentFilterConfig, AppError> {
+        let config: Option<Json<ContentFilterConfig>> =
+            sqlx::query_scalar("SELECT content_filters FROM workspaces WHERE id = $1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await?;
+        config
+            .map(|c| c.0)
+            .ok_or_else(|| AppError::NotFound(format!("workspace {}", id)))
+    }
+
+    /// Replace the content filters, they apply to the messages sent from now on.
+    pub async fn update_content_filters(
+        id: i64,
+        input: &ContentFilterConfig,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<ContentFilterConfig, AppError> {
+        Self::ensure_admin(id, user_id, pool).await?;
+        FilterChain::from_config(input)?;
+        sqlx::query("UPDATE workspaces SET content_filters = $2 WHERE id = $1")
+            .bind(id)
+            .bind(Json(input))
+            .execute(pool)
+            .await?;
+        Ok(input.clone())
+    }
+}
+
+impl MessageReport {
+    /// Report a message flagged by the content filters, there is no reporter.
+    async fn flag(
+        message: &Message,
+        ws_id: i64,
+        reason: &str,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let report = sqlx::query_as(
+            r#"
+            INSERT INTO message_reports (ws_id, message_id, chat_id, sender_id, content, reason)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, ws_id, message_id, chat_id, sender_id, content, reporter_id, reason,
+                action, note, resolved_by, resolved_at, created_at
+            "#,
+        )
+        .bind(ws_id)
+        .bind(message.id)
+        .bind(message.chat_id)
+        .bind(message.sender_id)
+        .bind(&message.content)
+        .bind(reason)
+        .fetch_one(pool)
+        .await?;
+        Ok(report)
+    }
+}
+
+/// Run the content filters of the workspace on a message about to be stored in the chat,
+/// then on the text it renders to, and return the message rendered. A rejected message is
+/// recorded and refused.
+pub(super) async fn filter_content(
+    content: &str,
+    format: MessageFormat,
+    attachments: &[Attachment],
+    chat: &Chat,
+    sender_id: i64,
+    pool: &PgPool,
+) -> Result<(FilterOutcome, Rendered), AppError> {
+    let config = Workspace::get_content_filters(chat.ws_id, pool).await?;
+    let ctx = FilterContext {
+        ws_id: chat.ws_id,
+        sender_id,
+        urls: attachments.iter().flat_map(Attachment::urls).collect(),
+        pool,
+    };
+    let chain = FilterChain::from_config(&config)?;
+    let mut outcome = chain.run(content, &ctx).await?;
+    let rendered = prepare(format, &outcome.content, attachments)?;
+    if rendered.text != outcome.content {
+        chain.recheck(&mut outcome, &rendered.text, &ctx).await?;
+    }
+    if let Some(hit) = outcome.rejection() {
+        record_hits(&outcome, chat.ws_id, sender_id, ("chat", chat.id), pool).await;
+        return Err(AppError::InvalidInput(format!(
+            "the message was rejected by the {} filter",
+            hit.filter
+        )));
+    }
+    Ok((outcome, rendered))
+}
+
+/// Record the filters which matched a stored message, flagged ones go to the moderation queue.
+pub(super) async fn report_filter_hits(
+    outcome: &FilterOutcome,
+    message: &Message,
+    ws_id: i64,
+    pool: &PgPool,
+) -> Result<(), AppError> {
+    let target = ("message", message.id);
+    record_hits(outcome, ws_id, message.sender_id, target, pool).await;
+    let flags: Vec<String> = outcome
+        .flags()
+        .map(|h| format!("{} filter: {}", h.filter, h.reason))
+        .collect();
+    if !flags.is_empty() {
+        MessageReport::flag(message, ws_id, &flags.join("; "), pool).await?;
+    }
+    Ok(())
+}
+
+async fn record_hits(
+    outcome: &FilterOutcome,
+    ws_id: i64,
+    sender_id: i64,
+    target: (&'static str, i64),
+    pool: &PgPool,
+) {
+    // filters run wherever messages are sent from, there is no request to take a context from
+    let audit = AuditContext::default();
+    for hit in &outcome.hits {
+        let event = NewAuditEvent {
+            ws_id: Some(ws_id),
+            actor_id: Some(sender_id),
+            action: AuditAction::MessageFilter,
+            target: Some(target),
+            metadata: json!(hit),
+        };
+        audit.record(event, pool).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        filters::{FilterAction, LengthFilterConfig},
+        models::{ListReports, User},
+        utils::create_test_pool,
+    };
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn content_filters_should_be_set_by_admins() -> Result<()> {
+        let db = create_test_pool().await?;
+        assert_eq!(
+            Workspace::get_content_filters(1, &db).await?,
+            ContentFilterConfig::default()
+        );
+        let input = ContentFilterConfig {
+            length: Some(LengthFilterConfig {
+                max_chars: 10,
+                action: FilterAction::Flag,
+            }),
+            ..Default::default()
+        };
+        let ret = Workspace::update_content_filters(1, &input, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Workspace::update_content_filters(1, &input, 1, &db).await?;
+        assert_eq!(Workspace::get_content_filters(1, &db).await?, input);
+
+        let invalid = ContentFilterConfig {
+            length: Some(LengthFilterConfig {
+                max_chars: 0,
+                action: FilterAction::Flag,
+            }),
+            ..Default::default()
+        };
+        let ret = Workspace::update_content_filters(1, &invalid, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        // flagged messages are sent and reported without a reporter
+        let chat = Chat::get_by_id(1, &db).await?.expect("chat exists");
+        let (outcome, _) = filter_content(
+            "a rather long message",
+            MessageFormat::Plain,
+            &[],
+            &chat,
+            2,
+            &db,
+        )
+        .await?;
+        assert_eq!(outcome.content, "a rather long message");
+        let message = Message::get_by_id(2, &db).await?.expect("message exists");
+        report_filter_hits(&outcome, &message, 1, &db).await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let reports = MessageReport::list(&ListReports::default(), &alice, &db).await?;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].reporter_id, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use super::{Chat, Draft, Message};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SaveDraft {
+    pub content: String,
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    /// increases with every change on the client, e.g. a timestamp in milliseconds
+    pub version: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GetDraft {
+    pub parent_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeleteDraft {
+    pub parent_id: Option<i64>,
+    /// only delete the draft if it is not newer than this version
+    pub version: Option<i64>,
+}
+
+impl Draft {
+    /// Save the draft unless a newer version is stored, the winning draft is returned.
+    pub async fn save(
+        chat_id: i64,
+        input: &SaveDraft,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        Chat::get_for_member(chat_id, user_id, pool).await?;
+        if let Some(parent_id) = input.parent_id {
+            Message::get_thread_root(parent_id, chat_id, pool).await?;
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_drafts (user_id, chat_id, parent_id, content, version)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, chat_id, COALESCE(parent_id, 0)) DO UPDATE
+            SET content = EXCLUDED.content, version = EXCLUDED.version, updated_at = now()
+            WHERE message_drafts.version < EXCLUDED.version
+            "#,
+        )
+        .bind(user_id)
+        .bind(chat_id)
+        .bind(input.parent_id)
+        .bind(&input.content)
+        .bind(input.version)
+        .execute(pool)
+        .await?;
+
+        let input = GetDraft {
+            parent_id: input.parent_id,
+        };
+        Self::get(chat_id, &input, user_id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("draft of chat {}", chat_id)))
+    }
+
+    pub async fn get(
+        chat_id: i64,
+        input: &GetDraft,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, AppError> {
+        Chat::get_for_member(chat_id, user_id, pool).await?;
+        let draft = sqlx::query_as(
+            r#"
+            SELECT chat_id, parent_id, content, version, updated_at
+            FROM message_drafts
+            WHERE user_id = $1 AND chat_id = $2 AND COALESCE(parent_id, 0) = COALESCE($3, 0)
+            "#,
+        )
+        .bind(user_id)
+        .bind(chat_id)
+        .bind(input.parent_id)
+        .fetch_optional(pool)
+        .await?;
+        Ok(draft)
+    }
+
+    pub async fn delete(
+        chat_id: i64,
+        input: &DeleteDraft,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            DELETE FROM message_drafts
+            WHERE user_id = $1 AND chat_id = $2 AND COALESCE(parent_id, 0) = COALESCE($3, 0)
+                AND ($4::bigint IS NULL OR version <= $4)
+            "#,
+        )
+        .bind(user_id)
+        .bind(chat_id)
+        .bind(input.parent_id)
+        .bind(input.version)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use crate::models::CreateMessage;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn save_draft_should_keep_the_latest_version() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = SaveDraft {
+            content: "from laptop".to_string(),
+            parent_id: None,
+            version: 2,
+        };
+        let draft = Draft::save(1, &input, 2, &db).await?;
+        assert_eq!(draft.content, "from laptop");
+
+        // a stale write from the phone loses
+        let input = SaveDraft {
+            content: "from phone".to_string(),
+            parent_id: None,
+            version: 1,
+        };
+        let draft = Draft::save(1, &input, 2, &db).await?;
+        assert_eq!(draft.content, "from laptop");
+        assert_eq!(draft.version, 2);
+
+        // thread drafts are kept apart
+        let input = SaveDraft {
+            content: "reply".to_string(),
+            parent_id: Some(1),
+            version: 1,
+        };
+        Draft::save(1, &input, 2, &db).await?;
+        let draft = Draft::get(1, &GetDraft::default(), 2, &db).await?;
+        assert_eq!(draft.map(|d| d.content), Some("from laptop".to_string()));
+
+        let chats = Chat::list_for_user(2, &db).await?;
+        assert_eq!(chats[0].draft.as_ref().map(|d| d.version), Some(2));
+        assert!(chats[1].draft.is_none());
+
+        let input = DeleteDraft {
+            parent_id: None,
+            version: Some(1),
+        };
+        Draft::delete(1, &input, 2, &db).await?;
+        assert!(Draft::get(1, &GetDraft::default(), 2, &db).await?.is_some());
+        Draft::delete(1, &DeleteDraft::default(), 2, &db).await?;
+        assert!(Draft::get(1, &GetDraft::default(), 2, &db).await?.is_none());
+
+        // sending the reply clears its draft
+        let input = CreateMessage {
+            content: "reply".to_string(),
+            parent_id: Some(1),
+            ..Default::default()
+        };
+        Message::create(&input, 1, 2, &db).await?;
+        let input = GetDraft { parent_id: Some(1) };
+        assert!(Draft::get(1, &input, 2, &db).await?.is_none());
+        Ok(())
+    }
+}
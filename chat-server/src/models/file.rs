@@ -0,0 +1,963 @@
+use std::time::Duration;
+
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::UploadConfig,
+    media::{image_mime, is_image, thumbnail_ext, MediaPool, ThumbnailSize},
+    storage::{ByteStream, Storage},
+    AppError,
+};
+
+use super::{Attachment, ChatFile, FileUpload, User, WorkspaceStorage};
+
+const MAX_EXT_LEN: usize = 8;
+const MAX_NAME_LEN: usize = 255;
+const DEFAULT_EXT: &str = "bin";
+const DEFAULT_MIME: &str = "application/octet-stream";
+/// How many bytes are read to tell the type of a file.
+const SNIFF_LEN: usize = 512;
+/// How long a client has to upload a file to a presigned url and complete it.
+const UPLOAD_URL_EXPIRY: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PresignUpload {
+    pub name: String,
+    /// the size of the file in bytes, checked against the limits before it is uploaded
+    pub size: u64,
+}
+
+/// Where to `PUT` the file, and the key to complete the upload with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresignedUpload {
+    pub key: String,
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompleteUpload {
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GetFile {
+    /// a thumbnail instead of the original image, images smaller than it are sent as they are
+    pub size: Option<ThumbnailSize>,
+}
+
+impl ChatFile {
+    /// Store an uploaded file of the user as it is streamed in, images are buffered to strip
+    /// their metadata and make thumbnails. Uploading the same content again returns the
+    /// stored file. The type of the file is sniffed from its content and has to be allowed
+    /// by the config, and the upload is stopped once it exceeds the size limit or a quota.
+    pub async fn upload(
+        name: &str,
+        body: &mut ByteStream<'_>,
+        user: &User,
+        config: &UploadConfig,
+        media: &MediaPool,
+        storage: &dyn Storage,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let name = check_name(name)?;
+        let remaining = WorkspaceStorage::remaining(user, config, &mut *pool.acquire().await?)
+            .await?
+            .max(0) as u64;
+        let (limit, reason) = match remaining < config.max_file_size {
+            true => (remaining, "storage quota exceeded".to_string()),
+            false => (
+                config.max_file_size,
+                format!("file is larger than {} bytes", config.max_file_size),
+            ),
+        };
+        let mut read = 0;
+        let mut body = body.map(move |chunk| {
+            let chunk = chunk?;
+            read += chunk.len() as u64;
+            match read > limit {
+                true => Err(AppError::PayloadTooLarge(reason.clone())),
+                false => Ok(chunk),
+            }
+        });
+
+        let mut head = Vec::new();
+        while head.len() < SNIFF_LEN {
+            match body.next().await {
+                Some(chunk) => head.extend_from_slice(&chunk?),
+                None => break,
+            }
+        }
+        if head.is_empty() {
+            return Err(AppError::InvalidInput("file cannot be empty".to_string()));
+        }
+        let mime = sniff_mime(&head);
+        if !config.allows(mime) {
+            return Err(AppError::UnsupportedMediaType(format!(
+                "{} files cannot be uploaded",
+                mime
+            )));
+        }
+        let stored = match is_image(&head) {
+            true => store_image(head, &mut body, user.ws_id, media, storage).await?,
+            false => {
+                let mime = mime.to_string();
+                store_file(head, &mut body, ext_of(name), mime, user.ws_id, storage).await?
+            }
+        };
+
+        Self::register(&stored, name, user, config, pool).await
+    }
+
+    /// Start an upload the client sends straight to the storage, for files too large to go
+    /// through the server. The url only takes a body of the declared size, which counts
+    /// against the quota until the upload is swept. The file is registered by
+    /// `complete_upload` once it was put to the url. Storages which cannot sign urls only
+    /// take uploads through the server.
+    pub async fn presign_upload(
+        input: &PresignUpload,
+        user: &User,
+        config: &UploadConfig,
+        storage: &dyn Storage,
+        pool: &PgPool,
+    ) -> Result<PresignedUpload, AppError> {
+        let name = check_name(&input.name)?;
+        let remaining =
+            WorkspaceStorage::remaining(user, config, &mut *pool.acquire().await?).await?;
+        let pending: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(sum(size), 0)::bigint
+            FROM file_uploads
+            WHERE owner_id = $1 AND completed_at IS NULL
+            "#,
+        )
+        .bind(user.id)
+        .fetch_one(pool)
+        .await?;
+        let remaining = (remaining - pending).max(0) as u64;
+        if input.size == 0 {
+            return Err(AppError::InvalidInput("file cannot be empty".to_string()));
+        }
+        if input.size > config.max_file_size {
+            return Err(AppError::PayloadTooLarge(format!(
+                "file is larger than {} bytes",
+                config.max_file_size
+            )));
+        }
+        if input.size > remaining {
+            return Err(AppError::PayloadTooLarge(
+                "storage quota exceeded".to_string(),
+            ));
+        }
+
+        let key = format!("tmp/{}", Uuid::now_v7());
+        let url = storage
+            .presigned_upload_url(&key, input.size, UPLOAD_URL_EXPIRY)
+            .await?
+            .ok_or_else(|| {
+                AppError::InvalidInput("the storage only takes files through /upload".to_string())
+            })?;
+        sqlx::query(
+            r#"
+            INSERT INTO file_uploads (key, ws_id, owner_id, name, size)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(&key)
+        .bind(user.ws_id)
+        .bind(user.id)
+        .bind(name)
+        .bind(input.size as i64)
+        .execute(pool)
+        .await?;
+        Ok(PresignedUpload {
+            key,
+            url,
+            expires_at: Utc::now() + UPLOAD_URL_EXPIRY,
+        })
+    }
+
+    /// Register a file the client put to the storage. The server never sees the content, so
+    /// it is stored under a random hash and not deduplicated. The type and size limits are
+    /// checked like for other uploads, images have to go through `/upload` to strip their
+    /// metadata. Rejected files are removed from the storage.
+    pub async fn complete_upload(
+        input: &CompleteUpload,
+        user: &User,
+        config: &UploadConfig,
+        storage: &dyn Storage,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let upload: Option<FileUpload> = sqlx::query_as(
+            r#"
+            SELECT key, ws_id, owner_id, name, size, completed_at, created_at
+            FROM file_uploads
+            WHERE key = $1 AND owner_id = $2 AND completed_at IS NULL AND created_at > $3
+            "#,
+        )
+        .bind(&input.key)
+        .bind(user.id)
+        .bind(Utc::now() - UPLOAD_URL_EXPIRY)
+        .fetch_optional(pool)
+        .await?;
+        let not_found = || AppError::NotFound(format!("upload {}", input.key));
+        let upload = upload.ok_or_else(not_found)?;
+        // the client may complete before the storage has the whole file
+        let size = storage.head(&upload.key).await?.ok_or_else(|| {
+            AppError::InvalidInput(format!("nothing was uploaded to {}", upload.key))
+        })?;
+        let ret = sqlx::query(
+            "UPDATE file_uploads SET completed_at = now() WHERE key = $1 AND completed_at IS NULL",
+        )
+        .bind(&upload.key)
+        .execute(pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(not_found());
+        }
+
+        let ret = store_upload(&upload, size, config, storage).await;
+        let stored = match ret {
+            Ok(stored) => stored,
+            Err(e) => {
+                storage.delete(&upload.key).await?;
+                return Err(e);
+            }
+        };
+        let ret = Self::register(&stored, &upload.name, user, config, pool).await;
+        if ret.is_err() {
+            storage
+                .delete(&file_key(user.ws_id, &stored.hash, &stored.ext))
+                .await?;
+        }
+        ret
+    }
+
+    /// Remove the uploads whose url expired, with whatever was put to their key. A put started
+    /// before the url expired may still be running, the uploads are kept a while longer.
+    /// Returns how many uploads were removed.
+    pub async fn sweep_uploads(
+        limit: i64,
+        storage: &dyn Storage,
+        pool: &PgPool,
+    ) -> Result<usize, AppError> {
+        let keys: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT key FROM file_uploads
+            WHERE created_at < $1
+            ORDER BY created_at
+            LIMIT $2
+            "#,
+        )
+        .bind(Utc::now() - UPLOAD_URL_EXPIRY * 2)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+        for key in &keys {
+            storage.delete(key).await?;
+            sqlx::query("DELETE FROM file_uploads WHERE key = $1")
+                .bind(key)
+                .execute(pool)
+                .await?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Add the stored file to the files of the user.
+    async fn register(
+        stored: &StoredFile,
+        name: &str,
+        user: &User,
+        config: &UploadConfig,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        // the usage of the workspace is only checked after the insert, uploads to the same
+        // workspace wait for each other so that together they cannot exceed its quota
+        let mut tx = pool.begin().await?;
+        sqlx::query("SELECT id FROM workspaces WHERE id = $1 FOR UPDATE")
+            .bind(user.ws_id)
+            .execute(&mut *tx)
+            .await?;
+        let file = sqlx::query_as(
+            r#"
+            INSERT INTO files (ws_id, owner_id, hash, ext, name, mime, size, width, height,
+                blurhash, thumbnails)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (ws_id, hash, ext, owner_id) DO UPDATE SET name = EXCLUDED.name
+            RETURNING id, ws_id, owner_id, hash, ext, name, mime, size, width, height, blurhash,
+                thumbnails, created_at
+            "#,
+        )
+        .bind(user.ws_id)
+        .bind(user.id)
+        .bind(&stored.hash)
+        .bind(&stored.ext)
+        .bind(name)
+        .bind(&stored.mime)
+        .bind(stored.size as i64)
+        .bind(stored.width)
+        .bind(stored.height)
+        .bind(&stored.blurhash)
+        .bind(&stored.thumbnails)
+        .fetch_one(&mut *tx)
+        .await?;
+        if WorkspaceStorage::remaining(user, config, &mut tx).await? < 0 {
+            return Err(AppError::PayloadTooLarge(
+                "storage quota exceeded".to_string(),
+            ));
+        }
+        tx.commit().await?;
+        Ok(file)
+    }
+
+    /// Find a file of the workspace by the path of its url, e.g. `0a1/b2c/3d4...e5f.png`.
+    pub async fn find(ws_id: i64, path: &str, pool: &PgPool) -> Result<Self, AppError> {
+        let not_found = || AppError::NotFound(format!("file {}", path));
+        let (hash, ext) = parse_path(path).ok_or_else(not_found)?;
+        let file: Option<Self> = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, owner_id, hash, ext, name, mime, size, width, height, blurhash,
+                thumbnails, created_at
+            FROM files
+            WHERE ws_id = $1 AND hash = $2 AND ext = $3
+            ORDER BY id
+            LIMIT 1
+            "#,
+        )
+        .bind(ws_id)
+        .bind(&hash)
+        .bind(ext)
+        .fetch_optional(pool)
+        .await?;
+        file.ok_or_else(not_found)
+    }
+
+    /// Whether the user can download the file. Guests only get the files they uploaded or
+    /// which were sent to their chats.
+    pub async fn can_access(&self, user_id: i64, pool: &PgPool) -> Result<bool, AppError> {
+        let reference = sqlx::types::Json(serde_json::json!([{ "url": self.url() }]));
+        let allowed = sqlx::query_scalar(
+            r#"
+            SELECT u.role <> 'guest'
+                OR EXISTS (
+                    SELECT 1 FROM files
+                    WHERE ws_id = $2 AND hash = $3 AND ext = $4 AND owner_id = u.id
+                )
+                OR EXISTS (
+                    SELECT 1 FROM messages m
+                    JOIN chats c ON c.id = m.chat_id
+                    WHERE u.id = ANY(c.members) AND m.attachments @> $5
+                )
+            FROM users u
+            WHERE u.id = $1 AND u.ws_id = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(self.ws_id)
+        .bind(&self.hash)
+        .bind(&self.ext)
+        .bind(&reference)
+        .fetch_optional(pool)
+        .await?;
+        Ok(allowed.unwrap_or(false))
+    }
+
+    /// Delete the files of the urls which no message refers to anymore, e.g. after messages
+    /// were purged. The deleted files are returned to remove them from the storage.
+    pub async fn delete_unreferenced(
+        urls: &[String],
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        let mut deleted = vec![];
+        for url in urls {
+            let Some((ws_id, hash, ext)) = parse_url(url) else {
+                continue;
+            };
+            let reference = sqlx::types::Json(serde_json::json!([{ "url": url }]));
+            let files: Vec<Self> = sqlx::query_as(
+                r#"
+                DELETE FROM files
+                WHERE ws_id = $1 AND hash = $2 AND ext = $3
+                    AND NOT EXISTS (SELECT 1 FROM messages WHERE attachments @> $4)
+                    AND NOT EXISTS (SELECT 1 FROM scheduled_messages WHERE attachments @> $4)
+                RETURNING id, ws_id, owner_id, hash, ext, name, mime, size, width, height,
+                    blurhash, thumbnails, created_at
+                "#,
+            )
+            .bind(ws_id)
+            .bind(&hash)
+            .bind(ext)
+            .bind(&reference)
+            .fetch_all(pool)
+            .await?;
+            // the rows of all uploaders share the stored content
+            deleted.extend(files.into_iter().take(1));
+        }
+        Ok(deleted)
+    }
+
+    /// The storage keys of the file and its thumbnails.
+    pub fn keys(&self) -> Vec<String> {
+        let thumbnails = ThumbnailSize::ALL
+            .into_iter()
+            .filter(|size| self.thumbnails.iter().any(|s| s == size.as_str()))
+            .map(|size| thumbnail_key(self.ws_id, &self.hash, &self.ext, size));
+        std::iter::once(file_key(self.ws_id, &self.hash, &self.ext))
+            .chain(thumbnails)
+            .collect()
+    }
+
+    /// The url of the file, served by `GET /api/files/:ws_id/*path`.
+    pub fn url(&self) -> String {
+        format!(
+            "/files/{}/{}/{}/{}.{}",
+            self.ws_id,
+            &self.hash[..3],
+            &self.hash[3..6],
+            &self.hash[6..],
+            self.ext
+        )
+    }
+
+    /// The storage key of the original or a thumbnail of the file, and its content type.
+    pub fn location(&self, size: Option<ThumbnailSize>) -> (String, String) {
+        match size {
+            Some(size) if self.thumbnails.iter().any(|s| s == size.as_str()) => {
+                let key = thumbnail_key(self.ws_id, &self.hash, &self.ext, size);
+                (key, image_mime(thumbnail_ext(&self.ext)).to_string())
+            }
+            _ => (
+                file_key(self.ws_id, &self.hash, &self.ext),
+                self.mime.clone(),
+            ),
+        }
+    }
+
+    /// The attachment to send the file in a message.
+    pub fn attachment(&self) -> Attachment {
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => Attachment::Image {
+                url: self.url(),
+                width: Some(width as u32),
+                height: Some(height as u32),
+                alt: None,
+                blurhash: self.blurhash.clone(),
+            },
+            _ => Attachment::File {
+                url: self.url(),
+                name: self.name.clone(),
+                size: self.size,
+                mime: Some(self.mime.clone()),
+            },
+        }
+    }
+}
+
+fn check_name(name: &str) -> Result<&str, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::InvalidInput(
+            "file name cannot be empty".to_string(),
+        ));
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(AppError::InvalidInput(format!(
+            "file name cannot be longer than {} characters",
+            MAX_NAME_LEN
+        )));
+    }
+    Ok(name)
+}
+
+/// Only short alphanumeric extensions are kept, they end up in file paths.
+fn ext_of(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((_, ext))
+            if !ext.is_empty()
+                && ext.len() <= MAX_EXT_LEN
+                && ext.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            ext.to_ascii_lowercase()
+        }
+        _ => DEFAULT_EXT.to_string(),
+    }
+}
+
+/// The content type of a file from its first bytes, the one sent by the client is not
+/// trusted. Files unknown to `infer` are text if they are valid UTF-8 without control
+/// characters.
+fn sniff_mime(head: &[u8]) -> &'static str {
+    if let Some(kind) = infer::get(head) {
+        return kind.mime_type();
+    }
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // the head may end in the middle of a character
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).expect("valid up to the error")
+        }
+        Err(_) => return DEFAULT_MIME,
+    };
+    match text.chars().all(|c| !c.is_control() || c.is_whitespace()) {
+        true => "text/plain",
+        false => DEFAULT_MIME,
+    }
+}
+
+/// The workspace, hash and extension of a url like `/files/1/0a1/b2c/3d4...e5f.png`.
+fn parse_url(url: &str) -> Option<(i64, String, &str)> {
+    let (ws_id, path) = url.strip_prefix("/files/")?.split_once('/')?;
+    let (hash, ext) = parse_path(path)?;
+    Some((ws_id.parse().ok()?, hash, ext))
+}
+
+fn parse_path(path: &str) -> Option<(String, &str)> {
+    let parts: Vec<&str> = path.split('/').collect();
+    let [a, b, rest] = parts[..] else {
+        return None;
+    };
+    let (c, ext) = rest.split_once('.')?;
+    let hash = format!("{}{}{}", a, b, c);
+    let valid = a.len() == 3
+        && b.len() == 3
+        && hash.len() == 64
+        && hash.chars().all(|c| c.is_ascii_hexdigit());
+    valid.then_some((hash, ext))
+}
+
+fn file_key(ws_id: i64, hash: &str, ext: &str) -> String {
+    format!(
+        "{}/{}/{}/{}.{}",
+        ws_id,
+        &hash[..3],
+        &hash[3..6],
+        &hash[6..],
+        ext
+    )
+}
+
+fn thumbnail_key(ws_id: i64, hash: &str, ext: &str, size: ThumbnailSize) -> String {
+    format!(
+        "{}/{}/{}/{}_{}.{}",
+        ws_id,
+        &hash[..3],
+        &hash[3..6],
+        &hash[6..],
+        size.as_str(),
+        thumbnail_ext(ext)
+    )
+}
+
+/// What was written to the storage for an upload.
+struct StoredFile {
+    hash: String,
+    ext: String,
+    mime: String,
+    size: u64,
+    width: Option<i32>,
+    height: Option<i32>,
+    blurhash: Option<String>,
+    thumbnails: Vec<&'static str>,
+}
+
+async fn store_image(
+    mut data: Vec<u8>,
+    body: &mut ByteStream<'_>,
+    ws_id: i64,
+    media: &MediaPool,
+    storage: &dyn Storage,
+) -> Result<StoredFile, AppError> {
+    while let Some(chunk) = body.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    let image = media
+        .process(Bytes::from(data))
+        .await?
+        .ok_or_else(|| AppError::InvalidInput("invalid image".to_string()))?;
+    let hash = hex::encode(Sha256::digest(&image.data));
+    let size = image.data.len() as u64;
+    put_once(storage, &file_key(ws_id, &hash, image.ext), image.data).await?;
+    let mut thumbnails = vec![];
+    for (thumbnail, data) in image.thumbnails {
+        put_once(
+            storage,
+            &thumbnail_key(ws_id, &hash, image.ext, thumbnail),
+            data,
+        )
+        .await?;
+        thumbnails.push(thumbnail.as_str());
+    }
+    Ok(StoredFile {
+        hash,
+        ext: image.ext.to_string(),
+        mime: image.mime.to_string(),
+        size,
+        width: Some(image.width as i32),
+        height: Some(image.height as i32),
+        blurhash: Some(image.blurhash),
+        thumbnails,
+    })
+}
+
+/// Other files are streamed to a temporary key while they are hashed, and then moved to the
+/// key of their content.
+async fn store_file(
+    head: Vec<u8>,
+    body: &mut ByteStream<'_>,
+    ext: String,
+    mime: String,
+    ws_id: i64,
+    storage: &dyn Storage,
+) -> Result<StoredFile, AppError> {
+    let tmp = format!("tmp/{}", Uuid::now_v7());
+    let mut hasher = Sha256::new();
+    let mut stream = stream::iter([Ok(Bytes::from(head))])
+        .chain(body)
+        .inspect_ok(|chunk| hasher.update(chunk));
+    let size = storage.put(&tmp, &mut stream).await?;
+    let hash = hex::encode(hasher.finalize());
+
+    let key = file_key(ws_id, &hash, &ext);
+    match storage.head(&key).await? {
+        Some(_) => storage.delete(&tmp).await?,
+        None => storage.rename(&tmp, &key).await?,
+    }
+    Ok(StoredFile {
+        hash,
+        ext,
+        mime,
+        size,
+        width: None,
+        height: None,
+        blurhash: None,
+        thumbnails: vec![],
+    })
+}
+
+/// Check a file put to the storage by the client and move it to its key.
+async fn store_upload(
+    upload: &FileUpload,
+    size: u64,
+    config: &UploadConfig,
+    storage: &dyn Storage,
+) -> Result<StoredFile, AppError> {
+    // the storage checks the signed size, a storage which does not is caught here
+    if size != upload.size as u64 {
+        return Err(AppError::InvalidInput(format!(
+            "{} bytes were uploaded instead of {}",
+            size, upload.size
+        )));
+    }
+    if size > config.max_file_size {
+        return Err(AppError::PayloadTooLarge(format!(
+            "file is larger than {} bytes",
+            config.max_file_size
+        )));
+    }
+    if size == 0 {
+        return Err(AppError::InvalidInput("file cannot be empty".to_string()));
+    }
+    let chunks: Vec<Bytes> = storage
+        .get(&upload.key, Some(0..size.min(SNIFF_LEN as u64)))
+        .await?
+        .try_collect()
+        .await?;
+    let head = chunks.concat();
+    let mime = sniff_mime(&head);
+    if !config.allows(mime) {
+        return Err(AppError::UnsupportedMediaType(format!(
+            "{} files cannot be uploaded",
+            mime
+        )));
+    }
+    if is_image(&head) {
+        return Err(AppError::InvalidInput(
+            "images are uploaded through /upload to strip their metadata".to_string(),
+        ));
+    }
+
+    let hash = hex::encode(Sha256::digest(Uuid::new_v4().as_bytes()));
+    let ext = ext_of(&upload.name);
+    storage
+        .rename(&upload.key, &file_key(upload.ws_id, &hash, &ext))
+        .await?;
+    Ok(StoredFile {
+        hash,
+        ext,
+        mime: mime.to_string(),
+        size,
+        width: None,
+        height: None,
+        blurhash: None,
+        thumbnails: vec![],
+    })
+}
+
+/// Files are content addressed, an existing file already has the same content.
+async fn put_once(storage: &dyn Storage, key: &str, data: Vec<u8>) -> Result<(), AppError> {
+    if storage.head(key).await?.is_none() {
+        let mut body = stream::iter([Ok(Bytes::from(data))]);
+        storage.put(key, &mut body).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        storage::{test_s3::start_s3_storage, LocalStorage},
+        utils::create_test_pool,
+        AppConfig,
+    };
+
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn file_path_should_be_parsed() {
+        let hash = "0a1b2c".repeat(10) + "0a1b";
+        let path = format!("{}/{}/{}.png", &hash[..3], &hash[3..6], &hash[6..]);
+        assert_eq!(parse_path(&path), Some((hash.clone(), "png")));
+        assert_eq!(parse_path("../../etc/passwd.png"), None);
+        let url = format!("/files/1/{}", path);
+        assert_eq!(parse_url(&url), Some((1, hash.clone(), "png")));
+        assert_eq!(parse_url("https://example.com/files/1/a.png"), None);
+        assert_eq!(ext_of("report.final.PDF"), "pdf");
+        assert_eq!(ext_of("notes"), "bin");
+        assert_eq!(ext_of("x.p/../ng"), "bin");
+    }
+
+    #[test]
+    fn mime_should_be_sniffed_from_content() {
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff_mime(b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(
+            sniff_mime(b"MZ\x90\0\x03"),
+            "application/vnd.microsoft.portable-executable"
+        );
+        assert_eq!(sniff_mime("hello\tw\u{f6}rld\n".as_bytes()), "text/plain");
+        assert_eq!(sniff_mime(&"\u{f6}".as_bytes()[..1]), "text/plain");
+        assert_eq!(sniff_mime(b"\0\x01\x02binary"), DEFAULT_MIME);
+    }
+
+    #[tokio::test]
+    async fn upload_should_store_the_file_once() -> Result<()> {
+        let db = create_test_pool().await?;
+        let storage = LocalStorage::new(std::env::temp_dir().join("crablink-test"));
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let media = MediaPool::new(1);
+        let content = format!("notes of {}", Uuid::new_v4());
+        let body = || {
+            stream::iter(
+                content
+                    .as_bytes()
+                    .chunks(8)
+                    .map(|c| Ok(Bytes::copy_from_slice(c)))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let config = AppConfig::load()?.upload;
+        let file = ChatFile::upload(
+            "notes.txt",
+            &mut body(),
+            &user,
+            &config,
+            &media,
+            &storage,
+            &db,
+        )
+        .await?;
+        assert_eq!(file.ext, "txt");
+        assert_eq!(file.mime, "text/plain");
+        assert_eq!(file.size, content.len() as i64);
+        assert_eq!(file.hash, hex::encode(Sha256::digest(&content)));
+        assert!(file.thumbnails.is_empty());
+        assert!(matches!(file.attachment(), Attachment::File { .. }));
+
+        let path = file.url().trim_start_matches("/files/1/").to_string();
+        let found = ChatFile::find(1, &path, &db).await?;
+        assert_eq!(found.id, file.id);
+        let (key, _) = found.location(Some(ThumbnailSize::Thumb));
+        let chunks: Vec<Bytes> = storage.get(&key, None).await?.try_collect().await?;
+        assert_eq!(chunks.concat(), content.as_bytes());
+
+        let again = ChatFile::upload(
+            "copy.txt",
+            &mut body(),
+            &user,
+            &config,
+            &media,
+            &storage,
+            &db,
+        )
+        .await?;
+        assert_eq!(again.id, file.id);
+        assert_eq!(again.name, "copy.txt");
+        assert!(matches!(
+            ChatFile::find(2, &path, &db).await,
+            Err(AppError::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_should_check_type_size_and_quota() -> Result<()> {
+        let db = create_test_pool().await?;
+        let storage = LocalStorage::new(std::env::temp_dir().join("crablink-test"));
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let media = MediaPool::new(1);
+        let mut config = AppConfig::load()?.upload;
+        config.max_file_size = 16;
+        let body = |content: &'static [u8]| stream::iter([Ok(Bytes::from_static(content))]);
+
+        // the name does not matter, the content is an executable
+        let exe = b"MZ\x90\0\x03\0\0\0";
+        let ret = ChatFile::upload(
+            "photo.png",
+            &mut body(exe),
+            &user,
+            &config,
+            &media,
+            &storage,
+            &db,
+        )
+        .await;
+        assert!(matches!(ret, Err(AppError::UnsupportedMediaType(_))));
+        let big = b"more than sixteen bytes";
+        let ret = ChatFile::upload(
+            "big.txt",
+            &mut body(big),
+            &user,
+            &config,
+            &media,
+            &storage,
+            &db,
+        )
+        .await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(e)) if e.contains("larger than")));
+
+        sqlx::query("UPDATE users SET storage_quota = 20 WHERE id = 1")
+            .execute(&*db)
+            .await?;
+        let file = ChatFile::upload(
+            "a.txt",
+            &mut body(b"twelve bytes"),
+            &user,
+            &config,
+            &media,
+            &storage,
+            &db,
+        )
+        .await?;
+        assert_eq!(file.mime, "text/plain");
+        let ret = ChatFile::upload(
+            "b.txt",
+            &mut body(b"twelve again"),
+            &user,
+            &config,
+            &media,
+            &storage,
+            &db,
+        )
+        .await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(e)) if e.contains("quota")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn presigned_upload_should_register_the_file() -> Result<()> {
+        let db = create_test_pool().await?;
+        let (storage, _, objects) = start_s3_storage().await?;
+        let user = User::new(1, "Alice", "alice@acme.org");
+        let mut config = AppConfig::load()?.upload;
+        config.max_file_size = 1024;
+
+        let input = PresignUpload {
+            name: "notes.txt".to_string(),
+            size: 2048,
+        };
+        let ret = ChatFile::presign_upload(&input, &user, &config, &storage, &db).await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(_))));
+        let input = PresignUpload { size: 13, ..input };
+        let local = LocalStorage::new(std::env::temp_dir().join("crablink-test"));
+        let ret = ChatFile::presign_upload(&input, &user, &config, &local, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        let upload = ChatFile::presign_upload(&input, &user, &config, &storage, &db).await?;
+        let complete = CompleteUpload { key: upload.key };
+        // nothing was put yet, the upload can be completed later
+        let ret = ChatFile::complete_upload(&complete, &user, &config, &storage, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let res = reqwest::Client::new()
+            .put(&upload.url)
+            .body("Hello, World!")
+            .send()
+            .await?;
+        assert!(res.status().is_success());
+        // only the owner completes the upload
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let ret = ChatFile::complete_upload(&complete, &bob, &config, &storage, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let file = ChatFile::complete_upload(&complete, &user, &config, &storage, &db).await?;
+        assert_eq!(file.mime, "text/plain");
+        assert_eq!(file.size, 13);
+        assert_eq!(file.name, "notes.txt");
+        assert_eq!(storage.head(&complete.key).await?, None);
+        let key = file_key(1, &file.hash, &file.ext);
+        assert_eq!(
+            objects.lock().unwrap().get(&key).cloned(),
+            Some(Bytes::from("Hello, World!"))
+        );
+        let ret = ChatFile::complete_upload(&complete, &user, &config, &storage, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        // the url still works until it expires, what is put is swept after that
+        let put = |url: String, body: &'static [u8]| async move {
+            reqwest::Client::new().put(url).body(body).send().await
+        };
+        put(upload.url, b"Hello, again!").await?;
+        assert_eq!(storage.head(&complete.key).await?, Some(13));
+
+        // executables are rejected and removed like through /upload
+        let exe = PresignUpload {
+            name: "setup.exe".to_string(),
+            size: 8,
+        };
+        let upload = ChatFile::presign_upload(&exe, &user, &config, &storage, &db).await?;
+        put(upload.url, b"MZ\x90\0\x03\0\0\0").await?;
+        let rejected = CompleteUpload { key: upload.key };
+        let ret = ChatFile::complete_upload(&rejected, &user, &config, &storage, &db).await;
+        assert!(matches!(ret, Err(AppError::UnsupportedMediaType(_))));
+        assert_eq!(storage.head(&rejected.key).await?, None);
+        // only the size signed into the url is taken
+        let upload = ChatFile::presign_upload(&input, &user, &config, &storage, &db).await?;
+        put(upload.url, b"short").await?;
+        let short = CompleteUpload { key: upload.key };
+        let ret = ChatFile::complete_upload(&short, &user, &config, &storage, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(e)) if e.contains("instead of")));
+
+        // pending uploads count against the quota
+        sqlx::query("UPDATE users SET storage_quota = $1 WHERE id = 1")
+            .bind(file.size + 20)
+            .execute(&*db)
+            .await?;
+        ChatFile::presign_upload(&input, &user, &config, &storage, &db).await?;
+        let ret = ChatFile::presign_upload(&input, &user, &config, &storage, &db).await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(e)) if e.contains("quota")));
+
+        assert_eq!(ChatFile::sweep_uploads(100, &storage, &db).await?, 0);
+        sqlx::query("UPDATE file_uploads SET created_at = now() - interval '3 hours'")
+            .execute(&*db)
+            .await?;
+        assert_eq!(ChatFile::sweep_uploads(100, &storage, &db).await?, 4);
+        assert_eq!(storage.head(&complete.key).await?, None);
+        assert_eq!(storage.head(&key).await?, Some(13));
+        Ok(())
+    }
+}
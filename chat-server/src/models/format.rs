@@ -0,0 +1,336 @@
+use std::ops::Range;
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+use crate::AppError;
+
+use super::{Attachment, MessageFormat};
+
+const MAX_ATTACHMENTS: usize = 10;
+const MAX_CODE_LEN: usize = 64 * 1024;
+const MAX_LANGUAGE_LEN: usize = 32;
+const MAX_IMAGE_SIDE: u32 = 100_000;
+
+/// The content of a message as it is stored, and its plain text rendering.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Rendered {
+    pub content: String,
+    pub text: String,
+}
+
+impl Attachment {
+    /// The urls the attachment shows or links to.
+    pub(crate) fn urls(&self) -> Vec<&str> {
+        match self {
+            Self::File { url, .. } | Self::Image { url, .. } => vec![url],
+            Self::Link { url, image_url, .. } => [Some(url), image_url.as_ref()]
+                .into_iter()
+                .flatten()
+                .map(|url| url.as_str())
+                .collect(),
+            Self::Code { .. } => vec![],
+        }
+    }
+}
+
+/// Validate the content and the attachments of a message, markdown is sanitized and rendered
+/// to plain text for search and notifications.
+pub(super) fn prepare(
+    format: MessageFormat,
+    content: &str,
+    attachments: &[Attachment],
+) -> Result<Rendered, AppError> {
+    check_attachments(attachments)?;
+    let rendered = match format {
+        MessageFormat::Plain => Rendered {
+            content: content.to_string(),
+            text: content.to_string(),
+        },
+        MessageFormat::Markdown => {
+            let content = sanitize(content);
+            let text = plain_text(&content);
+            Rendered { content, text }
+        }
+    };
+    if rendered.text.trim().is_empty() && attachments.is_empty() {
+        return Err(AppError::InvalidInput(
+            "content cannot be empty".to_string(),
+        ));
+    }
+    Ok(rendered)
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS
+}
+
+/// Raw html is escaped so clients show it as text, links and images with a dangerous url
+/// are replaced by their text. Everything else of the markdown is kept as written.
+fn sanitize(content: &str) -> String {
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    // the range, the nesting depth and the text of the unsafe link being replaced
+    let mut unsafe_link: Option<(Range<usize>, usize, String)> = None;
+    for (event, range) in Parser::new_ext(content, options()).into_offset_iter() {
+        if let Some((_, depth, text)) = unsafe_link.as_mut() {
+            match event {
+                Event::Start(Tag::Link { .. } | Tag::Image { .. }) => *depth += 1,
+                Event::End(TagEnd::Link | TagEnd::Image) if *depth > 0 => *depth -= 1,
+                Event::End(TagEnd::Link | TagEnd::Image) => {
+                    if let Some((range, _, text)) = unsafe_link.take() {
+                        edits.push((range, escape(&text)));
+                    }
+                }
+                Event::Text(s) | Event::Code(s) | Event::InlineHtml(s) => text.push_str(&s),
+                Event::SoftBreak | Event::HardBreak => text.push(' '),
+                _ => {}
+            }
+            continue;
+        }
+        match event {
+            Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. })
+                if !is_safe_url(&dest_url) =>
+            {
+                unsafe_link = Some((range, 0, String::new()));
+            }
+            Event::Html(s) | Event::InlineHtml(s) => {
+                edits.push((range, s.replace('<', "\\<")));
+            }
+            _ => {}
+        }
+    }
+
+    let mut sanitized = String::with_capacity(content.len());
+    let mut pos = 0;
+    for (range, replacement) in edits {
+        if range.start < pos {
+            continue;
+        }
+        sanitized.push_str(&content[pos..range.start]);
+        sanitized.push_str(&replacement);
+        pos = range.end;
+    }
+    sanitized.push_str(&content[pos..]);
+    sanitized
+}
+
+/// Render markdown as plain text, blocks are separated by new lines.
+fn plain_text(content: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new_ext(content, options()) {
+        match event {
+            Event::Text(s) | Event::Code(s) | Event::Html(s) | Event::InlineHtml(s) => {
+                text.push_str(&s)
+            }
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak | Event::Rule => end_line(&mut text),
+            Event::End(TagEnd::TableCell) => text.push(' '),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::CodeBlock
+                | TagEnd::HtmlBlock
+                | TagEnd::Item
+                | TagEnd::TableHead
+                | TagEnd::TableRow,
+            ) => end_line(&mut text),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+fn end_line(text: &mut String) {
+    text.truncate(text.trim_end_matches(' ').len());
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// Escape markdown punctuation so the text is shown as it is.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Urls without a scheme are relative, browsers ignore whitespace and control characters
+/// in a scheme so they are removed before checking it.
+fn is_safe_url(url: &str) -> bool {
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect();
+    match url.split_once(':') {
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => matches!(
+            scheme.to_ascii_lowercase().as_str(),
+            "http" | "https" | "mailto"
+        ),
+        _ => true,
+    }
+}
+
+/// Attachments link to uploaded files or to http urls.
+fn is_attachment_url(url: &str) -> bool {
+    (url.starts_with('/') && !url.starts_with("//"))
+        || url.starts_with("https://")
+        || url.starts_with("http://")
+}
+
+fn check_attachments(attachments: &[Attachment]) -> Result<(), AppError> {
+    if attachments.len() > MAX_ATTACHMENTS {
+        return Err(AppError::InvalidInput(format!(
+            "a message can have at most {} attachments",
+            MAX_ATTACHMENTS
+        )));
+    }
+    for attachment in attachments {
+        let urls = match attachment {
+            Attachment::File {
+                url, name, size, ..
+            } => {
+                if name.trim().is_empty() || *size < 0 {
+                    return Err(AppError::InvalidInput(format!(
+                        "invalid file attachment {}",
+                        url
+                    )));
+                }
+                vec![Some(url)]
+            }
+            Attachment::Image {
+                url, width, height, ..
+            } => {
+                let valid_side = |side: &Option<u32>| {
+                    side.is_none_or(|side| (1..=MAX_IMAGE_SIDE).contains(&side))
+                };
+                if !valid_side(width) || !valid_side(height) {
+                    return Err(AppError::InvalidInput(format!(
+                        "invalid dimensions of image {}",
+                        url
+                    )));
+                }
+                vec![Some(url)]
+            }
+            Attachment::Code { language, content } => {
+                if content.len() > MAX_CODE_LEN
+                    || language
+                        .as_ref()
+                        .is_some_and(|l| l.len() > MAX_LANGUAGE_LEN)
+                {
+                    return Err(AppError::InvalidInput(
+                        "code snippet is too long".to_string(),
+                    ));
+                }
+                vec![]
+            }
+            Attachment::Link { url, image_url, .. } => vec![Some(url), image_url.as_ref()],
+        };
+        if let Some(url) = urls
+            .into_iter()
+            .flatten()
+            .find(|url| !is_attachment_url(url))
+        {
+            return Err(AppError::InvalidInput(format!(
+                "invalid attachment url {}",
+                url
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markdown(content: &str) -> Rendered {
+        prepare(MessageFormat::Markdown, content, &[]).expect("valid markdown")
+    }
+
+    #[test]
+    fn markdown_should_be_rendered_as_plain_text() {
+        let ret = markdown("# Release\n\nShip **v2** with `cargo`\nto [prod](https://example.com)\n\n- one\n- two\n\n```rust\nfn main() {}\n```");
+        assert_eq!(
+            ret.text,
+            "Release\nShip v2 with cargo to prod\none\ntwo\nfn main() {}"
+        );
+        // safe markdown is kept as written
+        assert!(ret.content.starts_with("# Release\n\nShip **v2**"));
+    }
+
+    #[test]
+    fn markdown_should_be_sanitized() {
+        let ret = markdown("hi <script>alert(1)</script> there");
+        assert_eq!(ret.content, "hi \\<script>alert(1)\\</script> there");
+        assert_eq!(ret.text, "hi <script>alert(1)</script> there");
+
+        let ret = markdown("<div onclick=\"x()\">\nboom\n</div>");
+        assert!(!ret.content.contains("\n<"));
+        assert!(!ret.content.starts_with('<'));
+
+        let ret = markdown(
+            "click [me](javascript:alert(1)) or ![pic](JaVa&#9;Script:x) or [ok](/chats/1)",
+        );
+        assert_eq!(ret.content, "click me or pic or [ok](/chats/1)");
+        assert_eq!(ret.text, "click me or pic or ok");
+
+        let ret = markdown("<javascript:alert(1)>");
+        assert_eq!(ret.content, "javascript\\:alert\\(1\\)");
+    }
+
+    #[test]
+    fn plain_content_should_be_kept() {
+        let ret = prepare(MessageFormat::Plain, "**not bold** <b>", &[]).unwrap();
+        assert_eq!(ret.content, "**not bold** <b>");
+        assert_eq!(ret.text, "**not bold** <b>");
+
+        let ret = prepare(MessageFormat::Markdown, "  ", &[]);
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn attachments_should_be_validated() {
+        let image = Attachment::Image {
+            url: "/files/1/a.png".to_string(),
+            width: Some(640),
+            height: Some(480),
+            alt: None,
+            blurhash: None,
+        };
+        assert!(prepare(MessageFormat::Plain, "", &[image]).is_ok());
+
+        let invalid = [
+            Attachment::Image {
+                url: "/files/1/a.png".to_string(),
+                width: Some(0),
+                height: Some(480),
+                alt: None,
+                blurhash: None,
+            },
+            Attachment::Link {
+                url: "javascript:alert(1)".to_string(),
+                title: None,
+                description: None,
+                image_url: None,
+            },
+            Attachment::File {
+                url: "//evil.com/a.zip".to_string(),
+                name: "a.zip".to_string(),
+                size: 10,
+                mime: None,
+            },
+            Attachment::Code {
+                language: Some("rust".to_string()),
+                content: "x".repeat(MAX_CODE_LEN + 1),
+            },
+        ];
+        for attachment in invalid {
+            let ret = prepare(MessageFormat::Plain, "hi", &[attachment]);
+            assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        }
+    }
+}
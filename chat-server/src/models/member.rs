@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use super::{Chat, ChatType, User, UserBlock, WorkspaceRole};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AddMember {
+    pub user_id: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateDirectChat {
+    pub user_id: i64,
+}
+
+impl Chat {
+    /// Add a user of the same workspace to the chat, only admins can do that.
+    pub async fn add_member(
+        id: i64,
+        member_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let chat = Self::get_for_admin(id, user_id, pool).await?;
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::InvalidInput(format!(
+                "cannot add members to the direct chat {}",
+                id
+            )));
+        }
+        match User::find_by_id(member_id, pool).await? {
+            Some(member) if member.ws_id == chat.ws_id => {}
+            _ => return Err(AppError::NotFound(format!("user {}", member_id))),
+        }
+
+        let chat = sqlx::query_as(
+            r#"
+            UPDATE chats
+            SET members = CASE WHEN $2 = ANY(members) THEN members ELSE array_append(members, $2) END
+            WHERE id = $1
+            RETURNING id, ws_id, name, type, members, admins, pin_limit, topic, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(member_id)
+        .fetch_one(pool)
+        .await?;
+        Ok(chat)
+    }
+
+    /// Remove a member from the chat, admins can remove anyone and members themselves.
+    pub async fn remove_member(
+        id: i64,
+        member_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let chat = match member_id == user_id {
+            true => Self::get_for_member(id, user_id, pool).await?,
+            false => Self::get_for_admin(id, user_id, pool).await?,
+        };
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::InvalidInput(format!(
+                "cannot leave the direct chat {}",
+                id
+            )));
+        }
+        if !chat.is_member(member_id) {
+            return Err(AppError::NotFound(format!(
+                "user {} in chat {}",
+                member_id, id
+            )));
+        }
+
+        let chat = sqlx::query_as(
+            r#"
+            UPDATE chats
+            SET members = array_remove(members, $2), admins = array_remove(admins, $2)
+            WHERE id = $1
+            RETURNING id, ws_id, name, type, members, admins, pin_limit, topic, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(member_id)
+        .fetch_one(pool)
+        .await?;
+        Ok(chat)
+    }
+
+    /// The direct chat of the user with another user of the workspace, it is created the first
+    /// time. Users who blocked each other cannot talk, guests only talk in the chats they were
+    /// invited to.
+    pub async fn open_direct(
+        input: &CreateDirectChat,
+        user: &User,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let other_id = input.user_id;
+        if other_id == user.id {
+            return Err(AppError::InvalidInput(
+                "cannot open a direct chat with yourself".to_string(),
+            ));
+        }
+        if User::get_role(user.id, user.ws_id, pool).await? == Some(WorkspaceRole::Guest) {
+            return Err(AppError::PermissionDenied(format!(
+                "guest {} cannot open direct chats",
+                user.id
+            )));
+        }
+        match User::find_by_id(other_id, pool).await? {
+            Some(other) if other.ws_id == user.ws_id && other.role != WorkspaceRole::Guest => {}
+            _ => return Err(AppError::NotFound(format!("user {}", other_id))),
+        }
+        if UserBlock::between(user.id, other_id, pool).await? {
+            return Err(AppError::PermissionDenied(format!(
+                "user {} cannot message user {}",
+                user.id, other_id
+            )));
+        }
+
+        let (a, b) = (user.id.min(other_id), user.id.max(other_id));
+        let chat = sqlx::query_as(
+            r#"
+            WITH existing AS (
+                SELECT id, ws_id, name, type, members, admins, pin_limit, topic, created_at
+                FROM chats
+                WHERE type = 'single' AND members @> ARRAY[$2, $3]::bigint[]
+                    AND cardinality(members) = 2
+                LIMIT 1
+            ), created AS (
+                INSERT INTO chats (ws_id, name, type, members, admins)
+                SELECT $1, 'dm-' || $2 || '-' || $3, 'single', ARRAY[$2, $3]::bigint[], '{}'
+                WHERE NOT EXISTS (SELECT 1 FROM existing)
+                ON CONFLICT (name) DO UPDATE SET members = EXCLUDED.members
+                RETURNING id, ws_id, name, type, members, admins, pin_limit, topic, created_at
+            )
+            SELECT * FROM existing
+            UNION ALL
+            SELECT * FROM created
+            "#,
+        )
+        .bind(user.ws_id)
+        .bind(a)
+        .bind(b)
+        .fetch_one(pool)
+        .await?;
+        Ok(chat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn chat_members_should_be_managed_by_admins() -> Result<()> {
+        let db = create_test_pool().await?;
+        let ret = Chat::add_member(1, 4, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let chat = Chat::add_member(1, 4, 1, &db).await?;
+        assert_eq!(chat.members, [1, 2, 3, 4]);
+        let chat = Chat::add_member(1, 4, 1, &db).await?;
+        assert_eq!(chat.members, [1, 2, 3, 4]);
+
+        let ret = Chat::remove_member(1, 3, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let chat = Chat::remove_member(1, 2, 2, &db).await?;
+        assert_eq!(chat.members, [1, 3, 4]);
+
+        let ret = Chat::remove_member(2, 2, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn direct_chats_should_respect_blocks() -> Result<()> {
+        let db = create_test_pool().await?;
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let dave = User::new(4, "Dave", "dave@acme.org");
+        let input = CreateDirectChat { user_id: 3 };
+        let chat = Chat::open_direct(&input, &bob, &db).await?;
+        assert_eq!(chat.id, 2);
+
+        let input = CreateDirectChat { user_id: 2 };
+        let chat = Chat::open_direct(&input, &dave, &db).await?;
+        assert_eq!(
+            (chat.r#type, chat.members.as_slice()),
+            (ChatType::Single, &[2, 4][..])
+        );
+        let input = CreateDirectChat { user_id: 4 };
+        assert_eq!(Chat::open_direct(&input, &bob, &db).await?.id, chat.id);
+
+        UserBlock::create(&bob, 1, &db).await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = CreateDirectChat { user_id: 2 };
+        let ret = Chat::open_direct(&input, &alice, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use sqlx::{PgConnection, PgPool};
+
+use crate::AppError;
+
+use super::{Chat, ListMessages, MentionKind, Message, UserBlock};
+
+impl Message {
+    /// Messages mentioning the user in the chats they still belong to, from newest to oldest.
+    pub async fn list_mentions(
+        input: &ListMessages,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        let (last_id, limit) = input.page();
+        let messages = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.parent_id, m.format, m.content, m.content_text,
+                m.attachments, m.created_at, m.updated_at, m.deleted_at
+            FROM message_mentions mm
+            JOIN messages m ON m.id = mm.message_id
+            JOIN chats c ON c.id = m.chat_id
+            WHERE mm.user_id = $1
+                AND $1 = ANY(c.members)
+                AND m.deleted_at IS NULL
+                AND m.id < $2
+            ORDER BY m.id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(last_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+        Ok(messages)
+    }
+}
+
+/// Find the users mentioned in the content. `@here` and `@channel` mention every member of the
+/// chat, other mentions are the full name of a user without spaces, e.g. `@alice`.
+/// Mentioning a user outside of the chat is rejected, and so is `@channel` from non admins.
+pub(super) async fn resolve_mentions(
+    content: &str,
+    chat: &Chat,
+    user_id: i64,
+    pool: &PgPool,
+) -> Result<Vec<(i64, MentionKind)>, AppError> {
+    let handles = parse_mentions(content);
+    if handles.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut mentions: HashMap<i64, MentionKind> = HashMap::new();
+    for kind in [MentionKind::Channel, MentionKind::Here] {
+        let handle = match kind {
+            MentionKind::Channel => "channel",
+            _ => "here",
+        };
+        if !handles.iter().any(|h| h == handle) {
+            continue;
+        }
+        if kind == MentionKind::Channel && !chat.is_admin(user_id) {
+            return Err(AppError::PermissionDenied(format!(
+                "only admins can mention @channel in chat {}",
+                chat.id
+            )));
+        }
+        for member in &chat.members {
+            mentions.entry(*member).or_insert(kind);
+        }
+    }
+
+    let users: Vec<(i64, String)> = sqlx::query_as(
+        r#"
+        SELECT id, lower(regexp_replace(fullname, '\s', '', 'g')) AS handle
+        FROM users
+        WHERE ws_id = $1 AND lower(regexp_replace(fullname, '\s', '', 'g')) = ANY($2)
+        "#,
+    )
+    .bind(chat.ws_id)
+    .bind(&handles)
+    .fetch_all(pool)
+    .await?;
+    for (id, handle) in users {
+        if !chat.is_member(id) {
+            return Err(AppError::InvalidInput(format!(
+                "@{} is not a member of chat {}",
+                handle, chat.id
+            )));
+        }
+        mentions.insert(id, MentionKind::User);
+    }
+
+    mentions.remove(&user_id);
+    // users who blocked the sender are not notified
+    for id in UserBlock::blocked_by(user_id, pool).await? {
+        mentions.remove(&id);
+    }
+    let mut mentions: Vec<_> = mentions.into_iter().collect();
+    mentions.sort_by_key(|(id, _)| *id);
+    Ok(mentions)
+}
+
+/// Replace the mentions of a message.
+pub(super) async fn save_mentions(
+    message_id: i64,
+    mentions: &[(i64, MentionKind)],
+    conn: &mut PgConnection,
+) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM message_mentions WHERE message_id = $1")
+        .bind(message_id)
+        .execute(&mut *conn)
+        .await?;
+    if mentions.is_empty() {
+        return Ok(());
+    }
+
+    let (users, kinds): (Vec<i64>, Vec<MentionKind>) = mentions.iter().copied().unzip();
+    sqlx::query(
+        r#"
+        INSERT INTO message_mentions (message_id, user_id, kind)
+        SELECT $1, * FROM unnest($2::bigint[], $3::mention_kind[])
+        "#,
+    )
+    .bind(message_id)
+    .bind(&users)
+    .bind(&kinds)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Lowercase handles after `@`, an `@` inside a word like an email address is not a mention.
+fn parse_mentions(content: &str) -> Vec<String> {
+    let mut handles: Vec<String> = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut chars = content.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let at_word_start = prev.is_none_or(|p| !p.is_alphanumeric() && p != '@');
+        prev = Some(c);
+        if c != '@' || !at_word_start {
+            continue;
+        }
+        let start = i + c.len_utf8();
+        let mut end = start;
+        while let Some(&(j, c)) = chars.peek() {
+            if !(c.is_alphanumeric() || matches!(c, '_' | '.' | '-')) {
+                break;
+            }
+            end = j + c.len_utf8();
+            prev = Some(c);
+            chars.next();
+        }
+        let handle = content[start..end]
+            .trim_end_matches(['.', '-'])
+            .to_lowercase();
+        if !handle.is_empty() && !handles.contains(&handle) {
+            handles.push(handle);
+        }
+    }
+    handles
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::{CreateMessage, UpdateMessage},
+        utils::create_test_pool,
+    };
+
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn parse_mentions_should_work() {
+        assert_eq!(
+            parse_mentions("@Bob, ask @charlie. cc @here"),
+            vec!["bob", "charlie", "here"]
+        );
+        assert_eq!(
+            parse_mentions("mail bob@acme.org or @@x"),
+            Vec::<String>::new()
+        );
+        assert_eq!(parse_mentions("@bob @BOB"), vec!["bob"]);
+    }
+
+    #[tokio::test]
+    async fn mentions_should_be_listed_in_inbox() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateMessage {
+            content: "@bob please review".to_string(),
+            ..Default::default()
+        };
+        let message = Message::create(&input, 1, 1, &db).await?;
+
+        // alice is an admin of chat 1
+        let input = CreateMessage {
+            content: "@channel release is out".to_string(),
+            ..Default::default()
+        };
+        let all = Message::create(&input, 1, 1, &db).await?;
+
+        let messages = Message::list_mentions(&ListMessages::default(), 2, &db).await?;
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![all.id, message.id]);
+        let messages = Message::list_mentions(&ListMessages::default(), 3, &db).await?;
+        assert_eq!(messages.len(), 1);
+        assert!(Message::list_mentions(&ListMessages::default(), 1, &db)
+            .await?
+            .is_empty());
+
+        let input = UpdateMessage {
+            content: "never mind".to_string(),
+            ..Default::default()
+        };
+        Message::update(message.id, &input, 1, &db).await?;
+        let messages = Message::list_mentions(&ListMessages::default(), 2, &db).await?;
+        assert_eq!(messages.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mentions_should_be_restricted() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateMessage {
+            content: "@channel hey".to_string(),
+            ..Default::default()
+        };
+        let ret = Message::create(&input, 1, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let input = CreateMessage {
+            content: "hi @dave".to_string(),
+            ..Default::default()
+        };
+        let ret = Message::create(&input, 1, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        // unknown handles are plain text
+        let input = CreateMessage {
+            content: "working @home today".to_string(),
+            ..Default::default()
+        };
+        Message::create(&input, 1, 2, &db).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,640 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, PgConnection, PgPool};
+
+use crate::{filters::FilterOutcome, AppError};
+
+use super::{
+    content_filter::{filter_content, report_filter_hits},
+    format::Rendered,
+    mention::{resolve_mentions, save_mentions},
+    reaction::attach_reactions,
+    thread::follow_thread,
+    Attachment, Chat, ChatType, MentionKind, Message, MessageEdit, MessageFormat, UserBlock,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateMessage {
+    pub content: String,
+    #[serde(default)]
+    pub format: MessageFormat,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// reply in the thread of this message
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    /// send the message later instead of now
+    #[serde(default)]
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListMessages {
+    /// only messages older than this id are returned
+    pub last_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl ListMessages {
+    /// The cursor and the page size of a listing.
+    pub(super) fn page(&self) -> (i64, i64) {
+        let last_id = self.last_id.unwrap_or(i64::MAX);
+        let limit = self
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        (last_id, limit)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateMessage {
+    pub content: String,
+    /// keep the current format when absent
+    #[serde(default)]
+    pub format: Option<MessageFormat>,
+    /// keep the current attachments when absent
+    #[serde(default)]
+    pub attachments: Option<Vec<Attachment>>,
+}
+
+/// A message which passed the checks and the filters of its chat, ready to be stored.
+pub(super) struct NewMessage<'a> {
+    input: &'a CreateMessage,
+    user_id: i64,
+    chat: Chat,
+    filtered: FilterOutcome,
+    rendered: Rendered,
+    mentions: Vec<(i64, MentionKind)>,
+}
+
+impl NewMessage<'_> {
+    /// Store the message on the connection, usually a transaction.
+    pub(super) async fn insert(&self, conn: &mut PgConnection) -> Result<Message, AppError> {
+        let input = self.input;
+        let message: Message = sqlx::query_as(
+            r#"
+            INSERT INTO messages (chat_id, sender_id, parent_id, format, content, content_text, attachments)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, chat_id, sender_id, parent_id, format, content, content_text, attachments,
+                created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(self.chat.id)
+        .bind(self.user_id)
+        .bind(input.parent_id)
+        .bind(input.format)
+        .bind(&self.rendered.content)
+        .bind(&self.rendered.text)
+        .bind(Json(&input.attachments))
+        .fetch_one(&mut *conn)
+        .await?;
+        if let Some(parent_id) = input.parent_id {
+            follow_thread(parent_id, self.user_id, conn).await?;
+        }
+        save_mentions(message.id, &self.mentions, conn).await?;
+        // the draft has been sent
+        sqlx::query(
+            r#"
+            DELETE FROM message_drafts
+            WHERE user_id = $1 AND chat_id = $2 AND COALESCE(parent_id, 0) = COALESCE($3, 0)
+            "#,
+        )
+        .bind(self.user_id)
+        .bind(self.chat.id)
+        .bind(input.parent_id)
+        .execute(&mut *conn)
+        .await?;
+        Ok(message)
+    }
+
+    /// Report what the filters found once the message is committed.
+    pub(super) async fn report(&self, message: &Message, pool: &PgPool) -> Result<(), AppError> {
+        report_filter_hits(&self.filtered, message, self.chat.ws_id, pool).await
+    }
+}
+
+impl Message {
+    pub async fn create(
+        input: &CreateMessage,
+        chat_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let new = Self::check_new(input, chat_id, user_id, pool).await?;
+        let mut tx = pool.begin().await?;
+        let message = new.insert(&mut tx).await?;
+        tx.commit().await?;
+        new.report(&message, pool).await?;
+        Ok(message)
+    }
+
+    /// Check the sender can send the message to the chat and run the filters on it.
+    pub(super) async fn check_new<'a>(
+        input: &'a CreateMessage,
+        chat_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<NewMessage<'a>, AppError> {
+        let chat = Chat::get_for_member(chat_id, user_id, pool).await?;
+        check_blocks(&chat, user_id, pool).await?;
+        if let Some(parent_id) = input.parent_id {
+            Self::get_thread_root(parent_id, chat_id, pool).await?;
+        }
+        let (filtered, rendered) = filter_content(
+            &input.content,
+            input.format,
+            &input.attachments,
+            &chat,
+            user_id,
+            pool,
+        )
+        .await?;
+        let mentions = resolve_mentions(&rendered.text, &chat, user_id, pool).await?;
+        Ok(NewMessage {
+            input,
+            user_id,
+            chat,
+            filtered,
+            rendered,
+            mentions,
+        })
+    }
+
+    /// List top level messages of a chat from newest to oldest, deleted messages are kept as
+    /// tombstones. Replies are not listed here, only their count and the time of the last one.
+    /// Messages of the users the user blocked are left out.
+    pub async fn list(
+        input: &ListMessages,
+        chat_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        Chat::get_for_member(chat_id, user_id, pool).await?;
+
+        let (last_id, limit) = input.page();
+        let mut messages: Vec<Self> = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.parent_id, m.format, m.content, m.content_text,
+                m.attachments, m.created_at, m.updated_at, m.deleted_at, t.reply_count,
+                t.last_reply_at
+            FROM messages m
+            LEFT JOIN LATERAL (
+                SELECT count(*) AS reply_count, max(r.created_at) AS last_reply_at
+                FROM messages r
+                WHERE r.parent_id = m.id AND r.deleted_at IS NULL
+            ) t ON true
+            WHERE m.chat_id = $1 AND m.parent_id IS NULL AND m.id < $2
+                AND NOT EXISTS (
+                    SELECT 1 FROM user_blocks b WHERE b.user_id = $4 AND b.blocked_id = m.sender_id
+                )
+            ORDER BY m.id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(chat_id)
+        .bind(last_id)
+        .bind(limit)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        attach_reactions(&mut messages, user_id, pool).await?;
+        Ok(messages)
+    }
+
+    pub async fn get_by_id(id: i64, pool: &PgPool) -> Result<Option<Self>, AppError> {
+        sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, parent_id, format, content, content_text, attachments,
+                created_at, updated_at, deleted_at
+            FROM messages
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Load a message and lock it until the end of the transaction, so that it is not
+    /// changed between the checks and the update.
+    pub(super) async fn lock(id: i64, conn: &mut PgConnection) -> Result<Option<Self>, AppError> {
+        sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, parent_id, format, content, content_text, attachments,
+                created_at, updated_at, deleted_at
+            FROM messages
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Lock a message which is not deleted yet.
+    async fn lock_alive(id: i64, conn: &mut PgConnection) -> Result<Self, AppError> {
+        match Self::lock(id, conn).await? {
+            Some(message) if message.deleted_at.is_none() => Ok(message),
+            _ => Err(AppError::NotFound(format!("message {}", id))),
+        }
+    }
+
+    /// Only the sender can edit a message, the previous version goes to the edit history.
+    pub async fn update(
+        id: i64,
+        input: &UpdateMessage,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let mut tx = pool.begin().await?;
+        let message = Self::lock_alive(id, &mut tx).await?;
+        if message.sender_id != user_id {
+            return Err(AppError::PermissionDenied(format!(
+                "user {} cannot edit message {}",
+                user_id, id
+            )));
+        }
+        let format = input.format.unwrap_or(message.format);
+        let attachments = input.attachments.as_ref().unwrap_or(&message.attachments);
+        let chat = Chat::get_for_member(message.chat_id, user_id, pool).await?;
+        check_blocks(&chat, user_id, pool).await?;
+        let (filtered, rendered) =
+            filter_content(&input.content, format, attachments, &chat, user_id, pool).await?;
+        let mentions = resolve_mentions(&rendered.text, &chat, user_id, pool).await?;
+
+        archive(&message, user_id, &mut tx).await?;
+        save_mentions(id, &mentions, &mut tx).await?;
+        let message: Message = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET format = $2, content = $3, content_text = $4, attachments = $5, updated_at = now()
+            WHERE id = $1
+            RETURNING id, chat_id, sender_id, parent_id, format, content, content_text, attachments,
+                created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(id)
+        .bind(format)
+        .bind(&rendered.content)
+        .bind(&rendered.text)
+        .bind(Json(attachments))
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        report_filter_hits(&filtered, &message, chat.ws_id, pool).await?;
+        Ok(message)
+    }
+
+    /// Soft delete a message, the content is cleared and the row stays as a tombstone.
+    /// The sender and the chat admins are allowed to do that.
+    pub async fn delete(id: i64, user_id: i64, pool: &PgPool) -> Result<Self, AppError> {
+        let mut tx = pool.begin().await?;
+        let message = Self::lock_alive(id, &mut tx).await?;
+        let chat = Chat::get_by_id(message.chat_id, pool).await?;
+        let is_admin = chat.is_some_and(|chat| chat.is_admin(user_id));
+        if message.sender_id != user_id && !is_admin {
+            return Err(AppError::PermissionDenied(format!(
+                "user {} cannot delete message {}",
+                user_id, id
+            )));
+        }
+        let message = Self::tombstone(&message, user_id, &mut tx).await?;
+        tx.commit().await?;
+        Ok(message)
+    }
+
+    /// Clear the content of a locked message, the previous version goes to the edit history.
+    pub(super) async fn tombstone(
+        message: &Self,
+        user_id: i64,
+        conn: &mut PgConnection,
+    ) -> Result<Self, AppError> {
+        archive(message, user_id, conn).await?;
+        let message = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET content = '', content_text = '', attachments = '[]', deleted_at = now()
+            WHERE id = $1
+            RETURNING id, chat_id, sender_id, parent_id, format, content, content_text, attachments,
+                created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(message.id)
+        .fetch_one(conn)
+        .await?;
+        Ok(message)
+    }
+
+    /// Previous versions of a message from newest to oldest, visible to chat admins only.
+    pub async fn list_edits(
+        id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<MessageEdit>, AppError> {
+        let message = Self::get_by_id(id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("message {}", id)))?;
+        let chat = Chat::get_by_id(message.chat_id, pool).await?;
+        if !chat.is_some_and(|chat| chat.is_admin(user_id)) {
+            return Err(AppError::PermissionDenied(format!(
+                "user {} cannot see the edit history of message {}",
+                user_id, id
+            )));
+        }
+
+        let edits = sqlx::query_as(
+            r#"
+            SELECT id, message_id, format, content, attachments, edited_by, created_at
+            FROM message_edits
+            WHERE message_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        Ok(edits)
+    }
+}
+
+/// Users who blocked each other cannot message each other in their direct chat. Group chats
+/// stay open to everyone of them, the blocker does not get the messages of the blocked user
+/// listed nor is notified of its mentions.
+async fn check_blocks(chat: &Chat, user_id: i64, pool: &PgPool) -> Result<(), AppError> {
+    if chat.r#type != ChatType::Single {
+        return Ok(());
+    }
+    for other in chat.members.iter().filter(|id| **id != user_id) {
+        if UserBlock::between(user_id, *other, pool).await? {
+            return Err(AppError::PermissionDenied(format!(
+                "user {} cannot message user {}",
+                user_id, other
+            )));
+        }
+    }
+    Ok(())
+}
+
+async fn archive(message: &Message, user_id: i64, conn: &mut PgConnection) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO message_edits (message_id, format, content, attachments, edited_by)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(message.id)
+    .bind(message.format)
+    .bind(&message.content)
+    .bind(Json(&message.attachments))
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::{User, Workspace},
+        utils::create_test_pool,
+    };
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn create_and_list_messages_should_work() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            ..Default::default()
+        };
+        let message = Message::create(&input, 1, 1, &db).await?;
+        assert_eq!(message.content, "hello");
+
+        let ret = Message::create(&input, 2, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let input = ListMessages {
+            last_id: None,
+            limit: Some(2),
+        };
+        let messages = Message::list(&input, 1, 1, &db).await?;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].id, message.id);
+
+        let input = ListMessages {
+            last_id: Some(messages[1].id),
+            limit: None,
+        };
+        let messages = Message::list(&input, 1, 1, &db).await?;
+        assert_eq!(messages.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blocked_users_should_not_reach_each_other() -> Result<()> {
+        let db = create_test_pool().await?;
+        let charlie = User::new(3, "Charlie", "charlie@acme.org");
+        let input = CreateMessage {
+            content: "still there?".to_string(),
+            ..Default::default()
+        };
+        let sent = Message::create(&input, 2, 2, &db).await?;
+        UserBlock::create(&charlie, 2, &db).await?;
+
+        // bob and charlie only share the direct chat 2 and the group chat 1
+        let ret = Message::create(&input, 2, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = Message::create(&input, 2, 3, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let input = UpdateMessage {
+            content: "you there?".to_string(),
+            ..Default::default()
+        };
+        let ret = Message::update(sent.id, &input, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let input = CreateMessage {
+            content: "@charlie @alice see above".to_string(),
+            ..Default::default()
+        };
+        let message = Message::create(&input, 1, 2, &db).await?;
+        let mentioned: Vec<i64> =
+            sqlx::query_scalar("SELECT user_id FROM message_mentions WHERE message_id = $1")
+                .bind(message.id)
+                .fetch_all(&*db)
+                .await?;
+        assert_eq!(mentioned, vec![1]);
+
+        // charlie does not get the messages of bob listed in their group chat, alice does
+        let input = ListMessages::default();
+        let listed = Message::list(&input, 1, 3, &db).await?;
+        assert!(listed.iter().all(|m| m.sender_id != 2));
+        let listed = Message::list(&input, 1, 1, &db).await?;
+        assert_eq!(listed[0].id, message.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn messages_should_go_through_content_filters() -> Result<()> {
+        let db = create_test_pool().await?;
+        let config = serde_json::from_value(serde_json::json!({
+            "words": { "patterns": ["darn"], "action": "redact" },
+            "spam": { "max_repeats": 2, "window_secs": 60, "action": "reject" },
+        }))?;
+        Workspace::update_content_filters(1, &config, 1, &db).await?;
+
+        let input = CreateMessage {
+            content: "darn it".to_string(),
+            ..Default::default()
+        };
+        let message = Message::create(&input, 1, 2, &db).await?;
+        assert_eq!(message.content, "**** it");
+        Message::create(&input, 1, 2, &db).await?;
+        let ret = Message::create(&input, 1, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        let input = UpdateMessage {
+            content: "Darn, Alice".to_string(),
+            ..Default::default()
+        };
+        let message = Message::update(2, &input, 2, &db).await?;
+        assert_eq!(message.content, "****, Alice");
+        let actions: Vec<String> = sqlx::query_scalar(
+            "SELECT target_type FROM audit_events WHERE action = 'message.filter' ORDER BY id",
+        )
+        .fetch_all(&*db)
+        .await?;
+        // the rejected message was redacted before the spam filter refused it
+        assert_eq!(actions, ["message", "message", "chat", "chat", "message"]);
+
+        // markup does not get a word past the filters, the text it renders to is checked too
+        let input = CreateMessage {
+            content: "**d**arn it".to_string(),
+            format: MessageFormat::Markdown,
+            ..Default::default()
+        };
+        let ret = Message::create(&input, 1, 3, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(e)) if e.contains("words")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_should_keep_history() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = UpdateMessage {
+            content: "Hello, Alice".to_string(),
+            ..Default::default()
+        };
+        let ret = Message::update(2, &input, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let message = Message::update(2, &input, 2, &db).await?;
+        assert_eq!(message.content, "Hello, Alice");
+        assert!(message.updated_at.is_some());
+
+        let ret = Message::list_edits(2, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let edits = Message::list_edits(2, 1, &db).await?;
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].content, "Hi, Alice!");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_should_leave_tombstone() -> Result<()> {
+        let db = create_test_pool().await?;
+        let ret = Message::delete(3, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // alice is the admin of chat 1
+        let message = Message::delete(3, 1, &db).await?;
+        assert_eq!(message.content, "");
+        assert!(message.deleted_at.is_some());
+
+        let ret = Message::delete(3, 3, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let message = Message::get_by_id(3, &db).await?.expect("message exists");
+        assert!(message.deleted_at.is_some());
+        let edits = Message::list_edits(3, 1, &db).await?;
+        assert_eq!(edits[0].content, "Good morning");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_should_wait_for_a_concurrent_delete() -> Result<()> {
+        let db = create_test_pool().await?;
+        let mut tx = db.begin().await?;
+        let message = Message::lock_alive(2, &mut tx).await?;
+        Message::tombstone(&message, 1, &mut tx).await?;
+
+        let pool = (*db).clone();
+        let update = tokio::spawn(async move {
+            let input = UpdateMessage {
+                content: "Hello, Alice".to_string(),
+                ..Default::default()
+            };
+            Message::update(2, &input, 2, &pool).await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!update.is_finished());
+
+        // the deleted message is not edited back to life
+        tx.commit().await?;
+        let ret = update.await?;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        let message = Message::get_by_id(2, &db).await?.expect("message exists");
+        assert_eq!(message.content, "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn message_attachments_should_be_stored() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateMessage {
+            content: "".to_string(),
+            attachments: vec![
+                Attachment::Image {
+                    url: "/files/1/0a1/b2c.png".to_string(),
+                    width: Some(800),
+                    height: Some(600),
+                    alt: Some("screenshot".to_string()),
+                    blurhash: None,
+                },
+                Attachment::Code {
+                    language: Some("rust".to_string()),
+                    content: "fn main() {}".to_string(),
+                },
+            ],
+            ..Default::default()
+        };
+        let message = Message::create(&input, 1, 1, &db).await?;
+        let stored = Message::get_by_id(message.id, &db)
+            .await?
+            .expect("message exists");
+        assert_eq!(stored.attachments, input.attachments);
+        assert_eq!(stored.format, MessageFormat::Plain);
+
+        // editing the content keeps the attachments
+        let input = UpdateMessage {
+            content: "_look_".to_string(),
+            format: Some(MessageFormat::Markdown),
+            ..Default::default()
+        };
+        let message = Message::update(message.id, &input, 1, &db).await?;
+        assert_eq!(message.attachments.len(), 2);
+        assert_eq!(message.content_text, "look");
+        let edits = Message::list_edits(message.id, 1, &db).await?;
+        assert_eq!(edits[0].attachments.len(), 2);
+        Ok(())
+    }
+}
@@ -2,16 +2,621 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+mod audit;
+mod block;
+mod bot;
+mod chat;
+mod command;
+mod content_filter;
+mod draft;
+mod file;
+mod format;
+mod member;
+mod mention;
+mod message;
+mod moderation;
+mod oidc;
+mod passkey;
+mod pin;
+mod profile;
+mod quota;
+mod reaction;
+mod receipt;
+mod retention;
+mod role;
+mod schedule;
+mod search;
+mod thread;
 mod user;
+mod webhook;
+mod workspace;
+
+pub use audit::{AuditAction, AuditContext, ListAuditEvents, NewAuditEvent};
+pub use bot::{CreateBot, CreateBotToken, CreateIncomingWebhook, BOT_TOKEN_PREFIX};
+pub use command::CreateSlashCommand;
+pub use draft::{DeleteDraft, GetDraft, SaveDraft};
+pub use file::{CompleteUpload, GetFile, PresignUpload};
+pub use member::{AddMember, CreateDirectChat};
+pub use message::{CreateMessage, ListMessages, UpdateMessage};
+pub use moderation::{CreateReport, ListReports, ResolveReport};
+pub use passkey::{AuthenticatePasskey, RegisterPasskey, UpdateSecondFactor};
+pub use pin::UpdatePinLimit;
+pub use profile::{ListUsers, UpdateProfile, VerifyEmail};
+pub use receipt::MarkRead;
+pub use retention::{CreateLegalHold, UpdateChatRetention, UpdateRetention};
+pub use role::UpdateRole;
+pub use schedule::UpdateScheduledMessage;
+pub use search::SearchMessages;
+pub use webhook::{CreateWebhook, DueDelivery};
+pub use workspace::UpdateSearchConfig;
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize, Eq, PartialEq)]
 pub struct User {
     pub id: i64,
+    pub ws_id: i64,
     pub fullname: String,
     pub email: String,
     #[sqlx(default)]
     #[serde(skip)]
     pub password_hash: Option<String>,
+    /// bots authenticate with api tokens and only see the chats they were added to
+    #[sqlx(default)]
+    #[serde(default)]
+    pub is_bot: bool,
+    /// carried in the jwt, endpoints needing a role look up the current one
+    #[sqlx(default)]
+    #[serde(default)]
+    pub role: WorkspaceRole,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Roles are ordered, a role can do everything the roles below it can.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, sqlx::Type, Serialize, Deserialize,
+)]
+#[sqlx(type_name = "workspace_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceRole {
+    /// only sees the chats it was invited to
+    Guest,
+    #[default]
+    Member,
+    Admin,
+    Owner,
+}
+
+/// The profile of the current user.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserProfile {
+    pub id: i64,
+    pub ws_id: i64,
+    pub fullname: String,
+    pub email: String,
+    /// waiting for verification before it replaces `email`
+    pub pending_email: Option<String>,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub title: Option<String>,
+    pub timezone: Option<String>,
+    pub status_text: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What other users can see about a user, the email is only visible to admins.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PublicUser {
+    pub id: i64,
+    pub fullname: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub title: Option<String>,
+    pub timezone: Option<String>,
+    pub status_text: Option<String>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Workspace {
+    pub id: i64,
+    pub name: String,
+    pub owner_id: Option<i64>,
+    /// text search configuration of messages, e.g. `simple` or `english`
+    pub search_config: String,
+    /// days messages are kept, forever when absent
+    pub retention_days: Option<i32>,
+    /// whether expired messages are archived before they are deleted
+    pub retention_archive: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// How long the messages of a chat are kept.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChatRetention {
+    pub chat_id: i64,
+    /// the setting of the chat, the one of the workspace is used when absent
+    pub retention_days: Option<i32>,
+    /// days after which messages are purged, never when absent
+    pub effective_days: Option<i32>,
+}
+
+/// A security relevant action, e.g. a signin or a removed member.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub ws_id: Option<i64>,
+    pub actor_id: Option<i64>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<i64>,
+    pub metadata: serde_json::Value,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Suspends purging the messages of a chat or sent by a user until it is released.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LegalHold {
+    pub id: i64,
+    pub ws_id: i64,
+    pub chat_id: Option<i64>,
+    pub user_id: Option<i64>,
+    pub reason: String,
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+    pub released_at: Option<DateTime<Utc>>,
+}
+
+/// A user blocked by another one.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserBlock {
+    pub user_id: i64,
+    pub blocked_id: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An account of an identity provider linked to a user.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserIdentity {
+    pub provider: String,
+    /// the stable id of the account at the provider
+    pub subject: String,
+    pub user_id: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A login started at an identity provider, the state comes back with the user.
+#[derive(Debug, Clone, FromRow, PartialEq, Eq)]
+pub struct OidcLogin {
+    pub state: String,
+    pub provider: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A passkey of a user.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WebauthnCredential {
+    pub id: i64,
+    pub user_id: i64,
+    /// base64url encoded
+    pub credential_id: String,
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "webauthn_ceremony", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WebauthnCeremony {
+    Registration,
+    Authentication,
+}
+
+/// A challenge sent to an authenticator, it has to sign it back once.
+#[derive(Debug, Clone, FromRow, PartialEq, Eq)]
+pub struct WebauthnChallenge {
+    pub challenge: String,
+    pub ceremony: WebauthnCeremony,
+    /// none for passwordless sign ins, the passkey tells who the user is
+    pub user_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What an admin did about a reported message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "moderation_action", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    /// nothing was wrong with the message
+    Dismiss,
+    DeleteMessage,
+    /// send a warning to the sender
+    Warn,
+    /// the sender cannot sign in for a while
+    Suspend,
+}
+
+/// A message reported to the admins of the workspace, open until one of them resolves it.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MessageReport {
+    pub id: i64,
+    pub ws_id: i64,
+    pub message_id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    /// the content when it was reported
+    pub content: String,
+    /// none when a content filter flagged the message
+    pub reporter_id: Option<i64>,
+    pub reason: String,
+    pub action: Option<ModerationAction>,
+    pub note: Option<String>,
+    pub resolved_by: Option<i64>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserWarning {
+    pub id: i64,
+    pub ws_id: i64,
+    pub user_id: i64,
+    pub report_id: Option<i64>,
+    pub reason: String,
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "chat_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatType {
+    Single,
+    Group,
+    PrivateChannel,
+    PublicChannel,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Chat {
+    pub id: i64,
+    pub ws_id: i64,
+    pub name: String,
+    pub r#type: ChatType,
+    pub members: Vec<i64>,
+    pub admins: Vec<i64>,
+    /// how many messages can be pinned
+    pub pin_limit: i32,
+    pub topic: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A chat in the chat list of a user, with what clients need to render badges.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChatOverview {
+    #[serde(flatten)]
+    pub chat: Chat,
+    pub unread_count: i64,
+    pub last_read_message_id: Option<i64>,
+    pub last_message: Option<Message>,
+    /// draft of the user in the chat, drafts of threads are not included
+    pub draft: Option<Draft>,
+}
+
+/// An unsent message, kept on the server so it follows the user across devices.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Draft {
+    pub chat_id: i64,
+    pub parent_id: Option<i64>,
+    pub content: String,
+    pub version: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Message {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub parent_id: Option<i64>,
+    pub format: MessageFormat,
+    pub content: String,
+    /// the content rendered as plain text
+    pub content_text: String,
+    #[sqlx(json)]
+    pub attachments: Vec<Attachment>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// number of replies in the thread, only filled in history listings
+    #[sqlx(default)]
+    pub reply_count: i64,
+    #[sqlx(default)]
+    pub last_reply_at: Option<DateTime<Utc>>,
+    /// aggregated reactions, only filled in history listings
+    #[sqlx(skip)]
+    pub reactions: Vec<Reaction>,
+    /// a command reply only the caller sees, it is never stored
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ephemeral: bool,
+}
+
+/// How the content of a message is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "message_format", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    #[default]
+    Plain,
+    Markdown,
+}
+
+/// Structured content attached to a message, stored as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Attachment {
+    File {
+        url: String,
+        name: String,
+        size: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mime: Option<String>,
+    },
+    /// images migrated from the former `images` column have no dimensions
+    Image {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        width: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        height: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        alt: Option<String>,
+        /// a placeholder shown while the image is loading
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        blurhash: Option<String>,
+    },
+    Code {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+        content: String,
+    },
+    Link {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        image_url: Option<String>,
+    },
+}
+
+/// A file upload sent straight to the storage, until it is completed.
+#[derive(Debug, Clone, FromRow, PartialEq, Eq)]
+pub struct FileUpload {
+    pub key: String,
+    pub ws_id: i64,
+    pub owner_id: i64,
+    pub name: String,
+    pub size: i64,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A file uploaded to a workspace, stored under the sha256 of its content. Files uploaded
+/// straight to the storage get a random hash, the server never sees their content.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChatFile {
+    pub id: i64,
+    pub ws_id: i64,
+    pub owner_id: i64,
+    pub hash: String,
+    pub ext: String,
+    pub name: String,
+    pub mime: String,
+    pub size: i64,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub blurhash: Option<String>,
+    /// sizes of the generated thumbnails
+    pub thumbnails: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The files a user uploaded, `quota` is the user's own or the default of the config.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserStorage {
+    pub user_id: i64,
+    pub fullname: String,
+    pub email: String,
+    pub files: i64,
+    pub bytes: i64,
+    pub quota: i64,
+}
+
+/// The storage used by a workspace and each of its users.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WorkspaceStorage {
+    pub ws_id: i64,
+    pub files: i64,
+    pub bytes: i64,
+    pub quota: i64,
+    pub users: Vec<UserStorage>,
+}
+
+/// Users reacted to a message with the same emoji.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i64,
+    /// whether the current user is one of them
+    pub reacted: bool,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct SearchHit {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    /// matched fragments of the content, highlighted with `<mark>`
+    pub snippet: String,
+}
+
+/// A message to be sent by the worker at `send_at`.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ScheduledMessage {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub parent_id: Option<i64>,
+    pub format: MessageFormat,
+    pub content: String,
+    #[sqlx(json)]
+    pub attachments: Vec<Attachment>,
+    pub send_at: DateTime<Utc>,
+    /// set when the delivery failed
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// How a user was mentioned in a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "mention_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MentionKind {
+    User,
+    Here,
+    Channel,
+}
+
+/// A previous version of a message, recorded before it was edited or deleted.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct MessageEdit {
+    pub id: i64,
+    pub message_id: i64,
+    pub format: MessageFormat,
+    pub content: String,
+    #[sqlx(json)]
+    pub attachments: Vec<Attachment>,
+    pub edited_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A url of a chat receiving signed chat events.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Webhook {
+    pub id: i64,
+    pub chat_id: i64,
+    pub url: String,
+    /// key of the `x-webhook-signature` header, only visible to chat admins
+    pub secret: String,
+    /// subscribed events, empty for all of them
+    pub events: Vec<String>,
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "webhook_delivery_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    /// ran out of attempts, a copy is kept in the dead letters
+    Dead,
+}
+
+/// An event sent to a webhook, with the outcome of the last attempt.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// A delivery given up on, until an admin retries it.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct WebhookDeadLetter {
+    pub id: i64,
+    pub delivery_id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A bot user, owned by the user who created it.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Bot {
+    pub id: i64,
+    pub ws_id: i64,
+    pub fullname: String,
+    pub owner_id: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An api token of a bot, sent as `Authorization: Bearer <token>`.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BotToken {
+    pub id: i64,
+    pub bot_id: i64,
+    pub name: String,
+    /// only returned when the token is issued
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// A url posting into a chat as its bot, `POST /api/hooks/<token>`.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IncomingWebhook {
+    pub id: i64,
+    pub chat_id: i64,
+    pub bot_id: i64,
+    pub name: String,
+    /// only returned when the webhook is created
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A command of a workspace answered by a bot, `/name args` in a message.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SlashCommand {
+    pub id: i64,
+    pub ws_id: i64,
+    pub name: String,
+    pub description: String,
+    pub bot_id: i64,
+    pub url: String,
+    /// key of the `x-command-signature` header, only returned when the command is registered
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub created_by: i64,
     pub created_at: DateTime<Utc>,
 }
 
@@ -44,9 +649,12 @@ impl User {
     pub fn new(id: i64, fullname: &str, email: &str) -> Self {
         Self {
             id,
+            ws_id: 1,
             fullname: fullname.to_string(),
             email: email.to_string(),
             password_hash: Default::default(),
+            is_bot: false,
+            role: WorkspaceRole::Member,
             created_at: Utc::now(),
         }
     }
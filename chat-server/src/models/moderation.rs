@@ -0,0 +1,410 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use super::{
+    Chat, Message, MessageReport, ModerationAction, User, UserWarning, Workspace, WorkspaceRole,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+const MAX_REASON_LEN: usize = 1000;
+const MAX_SUSPEND_DAYS: i32 = 3650;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateReport {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListReports {
+    /// resolved reports instead of the open ones
+    #[serde(default)]
+    pub resolved: bool,
+    /// only reports older than this id are returned
+    pub last_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveReport {
+    pub action: ModerationAction,
+    /// sent to the user with a warning, the reason of the report is used when absent
+    pub note: Option<String>,
+    /// how long the sender is suspended, until it is lifted when absent
+    pub suspend_days: Option<i32>,
+}
+
+impl MessageReport {
+    /// Report a message of one of the chats of the user to the admins of the workspace.
+    pub async fn create(
+        message_id: i64,
+        input: &CreateReport,
+        user: &User,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let reason = input.reason.trim();
+        if reason.is_empty() || reason.chars().count() > MAX_REASON_LEN {
+            return Err(AppError::InvalidInput(format!(
+                "the reason must have 1 to {} characters",
+                MAX_REASON_LEN
+            )));
+        }
+        let message = match Message::get_by_id(message_id, pool).await? {
+            Some(message) if message.deleted_at.is_none() => message,
+            _ => return Err(AppError::NotFound(format!("message {}", message_id))),
+        };
+        let chat = Chat::get_for_member(message.chat_id, user.id, pool).await?;
+        if message.sender_id == user.id {
+            return Err(AppError::InvalidInput(
+                "cannot report your own message".to_string(),
+            ));
+        }
+
+        let report: Option<Self> = sqlx::query_as(
+            r#"
+            INSERT INTO message_reports (ws_id, message_id, chat_id, sender_id, content,
+                reporter_id, reason)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (message_id, reporter_id) DO NOTHING
+            RETURNING id, ws_id, message_id, chat_id, sender_id, content, reporter_id, reason,
+                action, note, resolved_by, resolved_at, created_at
+            "#,
+        )
+        .bind(chat.ws_id)
+        .bind(message.id)
+        .bind(chat.id)
+        .bind(message.sender_id)
+        .bind(&message.content)
+        .bind(user.id)
+        .bind(reason)
+        .fetch_optional(pool)
+        .await?;
+        report.ok_or_else(|| {
+            AppError::InvalidInput(format!("message {} was already reported", message_id))
+        })
+    }
+
+    /// The moderation queue of the workspace, newest first, for its admins.
+    pub async fn list(
+        input: &ListReports,
+        user: &User,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        let ws = Workspace::ensure_admin(user.ws_id, user.id, pool).await?;
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let reports = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, message_id, chat_id, sender_id, content, reporter_id, reason,
+                action, note, resolved_by, resolved_at, created_at
+            FROM message_reports
+            WHERE ws_id = $1 AND (resolved_at IS NOT NULL) = $2 AND id < $3
+            ORDER BY id DESC
+            LIMIT $4
+            "#,
+        )
+        .bind(ws.id)
+        .bind(input.resolved)
+        .bind(input.last_id.unwrap_or(i64::MAX))
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+        Ok(reports)
+    }
+
+    /// Act on a report, the other open reports of the message are resolved with it.
+    pub async fn resolve(
+        id: i64,
+        input: &ResolveReport,
+        user: &User,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let ws = Workspace::ensure_admin(user.ws_id, user.id, pool).await?;
+        let report: Self = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, message_id, chat_id, sender_id, content, reporter_id, reason,
+                action, note, resolved_by, resolved_at, created_at
+            FROM message_reports
+            WHERE id = $1 AND ws_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(ws.id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("report {}", id)))?;
+        if report.resolved_at.is_some() {
+            return Err(AppError::InvalidInput(format!(
+                "report {} is already resolved",
+                id
+            )));
+        }
+        let note = input
+            .note
+            .as_deref()
+            .map(str::trim)
+            .filter(|n| !n.is_empty());
+
+        match input.action {
+            ModerationAction::Dismiss => {}
+            ModerationAction::DeleteMessage => {
+                let mut tx = pool.begin().await?;
+                if let Some(message) = Message::lock(report.message_id, &mut tx).await? {
+                    if message.deleted_at.is_none() {
+                        Message::tombstone(&message, user.id, &mut tx).await?;
+                    }
+                }
+                tx.commit().await?;
+            }
+            ModerationAction::Warn => {
+                let reason = note.unwrap_or(&report.reason);
+                UserWarning::create(report.sender_id, Some(report.id), reason, user, pool).await?;
+            }
+            ModerationAction::Suspend => {
+                User::suspend(report.sender_id, input.suspend_days, user, &ws, pool).await?;
+            }
+        }
+
+        let reports: Vec<Self> = sqlx::query_as(
+            r#"
+            UPDATE message_reports
+            SET action = $2, note = $3, resolved_by = $4, resolved_at = now()
+            WHERE message_id = $1 AND resolved_at IS NULL
+            RETURNING id, ws_id, message_id, chat_id, sender_id, content, reporter_id, reason,
+                action, note, resolved_by, resolved_at, created_at
+            "#,
+        )
+        .bind(report.message_id)
+        .bind(input.action)
+        .bind(note)
+        .bind(user.id)
+        .fetch_all(pool)
+        .await?;
+        reports
+            .into_iter()
+            .find(|r| r.id == id)
+            .ok_or_else(|| AppError::InvalidInput(format!("report {} is already resolved", id)))
+    }
+}
+
+impl UserWarning {
+    pub async fn create(
+        user_id: i64,
+        report_id: Option<i64>,
+        reason: &str,
+        by: &User,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let warning = sqlx::query_as(
+            r#"
+            INSERT INTO user_warnings (ws_id, user_id, report_id, reason, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, ws_id, user_id, report_id, reason, created_by, created_at
+            "#,
+        )
+        .bind(by.ws_id)
+        .bind(user_id)
+        .bind(report_id)
+        .bind(reason)
+        .bind(by.id)
+        .fetch_one(pool)
+        .await?;
+        Ok(warning)
+    }
+
+    /// The warnings the user received, newest first.
+    pub async fn list(user_id: i64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        let warnings = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, user_id, report_id, reason, created_by, created_at
+            FROM user_warnings
+            WHERE user_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(warnings)
+    }
+}
+
+impl User {
+    /// Whether the user cannot use the workspace for now.
+    pub async fn is_suspended(id: i64, pool: &PgPool) -> Result<bool, AppError> {
+        let suspended = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND suspended_until > now())",
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+        Ok(suspended)
+    }
+
+    /// Suspend a user of the workspace for some days or until it is lifted. Only the owner
+    /// can suspend admins, the owner itself cannot be suspended.
+    async fn suspend(
+        id: i64,
+        days: Option<i32>,
+        by: &User,
+        ws: &Workspace,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        if let Some(days) = days {
+            if !(1..=MAX_SUSPEND_DAYS).contains(&days) {
+                return Err(AppError::InvalidInput(format!(
+                    "suspend_days must be between 1 and {}",
+                    MAX_SUSPEND_DAYS
+                )));
+            }
+        }
+        match User::get_role(id, ws.id, pool).await? {
+            None => return Err(AppError::NotFound(format!("user {}", id))),
+            Some(WorkspaceRole::Owner) => {
+                return Err(AppError::InvalidInput(
+                    "the owner of a workspace cannot be suspended".to_string(),
+                ))
+            }
+            Some(WorkspaceRole::Admin) if ws.owner_id != Some(by.id) => {
+                return Err(AppError::PermissionDenied(format!(
+                    "only the owner of workspace {} can suspend its admins",
+                    ws.id
+                )))
+            }
+            Some(_) => {}
+        }
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET suspended_until = CASE WHEN $2::int IS NULL THEN 'infinity'::timestamptz
+                ELSE now() + make_interval(days => $2) END
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(days)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Lift the suspension of a user of the workspace, for its admins.
+    pub async fn unsuspend(id: i64, by: &User, pool: &PgPool) -> Result<(), AppError> {
+        let ws = Workspace::ensure_admin(by.ws_id, by.id, pool).await?;
+        let ret =
+            sqlx::query("UPDATE users SET suspended_until = NULL WHERE id = $1 AND ws_id = $2")
+                .bind(id)
+                .bind(ws.id)
+                .execute(pool)
+                .await?;
+        match ret.rows_affected() {
+            0 => Err(AppError::NotFound(format!("user {}", id))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn reports_should_be_resolved_by_admins() -> Result<()> {
+        let db = create_test_pool().await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let charlie = User::new(3, "Charlie", "charlie@acme.org");
+        let input = CreateReport {
+            reason: "rude".to_string(),
+        };
+
+        // message 2 is bob's "Hi, Alice!" in chat 1, dave is not a member of it
+        let ret = MessageReport::create(2, &input, &bob, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let dave = User::new(4, "Dave", "dave@acme.org");
+        let ret = MessageReport::create(2, &input, &dave, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let report = MessageReport::create(2, &input, &charlie, &db).await?;
+        assert_eq!(
+            (report.sender_id, report.content.as_str()),
+            (2, "Hi, Alice!")
+        );
+        let ret = MessageReport::create(2, &input, &charlie, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let other = MessageReport::create(2, &input, &alice, &db).await?;
+
+        let ret = MessageReport::list(&ListReports::default(), &bob, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let reports = MessageReport::list(&ListReports::default(), &alice, &db).await?;
+        assert_eq!(reports.len(), 2);
+
+        let input = ResolveReport {
+            action: ModerationAction::Warn,
+            note: Some("be nice".to_string()),
+            suspend_days: None,
+        };
+        let report = MessageReport::resolve(report.id, &input, &alice, &db).await?;
+        assert_eq!(report.action, Some(ModerationAction::Warn));
+        let warnings = UserWarning::list(2, &db).await?;
+        assert_eq!(warnings[0].reason, "be nice");
+        // the report of alice was resolved with it
+        assert!(MessageReport::list(&ListReports::default(), &alice, &db)
+            .await?
+            .is_empty());
+        let ret = MessageReport::resolve(other.id, &input, &alice, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_should_delete_messages_and_suspend_senders() -> Result<()> {
+        let db = create_test_pool().await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let charlie = User::new(3, "Charlie", "charlie@acme.org");
+        let input = CreateReport {
+            reason: "spam".to_string(),
+        };
+        let report = MessageReport::create(2, &input, &charlie, &db).await?;
+        let input = ResolveReport {
+            action: ModerationAction::DeleteMessage,
+            note: None,
+            suspend_days: None,
+        };
+        MessageReport::resolve(report.id, &input, &alice, &db).await?;
+        let message = Message::get_by_id(2, &db).await?.unwrap();
+        assert!(message.deleted_at.is_some());
+
+        let input = CreateReport {
+            reason: "spam again".to_string(),
+        };
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let report = MessageReport::create(3, &input, &bob, &db).await?;
+        let input = ResolveReport {
+            action: ModerationAction::Suspend,
+            note: None,
+            suspend_days: Some(7),
+        };
+        MessageReport::resolve(report.id, &input, &alice, &db).await?;
+        assert!(User::is_suspended(3, &db).await?);
+        User::unsuspend(3, &alice, &db).await?;
+        assert!(!User::is_suspended(3, &db).await?);
+
+        // the owner cannot be suspended
+        let report = MessageReport::create(1, &CreateReport::default(), &bob, &db).await;
+        assert!(matches!(report, Err(AppError::InvalidInput(_))));
+        let offensive = CreateReport {
+            reason: "offensive".to_string(),
+        };
+        let report = MessageReport::create(1, &offensive, &bob, &db).await?;
+        let ret = MessageReport::resolve(report.id, &input, &alice, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+use sqlx::PgPool;
+
+use crate::{
+    config::OidcProviderConfig,
+    utils::{random_token, OidcIdentity},
+    AppError,
+};
+
+use super::{OidcLogin, User, UserIdentity, Workspace, WorkspaceRole};
+
+/// Logins have to be finished within ten minutes.
+const LOGIN_TTL_SECS: f64 = 600.0;
+const MAX_FULLNAME_LEN: usize = 64;
+const MAX_EMAIL_LEN: usize = 64;
+
+impl OidcLogin {
+    /// Start a login at the provider, expired logins are dropped on the way.
+    pub async fn create(provider: &str, pool: &PgPool) -> Result<Self, AppError> {
+        sqlx::query("DELETE FROM oidc_logins WHERE created_at < now() - make_interval(secs => $1)")
+            .bind(LOGIN_TTL_SECS)
+            .execute(pool)
+            .await?;
+        let login = sqlx::query_as(
+            r#"
+            INSERT INTO oidc_logins (state, provider, code_verifier, nonce)
+            VALUES ($1, $2, $3, $4)
+            RETURNING state, provider, code_verifier, nonce, created_at
+            "#,
+        )
+        .bind(random_token())
+        .bind(provider)
+        .bind(random_token())
+        .bind(random_token())
+        .fetch_one(pool)
+        .await?;
+        Ok(login)
+    }
+
+    /// Finish a login, its state can only be used once and only with its provider.
+    pub async fn take(state: &str, provider: &str, pool: &PgPool) -> Result<Self, AppError> {
+        let login: Option<Self> = sqlx::query_as(
+            r#"
+            DELETE FROM oidc_logins
+            WHERE state = $1
+            RETURNING state, provider, code_verifier, nonce, created_at
+            "#,
+        )
+        .bind(state)
+        .fetch_optional(pool)
+        .await?;
+        let ttl = chrono::Duration::seconds(LOGIN_TTL_SECS as i64);
+        match login {
+            Some(login)
+                if login.provider == provider && login.created_at + ttl > chrono::Utc::now() =>
+            {
+                Ok(login)
+            }
+            _ => Err(AppError::InvalidInput(
+                "unknown or expired login".to_string(),
+            )),
+        }
+    }
+}
+
+impl User {
+    /// The user signed in with an identity provider. Users are created the first time they
+    /// sign in, an existing user with the same email is linked if the provider is trusted and
+    /// verified it. When only some domains are allowed the email must always be verified.
+    /// Returns whether the user was created.
+    pub async fn from_identity(
+        provider: &str,
+        identity: &OidcIdentity,
+        config: &OidcProviderConfig,
+        pool: &PgPool,
+    ) -> Result<(Self, bool), AppError> {
+        let allowed_domains = &config.allowed_domains;
+        let email = identity
+            .profile
+            .email
+            .as_deref()
+            .map(|e| e.trim().to_lowercase())
+            .filter(|e| !e.is_empty())
+            .ok_or_else(|| {
+                AppError::PermissionDenied("the identity provider shared no email".to_string())
+            })?;
+        if !is_allowed_domain(&email, allowed_domains) {
+            return Err(AppError::PermissionDenied(format!(
+                "the domain of {} is not allowed",
+                email
+            )));
+        }
+        // anyone can claim an email the provider did not verify
+        if !allowed_domains.is_empty() && identity.profile.email_verified != Some(true) {
+            return Err(AppError::PermissionDenied(format!(
+                "the identity provider did not verify {}",
+                email
+            )));
+        }
+
+        let linked: Option<UserIdentity> = sqlx::query_as(
+            r#"
+            SELECT provider, subject, user_id, created_at
+            FROM user_identities
+            WHERE provider = $1 AND subject = $2
+            "#,
+        )
+        .bind(provider)
+        .bind(&identity.subject)
+        .fetch_optional(pool)
+        .await?;
+        let (mut user, created) = match linked {
+            Some(linked) => {
+                let user = Self::find_by_id(linked.user_id, pool)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound(format!("user {}", linked.user_id)))?;
+                (user, false)
+            }
+            None => Self::link_identity(provider, identity, &email, config.trusted, pool).await?,
+        };
+
+        if created && Workspace::claim_owner(&user, pool).await? {
+            user.role = WorkspaceRole::Owner;
+        }
+        if Self::is_suspended(user.id, pool).await? {
+            return Err(AppError::PermissionDenied(format!(
+                "user {} is suspended",
+                user.id
+            )));
+        }
+        Ok((user, created))
+    }
+
+    async fn link_identity(
+        provider: &str,
+        identity: &OidcIdentity,
+        email: &str,
+        trusted: bool,
+        pool: &PgPool,
+    ) -> Result<(Self, bool), AppError> {
+        if email.len() > MAX_EMAIL_LEN {
+            return Err(AppError::InvalidInput(format!(
+                "email {} is too long",
+                email
+            )));
+        }
+        let existing: Option<Self> = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, fullname, email, is_bot, role, created_at
+            FROM users
+            WHERE lower(email) = $1
+            "#,
+        )
+        .bind(email)
+        .fetch_optional(pool)
+        .await?;
+
+        let mut tx = pool.begin().await?;
+        let (user, created) = match existing {
+            // anyone can get an account with any email at some providers
+            Some(user)
+                if trusted && !user.is_bot && identity.profile.email_verified == Some(true) =>
+            {
+                (user, false)
+            }
+            Some(_) => {
+                return Err(AppError::EmailIsExist(email.to_string()));
+            }
+            None => {
+                let fullname = identity
+                    .profile
+                    .name
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|n| !n.is_empty())
+                    .unwrap_or_else(|| email.split('@').next().unwrap_or(email));
+                let fullname: String = fullname.chars().take(MAX_FULLNAME_LEN).collect();
+                // no password, the user signs in with the provider
+                let user = sqlx::query_as(
+                    r#"
+                    INSERT INTO users (email, fullname)
+                    VALUES ($1, $2)
+                    RETURNING id, ws_id, fullname, email, role, created_at
+                    "#,
+                )
+                .bind(email)
+                .bind(fullname)
+                .fetch_one(&mut *tx)
+                .await?;
+                (user, true)
+            }
+        };
+        sqlx::query("INSERT INTO user_identities (provider, subject, user_id) VALUES ($1, $2, $3)")
+            .bind(provider)
+            .bind(&identity.subject)
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok((user, created))
+    }
+}
+
+fn is_allowed_domain(email: &str, allowed_domains: &[String]) -> bool {
+    let Some((_, domain)) = email.rsplit_once('@') else {
+        return false;
+    };
+    allowed_domains.is_empty()
+        || allowed_domains
+            .iter()
+            .any(|d| d.eq_ignore_ascii_case(domain))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::VerifyUser,
+        utils::{create_test_pool, OidcProfile},
+    };
+
+    use super::*;
+    use anyhow::Result;
+
+    fn identity(subject: &str, email: &str, email_verified: Option<bool>) -> OidcIdentity {
+        OidcIdentity {
+            subject: subject.to_string(),
+            profile: OidcProfile {
+                email: Some(email.to_string()),
+                email_verified,
+                name: Some("Erin Example".to_string()),
+            },
+        }
+    }
+
+    fn provider(allowed_domains: &[&str], trusted: bool) -> OidcProviderConfig {
+        OidcProviderConfig {
+            issuer: "https://sso.acme.org".to_string(),
+            client_id: "crablink".to_string(),
+            client_secret: None,
+            redirect_uri: "https://chat.acme.org/api/auth/oidc/acme/callback".to_string(),
+            allowed_domains: allowed_domains.iter().map(|d| d.to_string()).collect(),
+            trusted,
+        }
+    }
+
+    #[tokio::test]
+    async fn oidc_login_should_be_used_once() -> Result<()> {
+        let db = create_test_pool().await?;
+        let login = OidcLogin::create("acme", &db).await?;
+        let ret = OidcLogin::take(&login.state, "other", &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        // a state used with the wrong provider is gone too
+        let ret = OidcLogin::take(&login.state, "acme", &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        let login = OidcLogin::create("acme", &db).await?;
+        assert_eq!(OidcLogin::take(&login.state, "acme", &db).await?, login);
+        let ret = OidcLogin::take(&login.state, "acme", &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn identities_should_be_provisioned_and_linked() -> Result<()> {
+        let db = create_test_pool().await?;
+        let acme = provider(&["acme.org"], true);
+
+        // the domain of an unverified email proves nothing
+        let ret =
+            User::from_identity("acme", &identity("e-1", "Erin@acme.org", None), &acme, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = User::from_identity(
+            "acme",
+            &identity("e-1", "Erin@acme.org", Some(false)),
+            &acme,
+            &db,
+        )
+        .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let (erin, created) = User::from_identity(
+            "acme",
+            &identity("e-1", "Erin@acme.org", Some(true)),
+            &acme,
+            &db,
+        )
+        .await?;
+        assert!(created);
+        assert_eq!(
+            (erin.email.as_str(), erin.fullname.as_str(), erin.role),
+            ("erin@acme.org", "Erin Example", WorkspaceRole::Member)
+        );
+        // sso users have no password
+        let input = VerifyUser::new("erin@acme.org", "");
+        assert_eq!(User::verify(&input, &db).await?, None);
+        let (user, created) = User::from_identity(
+            "acme",
+            &identity("e-1", "erin@acme.org", Some(true)),
+            &acme,
+            &db,
+        )
+        .await?;
+        assert_eq!((user.id, created), (erin.id, false));
+
+        // existing users are linked only with a verified email, whatever the domain
+        let any = provider(&[], true);
+        let ret =
+            User::from_identity("acme", &identity("b-1", "bob@acme.org", None), &any, &db).await;
+        assert!(matches!(ret, Err(AppError::EmailIsExist(_))));
+        // and only by the providers trusted with the emails of the workspace
+        let bob = identity("b-1", "bob@acme.org", Some(true));
+        let social = provider(&[], false);
+        let ret = User::from_identity("social", &bob, &social, &db).await;
+        assert!(matches!(ret, Err(AppError::EmailIsExist(_))));
+        let (user, created) = User::from_identity("acme", &bob, &acme, &db).await?;
+        assert_eq!((user.id, created), (2, false));
+
+        let ret = User::from_identity(
+            "acme",
+            &identity("m-1", "mallory@evil.com", Some(true)),
+            &acme,
+            &db,
+        )
+        .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,589 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    config::WebauthnConfig,
+    utils::{
+        decode_b64, encode_b64, new_challenge, parse_client_data, verify_assertion,
+        verify_registration,
+    },
+    AppError,
+};
+
+use super::{User, WebauthnCeremony, WebauthnChallenge, WebauthnCredential};
+
+/// Ceremonies have to be finished within five minutes.
+const CHALLENGE_TTL_SECS: f64 = 300.0;
+const MAX_NAME_LEN: usize = 64;
+const DEFAULT_NAME: &str = "Passkey";
+const PUBLIC_KEY_TYPE: &str = "public-key";
+/// ES256, the only algorithm passkeys are accepted with
+const COSE_ALG_ES256: i64 = -7;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyUser {
+    /// the user handle stored in the passkey, base64url
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialParam {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+/// The options of `navigator.credentials.create()`, binary values are base64url.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub challenge: String,
+    pub rp: RelyingParty,
+    pub user: PasskeyUser,
+    pub pub_key_cred_params: Vec<CredentialParam>,
+    /// passkeys the user already has, the authenticator refuses to create a second one
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: String,
+    /// milliseconds
+    pub timeout: u64,
+}
+
+/// The options of `navigator.credentials.get()`, binary values are base64url.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    /// the passkeys of the user for a second factor, empty to let the user pick one
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: String,
+    /// milliseconds
+    pub timeout: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+/// The `PublicKeyCredential` created by the browser, binary values are base64url.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterPasskey {
+    pub id: String,
+    pub response: AttestationResponse,
+    /// shown in the list of passkeys of the user
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(default)]
+    pub user_handle: Option<String>,
+}
+
+/// The `PublicKeyCredential` returned by the browser, binary values are base64url.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticatePasskey {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateSecondFactor {
+    /// whether signing in with a password also needs a passkey
+    pub enabled: bool,
+}
+
+impl WebauthnChallenge {
+    /// Start a ceremony, expired challenges are dropped on the way.
+    pub async fn create(
+        ceremony: WebauthnCeremony,
+        user_id: Option<i64>,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        sqlx::query(
+            "DELETE FROM webauthn_challenges WHERE created_at < now() - make_interval(secs => $1)",
+        )
+        .bind(CHALLENGE_TTL_SECS)
+        .execute(pool)
+        .await?;
+        let challenge = sqlx::query_as(
+            r#"
+            INSERT INTO webauthn_challenges (challenge, ceremony, user_id)
+            VALUES ($1, $2, $3)
+            RETURNING challenge, ceremony, user_id, created_at
+            "#,
+        )
+        .bind(new_challenge())
+        .bind(ceremony)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+        Ok(challenge)
+    }
+
+    /// Finish a ceremony, its challenge can only be answered once.
+    pub async fn take(
+        challenge: &str,
+        ceremony: WebauthnCeremony,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let challenge: Option<Self> = sqlx::query_as(
+            r#"
+            DELETE FROM webauthn_challenges
+            WHERE challenge = $1
+            RETURNING challenge, ceremony, user_id, created_at
+            "#,
+        )
+        .bind(challenge)
+        .fetch_optional(pool)
+        .await?;
+        let ttl = chrono::Duration::seconds(CHALLENGE_TTL_SECS as i64);
+        match challenge {
+            Some(challenge)
+                if challenge.ceremony == ceremony
+                    && challenge.created_at + ttl > chrono::Utc::now() =>
+            {
+                Ok(challenge)
+            }
+            _ => Err(AppError::InvalidInput(
+                "unknown or expired challenge".to_string(),
+            )),
+        }
+    }
+}
+
+impl WebauthnCredential {
+    pub async fn list(user_id: i64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        let credentials = sqlx::query_as(
+            r#"
+            SELECT id, user_id, credential_id, public_key, sign_count, name, created_at, last_used_at
+            FROM webauthn_credentials
+            WHERE user_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(credentials)
+    }
+
+    /// The options the browser creates a new passkey of the user with.
+    pub async fn start_registration(
+        user: &User,
+        config: &WebauthnConfig,
+        pool: &PgPool,
+    ) -> Result<CreationOptions, AppError> {
+        let challenge =
+            WebauthnChallenge::create(WebauthnCeremony::Registration, Some(user.id), pool).await?;
+        let exclude_credentials = Self::list(user.id, pool)
+            .await?
+            .into_iter()
+            .map(|c| descriptor(c.credential_id))
+            .collect();
+        Ok(CreationOptions {
+            challenge: challenge.challenge,
+            rp: RelyingParty {
+                id: config.rp_id.clone(),
+                name: config.rp_name.clone(),
+            },
+            user: PasskeyUser {
+                id: encode_b64(&user.id.to_be_bytes()),
+                name: user.email.clone(),
+                display_name: user.fullname.clone(),
+            },
+            pub_key_cred_params: vec![CredentialParam {
+                ty: PUBLIC_KEY_TYPE.to_string(),
+                alg: COSE_ALG_ES256,
+            }],
+            exclude_credentials,
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "preferred".to_string(),
+                user_verification: "preferred".to_string(),
+            },
+            attestation: "none".to_string(),
+            timeout: CHALLENGE_TTL_SECS as u64 * 1000,
+        })
+    }
+
+    /// Store the passkey the browser created for a registration started by the same user.
+    pub async fn register(
+        input: &RegisterPasskey,
+        user: &User,
+        config: &WebauthnConfig,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let client_data = decode_b64(&input.response.client_data_json)?;
+        let challenge = parse_client_data(&client_data, "webauthn.create", config)?;
+        let challenge =
+            WebauthnChallenge::take(&challenge, WebauthnCeremony::Registration, pool).await?;
+        if challenge.user_id != Some(user.id) {
+            return Err(AppError::PermissionDenied(
+                "the registration was started by another user".to_string(),
+            ));
+        }
+        let passkey =
+            verify_registration(&decode_b64(&input.response.attestation_object)?, config)?;
+        let credential_id = encode_b64(&passkey.credential_id);
+        if credential_id != input.id {
+            return Err(AppError::InvalidInput(
+                "the id does not match the credential".to_string(),
+            ));
+        }
+        let name = input.name.as_deref().map(str::trim).unwrap_or_default();
+        let name = match name.chars().count() {
+            0 => DEFAULT_NAME,
+            n if n <= MAX_NAME_LEN => name,
+            _ => {
+                return Err(AppError::InvalidInput(format!(
+                    "the name must have at most {} characters",
+                    MAX_NAME_LEN
+                )))
+            }
+        };
+
+        let ret = sqlx::query_as(
+            r#"
+            INSERT INTO webauthn_credentials (user_id, credential_id, public_key, sign_count, name)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, user_id, credential_id, public_key, sign_count, name, created_at, last_used_at
+            "#,
+        )
+        .bind(user.id)
+        .bind(&credential_id)
+        .bind(&passkey.public_key)
+        .bind(passkey.sign_count as i64)
+        .bind(name)
+        .fetch_one(pool)
+        .await;
+        match ret {
+            Ok(credential) => Ok(credential),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(
+                AppError::InvalidInput("the passkey is already registered".to_string()),
+            ),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Remove a passkey of the user, the last one cannot go while it is a second factor.
+    pub async fn delete(id: i64, user_id: i64, pool: &PgPool) -> Result<Self, AppError> {
+        let mut tx = pool.begin().await?;
+        // lock the user, a concurrent delete sees this one
+        let required: bool =
+            sqlx::query_scalar("SELECT passkey_required FROM users WHERE id = $1 FOR UPDATE")
+                .bind(user_id)
+                .fetch_one(&mut *tx)
+                .await?;
+        let credential: Self = sqlx::query_as(
+            r#"
+            DELETE FROM webauthn_credentials
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, user_id, credential_id, public_key, sign_count, name, created_at, last_used_at
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("passkey {}", id)))?;
+        let remaining: i64 =
+            sqlx::query_scalar("SELECT count(*) FROM webauthn_credentials WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_one(&mut *tx)
+                .await?;
+        if required && remaining == 0 {
+            return Err(AppError::InvalidInput(
+                "the last passkey is needed as a second factor".to_string(),
+            ));
+        }
+        tx.commit().await?;
+        Ok(credential)
+    }
+
+    /// The options the browser signs in with. Without a user any passkey can be used and the
+    /// authenticator has to verify the user, with one only its passkeys are accepted.
+    pub async fn start_authentication(
+        user_id: Option<i64>,
+        config: &WebauthnConfig,
+        pool: &PgPool,
+    ) -> Result<RequestOptions, AppError> {
+        let challenge =
+            WebauthnChallenge::create(WebauthnCeremony::Authentication, user_id, pool).await?;
+        let allow_credentials = match user_id {
+            Some(user_id) => Self::list(user_id, pool)
+                .await?
+                .into_iter()
+                .map(|c| descriptor(c.credential_id))
+                .collect(),
+            None => vec![],
+        };
+        let user_verification = match user_id {
+            Some(_) => "preferred",
+            None => "required",
+        };
+        Ok(RequestOptions {
+            challenge: challenge.challenge,
+            rp_id: config.rp_id.clone(),
+            allow_credentials,
+            user_verification: user_verification.to_string(),
+            timeout: CHALLENGE_TTL_SECS as u64 * 1000,
+        })
+    }
+
+    /// Verify the passkey the browser signed the challenge with, returning its user and whether
+    /// it was a second factor after a password.
+    pub async fn authenticate(
+        input: &AuthenticatePasskey,
+        config: &WebauthnConfig,
+        pool: &PgPool,
+    ) -> Result<(User, bool), AppError> {
+        let client_data = decode_b64(&input.response.client_data_json)?;
+        let challenge = parse_client_data(&client_data, "webauthn.get", config)?;
+        let challenge =
+            WebauthnChallenge::take(&challenge, WebauthnCeremony::Authentication, pool).await?;
+        let credential: Self = sqlx::query_as(
+            r#"
+            SELECT id, user_id, credential_id, public_key, sign_count, name, created_at, last_used_at
+            FROM webauthn_credentials
+            WHERE credential_id = $1
+            "#,
+        )
+        .bind(&input.id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| denied("unknown passkey"))?;
+        if challenge.user_id.is_some_and(|id| id != credential.user_id) {
+            return Err(denied("the passkey belongs to another user"));
+        }
+
+        // a passkey alone has to prove who the user is, not only that someone is there
+        let sign_count = verify_assertion(
+            &credential.public_key,
+            &decode_b64(&input.response.authenticator_data)?,
+            &client_data,
+            &decode_b64(&input.response.signature)?,
+            challenge.user_id.is_none(),
+            config,
+        )? as i64;
+        // authenticators without a counter always send 0, otherwise it has to grow or the
+        // passkey may have been cloned
+        if sign_count <= credential.sign_count && (sign_count, credential.sign_count) != (0, 0) {
+            return Err(denied("the sign count did not increase"));
+        }
+        let ret = sqlx::query(
+            r#"
+            UPDATE webauthn_credentials SET sign_count = $2, last_used_at = now()
+            WHERE id = $1 AND sign_count = $3
+            "#,
+        )
+        .bind(credential.id)
+        .bind(sign_count)
+        .bind(credential.sign_count)
+        .execute(pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(denied("the passkey was used concurrently"));
+        }
+
+        let user = match User::find_by_id(credential.user_id, pool).await? {
+            Some(user) if !user.is_bot => user,
+            _ => return Err(denied("unknown passkey")),
+        };
+        if User::is_suspended(user.id, pool).await? {
+            return Err(AppError::PermissionDenied(format!(
+                "user {} is suspended",
+                user.id
+            )));
+        }
+        Ok((user, challenge.user_id.is_some()))
+    }
+}
+
+impl User {
+    /// Whether signing in with a password or an identity provider also needs a passkey.
+    pub async fn passkey_required(id: i64, pool: &PgPool) -> Result<bool, AppError> {
+        let required = sqlx::query_scalar("SELECT passkey_required FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+        Ok(required.unwrap_or(false))
+    }
+
+    /// Turn passkeys as a second factor on or off, turning it on needs a passkey.
+    pub async fn update_second_factor(
+        id: i64,
+        input: &UpdateSecondFactor,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE users SET passkey_required = $2
+            WHERE id = $1
+                AND (NOT $2 OR EXISTS (SELECT 1 FROM webauthn_credentials WHERE user_id = $1))
+            "#,
+        )
+        .bind(id)
+        .bind(input.enabled)
+        .execute(pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::InvalidInput(
+                "register a passkey before using it as a second factor".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn descriptor(credential_id: String) -> CredentialDescriptor {
+    CredentialDescriptor {
+        ty: PUBLIC_KEY_TYPE.to_string(),
+        id: credential_id,
+    }
+}
+
+fn denied(reason: &str) -> AppError {
+    AppError::PermissionDenied(format!("passkey rejected: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        utils::{create_test_pool, SoftAuthenticator},
+        AppConfig,
+    };
+
+    use super::*;
+    use anyhow::Result;
+
+    async fn register(
+        authenticator: &SoftAuthenticator,
+        user: &User,
+        config: &WebauthnConfig,
+        pool: &PgPool,
+    ) -> Result<WebauthnCredential> {
+        let options = WebauthnCredential::start_registration(user, config, pool).await?;
+        let input: RegisterPasskey =
+            serde_json::from_value(authenticator.register(&options.challenge))?;
+        Ok(WebauthnCredential::register(&input, user, config, pool).await?)
+    }
+
+    async fn authenticate(
+        authenticator: &mut SoftAuthenticator,
+        user_id: Option<i64>,
+        user_verified: bool,
+        config: &WebauthnConfig,
+        pool: &PgPool,
+    ) -> Result<AuthenticatePasskey> {
+        let options = WebauthnCredential::start_authentication(user_id, config, pool).await?;
+        let input = authenticator.authenticate(&options.challenge, user_verified);
+        Ok(serde_json::from_value(input)?)
+    }
+
+    #[tokio::test]
+    async fn passkeys_should_sign_users_in() -> Result<()> {
+        let config = AppConfig::load()?.auth.webauthn;
+        let db = create_test_pool().await?;
+        let bob = User::find_by_id(2, &db).await?.unwrap();
+        let mut authenticator = SoftAuthenticator::new(&config);
+
+        let credential = register(&authenticator, &bob, &config, &db).await?;
+        assert_eq!(
+            (credential.user_id, credential.name.as_str()),
+            (2, "Passkey")
+        );
+        // the same passkey cannot be registered twice
+        let ret = register(&authenticator, &bob, &config, &db).await;
+        assert!(ret.is_err());
+
+        let input = authenticate(&mut authenticator, None, true, &config, &db).await?;
+        let (user, second_factor) = WebauthnCredential::authenticate(&input, &config, &db).await?;
+        assert_eq!((user.id, second_factor), (2, false));
+        // a challenge is answered once
+        let ret = WebauthnCredential::authenticate(&input, &config, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        // passwordless sign ins need the user to be verified
+        let input = authenticate(&mut authenticator, None, false, &config, &db).await?;
+        let ret = WebauthnCredential::authenticate(&input, &config, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // a cloned authenticator shows a sign count that went back
+        authenticator.sign_count = 0;
+        let input = authenticate(&mut authenticator, None, true, &config, &db).await?;
+        let ret = WebauthnCredential::authenticate(&input, &config, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn passkeys_should_be_a_second_factor() -> Result<()> {
+        let config = AppConfig::load()?.auth.webauthn;
+        let db = create_test_pool().await?;
+        let bob = User::find_by_id(2, &db).await?.unwrap();
+        let charlie = User::find_by_id(3, &db).await?.unwrap();
+        let enable = UpdateSecondFactor { enabled: true };
+
+        let ret = User::update_second_factor(bob.id, &enable, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let mut authenticator = SoftAuthenticator::new(&config);
+        let credential = register(&authenticator, &bob, &config, &db).await?;
+        User::update_second_factor(bob.id, &enable, &db).await?;
+        assert!(User::passkey_required(bob.id, &db).await?);
+        let mut other = SoftAuthenticator::new(&config);
+        register(&other, &charlie, &config, &db).await?;
+
+        // the passkey of another user does not confirm a password
+        let input = authenticate(&mut other, Some(bob.id), true, &config, &db).await?;
+        let ret = WebauthnCredential::authenticate(&input, &config, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        // a second factor does not need the user to be verified
+        let input = authenticate(&mut authenticator, Some(bob.id), false, &config, &db).await?;
+        let (user, second_factor) = WebauthnCredential::authenticate(&input, &config, &db).await?;
+        assert_eq!((user.id, second_factor), (bob.id, true));
+
+        // the last passkey stays while it is required
+        let ret = WebauthnCredential::delete(credential.id, bob.id, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        User::update_second_factor(bob.id, &UpdateSecondFactor { enabled: false }, &db).await?;
+        let ret = WebauthnCredential::delete(credential.id, charlie.id, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        WebauthnCredential::delete(credential.id, bob.id, &db).await?;
+        assert_eq!(WebauthnCredential::list(bob.id, &db).await?, vec![]);
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use super::{reaction::attach_reactions, Chat, ListMessages, Message};
+
+const MAX_PIN_LIMIT: i32 = 1000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdatePinLimit {
+    pub pin_limit: i32,
+}
+
+impl Chat {
+    /// Pin a message of the chat, only admins can do that and only up to the pin limit.
+    pub async fn pin(
+        id: i64,
+        message_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        let chat = Self::get_for_admin(id, user_id, pool).await?;
+        match Message::get_by_id(message_id, pool).await? {
+            Some(message) if message.chat_id == id && message.deleted_at.is_none() => {}
+            _ => return Err(AppError::NotFound(format!("message {}", message_id))),
+        }
+
+        let ret = sqlx::query(
+            r#"
+            INSERT INTO chat_pins (chat_id, message_id, pinned_by)
+            SELECT $1, $2, $3
+            WHERE (SELECT count(*) FROM chat_pins WHERE chat_id = $1) < $4
+                OR EXISTS(SELECT 1 FROM chat_pins WHERE chat_id = $1 AND message_id = $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(message_id)
+        .bind(user_id)
+        .bind(chat.pin_limit as i64)
+        .execute(pool)
+        .await?;
+        if ret.rows_affected() == 0 && !Self::is_pinned(id, message_id, pool).await? {
+            return Err(AppError::InvalidInput(format!(
+                "chat {} cannot have more than {} pinned messages",
+                id, chat.pin_limit
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn unpin(
+        id: i64,
+        message_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        Self::get_for_admin(id, user_id, pool).await?;
+        sqlx::query("DELETE FROM chat_pins WHERE chat_id = $1 AND message_id = $2")
+            .bind(id)
+            .bind(message_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Pinned messages of the chat, the most recently pinned first.
+    pub async fn list_pins(id: i64, user_id: i64, pool: &PgPool) -> Result<Vec<Message>, AppError> {
+        Self::get_for_member(id, user_id, pool).await?;
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.parent_id, m.format, m.content, m.content_text,
+                m.attachments, m.created_at, m.updated_at, m.deleted_at
+            FROM chat_pins p
+            JOIN messages m ON m.id = p.message_id
+            WHERE p.chat_id = $1
+            ORDER BY p.created_at DESC, m.id DESC
+            "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        attach_reactions(&mut messages, user_id, pool).await?;
+        Ok(messages)
+    }
+
+    pub async fn update_pin_limit(
+        id: i64,
+        input: &UpdatePinLimit,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        Self::get_for_admin(id, user_id, pool).await?;
+        if !(1..=MAX_PIN_LIMIT).contains(&input.pin_limit) {
+            return Err(AppError::InvalidInput(format!(
+                "pin limit must be between 1 and {}",
+                MAX_PIN_LIMIT
+            )));
+        }
+        sqlx::query("UPDATE chats SET pin_limit = $2 WHERE id = $1")
+            .bind(id)
+            .bind(input.pin_limit)
+            .execute(pool)
+            .await?;
+        Self::get_for_member(id, user_id, pool).await
+    }
+
+    /// Load a chat the user is an admin of.
+    pub async fn get_for_admin(id: i64, user_id: i64, pool: &PgPool) -> Result<Self, AppError> {
+        let chat = Self::get_for_member(id, user_id, pool).await?;
+        match chat.is_admin(user_id) {
+            true => Ok(chat),
+            false => Err(AppError::PermissionDenied(format!(
+                "user {} is not an admin of chat {}",
+                user_id, id
+            ))),
+        }
+    }
+
+    async fn is_pinned(id: i64, message_id: i64, pool: &PgPool) -> Result<bool, AppError> {
+        let pinned = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM chat_pins WHERE chat_id = $1 AND message_id = $2)",
+        )
+        .bind(id)
+        .bind(message_id)
+        .fetch_one(pool)
+        .await?;
+        Ok(pinned)
+    }
+}
+
+impl Message {
+    /// Save a message the user can see into their private list.
+    pub async fn save(id: i64, user_id: i64, pool: &PgPool) -> Result<(), AppError> {
+        let message = match Self::get_by_id(id, pool).await? {
+            Some(message) if message.deleted_at.is_none() => message,
+            _ => return Err(AppError::NotFound(format!("message {}", id))),
+        };
+        Chat::get_for_member(message.chat_id, user_id, pool).await?;
+
+        sqlx::query(
+            "INSERT INTO saved_messages (user_id, message_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(user_id)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn unsave(id: i64, user_id: i64, pool: &PgPool) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM saved_messages WHERE user_id = $1 AND message_id = $2")
+            .bind(user_id)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Saved messages of the user, the most recently saved first.
+    pub async fn list_saved(
+        input: &ListMessages,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        let (last_id, limit) = input.page();
+        let mut messages: Vec<Self> = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.parent_id, m.format, m.content, m.content_text,
+                m.attachments, m.created_at, m.updated_at, m.deleted_at
+            FROM saved_messages s
+            JOIN messages m ON m.id = s.message_id
+            WHERE s.user_id = $1 AND m.id < $2
+            ORDER BY m.id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(last_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+        attach_reactions(&mut messages, user_id, pool).await?;
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn pin_should_respect_the_limit() -> Result<()> {
+        let db = create_test_pool().await?;
+        let ret = Chat::pin(1, 1, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let input = UpdatePinLimit { pin_limit: 1 };
+        let chat = Chat::update_pin_limit(1, &input, 1, &db).await?;
+        assert_eq!(chat.pin_limit, 1);
+
+        Chat::pin(1, 1, 1, &db).await?;
+        Chat::pin(1, 1, 1, &db).await?;
+        let ret = Chat::pin(1, 2, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let ret = Chat::pin(1, 4, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let pins = Chat::list_pins(1, 3, &db).await?;
+        assert_eq!(pins.len(), 1);
+        assert_eq!(pins[0].id, 1);
+
+        Chat::unpin(1, 1, 1, &db).await?;
+        assert!(Chat::list_pins(1, 3, &db).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleted_messages_should_leave_pins_and_saved() -> Result<()> {
+        let db = create_test_pool().await?;
+        Chat::pin(1, 2, 1, &db).await?;
+        Message::save(2, 3, &db).await?;
+        Message::save(1, 3, &db).await?;
+        let ret = Message::save(4, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        Message::delete(2, 2, &db).await?;
+        assert!(Chat::list_pins(1, 1, &db).await?.is_empty());
+        let saved = Message::list_saved(&ListMessages::default(), 3, &db).await?;
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn removed_members_should_lose_saved_messages() -> Result<()> {
+        let db = create_test_pool().await?;
+        Message::save(1, 3, &db).await?;
+        Message::save(4, 3, &db).await?;
+        sqlx::query("UPDATE chats SET members = '{1, 2}' WHERE id = 1")
+            .execute(&*db)
+            .await?;
+
+        let saved = Message::list_saved(&ListMessages::default(), 3, &db).await?;
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].id, 4);
+        Ok(())
+    }
+}
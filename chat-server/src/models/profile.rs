@@ -0,0 +1,387 @@
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    mail::{Mail, Mailer},
+    AppError,
+};
+
+use super::{PublicUser, User, UserProfile, WorkspaceRole};
+
+const EMAIL_TOKEN_TTL_HOURS: i64 = 24;
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// Fields to change, an empty string clears an optional field.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateProfile {
+    pub fullname: Option<String>,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub title: Option<String>,
+    pub timezone: Option<String>,
+    pub status_text: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListUsers {
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl UserProfile {
+    pub async fn get(user_id: i64, pool: &PgPool) -> Result<Self, AppError> {
+        sqlx::query_as(
+            r#"
+            SELECT id, ws_id, fullname, email, pending_email, display_name, avatar, title,
+                timezone, status_text, created_at
+            FROM users
+            WHERE id = $1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("user {}", user_id)))
+    }
+
+    /// Update the profile, a new email waits for verification with a token mailed to it. Only
+    /// the hash of the token is stored.
+    pub async fn update(
+        user_id: i64,
+        input: &UpdateProfile,
+        mailer: &dyn Mailer,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let current = Self::get(user_id, pool).await?;
+        if let Some(fullname) = &input.fullname {
+            check_len("fullname", fullname, 64)?;
+            if fullname.trim().is_empty() {
+                return Err(AppError::InvalidInput(
+                    "fullname cannot be empty".to_string(),
+                ));
+            }
+        }
+        check_len(
+            "display_name",
+            input.display_name.as_deref().unwrap_or_default(),
+            64,
+        )?;
+        check_len("avatar", input.avatar.as_deref().unwrap_or_default(), 256)?;
+        check_len("title", input.title.as_deref().unwrap_or_default(), 64)?;
+        check_len(
+            "status_text",
+            input.status_text.as_deref().unwrap_or_default(),
+            128,
+        )?;
+        if let Some(timezone) = input.timezone.as_deref().filter(|tz| !tz.is_empty()) {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT EXISTS(SELECT 1 FROM pg_timezone_names WHERE name = $1)",
+            )
+            .bind(timezone)
+            .fetch_one(pool)
+            .await?;
+            if !exists {
+                return Err(AppError::InvalidInput(format!(
+                    "unknown timezone: {}",
+                    timezone
+                )));
+            }
+        }
+
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET fullname = COALESCE($2, fullname),
+                display_name = CASE WHEN $3::text IS NULL THEN display_name ELSE NULLIF($3, '') END,
+                avatar = CASE WHEN $4::text IS NULL THEN avatar ELSE NULLIF($4, '') END,
+                title = CASE WHEN $5::text IS NULL THEN title ELSE NULLIF($5, '') END,
+                timezone = CASE WHEN $6::text IS NULL THEN timezone ELSE NULLIF($6, '') END,
+                status_text = CASE WHEN $7::text IS NULL THEN status_text ELSE NULLIF($7, '') END
+            WHERE id = $1
+            "#,
+        )
+        .bind(user_id)
+        .bind(&input.fullname)
+        .bind(&input.display_name)
+        .bind(&input.avatar)
+        .bind(&input.title)
+        .bind(&input.timezone)
+        .bind(&input.status_text)
+        .execute(&mut *tx)
+        .await?;
+
+        let mut verification = None;
+        if let Some(email) = input.email.as_deref().filter(|e| *e != current.email) {
+            check_len("email", email, 64)?;
+            if !email.contains('@') {
+                return Err(AppError::InvalidInput(format!("invalid email: {}", email)));
+            }
+            let taken: bool =
+                sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)")
+                    .bind(email)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            if taken {
+                return Err(AppError::EmailIsExist(email.to_string()));
+            }
+            let token = Uuid::new_v4().simple().to_string();
+            sqlx::query(
+                r#"
+                UPDATE users
+                SET pending_email = $2, email_token = $3, email_token_expires_at = $4
+                WHERE id = $1
+                "#,
+            )
+            .bind(user_id)
+            .bind(email)
+            .bind(hash_token(&token))
+            .bind(Utc::now() + Duration::hours(EMAIL_TOKEN_TTL_HOURS))
+            .execute(&mut *tx)
+            .await?;
+            info!(user_id, email, "email verification requested");
+            verification = Some(Mail {
+                to: email.to_string(),
+                subject: "Confirm your new email".to_string(),
+                text: format!(
+                    "Confirm {} as the email of your account with this code, it expires in {} hours:\n\n{}\n",
+                    email, EMAIL_TOKEN_TTL_HOURS, token
+                ),
+            });
+        }
+        tx.commit().await?;
+        // a mail which fails is sent again by asking for the same email
+        if let Some(mail) = verification {
+            mailer.send(&mail).await?;
+        }
+
+        Self::get(user_id, pool).await
+    }
+
+    /// Replace the email with the pending one if the token matches and is not expired.
+    pub async fn verify_email(
+        user_id: i64,
+        input: &VerifyEmail,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE users
+            SET email = pending_email, pending_email = NULL, email_token = NULL,
+                email_token_expires_at = NULL
+            WHERE id = $1 AND pending_email IS NOT NULL AND email_token = $2
+                AND email_token_expires_at > now()
+            "#,
+        )
+        .bind(user_id)
+        .bind(hash_token(&input.token))
+        .execute(pool)
+        .await;
+        match ret {
+            Ok(ret) if ret.rows_affected() == 1 => Self::get(user_id, pool).await,
+            Ok(_) => Err(AppError::InvalidInput(
+                "invalid or expired email token".to_string(),
+            )),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                Err(AppError::EmailIsExist("pending email".to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Tokens are stored hashed, a leaked database does not verify emails.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl PublicUser {
+    /// Users of the same workspace matching the query, everyone can search but only admins see
+    /// emails and match on them. Guests only see the members of their chats.
+    pub async fn list(
+        input: &ListUsers,
+        user: &User,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        let role = User::get_role(user.id, user.ws_id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("user {}", user.id)))?;
+        let is_admin = role >= WorkspaceRole::Admin;
+        let is_guest = role == WorkspaceRole::Guest;
+        let q = input.q.as_deref().map(str::trim).unwrap_or_default();
+        let pattern = format!("%{}%", escape_like(q));
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+
+        let users = sqlx::query_as(
+            r#"
+            SELECT id, fullname, CASE WHEN $2 THEN email END AS email, display_name, avatar,
+                title, timezone, status_text
+            FROM users
+            WHERE ws_id = $1
+                AND (fullname ILIKE $3 OR display_name ILIKE $3 OR ($2 AND email ILIKE $3))
+                AND (NOT $5 OR id IN (
+                    SELECT unnest(members) FROM chats WHERE $6 = ANY(members)
+                ))
+            ORDER BY fullname, id
+            LIMIT $4
+            "#,
+        )
+        .bind(user.ws_id)
+        .bind(is_admin)
+        .bind(&pattern)
+        .bind(limit)
+        .bind(is_guest)
+        .bind(user.id)
+        .fetch_all(pool)
+        .await?;
+        Ok(users)
+    }
+}
+
+fn check_len(field: &str, value: &str, max: usize) -> Result<(), AppError> {
+    match value.chars().count() > max {
+        true => Err(AppError::InvalidInput(format!(
+            "{} is longer than {} characters",
+            field, max
+        ))),
+        false => Ok(()),
+    }
+}
+
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mail::MemoryMailer, utils::create_test_pool};
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn update_profile_should_work() -> Result<()> {
+        let db = create_test_pool().await?;
+        let mailer = MemoryMailer::default();
+        let input = UpdateProfile {
+            display_name: Some("bobby".to_string()),
+            timezone: Some("Asia/Shanghai".to_string()),
+            ..Default::default()
+        };
+        let profile = UserProfile::update(2, &input, &mailer, &db).await?;
+        assert_eq!(profile.display_name.as_deref(), Some("bobby"));
+        assert_eq!(profile.timezone.as_deref(), Some("Asia/Shanghai"));
+
+        let input = UpdateProfile {
+            display_name: Some("".to_string()),
+            ..Default::default()
+        };
+        let profile = UserProfile::update(2, &input, &mailer, &db).await?;
+        assert!(profile.display_name.is_none());
+        assert_eq!(profile.timezone.as_deref(), Some("Asia/Shanghai"));
+
+        let input = UpdateProfile {
+            timezone: Some("Mars/Olympus".to_string()),
+            ..Default::default()
+        };
+        let ret = UserProfile::update(2, &input, &mailer, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn email_change_should_require_verification() -> Result<()> {
+        let db = create_test_pool().await?;
+        let mailer = MemoryMailer::default();
+        let input = UpdateProfile {
+            email: Some("alice@acme.org".to_string()),
+            ..Default::default()
+        };
+        let ret = UserProfile::update(2, &input, &mailer, &db).await;
+        assert!(matches!(ret, Err(AppError::EmailIsExist(_))));
+
+        let input = UpdateProfile {
+            email: Some("robert@acme.org".to_string()),
+            ..Default::default()
+        };
+        let profile = UserProfile::update(2, &input, &mailer, &db).await?;
+        assert_eq!(profile.email, "bob@acme.org");
+        assert_eq!(profile.pending_email.as_deref(), Some("robert@acme.org"));
+
+        let input = VerifyEmail {
+            token: "wrong".to_string(),
+        };
+        let ret = UserProfile::verify_email(2, &input, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        // the token is mailed to the new email, the database only has its hash
+        let mail = mailer.sent().pop().expect("a verification mail");
+        assert_eq!(mail.to, "robert@acme.org");
+        let token = mail.text.lines().nth(2).expect("a token").to_string();
+        let stored: String = sqlx::query_scalar("SELECT email_token FROM users WHERE id = 2")
+            .fetch_one(&*db)
+            .await?;
+        assert_eq!(stored, hash_token(&token));
+        let input = VerifyEmail { token: stored };
+        let ret = UserProfile::verify_email(2, &input, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let profile = UserProfile::verify_email(2, &VerifyEmail { token }, &db).await?;
+        assert_eq!(profile.email, "robert@acme.org");
+        assert!(profile.pending_email.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_users_should_hide_email_from_non_admins() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = ListUsers {
+            q: Some("li".to_string()),
+            limit: None,
+        };
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let users = PublicUser::list(&input, &bob, &db).await?;
+        let names: Vec<_> = users.iter().map(|u| u.fullname.as_str()).collect();
+        assert_eq!(names, vec!["Alice", "Charlie"]);
+        assert!(users.iter().all(|u| u.email.is_none()));
+
+        // alice owns the workspace
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let users = PublicUser::list(&input, &alice, &db).await?;
+        assert_eq!(users[0].email.as_deref(), Some("alice@acme.org"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_users_should_only_show_chat_members_to_guests() -> Result<()> {
+        let db = create_test_pool().await?;
+        sqlx::query("UPDATE users SET role = 'guest' WHERE id IN (3, 4)")
+            .execute(&*db)
+            .await?;
+        let input = ListUsers::default();
+        let charlie = User::new(3, "Charlie", "charlie@acme.org");
+        let users = PublicUser::list(&input, &charlie, &db).await?;
+        let names: Vec<_> = users.iter().map(|u| u.fullname.as_str()).collect();
+        assert_eq!(names, vec!["Alice", "Bob", "Charlie"]);
+
+        // dave was not invited to any chat yet
+        let dave = User::new(4, "Dave", "dave@acme.org");
+        assert!(PublicUser::list(&input, &dave, &db).await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use sqlx::{PgConnection, PgPool};
+
+use crate::{config::UploadConfig, AppError};
+
+use super::{User, UserStorage, Workspace, WorkspaceStorage};
+
+impl WorkspaceStorage {
+    /// The usage of the workspace by user, only its admins can see it.
+    pub async fn get(
+        ws_id: i64,
+        user_id: i64,
+        config: &UploadConfig,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let ws = Workspace::ensure_admin(ws_id, user_id, pool).await?;
+        let users: Vec<UserStorage> = sqlx::query_as(
+            r#"
+            SELECT u.id AS user_id, u.fullname, u.email,
+                COALESCE(s.files, 0) AS files, COALESCE(s.bytes, 0) AS bytes,
+                COALESCE(u.storage_quota, $2) AS quota
+            FROM users u
+            LEFT JOIN storage_usage s ON s.user_id = u.id
+            WHERE u.ws_id = $1
+            ORDER BY bytes DESC, u.id
+            "#,
+        )
+        .bind(ws_id)
+        .bind(config.user_quota as i64)
+        .fetch_all(pool)
+        .await?;
+        let quota: i64 =
+            sqlx::query_scalar("SELECT COALESCE(storage_quota, $2) FROM workspaces WHERE id = $1")
+                .bind(ws.id)
+                .bind(config.workspace_quota as i64)
+                .fetch_one(pool)
+                .await?;
+        Ok(Self {
+            ws_id,
+            files: users.iter().map(|u| u.files).sum(),
+            bytes: users.iter().map(|u| u.bytes).sum(),
+            quota,
+            users,
+        })
+    }
+
+    /// How many bytes the user can still upload, within both its own quota and the one of
+    /// its workspace. It is negative once a quota is exceeded.
+    pub async fn remaining(
+        user: &User,
+        config: &UploadConfig,
+        conn: &mut PgConnection,
+    ) -> Result<i64, AppError> {
+        let remaining = sqlx::query_scalar(
+            r#"
+            SELECT LEAST(
+                COALESCE(u.storage_quota, $3)
+                    - COALESCE((SELECT bytes FROM storage_usage WHERE user_id = u.id), 0),
+                COALESCE(w.storage_quota, $4)
+                    - COALESCE((SELECT sum(bytes) FROM storage_usage WHERE ws_id = w.id), 0)
+            )::bigint
+            FROM users u
+            JOIN workspaces w ON w.id = u.ws_id
+            WHERE u.id = $1 AND w.id = $2
+            "#,
+        )
+        .bind(user.id)
+        .bind(user.ws_id)
+        .bind(config.user_quota as i64)
+        .bind(config.workspace_quota as i64)
+        .fetch_optional(conn)
+        .await?;
+        remaining.ok_or_else(|| AppError::NotFound(format!("user {}", user.id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{utils::create_test_pool, AppConfig};
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn storage_usage_should_follow_files() -> Result<()> {
+        let db = create_test_pool().await?;
+        let config = AppConfig::load()?.upload;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        sqlx::query("UPDATE users SET storage_quota = 100 WHERE id = 1")
+            .execute(&*db)
+            .await?;
+        let mut conn = db.acquire().await?;
+        assert_eq!(
+            WorkspaceStorage::remaining(&alice, &config, &mut conn).await?,
+            100
+        );
+
+        sqlx::query(
+            r#"
+            INSERT INTO files (ws_id, owner_id, hash, ext, name, mime, size)
+            VALUES (1, 1, repeat('a', 64), 'txt', 'a.txt', 'text/plain', 30),
+                (1, 1, repeat('b', 64), 'txt', 'b.txt', 'text/plain', 50),
+                (1, 2, repeat('c', 64), 'txt', 'c.txt', 'text/plain', 7)
+            "#,
+        )
+        .execute(&*db)
+        .await?;
+        assert_eq!(
+            WorkspaceStorage::remaining(&alice, &config, &mut conn).await?,
+            20
+        );
+        sqlx::query("DELETE FROM files WHERE hash = repeat('b', 64)")
+            .execute(&*db)
+            .await?;
+        assert_eq!(
+            WorkspaceStorage::remaining(&alice, &config, &mut conn).await?,
+            70
+        );
+
+        sqlx::query("UPDATE workspaces SET storage_quota = 40 WHERE id = 1")
+            .execute(&*db)
+            .await?;
+        assert_eq!(
+            WorkspaceStorage::remaining(&alice, &config, &mut conn).await?,
+            3
+        );
+
+        let ret = WorkspaceStorage::get(1, 2, &config, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let usage = WorkspaceStorage::get(1, 1, &config, &db).await?;
+        assert_eq!((usage.files, usage.bytes, usage.quota), (2, 37, 40));
+        assert_eq!(usage.users[0].user_id, 1);
+        assert_eq!(usage.users[0].bytes, 30);
+        assert_eq!(usage.users[0].quota, 100);
+        assert_eq!(usage.users[1].user_id, 2);
+        assert_eq!(usage.users[1].quota, config.user_quota as i64);
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use sqlx::{FromRow, PgPool};
+
+use crate::AppError;
+
+use super::{Chat, Message, Reaction};
+
+const MAX_EMOJI_LEN: usize = 64;
+
+#[derive(Debug, FromRow)]
+struct ReactionRow {
+    message_id: i64,
+    #[sqlx(flatten)]
+    reaction: Reaction,
+}
+
+impl Message {
+    /// React to a message, reacting twice with the same emoji is a no-op.
+    pub async fn add_reaction(
+        id: i64,
+        emoji: &str,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        validate_emoji(emoji)?;
+        let message = Self::get_by_id(id, pool).await?;
+        let message = match message {
+            Some(message) if message.deleted_at.is_none() => message,
+            _ => return Err(AppError::NotFound(format!("message {}", id))),
+        };
+        Chat::get_for_member(message.chat_id, user_id, pool).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(emoji)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_reaction(
+        id: i64,
+        emoji: &str,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        let message = Self::get_by_id(id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("message {}", id)))?;
+        Chat::get_for_member(message.chat_id, user_id, pool).await?;
+
+        sqlx::query(
+            "DELETE FROM message_reactions WHERE message_id = $1 AND user_id = $2 AND emoji = $3",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(emoji)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Fill the reactions of a page of messages with a single query.
+pub(super) async fn attach_reactions(
+    messages: &mut [Message],
+    user_id: i64,
+    pool: &PgPool,
+) -> Result<(), AppError> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+    let rows: Vec<ReactionRow> = sqlx::query_as(
+        r#"
+        SELECT message_id, emoji, count(*) AS count, bool_or(user_id = $2) AS reacted
+        FROM message_reactions
+        WHERE message_id = ANY($1)
+        GROUP BY message_id, emoji
+        ORDER BY message_id, min(created_at), emoji
+        "#,
+    )
+    .bind(&ids)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut reactions: HashMap<i64, Vec<Reaction>> = HashMap::new();
+    for row in rows {
+        reactions
+            .entry(row.message_id)
+            .or_default()
+            .push(row.reaction);
+    }
+    for message in messages.iter_mut() {
+        message.reactions = reactions.remove(&message.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+fn validate_emoji(emoji: &str) -> Result<(), AppError> {
+    if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN || emoji.chars().any(char::is_whitespace) {
+        return Err(AppError::InvalidInput(format!("invalid emoji: {}", emoji)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{models::ListMessages, utils::create_test_pool};
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn reactions_should_be_aggregated_in_history() -> Result<()> {
+        let db = create_test_pool().await?;
+        Message::add_reaction(1, "👍", 1, &db).await?;
+        Message::add_reaction(1, "👍", 2, &db).await?;
+        Message::add_reaction(1, "👍", 2, &db).await?;
+        Message::add_reaction(1, "🎉", 3, &db).await?;
+        Message::add_reaction(2, "🎉", 3, &db).await?;
+
+        let messages = Message::list(&ListMessages::default(), 1, 2, &db).await?;
+        let message = messages.iter().find(|m| m.id == 1).expect("message exists");
+        assert_eq!(
+            message.reactions,
+            vec![
+                Reaction {
+                    emoji: "👍".to_string(),
+                    count: 2,
+                    reacted: true,
+                },
+                Reaction {
+                    emoji: "🎉".to_string(),
+                    count: 1,
+                    reacted: false,
+                },
+            ]
+        );
+        let message = messages.iter().find(|m| m.id == 3).expect("message exists");
+        assert!(message.reactions.is_empty());
+
+        Message::remove_reaction(1, "👍", 2, &db).await?;
+        let messages = Message::list(&ListMessages::default(), 1, 2, &db).await?;
+        let message = messages.iter().find(|m| m.id == 1).expect("message exists");
+        assert_eq!(message.reactions[0].count, 1);
+        assert!(!message.reactions[0].reacted);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reaction_should_require_membership() -> Result<()> {
+        let db = create_test_pool().await?;
+        let ret = Message::add_reaction(4, "👍", 1, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = Message::add_reaction(1, "a b", 1, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        Ok(())
+    }
+}
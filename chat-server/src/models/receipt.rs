@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use super::{Chat, ChatType, Message};
+
+/// Read receipts are only tracked per message for small groups.
+const MAX_READ_RECEIPT_MEMBERS: usize = 32;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarkRead {
+    pub message_id: i64,
+}
+
+impl Message {
+    /// Advance the read position of a member, it never moves backwards.
+    pub async fn mark_read(
+        id: i64,
+        chat_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        Chat::get_for_member(chat_id, user_id, pool).await?;
+        match Self::get_by_id(id, pool).await? {
+            Some(message) if message.chat_id == chat_id => {}
+            _ => return Err(AppError::NotFound(format!("message {}", id))),
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO chat_reads (chat_id, user_id, last_read_message_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+            SET last_read_message_id = GREATEST(chat_reads.last_read_message_id, EXCLUDED.last_read_message_id),
+                updated_at = now()
+            "#,
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Members who have read the message, available in small single and group chats.
+    pub async fn read_by(id: i64, user_id: i64, pool: &PgPool) -> Result<Vec<i64>, AppError> {
+        let message = Self::get_by_id(id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("message {}", id)))?;
+        let chat = Chat::get_for_member(message.chat_id, user_id, pool).await?;
+        if !matches!(chat.r#type, ChatType::Single | ChatType::Group)
+            || chat.members.len() > MAX_READ_RECEIPT_MEMBERS
+        {
+            return Err(AppError::InvalidInput(format!(
+                "read receipts are not available in chat {}",
+                chat.id
+            )));
+        }
+
+        let users = sqlx::query_scalar(
+            r#"
+            SELECT user_id
+            FROM chat_reads
+            WHERE chat_id = $1 AND last_read_message_id >= $2 AND user_id = ANY($3)
+            ORDER BY user_id
+            "#,
+        )
+        .bind(chat.id)
+        .bind(id)
+        .bind(&chat.members)
+        .fetch_all(pool)
+        .await?;
+        Ok(users)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn mark_read_should_never_move_backwards() -> Result<()> {
+        let db = create_test_pool().await?;
+        Message::mark_read(3, 1, 2, &db).await?;
+        Message::mark_read(1, 1, 2, &db).await?;
+        Message::mark_read(2, 1, 3, &db).await?;
+
+        assert_eq!(Message::read_by(3, 1, &db).await?, vec![2]);
+        assert_eq!(Message::read_by(2, 1, &db).await?, vec![2, 3]);
+
+        let ret = Message::mark_read(4, 1, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,504 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::warn;
+
+use crate::{storage::Storage, AppError};
+
+use super::{Attachment, Chat, ChatFile, ChatRetention, LegalHold, Message, Workspace};
+
+/// Retention is limited to about a hundred years.
+const MAX_RETENTION_DAYS: i32 = 36_500;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateRetention {
+    /// days messages are kept, forever when absent
+    pub retention_days: Option<i32>,
+    /// archive expired messages before they are deleted
+    #[serde(default)]
+    pub archive: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateChatRetention {
+    /// days messages of the chat are kept, the setting of the workspace is used when absent
+    pub retention_days: Option<i32>,
+}
+
+/// Either a chat or a user is held.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateLegalHold {
+    pub chat_id: Option<i64>,
+    pub user_id: Option<i64>,
+    pub reason: String,
+}
+
+impl Workspace {
+    /// Change how long messages of the workspace are kept, for its admins.
+    pub async fn update_retention(
+        id: i64,
+        input: &UpdateRetention,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let ws = Self::ensure_admin(id, user_id, pool).await?;
+        check_retention_days(input.retention_days)?;
+        sqlx::query(
+            "UPDATE workspaces SET retention_days = $2, retention_archive = $3 WHERE id = $1",
+        )
+        .bind(id)
+        .bind(input.retention_days)
+        .bind(input.archive)
+        .execute(pool)
+        .await?;
+        Ok(Workspace {
+            retention_days: input.retention_days,
+            retention_archive: input.archive,
+            ..ws
+        })
+    }
+}
+
+impl Chat {
+    /// How long the messages of the chat are kept, members can see it.
+    pub async fn get_retention(
+        id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<ChatRetention, AppError> {
+        Self::get_for_member(id, user_id, pool).await?;
+        Self::retention(id, pool).await
+    }
+
+    /// Change how long the messages of a chat are kept, for the admins of its workspace.
+    pub async fn update_retention(
+        id: i64,
+        input: &UpdateChatRetention,
+        user_id: i64,
+        ws_id: i64,
+        pool: &PgPool,
+    ) -> Result<ChatRetention, AppError> {
+        Workspace::ensure_admin(ws_id, user_id, pool).await?;
+        match Self::get_by_id(id, pool).await? {
+            Some(chat) if chat.ws_id == ws_id => {}
+            _ => return Err(AppError::NotFound(format!("chat {}", id))),
+        }
+        check_retention_days(input.retention_days)?;
+        sqlx::query("UPDATE chats SET retention_days = $2 WHERE id = $1")
+            .bind(id)
+            .bind(input.retention_days)
+            .execute(pool)
+            .await?;
+        Self::retention(id, pool).await
+    }
+
+    async fn retention(id: i64, pool: &PgPool) -> Result<ChatRetention, AppError> {
+        sqlx::query_as(
+            r#"
+            SELECT c.id AS chat_id, c.retention_days,
+                COALESCE(c.retention_days, w.retention_days) AS effective_days
+            FROM chats c
+            JOIN workspaces w ON w.id = c.ws_id
+            WHERE c.id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("chat {}", id)))
+    }
+}
+
+impl LegalHold {
+    /// Holds of the workspace, active ones first, for its admins.
+    pub async fn list(ws_id: i64, user_id: i64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        Workspace::ensure_admin(ws_id, user_id, pool).await?;
+        let holds = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, chat_id, user_id, reason, created_by, created_at, released_at
+            FROM legal_holds
+            WHERE ws_id = $1
+            ORDER BY released_at IS NOT NULL, id DESC
+            "#,
+        )
+        .bind(ws_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(holds)
+    }
+
+    pub async fn create(
+        input: &CreateLegalHold,
+        user_id: i64,
+        ws_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        Workspace::ensure_admin(ws_id, user_id, pool).await?;
+        if input.reason.trim().is_empty() {
+            return Err(AppError::InvalidInput(
+                "the reason of a legal hold cannot be empty".to_string(),
+            ));
+        }
+        let in_workspace: bool = match (input.chat_id, input.user_id) {
+            (Some(chat_id), None) => {
+                sqlx::query_scalar(
+                    "SELECT EXISTS(SELECT 1 FROM chats WHERE id = $1 AND ws_id = $2)",
+                )
+                .bind(chat_id)
+                .bind(ws_id)
+                .fetch_one(pool)
+                .await?
+            }
+            (None, Some(held_id)) => {
+                sqlx::query_scalar(
+                    "SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND ws_id = $2)",
+                )
+                .bind(held_id)
+                .bind(ws_id)
+                .fetch_one(pool)
+                .await?
+            }
+            _ => {
+                return Err(AppError::InvalidInput(
+                    "a legal hold is either for a chat or a user".to_string(),
+                ))
+            }
+        };
+        if !in_workspace {
+            return Err(AppError::NotFound(format!(
+                "chat or user of workspace {}",
+                ws_id
+            )));
+        }
+
+        let hold = sqlx::query_as(
+            r#"
+            INSERT INTO legal_holds (ws_id, chat_id, user_id, reason, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, ws_id, chat_id, user_id, reason, created_by, created_at, released_at
+            "#,
+        )
+        .bind(ws_id)
+        .bind(input.chat_id)
+        .bind(input.user_id)
+        .bind(input.reason.trim())
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+        Ok(hold)
+    }
+
+    /// Purging resumes once all holds of a chat or user are released.
+    pub async fn release(
+        id: i64,
+        user_id: i64,
+        ws_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        Workspace::ensure_admin(ws_id, user_id, pool).await?;
+        let hold: Option<Self> = sqlx::query_as(
+            r#"
+            UPDATE legal_holds
+            SET released_at = COALESCE(released_at, now())
+            WHERE id = $1 AND ws_id = $2
+            RETURNING id, ws_id, chat_id, user_id, reason, created_by, created_at, released_at
+            "#,
+        )
+        .bind(id)
+        .bind(ws_id)
+        .fetch_optional(pool)
+        .await?;
+        hold.ok_or_else(|| AppError::NotFound(format!("legal hold {}", id)))
+    }
+}
+
+impl Message {
+    /// Purge a batch of messages older than the retention of their chat, returns how many
+    /// were purged. Messages of held chats and senders are kept. Thread roots with replies
+    /// which are not purged yet become tombstones, their content, attachments, edits and
+    /// reactions are removed but the row stays for the replies. Workspaces archiving messages
+    /// keep the message with its reactions and attachments, otherwise files no other message
+    /// refers to are deleted from the storage too.
+    pub async fn purge_expired(
+        limit: i64,
+        storage: &dyn Storage,
+        pool: &PgPool,
+    ) -> Result<usize, AppError> {
+        let mut tx = pool.begin().await?;
+        // locked rows are skipped, the batch only waits for the rows it deletes
+        let expired: Vec<(i64, bool, bool)> = sqlx::query_as(
+            r#"
+            WITH policies AS (
+                SELECT c.id AS chat_id, w.retention_archive AS archive,
+                    now() - make_interval(days => COALESCE(c.retention_days, w.retention_days))
+                        AS cutoff
+                FROM chats c
+                JOIN workspaces w ON w.id = c.ws_id
+                WHERE COALESCE(c.retention_days, w.retention_days) IS NOT NULL
+                    AND NOT EXISTS (
+                        SELECT 1 FROM legal_holds h
+                        WHERE h.chat_id = c.id AND h.released_at IS NULL
+                    )
+            )
+            SELECT m.id, p.archive, r.replied
+            FROM messages m
+            JOIN policies p ON p.chat_id = m.chat_id
+            CROSS JOIN LATERAL (
+                SELECT EXISTS (SELECT 1 FROM messages r WHERE r.parent_id = m.id) AS replied
+            ) r
+            WHERE m.created_at < p.cutoff
+                AND NOT EXISTS (
+                    SELECT 1 FROM legal_holds h
+                    WHERE h.user_id = m.sender_id AND h.released_at IS NULL
+                )
+                -- thread roots which are tombstones already wait for their replies
+                AND (NOT r.replied OR m.deleted_at IS NULL OR m.content <> ''
+                    OR EXISTS (SELECT 1 FROM message_edits e WHERE e.message_id = m.id))
+            ORDER BY m.id
+            LIMIT $1
+            FOR UPDATE OF m SKIP LOCKED
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        let ids: Vec<i64> = expired.iter().map(|(id, _, _)| *id).collect();
+        let roots: Vec<i64> = expired
+            .iter()
+            .filter(|(_, _, replied)| *replied)
+            .map(|(id, _, _)| *id)
+            .collect();
+        let archived: Vec<i64> = expired
+            .iter()
+            .filter(|(_, archive, _)| *archive)
+            .map(|(id, _, _)| *id)
+            .collect();
+        if !archived.is_empty() {
+            sqlx::query(
+                r#"
+                INSERT INTO archived_messages (id, chat_id, sender_id, message, reactions,
+                    created_at)
+                SELECT m.id, m.chat_id, m.sender_id, to_jsonb(m) - 'content_tsv',
+                    COALESCE((
+                        SELECT jsonb_agg(jsonb_build_object('user_id', r.user_id,
+                            'emoji', r.emoji, 'created_at', r.created_at))
+                        FROM message_reactions r
+                        WHERE r.message_id = m.id
+                    ), '[]'),
+                    m.created_at
+                FROM messages m
+                WHERE m.id = ANY($1)
+                ON CONFLICT (id) DO NOTHING
+                "#,
+            )
+            .bind(&archived)
+            .execute(&mut *tx)
+            .await?;
+        }
+        // edits, reactions, mentions, pins and followers are deleted with the message
+        let mut deleted: Vec<(i64, sqlx::types::Json<Vec<Attachment>>)> = sqlx::query_as(
+            "DELETE FROM messages WHERE id = ANY($1) AND NOT id = ANY($2) RETURNING id, attachments",
+        )
+        .bind(&ids)
+        .bind(&roots)
+        .fetch_all(&mut *tx)
+        .await?;
+        if !roots.is_empty() {
+            sqlx::query("DELETE FROM message_edits WHERE message_id = ANY($1)")
+                .bind(&roots)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM message_reactions WHERE message_id = ANY($1)")
+                .bind(&roots)
+                .execute(&mut *tx)
+                .await?;
+            let cleared: Vec<(i64, sqlx::types::Json<Vec<Attachment>>)> = sqlx::query_as(
+                r#"
+                UPDATE messages m
+                SET content = '', content_text = '', attachments = '[]',
+                    deleted_at = COALESCE(m.deleted_at, now())
+                FROM messages old
+                WHERE old.id = m.id AND m.id = ANY($1)
+                RETURNING m.id, old.attachments
+                "#,
+            )
+            .bind(&roots)
+            .fetch_all(&mut *tx)
+            .await?;
+            deleted.extend(cleared);
+        }
+        tx.commit().await?;
+
+        let urls: Vec<String> = deleted
+            .into_iter()
+            .filter(|(id, _)| !archived.contains(id))
+            .flat_map(|(_, attachments)| attachments.0)
+            .filter_map(|attachment| match attachment {
+                Attachment::File { url, .. } | Attachment::Image { url, .. } => Some(url),
+                _ => None,
+            })
+            .collect();
+        for file in ChatFile::delete_unreferenced(&urls, pool).await? {
+            for key in file.keys() {
+                // the rows are gone already, a failed delete leaves an unused object behind
+                if let Err(e) = storage.delete(&key).await {
+                    warn!("failed to delete {} from the storage: {}", key, e);
+                }
+            }
+        }
+        Ok(ids.len())
+    }
+}
+
+fn check_retention_days(days: Option<i32>) -> Result<(), AppError> {
+    match days {
+        Some(days) if !(1..=MAX_RETENTION_DAYS).contains(&days) => {
+            Err(AppError::InvalidInput(format!(
+                "retention must be between 1 and {} days",
+                MAX_RETENTION_DAYS
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Bytes;
+    use futures_util::stream;
+
+    use crate::{
+        media::MediaPool,
+        models::{ChatFile, User},
+        storage::LocalStorage,
+        utils::create_test_pool,
+        AppConfig,
+    };
+
+    use super::*;
+    use anyhow::Result;
+
+    async fn message_ids(db: &PgPool) -> Result<Vec<i64>> {
+        Ok(sqlx::query_scalar("SELECT id FROM messages ORDER BY id")
+            .fetch_all(db)
+            .await?)
+    }
+
+    #[tokio::test]
+    async fn purge_expired_should_follow_retention_and_holds() -> Result<()> {
+        let db = create_test_pool().await?;
+        let dir = std::env::temp_dir().join(format!("crablink-{}", uuid::Uuid::new_v4()));
+        let storage = LocalStorage::new(&dir);
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let config = AppConfig::load()?.upload;
+        let mut body = stream::iter([Ok(Bytes::from_static(b"minutes of the meeting"))]);
+        let file = ChatFile::upload(
+            "minutes.txt",
+            &mut body,
+            &alice,
+            &config,
+            &MediaPool::new(1),
+            &storage,
+            &db,
+        )
+        .await?;
+        sqlx::query("UPDATE messages SET created_at = now() - interval '100 days', attachments = $1 WHERE id = 1")
+            .bind(sqlx::types::Json(vec![file.attachment()]))
+            .execute(&*db)
+            .await?;
+        sqlx::query("UPDATE messages SET created_at = now() - interval '100 days' WHERE id > 1")
+            .execute(&*db)
+            .await?;
+        sqlx::query(
+            "INSERT INTO message_reactions (message_id, user_id, emoji) VALUES (1, 2, '👍')",
+        )
+        .execute(&*db)
+        .await?;
+        // a recent reply keeps its thread
+        sqlx::query("INSERT INTO messages (chat_id, sender_id, content, parent_id) VALUES (1, 1, 'still here', 2)")
+            .execute(&*db)
+            .await?;
+        assert_eq!(Message::purge_expired(100, &storage, &db).await?, 0);
+
+        let input = UpdateRetention {
+            retention_days: Some(90),
+            archive: false,
+        };
+        let ws = Workspace::update_retention(1, &input, 1, &db).await?;
+        assert_eq!(ws.retention_days, Some(90));
+        let input = UpdateChatRetention {
+            retention_days: Some(365),
+        };
+        let ret = Chat::update_retention(2, &input, 2, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let retention = Chat::update_retention(2, &input, 1, 1, &db).await?;
+        assert_eq!(retention.effective_days, Some(365));
+        let retention = Chat::get_retention(1, 2, &db).await?;
+        assert_eq!(
+            (retention.retention_days, retention.effective_days),
+            (None, Some(90))
+        );
+        let input = CreateLegalHold {
+            user_id: Some(3),
+            reason: "case 42".to_string(),
+            ..Default::default()
+        };
+        let hold = LegalHold::create(&input, 1, 1, &db).await?;
+
+        // the expired root of the recent reply becomes a tombstone
+        assert_eq!(Message::purge_expired(100, &storage, &db).await?, 2);
+        assert_eq!(message_ids(&db).await?, [2, 3, 4, 5]);
+        let root = Message::get_by_id(2, &db).await?.expect("message exists");
+        assert_eq!((root.content.as_str(), root.attachments.len()), ("", 0));
+        assert!(root.deleted_at.is_some());
+        assert_eq!(Message::purge_expired(100, &storage, &db).await?, 0);
+        let reactions: i64 = sqlx::query_scalar("SELECT count(*) FROM message_reactions")
+            .fetch_one(&*db)
+            .await?;
+        assert_eq!(reactions, 0);
+        assert_eq!(storage.head(&file.keys()[0]).await?, None);
+
+        LegalHold::release(hold.id, 1, 1, &db).await?;
+        let input = UpdateRetention {
+            retention_days: Some(30),
+            archive: true,
+        };
+        Workspace::update_retention(1, &input, 1, &db).await?;
+        assert_eq!(Message::purge_expired(100, &storage, &db).await?, 1);
+        assert_eq!(message_ids(&db).await?, [2, 4, 5]);
+        let archived: Vec<i64> = sqlx::query_scalar("SELECT id FROM archived_messages")
+            .fetch_all(&*db)
+            .await?;
+        assert_eq!(archived, [3]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn legal_hold_should_be_for_a_chat_or_a_user() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateLegalHold {
+            chat_id: Some(1),
+            user_id: Some(2),
+            reason: "case 42".to_string(),
+        };
+        let ret = LegalHold::create(&input, 1, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        let input = CreateLegalHold {
+            chat_id: Some(2),
+            reason: "case 42".to_string(),
+            ..Default::default()
+        };
+        let ret = LegalHold::create(&input, 2, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let hold = LegalHold::create(&input, 1, 1, &db).await?;
+        let released = LegalHold::release(hold.id, 1, 1, &db).await?;
+        assert!(released.released_at.is_some());
+        let holds = LegalHold::list(1, 1, &db).await?;
+        assert_eq!(holds, [released]);
+        Ok(())
+    }
+}
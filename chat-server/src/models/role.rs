@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use super::{User, Workspace, WorkspaceRole};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateRole {
+    pub role: WorkspaceRole,
+}
+
+impl User {
+    /// The current role of the user in the workspace, none when it is not one of its users.
+    pub async fn get_role(
+        id: i64,
+        ws_id: i64,
+        pool: &PgPool,
+    ) -> Result<Option<WorkspaceRole>, AppError> {
+        let role = sqlx::query_scalar("SELECT role FROM users WHERE id = $1 AND ws_id = $2")
+            .bind(id)
+            .bind(ws_id)
+            .fetch_optional(pool)
+            .await?;
+        Ok(role)
+    }
+
+    /// Change the role of a user of the workspace, returning its previous role. Admins manage
+    /// members and guests, only the owner manages admins. The owner cannot be changed here.
+    pub async fn update_role(
+        id: i64,
+        input: &UpdateRole,
+        user: &User,
+        pool: &PgPool,
+    ) -> Result<(Self, WorkspaceRole), AppError> {
+        let ws = Workspace::ensure_admin(user.ws_id, user.id, pool).await?;
+        let target = match Self::find_by_id(id, pool).await? {
+            Some(target) if target.ws_id == ws.id && !target.is_bot => target,
+            _ => return Err(AppError::NotFound(format!("user {}", id))),
+        };
+        if input.role == WorkspaceRole::Owner || target.role == WorkspaceRole::Owner {
+            return Err(AppError::InvalidInput(
+                "the owner of a workspace cannot be changed".to_string(),
+            ));
+        }
+        let is_owner = ws.owner_id == Some(user.id);
+        if !is_owner && (target.role >= WorkspaceRole::Admin || input.role >= WorkspaceRole::Admin)
+        {
+            return Err(AppError::PermissionDenied(format!(
+                "only the owner of workspace {} can manage its admins",
+                ws.id
+            )));
+        }
+
+        let updated = sqlx::query_as(
+            r#"
+            UPDATE users SET role = $2
+            WHERE id = $1
+            RETURNING id, ws_id, fullname, email, is_bot, role, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(input.role)
+        .fetch_one(pool)
+        .await?;
+        Ok((updated, target.role))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn roles_should_be_managed_by_admins() -> Result<()> {
+        let db = create_test_pool().await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        let admin = UpdateRole {
+            role: WorkspaceRole::Admin,
+        };
+        let guest = UpdateRole {
+            role: WorkspaceRole::Guest,
+        };
+
+        let ret = User::update_role(3, &guest, &bob, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let (user, previous) = User::update_role(2, &admin, &alice, &db).await?;
+        assert_eq!(
+            (user.role, previous),
+            (WorkspaceRole::Admin, WorkspaceRole::Member)
+        );
+        assert_eq!(User::get_role(2, 1, &db).await?, Some(WorkspaceRole::Admin));
+        assert!(Workspace::ensure_admin(1, 2, &db).await.is_ok());
+
+        // admins manage members and guests but not other admins
+        let (user, _) = User::update_role(3, &guest, &bob, &db).await?;
+        assert_eq!(user.role, WorkspaceRole::Guest);
+        let ret = User::update_role(3, &admin, &bob, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = User::update_role(2, &guest, &bob, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let ret = User::update_role(1, &guest, &bob, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let owner = UpdateRole {
+            role: WorkspaceRole::Owner,
+        };
+        let ret = User::update_role(2, &owner, &alice, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        assert_eq!(User::get_role(2, 2, &db).await?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, PgConnection, PgPool, Postgres, Transaction};
+use tracing::warn;
+
+use crate::AppError;
+
+use super::{
+    format::prepare, Attachment, Chat, CreateMessage, Message, MessageFormat, ScheduledMessage,
+};
+
+/// How far in the future a message can be scheduled.
+const MAX_SCHEDULE_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateScheduledMessage {
+    pub content: Option<String>,
+    pub format: Option<MessageFormat>,
+    pub attachments: Option<Vec<Attachment>>,
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+impl ScheduledMessage {
+    /// Store a message to be sent at `send_at`, the membership is checked again on delivery.
+    pub async fn create(
+        input: &CreateMessage,
+        send_at: DateTime<Utc>,
+        chat_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let rendered = prepare(input.format, &input.content, &input.attachments)?;
+        check_send_at(send_at)?;
+        Chat::get_for_member(chat_id, user_id, pool).await?;
+        if let Some(parent_id) = input.parent_id {
+            Message::get_thread_root(parent_id, chat_id, pool).await?;
+        }
+
+        let scheduled = sqlx::query_as(
+            r#"
+            INSERT INTO scheduled_messages (chat_id, sender_id, parent_id, format, content, attachments,
+                send_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, chat_id, sender_id, parent_id, format, content, attachments, send_at, error,
+                created_at
+            "#,
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .bind(input.parent_id)
+        .bind(input.format)
+        .bind(&rendered.content)
+        .bind(Json(&input.attachments))
+        .bind(send_at)
+        .fetch_one(pool)
+        .await?;
+        Ok(scheduled)
+    }
+
+    /// Scheduled messages of the user, the next to be sent first. Failed ones are listed too.
+    pub async fn list(user_id: i64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        let scheduled = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, parent_id, format, content, attachments, send_at, error,
+                created_at
+            FROM scheduled_messages
+            WHERE sender_id = $1
+            ORDER BY send_at, id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(scheduled)
+    }
+
+    /// Change a pending message of the user.
+    pub async fn update(
+        id: i64,
+        input: &UpdateScheduledMessage,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        if let Some(send_at) = input.send_at {
+            check_send_at(send_at)?;
+        }
+        let current: Self = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, parent_id, format, content, attachments, send_at, error,
+                created_at
+            FROM scheduled_messages
+            WHERE id = $1 AND sender_id = $2 AND error IS NULL
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("scheduled message {}", id)))?;
+        let format = input.format.unwrap_or(current.format);
+        let content = input.content.as_ref().unwrap_or(&current.content);
+        let attachments = input.attachments.as_ref().unwrap_or(&current.attachments);
+        let rendered = prepare(format, content, attachments)?;
+
+        let scheduled = sqlx::query_as(
+            r#"
+            UPDATE scheduled_messages
+            SET format = $3, content = $4, attachments = $5, send_at = COALESCE($6, send_at)
+            WHERE id = $1 AND sender_id = $2 AND error IS NULL
+            RETURNING id, chat_id, sender_id, parent_id, format, content, attachments, send_at, error,
+                created_at
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(format)
+        .bind(&rendered.content)
+        .bind(Json(attachments))
+        .bind(input.send_at)
+        .fetch_optional(pool)
+        .await?;
+        scheduled.ok_or_else(|| AppError::NotFound(format!("scheduled message {}", id)))
+    }
+
+    /// Cancel a scheduled message, failed ones can be dismissed the same way.
+    pub async fn cancel(id: i64, user_id: i64, pool: &PgPool) -> Result<(), AppError> {
+        let ret = sqlx::query("DELETE FROM scheduled_messages WHERE id = $1 AND sender_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        match ret.rows_affected() {
+            0 => Err(AppError::NotFound(format!("scheduled message {}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send the due messages through the same path as `POST /api/chats/:id/messages`.
+    /// Each row stays locked while its message is created and is deleted in the same
+    /// transaction, so a message is neither sent twice by several workers nor lost when the
+    /// server stops halfway. A failed delivery keeps the row with its error.
+    pub async fn deliver_due(limit: i64, pool: &PgPool) -> Result<usize, AppError> {
+        let mut delivered = 0;
+        for _ in 0..limit {
+            let mut tx = pool.begin().await?;
+            let Some(scheduled) = Self::claim_due(&mut tx).await? else {
+                break;
+            };
+            match Self::deliver(&scheduled, tx, pool).await {
+                Ok(()) => delivered += 1,
+                Err(e) => {
+                    warn!(
+                        "failed to deliver scheduled message {}: {}",
+                        scheduled.id, e
+                    );
+                    // the row is retried on the next run if it cannot be marked
+                    if let Err(e) = Self::mark_failed(scheduled.id, &e.to_string(), pool).await {
+                        warn!("failed to mark scheduled message {}: {}", scheduled.id, e);
+                    }
+                }
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Lock the next due message, rows locked by other workers are skipped.
+    async fn claim_due(conn: &mut PgConnection) -> Result<Option<Self>, AppError> {
+        let scheduled = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, parent_id, format, content, attachments, send_at, error,
+                created_at
+            FROM scheduled_messages
+            WHERE send_at <= now() AND error IS NULL
+            ORDER BY send_at, id
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .fetch_optional(conn)
+        .await?;
+        Ok(scheduled)
+    }
+
+    /// Create the message and remove the scheduled one in the transaction which locked it.
+    async fn deliver(
+        scheduled: &Self,
+        mut tx: Transaction<'_, Postgres>,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        let input = CreateMessage {
+            content: scheduled.content.clone(),
+            format: scheduled.format,
+            attachments: scheduled.attachments.clone(),
+            parent_id: scheduled.parent_id,
+            send_at: None,
+        };
+        let new = Message::check_new(&input, scheduled.chat_id, scheduled.sender_id, pool).await?;
+        let message = new.insert(&mut tx).await?;
+        sqlx::query("DELETE FROM scheduled_messages WHERE id = $1")
+            .bind(scheduled.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        // the message is sent, it is not marked failed for its reports
+        if let Err(e) = new.report(&message, pool).await {
+            warn!("failed to report message {}: {}", message.id, e);
+        }
+        Ok(())
+    }
+
+    /// Keep a message which cannot be delivered with its error, failed messages are not
+    /// retried.
+    async fn mark_failed(id: i64, error: &str, pool: &PgPool) -> Result<(), AppError> {
+        sqlx::query("UPDATE scheduled_messages SET error = $2 WHERE id = $1")
+            .bind(id)
+            .bind(error)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+fn check_send_at(send_at: DateTime<Utc>) -> Result<(), AppError> {
+    let now = Utc::now();
+    if send_at <= now || send_at > now + Duration::days(MAX_SCHEDULE_DAYS) {
+        return Err(AppError::InvalidInput(format!(
+            "send_at must be within {} days from now",
+            MAX_SCHEDULE_DAYS
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{models::ListMessages, utils::create_test_pool};
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn scheduled_message_should_be_delivered_when_due() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateMessage {
+            content: "good night".to_string(),
+            ..Default::default()
+        };
+        let send_at = Utc::now() + Duration::hours(1);
+        let scheduled = ScheduledMessage::create(&input, send_at, 1, 2, &db).await?;
+        assert_eq!(ScheduledMessage::deliver_due(10, &db).await?, 0);
+
+        let input = UpdateScheduledMessage {
+            content: Some("good night!".to_string()),
+            ..Default::default()
+        };
+        let ret = ScheduledMessage::update(scheduled.id, &input, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        let scheduled = ScheduledMessage::update(scheduled.id, &input, 2, &db).await?;
+        assert_eq!(scheduled.content, "good night!");
+
+        sqlx::query("UPDATE scheduled_messages SET send_at = now() WHERE id = $1")
+            .bind(scheduled.id)
+            .execute(&*db)
+            .await?;
+        // a message another worker is delivering is left to it
+        let mut other = db.begin().await?;
+        sqlx::query("SELECT id FROM scheduled_messages WHERE id = $1 FOR UPDATE")
+            .bind(scheduled.id)
+            .execute(&mut *other)
+            .await?;
+        assert_eq!(ScheduledMessage::deliver_due(10, &db).await?, 0);
+        // and comes back when that worker stops before it is done
+        other.rollback().await?;
+        assert_eq!(ScheduledMessage::deliver_due(10, &db).await?, 1);
+        assert!(ScheduledMessage::list(2, &db).await?.is_empty());
+        let messages = Message::list(&ListMessages::default(), 1, 2, &db).await?;
+        assert_eq!(messages[0].content, "good night!");
+        assert_eq!(messages[0].sender_id, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_message_should_fail_without_membership() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateMessage {
+            content: "bye".to_string(),
+            ..Default::default()
+        };
+        let ret = ScheduledMessage::create(&input, Utc::now(), 1, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        let send_at = Utc::now() + Duration::hours(1);
+        let scheduled = ScheduledMessage::create(&input, send_at, 1, 3, &db).await?;
+        sqlx::query("UPDATE scheduled_messages SET send_at = now() WHERE id = $1")
+            .bind(scheduled.id)
+            .execute(&*db)
+            .await?;
+        sqlx::query("UPDATE chats SET members = '{1, 2}' WHERE id = 1")
+            .execute(&*db)
+            .await?;
+
+        assert_eq!(ScheduledMessage::deliver_due(10, &db).await?, 0);
+        let scheduled = ScheduledMessage::list(3, &db).await?;
+        assert!(scheduled[0].error.is_some());
+
+        ScheduledMessage::cancel(scheduled[0].id, 3, &db).await?;
+        assert!(ScheduledMessage::list(3, &db).await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use super::{Message, SearchHit, User, Workspace};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchMessages {
+    /// words, `"quoted phrases"` and `prefix*` terms, all of them must match
+    pub q: String,
+    pub chat_id: Option<i64>,
+    /// id of the sender
+    pub from: Option<i64>,
+    pub before: Option<DateTime<Utc>>,
+    pub after: Option<DateTime<Utc>>,
+    /// only messages older than this id are returned
+    pub last_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl Message {
+    /// Search messages in the chats the user belongs to, from newest to oldest. The snippet
+    /// is HTML with the matches in `<mark>`, the text of the message is escaped.
+    pub async fn search(
+        input: &SearchMessages,
+        user: &User,
+        pool: &PgPool,
+    ) -> Result<Vec<SearchHit>, AppError> {
+        let query = parse_query(&input.q)
+            .ok_or_else(|| AppError::InvalidInput("search query cannot be empty".to_string()))?;
+        let ws = Workspace::get_by_id(user.ws_id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace {}", user.ws_id)))?;
+
+        let last_id = input.last_id.unwrap_or(i64::MAX);
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let hits = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.parent_id, m.format, m.content, m.content_text,
+                m.attachments, m.created_at, m.updated_at, m.deleted_at,
+                ts_headline(m.ts_config,
+                    replace(replace(replace(replace(replace(m.content_text,
+                        '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;'),
+                    q.query, 'StartSel=<mark>, StopSel=</mark>, MaxFragments=2') AS snippet
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id
+            CROSS JOIN (SELECT to_tsquery($2::regconfig, $3) AS query) q
+            WHERE m.content_tsv @@ q.query
+                AND $1 = ANY(c.members)
+                AND m.deleted_at IS NULL
+                AND ($4::bigint IS NULL OR m.chat_id = $4)
+                AND ($5::bigint IS NULL OR m.sender_id = $5)
+                AND ($6::timestamptz IS NULL OR m.created_at < $6)
+                AND ($7::timestamptz IS NULL OR m.created_at > $7)
+                AND m.id < $8
+            ORDER BY m.id DESC
+            LIMIT $9
+            "#,
+        )
+        .bind(user.id)
+        .bind(&ws.search_config)
+        .bind(&query)
+        .bind(input.chat_id)
+        .bind(input.from)
+        .bind(input.before)
+        .bind(input.after)
+        .bind(last_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+        Ok(hits)
+    }
+}
+
+/// Turn a user query into the `to_tsquery` syntax. Only letters and digits of every term are
+/// kept, so users cannot inject tsquery operators.
+fn parse_query(q: &str) -> Option<String> {
+    let mut terms = Vec::new();
+    for (i, part) in q.split('"').enumerate() {
+        if i % 2 == 1 {
+            let words: Vec<String> = part.split_whitespace().filter_map(sanitize).collect();
+            if !words.is_empty() {
+                terms.push(words.join(" <-> "));
+            }
+            continue;
+        }
+        for word in part.split_whitespace() {
+            if let Some(term) = sanitize(word) {
+                match word.ends_with('*') {
+                    true => terms.push(format!("{}:*", term)),
+                    false => terms.push(term),
+                }
+            }
+        }
+    }
+    match terms.is_empty() {
+        true => None,
+        false => Some(terms.join(" & ")),
+    }
+}
+
+fn sanitize(word: &str) -> Option<String> {
+    let word: String = word.chars().filter(|c| c.is_alphanumeric()).collect();
+    match word.is_empty() {
+        true => None,
+        false => Some(word),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::{CreateMessage, MessageFormat},
+        utils::create_test_pool,
+    };
+
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn parse_query_should_support_phrase_and_prefix() {
+        assert_eq!(
+            parse_query("hello world"),
+            Some("hello & world".to_string())
+        );
+        assert_eq!(
+            parse_query(r#"lunch "good morning" wor*"#),
+            Some("lunch & good <-> morning & wor:*".to_string())
+        );
+        assert_eq!(parse_query("a&b | !c:*"), Some("ab & c:*".to_string()));
+        assert_eq!(parse_query(r#"  "" & "#), None);
+    }
+
+    #[tokio::test]
+    async fn search_should_only_find_messages_of_member_chats() -> Result<()> {
+        let db = create_test_pool().await?;
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = SearchMessages {
+            q: "hel*".to_string(),
+            ..Default::default()
+        };
+        let hits = Message::search(&input, &alice, &db).await?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message.id, 1);
+        assert_eq!(hits[0].snippet, "<mark>Hello</mark>, world");
+
+        let input = SearchMessages {
+            q: r#""good morning""#.to_string(),
+            from: Some(3),
+            ..Default::default()
+        };
+        let hits = Message::search(&input, &alice, &db).await?;
+        assert_eq!(hits.len(), 1);
+
+        // alice is not a member of chat 2
+        let input = SearchMessages {
+            q: "lunch".to_string(),
+            ..Default::default()
+        };
+        assert!(Message::search(&input, &alice, &db).await?.is_empty());
+        let bob = User::new(2, "Bob", "bob@acme.org");
+        assert_eq!(Message::search(&input, &bob, &db).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_should_match_the_plain_text_of_markdown() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateMessage {
+            content: "deploy **[the docs](https://docs.acme.org/strong)** now".to_string(),
+            format: MessageFormat::Markdown,
+            ..Default::default()
+        };
+        let message = Message::create(&input, 1, 1, &db).await?;
+        assert_eq!(message.content_text, "deploy the docs now");
+
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = SearchMessages {
+            q: "docs".to_string(),
+            ..Default::default()
+        };
+        let hits = Message::search(&input, &alice, &db).await?;
+        assert_eq!(hits[0].message.id, message.id);
+        assert!(hits[0].snippet.starts_with("deploy the <mark>docs</mark>"));
+
+        // the url is not part of the text
+        let input = SearchMessages {
+            q: "strong".to_string(),
+            ..Default::default()
+        };
+        assert!(Message::search(&input, &alice, &db).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_snippet_should_escape_html() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateMessage {
+            content: r#"<img src=x onerror="alert('pwned')"> & <mark>pwned</mark>"#.to_string(),
+            ..Default::default()
+        };
+        let message = Message::create(&input, 1, 1, &db).await?;
+
+        let alice = User::new(1, "Alice", "alice@acme.org");
+        let input = SearchMessages {
+            q: "pwned".to_string(),
+            ..Default::default()
+        };
+        let hits = Message::search(&input, &alice, &db).await?;
+        assert_eq!(hits[0].message.id, message.id);
+        // the only tags are the marks of the matches
+        let snippet = &hits[0].snippet;
+        let text = snippet.replace("<mark>", "").replace("</mark>", "");
+        assert!(!text.contains(['<', '>', '"', '\'']), "{}", snippet);
+        assert!(snippet.contains(
+            "onerror=&quot;alert(&#39;<mark>pwned</mark>&#39;)&quot;&gt; &amp; &lt;mark&gt;"
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use sqlx::{PgConnection, PgPool};
+
+use crate::AppError;
+
+use super::{reaction::attach_reactions, Chat, ListMessages, Message};
+
+impl Message {
+    /// Load a top level message of the chat, threads are only one level deep.
+    pub(super) async fn get_thread_root(
+        id: i64,
+        chat_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        match Self::get_by_id(id, pool).await? {
+            Some(message) if message.chat_id == chat_id && message.parent_id.is_none() => {
+                Ok(message)
+            }
+            Some(_) => Err(AppError::InvalidInput(format!(
+                "message {} cannot start a thread",
+                id
+            ))),
+            None => Err(AppError::NotFound(format!("message {}", id))),
+        }
+    }
+
+    /// List replies of a thread from newest to oldest, leaving out the replies of the users the
+    /// user blocked.
+    pub async fn list_thread(
+        id: i64,
+        input: &ListMessages,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        let root = Self::get_by_id(id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("message {}", id)))?;
+        Chat::get_for_member(root.chat_id, user_id, pool).await?;
+
+        let (last_id, limit) = input.page();
+        let mut messages: Vec<Self> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, parent_id, format, content, content_text, attachments,
+                created_at, updated_at, deleted_at
+            FROM messages
+            WHERE parent_id = $1 AND id < $2
+                AND NOT EXISTS (
+                    SELECT 1 FROM user_blocks b WHERE b.user_id = $4 AND b.blocked_id = sender_id
+                )
+            ORDER BY id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(id)
+        .bind(last_id)
+        .bind(limit)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        attach_reactions(&mut messages, user_id, pool).await?;
+        Ok(messages)
+    }
+
+    pub async fn follow(id: i64, user_id: i64, pool: &PgPool) -> Result<(), AppError> {
+        let message = Self::get_by_id(id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("message {}", id)))?;
+        Chat::get_for_member(message.chat_id, user_id, pool).await?;
+        Self::get_thread_root(id, message.chat_id, pool).await?;
+
+        let mut tx = pool.begin().await?;
+        follow_thread(id, user_id, &mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn unfollow(id: i64, user_id: i64, pool: &PgPool) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM thread_followers WHERE message_id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Follow a thread, the author of the thread root follows it too.
+pub(super) async fn follow_thread(
+    id: i64,
+    user_id: i64,
+    conn: &mut PgConnection,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO thread_followers (message_id, user_id)
+        SELECT id, unnest(ARRAY[sender_id, $2]) FROM messages WHERE id = $1
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{models::CreateMessage, utils::create_test_pool};
+
+    use super::*;
+    use anyhow::Result;
+
+    async fn followers(id: i64, pool: &PgPool) -> Result<Vec<i64>> {
+        let followers = sqlx::query_scalar(
+            "SELECT user_id FROM thread_followers WHERE message_id = $1 ORDER BY user_id",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        Ok(followers)
+    }
+
+    #[tokio::test]
+    async fn reply_should_be_listed_in_thread() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateMessage {
+            content: "welcome".to_string(),
+            parent_id: Some(1),
+            ..Default::default()
+        };
+        let reply = Message::create(&input, 1, 2, &db).await?;
+        assert_eq!(reply.parent_id, Some(1));
+
+        // replies are not allowed to start another thread
+        let input = CreateMessage {
+            parent_id: Some(reply.id),
+            ..input
+        };
+        let ret = Message::create(&input, 1, 3, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        let replies = Message::list_thread(1, &ListMessages::default(), 3, &db).await?;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].id, reply.id);
+
+        let messages = Message::list(&ListMessages::default(), 1, 1, &db).await?;
+        assert!(messages.iter().all(|m| m.parent_id.is_none()));
+        let root = messages.iter().find(|m| m.id == 1).expect("root exists");
+        assert_eq!(root.reply_count, 1);
+        assert_eq!(root.last_reply_at, Some(reply.created_at));
+
+        assert_eq!(followers(1, &db).await?, vec![1, 2]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follow_and_unfollow_should_work() -> Result<()> {
+        let db = create_test_pool().await?;
+        Message::follow(2, 3, &db).await?;
+        assert_eq!(followers(2, &db).await?, vec![2, 3]);
+
+        Message::unfollow(2, 3, &db).await?;
+        assert_eq!(followers(2, &db).await?, vec![2]);
+
+        let ret = Message::follow(4, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+}
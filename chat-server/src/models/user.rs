@@ -2,7 +2,7 @@ use std::mem;
 
 use crate::AppError;
 
-use super::{CreateUser, User, VerifyUser};
+use super::{CreateUser, User, VerifyUser, Workspace, WorkspaceRole};
 use anyhow::Result;
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
@@ -19,17 +19,36 @@ impl User {
             .map_err(Into::into)
     }
 
+    /// The id and workspace of the account with the email, e.g. to record failed signins.
+    pub async fn find_account(email: &str, pool: &PgPool) -> Result<Option<(i64, i64)>, AppError> {
+        sqlx::query_as("SELECT id, ws_id FROM users WHERE email = $1")
+            .bind(email)
+            .fetch_optional(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn find_by_id(id: i64, pool: &PgPool) -> Result<Option<Self>, AppError> {
+        sqlx::query_as(
+            "SELECT id, ws_id, fullname, email, is_bot, role, created_at FROM users WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Into::into)
+    }
+
     pub async fn create(dto: &CreateUser, pool: &PgPool) -> Result<Self, AppError> {
         let user = Self::find_by_email(&dto.email, pool).await;
         if user.is_err() {
             return Err(AppError::EmailIsExist(dto.email.clone()));
         }
         let password_hash = hash_password(&dto.password)?;
-        let user = sqlx::query_as(
+        let mut user: User = sqlx::query_as(
             r#"
             INSERT INTO users (email, fullname, password_hash)
             VALUES ($1, $2, $3)
-            RETURNING id, fullname, email, created_at
+            RETURNING id, ws_id, fullname, email, role, created_at
             "#,
         )
         .bind(&dto.email)
@@ -37,24 +56,41 @@ impl User {
         .bind(&password_hash)
         .fetch_one(pool)
         .await?;
+        if Workspace::claim_owner(&user, pool).await? {
+            user.role = WorkspaceRole::Owner;
+        }
         Ok(user)
     }
 
     pub async fn verify(dto: &VerifyUser, pool: &PgPool) -> Result<Option<Self>, AppError> {
+        // bots have no password
         let user: Option<User> = sqlx::query_as(
-            "SELECT id, fullname, email, password_hash, created_at FROM users WHERE email = $1",
+            r#"
+            SELECT id, ws_id, fullname, email, password_hash, role, created_at
+            FROM users
+            WHERE email = $1 AND NOT is_bot
+            "#,
         )
         .bind(&dto.email)
         .fetch_optional(pool)
         .await?;
         match user {
             Some(mut user) => {
-                let password_hash = mem::take(&mut user.password_hash);
-                let is_valid = verify_password(&dto.password, &password_hash.unwrap_or_default())?;
-                match is_valid {
-                    true => Ok(Some(user)),
-                    false => Ok(None),
+                // users signed up with an identity provider have no password
+                let Some(password_hash) = mem::take(&mut user.password_hash) else {
+                    return Ok(None);
+                };
+                let is_valid = verify_password(&dto.password, &password_hash)?;
+                if !is_valid {
+                    return Ok(None);
+                }
+                if Self::is_suspended(user.id, pool).await? {
+                    return Err(AppError::PermissionDenied(format!(
+                        "user {} is suspended",
+                        user.id
+                    )));
                 }
+                Ok(Some(user))
             }
             None => Ok(None),
         }
@@ -0,0 +1,417 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::{utils::check_outbound_url, AppError};
+
+use super::{
+    Chat, ListMessages, Webhook, WebhookDeadLetter, WebhookDelivery, WebhookDeliveryStatus,
+};
+
+/// Events sent to webhooks, the deliveries are queued by triggers on messages and chats.
+const WEBHOOK_EVENTS: [&str; 4] = [
+    "message.created",
+    "message.edited",
+    "message.deleted",
+    "chat.members_changed",
+];
+
+/// A delivery moves to the dead letters after this many failed attempts.
+const MAX_DELIVERY_ATTEMPTS: i32 = 8;
+
+/// How long a claimed delivery is hidden from other workers while it is sent.
+const DELIVERY_LEASE_SECS: i64 = 300;
+
+const MAX_WEBHOOKS_PER_CHAT: i64 = 10;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateWebhook {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+/// A delivery claimed by the worker, with where to send it.
+#[derive(Debug, Clone, FromRow)]
+pub struct DueDelivery {
+    pub id: i64,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+impl Webhook {
+    /// Register a webhook on a chat, only admins can do that. The secret is generated here.
+    /// Urls of private hosts are rejected unless `allow_private` is set.
+    pub async fn create(
+        chat_id: i64,
+        input: &CreateWebhook,
+        user_id: i64,
+        allow_private: bool,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        Chat::get_for_admin(chat_id, user_id, pool).await?;
+        check_outbound_url(&input.url, allow_private).await?;
+        if let Some(event) = input
+            .events
+            .iter()
+            .find(|e| !WEBHOOK_EVENTS.contains(&e.as_str()))
+        {
+            return Err(AppError::InvalidInput(format!(
+                "unknown webhook event: {}",
+                event
+            )));
+        }
+
+        let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let webhook = sqlx::query_as(
+            r#"
+            INSERT INTO chat_webhooks (chat_id, url, secret, events, created_by)
+            SELECT $1, $2, $3, $4, $5
+            WHERE (SELECT count(*) FROM chat_webhooks WHERE chat_id = $1) < $6
+            RETURNING id, chat_id, url, secret, events, created_by, created_at
+            "#,
+        )
+        .bind(chat_id)
+        .bind(&input.url)
+        .bind(secret)
+        .bind(&input.events)
+        .bind(user_id)
+        .bind(MAX_WEBHOOKS_PER_CHAT)
+        .fetch_optional(pool)
+        .await?;
+        webhook.ok_or_else(|| {
+            AppError::InvalidInput(format!(
+                "chat {} cannot have more than {} webhooks",
+                chat_id, MAX_WEBHOOKS_PER_CHAT
+            ))
+        })
+    }
+
+    pub async fn list(chat_id: i64, user_id: i64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        Chat::get_for_admin(chat_id, user_id, pool).await?;
+        let webhooks = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, url, secret, events, created_by, created_at
+            FROM chat_webhooks
+            WHERE chat_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(chat_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(webhooks)
+    }
+
+    /// Remove a webhook, its pending deliveries and log go with it.
+    pub async fn delete(
+        chat_id: i64,
+        id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        Chat::get_for_admin(chat_id, user_id, pool).await?;
+        let ret = sqlx::query("DELETE FROM chat_webhooks WHERE id = $1 AND chat_id = $2")
+            .bind(id)
+            .bind(chat_id)
+            .execute(pool)
+            .await?;
+        match ret.rows_affected() {
+            0 => Err(AppError::NotFound(format!("webhook {}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The webhook of a chat the user administers.
+    async fn get_for_admin(
+        chat_id: i64,
+        id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        Chat::get_for_admin(chat_id, user_id, pool).await?;
+        let webhook: Option<Self> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, url, secret, events, created_by, created_at
+            FROM chat_webhooks
+            WHERE id = $1 AND chat_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(chat_id)
+        .fetch_optional(pool)
+        .await?;
+        webhook.ok_or_else(|| AppError::NotFound(format!("webhook {}", id)))
+    }
+}
+
+impl WebhookDelivery {
+    /// The delivery log of a webhook, newest first.
+    pub async fn list(
+        chat_id: i64,
+        webhook_id: i64,
+        input: &ListMessages,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        Webhook::get_for_admin(chat_id, webhook_id, user_id, pool).await?;
+        let (last_id, limit) = input.page();
+        let deliveries = sqlx::query_as(
+            r#"
+            SELECT id, webhook_id, event, payload, status, attempts, next_attempt_at,
+                response_status, error, created_at, delivered_at
+            FROM webhook_deliveries
+            WHERE webhook_id = $1 AND id < $2
+            ORDER BY id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(webhook_id)
+        .bind(last_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+        Ok(deliveries)
+    }
+
+    /// Claim the due deliveries for sending. They are leased rather than locked, so a worker
+    /// which dies while sending only delays them.
+    pub async fn claim_due(limit: i64, pool: &PgPool) -> Result<Vec<DueDelivery>, AppError> {
+        let due = sqlx::query_as(
+            r#"
+            UPDATE webhook_deliveries d
+            SET next_attempt_at = now() + make_interval(secs => $2)
+            FROM chat_webhooks w
+            WHERE w.id = d.webhook_id AND d.id IN (
+                SELECT id
+                FROM webhook_deliveries
+                WHERE status = 'pending' AND next_attempt_at <= now()
+                ORDER BY next_attempt_at, id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING d.id, d.event, d.payload, d.attempts, w.url, w.secret
+            "#,
+        )
+        .bind(limit)
+        .bind(DELIVERY_LEASE_SECS as f64)
+        .fetch_all(pool)
+        .await?;
+        Ok(due)
+    }
+
+    pub async fn mark_delivered(
+        id: i64,
+        response_status: u16,
+        pool: &PgPool,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = 'delivered', attempts = attempts + 1, response_status = $2,
+                error = NULL, delivered_at = now()
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(response_status as i32)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Schedule the next attempt with an exponential backoff, or give up and move the delivery
+    /// to the dead letters once it ran out of attempts.
+    pub async fn mark_failed(
+        delivery: &DueDelivery,
+        response_status: Option<u16>,
+        error: &str,
+        pool: &PgPool,
+    ) -> Result<WebhookDeliveryStatus, AppError> {
+        let attempts = delivery.attempts + 1;
+        let status = match attempts >= MAX_DELIVERY_ATTEMPTS {
+            true => WebhookDeliveryStatus::Dead,
+            false => WebhookDeliveryStatus::Pending,
+        };
+        sqlx::query(
+            r#"
+            WITH d AS (
+                UPDATE webhook_deliveries
+                SET status = $2, attempts = $3, response_status = $4, error = $5,
+                    next_attempt_at = $6
+                WHERE id = $1
+                RETURNING id, webhook_id, event, payload, attempts, error, status
+            )
+            INSERT INTO webhook_dead_letters (delivery_id, webhook_id, event, payload, attempts, error)
+            SELECT id, webhook_id, event, payload, attempts, error
+            FROM d
+            WHERE status = 'dead'
+            "#,
+        )
+        .bind(delivery.id)
+        .bind(status)
+        .bind(attempts)
+        .bind(response_status.map(|s| s as i32))
+        .bind(error)
+        .bind(next_attempt_at(attempts))
+        .execute(pool)
+        .await?;
+        Ok(status)
+    }
+}
+
+impl WebhookDeadLetter {
+    pub async fn list(
+        chat_id: i64,
+        webhook_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, AppError> {
+        Webhook::get_for_admin(chat_id, webhook_id, user_id, pool).await?;
+        let dead_letters = sqlx::query_as(
+            r#"
+            SELECT id, delivery_id, webhook_id, event, payload, attempts, error, created_at
+            FROM webhook_dead_letters
+            WHERE webhook_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(webhook_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(dead_letters)
+    }
+
+    /// Put a dead delivery back into the queue with a fresh set of attempts.
+    pub async fn retry(
+        chat_id: i64,
+        webhook_id: i64,
+        id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<WebhookDelivery, AppError> {
+        Webhook::get_for_admin(chat_id, webhook_id, user_id, pool).await?;
+        let mut tx = pool.begin().await?;
+        let delivery_id: Option<i64> = sqlx::query_scalar(
+            "DELETE FROM webhook_dead_letters WHERE id = $1 AND webhook_id = $2 RETURNING delivery_id",
+        )
+        .bind(id)
+        .bind(webhook_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(delivery_id) = delivery_id else {
+            return Err(AppError::NotFound(format!("dead letter {}", id)));
+        };
+        let delivery = sqlx::query_as(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = 'pending', attempts = 0, next_attempt_at = now()
+            WHERE id = $1
+            RETURNING id, webhook_id, event, payload, status, attempts, next_attempt_at,
+                response_status, error, created_at, delivered_at
+            "#,
+        )
+        .bind(delivery_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(delivery)
+    }
+}
+
+/// 10s, 20s, 40s, ... capped at an hour.
+fn next_attempt_at(attempts: i32) -> DateTime<Utc> {
+    let secs = 10i64 << attempts.clamp(0, 20);
+    Utc::now() + Duration::seconds(secs.min(3600))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn create_webhook_should_be_limited_to_admins() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateWebhook {
+            url: "https://example.com/hook".to_string(),
+            events: vec!["message.created".to_string()],
+        };
+        let ret = Webhook::create(1, &input, 2, false, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let webhook = Webhook::create(1, &input, 1, false, &db).await?;
+        assert_eq!(webhook.secret.len(), 64);
+        assert_eq!(Webhook::list(1, 1, &db).await?, vec![webhook.clone()]);
+
+        let input = CreateWebhook {
+            url: "ftp://example.com/hook".to_string(),
+            events: vec![],
+        };
+        let ret = Webhook::create(1, &input, 1, false, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        // the network of the server is off limits
+        let input = CreateWebhook {
+            url: "http://127.0.0.1/hook".to_string(),
+            events: vec![],
+        };
+        let ret = Webhook::create(1, &input, 1, false, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let input = CreateWebhook {
+            url: "https://example.com/hook".to_string(),
+            events: vec!["message.pinned".to_string()],
+        };
+        let ret = Webhook::create(1, &input, 1, false, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        Webhook::delete(1, webhook.id, 1, &db).await?;
+        assert!(Webhook::list(1, 1, &db).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_events_should_be_queued_for_subscribed_webhooks() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = CreateWebhook {
+            url: "https://example.com/all".to_string(),
+            events: vec![],
+        };
+        let all = Webhook::create(1, &input, 1, false, &db).await?;
+        let input = CreateWebhook {
+            url: "https://example.com/members".to_string(),
+            events: vec!["chat.members_changed".to_string()],
+        };
+        let members = Webhook::create(1, &input, 1, false, &db).await?;
+
+        sqlx::query("UPDATE messages SET content = 'Hello, everyone!' WHERE id = 1")
+            .execute(&*db)
+            .await?;
+        sqlx::query("UPDATE chats SET members = '{1, 2, 4}' WHERE id = 1")
+            .execute(&*db)
+            .await?;
+
+        let input = ListMessages::default();
+        let deliveries = WebhookDelivery::list(1, all.id, &input, 1, &db).await?;
+        let events: Vec<_> = deliveries.iter().map(|d| d.event.as_str()).collect();
+        assert_eq!(events, ["chat.members_changed", "message.edited"]);
+        assert_eq!(
+            deliveries[0].payload["data"]["added"],
+            serde_json::json!([4])
+        );
+        assert_eq!(
+            deliveries[0].payload["data"]["removed"],
+            serde_json::json!([3])
+        );
+        assert_eq!(deliveries[1].payload["data"]["content"], "Hello, everyone!");
+
+        let deliveries = WebhookDelivery::list(1, members.id, &input, 1, &db).await?;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].status, WebhookDeliveryStatus::Pending);
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppError;
+
+use super::{User, Workspace, WorkspaceRole};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateSearchConfig {
+    pub search_config: String,
+}
+
+impl Workspace {
+    pub async fn get_by_id(id: i64, pool: &PgPool) -> Result<Option<Self>, AppError> {
+        sqlx::query_as(
+            r#"
+            SELECT id, name, owner_id, search_config::text AS search_config, retention_days,
+                retention_archive, created_at
+            FROM workspaces
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// The first user of a workspace without owner becomes its owner, true when it did.
+    pub async fn claim_owner(user: &User, pool: &PgPool) -> Result<bool, AppError> {
+        let ret = sqlx::query(
+            r#"
+            WITH ws AS (
+                UPDATE workspaces SET owner_id = $2 WHERE id = $1 AND owner_id IS NULL RETURNING id
+            )
+            UPDATE users SET role = 'owner'
+            WHERE id = $2 AND EXISTS (SELECT 1 FROM ws)
+            "#,
+        )
+        .bind(user.ws_id)
+        .bind(user.id)
+        .execute(pool)
+        .await?;
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// The workspace, if the user is one of its admins or its owner.
+    pub async fn ensure_admin(id: i64, user_id: i64, pool: &PgPool) -> Result<Self, AppError> {
+        let ws = Self::get_by_id(id, pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace {}", id)))?;
+        match User::get_role(user_id, id, pool).await? {
+            Some(role) if role >= WorkspaceRole::Admin => Ok(ws),
+            _ => Err(AppError::PermissionDenied(format!(
+                "user {} is not an admin of workspace {}",
+                user_id, id
+            ))),
+        }
+    }
+
+    /// Change the text search configuration, existing messages are indexed again with it.
+    pub async fn update_search_config(
+        id: i64,
+        input: &UpdateSearchConfig,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let ws = Self::ensure_admin(id, user_id, pool).await?;
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM pg_ts_config WHERE cfgname = $1)")
+                .bind(&input.search_config)
+                .fetch_one(pool)
+                .await?;
+        if !exists {
+            return Err(AppError::InvalidInput(format!(
+                "unknown search config: {}",
+                input.search_config
+            )));
+        }
+
+        let mut tx = pool.begin().await?;
+        sqlx::query("UPDATE workspaces SET search_config = $2::regconfig WHERE id = $1")
+            .bind(id)
+            .bind(&input.search_config)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE messages
+            SET ts_config = $2::regconfig
+            WHERE chat_id IN (SELECT id FROM chats WHERE ws_id = $1)
+            "#,
+        )
+        .bind(id)
+        .bind(&input.search_config)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Workspace {
+            search_config: input.search_config.clone(),
+            ..ws
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::create_test_pool;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn update_search_config_should_work() -> Result<()> {
+        let db = create_test_pool().await?;
+        let input = UpdateSearchConfig {
+            search_config: "english".to_string(),
+        };
+        let ret = Workspace::update_search_config(1, &input, 2, &db).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let ws = Workspace::update_search_config(1, &input, 1, &db).await?;
+        assert_eq!(ws.search_config, "english");
+        let ws = Workspace::get_by_id(1, &db)
+            .await?
+            .expect("workspace exists");
+        assert_eq!(ws.search_config, "english");
+
+        let input = UpdateSearchConfig {
+            search_config: "klingon".to_string(),
+        };
+        let ret = Workspace::update_search_config(1, &input, 1, &db).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        Ok(())
+    }
+}
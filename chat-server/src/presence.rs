@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+/// A user without heartbeat for this long is offline.
+const PRESENCE_TTL: Duration = Duration::from_secs(60);
+/// A typing indicator is dropped if it is not refreshed in time.
+const TYPING_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserPresence {
+    pub user_id: i64,
+    pub status: PresenceStatus,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Heartbeat {
+    status: PresenceStatus,
+    at: Instant,
+    last_seen_at: DateTime<Utc>,
+}
+
+/// Presence and typing indicators, they only live in memory and expire on their own.
+#[derive(Debug)]
+pub struct PresenceTracker {
+    presence_ttl: Duration,
+    typing_ttl: Duration,
+    users: DashMap<i64, Heartbeat>,
+    // chat id -> user id -> when the user started typing
+    typing: DashMap<i64, HashMap<i64, Instant>>,
+}
+
+impl Default for PresenceTracker {
+    fn default() -> Self {
+        Self::new(PRESENCE_TTL, TYPING_TTL)
+    }
+}
+
+impl PresenceTracker {
+    pub fn new(presence_ttl: Duration, typing_ttl: Duration) -> Self {
+        Self {
+            presence_ttl,
+            typing_ttl,
+            users: DashMap::new(),
+            typing: DashMap::new(),
+        }
+    }
+
+    pub fn heartbeat(&self, user_id: i64, status: PresenceStatus) {
+        let heartbeat = Heartbeat {
+            status,
+            at: Instant::now(),
+            last_seen_at: Utc::now(),
+        };
+        self.users.insert(user_id, heartbeat);
+    }
+
+    pub fn get(&self, user_id: i64) -> UserPresence {
+        match self.users.get(&user_id) {
+            Some(heartbeat) => {
+                let status = match heartbeat.at.elapsed() < self.presence_ttl {
+                    true => heartbeat.status,
+                    false => PresenceStatus::Offline,
+                };
+                UserPresence {
+                    user_id,
+                    status,
+                    last_seen_at: Some(heartbeat.last_seen_at),
+                }
+            }
+            None => UserPresence {
+                user_id,
+                status: PresenceStatus::Offline,
+                last_seen_at: None,
+            },
+        }
+    }
+
+    pub fn get_many(&self, user_ids: &[i64]) -> Vec<UserPresence> {
+        user_ids.iter().map(|id| self.get(*id)).collect()
+    }
+
+    /// Mark the user as typing in the chat, typing also counts as a heartbeat.
+    pub fn typing(&self, chat_id: i64, user_id: i64) {
+        let mut users = self.typing.entry(chat_id).or_default();
+        users.retain(|_, at| at.elapsed() < self.typing_ttl);
+        users.insert(user_id, Instant::now());
+        drop(users);
+
+        if self.get(user_id).status == PresenceStatus::Offline {
+            self.heartbeat(user_id, PresenceStatus::Online);
+        }
+    }
+
+    pub fn stop_typing(&self, chat_id: i64, user_id: i64) {
+        if let Some(mut users) = self.typing.get_mut(&chat_id) {
+            users.remove(&user_id);
+        }
+        self.typing.remove_if(&chat_id, |_, users| users.is_empty());
+    }
+
+    /// Users currently typing in the chat, expired indicators are dropped on the way.
+    pub fn typing_users(&self, chat_id: i64) -> Vec<i64> {
+        let Some(mut users) = self.typing.get_mut(&chat_id) else {
+            return vec![];
+        };
+        users.retain(|_, at| at.elapsed() < self.typing_ttl);
+        let mut ids: Vec<i64> = users.keys().copied().collect();
+        drop(users);
+
+        self.typing.remove_if(&chat_id, |_, users| users.is_empty());
+        ids.sort();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presence_should_expire() {
+        let tracker = PresenceTracker::new(Duration::from_millis(50), TYPING_TTL);
+        assert_eq!(tracker.get(1).status, PresenceStatus::Offline);
+        assert!(tracker.get(1).last_seen_at.is_none());
+
+        tracker.heartbeat(1, PresenceStatus::Away);
+        tracker.heartbeat(2, PresenceStatus::Online);
+        tracker.heartbeat(3, PresenceStatus::Offline);
+        let ret = tracker.get_many(&[1, 2, 3, 4]);
+        assert_eq!(ret[0].status, PresenceStatus::Away);
+        assert_eq!(ret[1].status, PresenceStatus::Online);
+        assert_eq!(ret[2].status, PresenceStatus::Offline);
+        assert_eq!(ret[3].status, PresenceStatus::Offline);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(tracker.get(2).status, PresenceStatus::Offline);
+        assert!(tracker.get(2).last_seen_at.is_some());
+    }
+
+    #[test]
+    fn typing_should_expire() {
+        let tracker = PresenceTracker::new(PRESENCE_TTL, Duration::from_millis(50));
+        tracker.typing(1, 2);
+        tracker.typing(1, 3);
+        assert_eq!(tracker.typing_users(1), vec![2, 3]);
+        assert_eq!(tracker.get(2).status, PresenceStatus::Online);
+
+        tracker.stop_typing(1, 3);
+        assert_eq!(tracker.typing_users(1), vec![2]);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(tracker.typing_users(1).is_empty());
+        assert!(tracker.typing.is_empty());
+    }
+}
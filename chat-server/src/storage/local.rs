@@ -0,0 +1,180 @@
+use std::{
+    io::{ErrorKind, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::{stream::BoxStream, StreamExt, TryStreamExt};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tokio_util::io::ReaderStream;
+
+use crate::AppError;
+
+use super::{ByteStream, Storage};
+
+/// Files in a directory of the server.
+#[derive(Debug)]
+pub(crate) struct LocalStorage {
+    base_dir: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(base_dir: impl AsRef<Path>) -> Self {
+        Self {
+            base_dir: base_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, AppError> {
+        if key.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(AppError::InvalidInput(format!("invalid key {}", key)));
+        }
+        Ok(self.base_dir.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, body: &mut ByteStream<'_>) -> Result<u64, AppError> {
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut file = File::create(&path).await?;
+        let mut size = 0;
+        while let Some(chunk) = body.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    drop(file);
+                    fs::remove_file(&path).await.ok();
+                    return Err(e);
+                }
+            };
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+        }
+        file.flush().await?;
+        Ok(size)
+    }
+
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<BoxStream<'static, Result<Bytes, AppError>>, AppError> {
+        let mut file = match File::open(self.path(key)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(AppError::NotFound(format!("file {}", key)))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let stream = match range {
+            Some(range) => {
+                file.seek(SeekFrom::Start(range.start)).await?;
+                ReaderStream::new(file.take(range.end - range.start)).boxed()
+            }
+            None => ReaderStream::new(file).boxed(),
+        };
+        Ok(stream.map_err(AppError::from).boxed())
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<u64>, AppError> {
+        match fs::metadata(self.path(key)?).await {
+            Ok(meta) => Ok(Some(meta.len())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), AppError> {
+        let to = self.path(to)?;
+        if let Some(dir) = to.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::rename(self.path(from)?, to).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        match fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn presigned_url(
+        &self,
+        _key: &str,
+        _expires_in: Duration,
+    ) -> Result<Option<String>, AppError> {
+        Ok(None)
+    }
+
+    async fn presigned_upload_url(
+        &self,
+        _key: &str,
+        _size: u64,
+        _expires_in: Duration,
+    ) -> Result<Option<String>, AppError> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use futures_util::stream;
+
+    use super::*;
+
+    async fn read(storage: &LocalStorage, key: &str, range: Option<Range<u64>>) -> Result<Vec<u8>> {
+        let chunks: Vec<Bytes> = storage.get(key, range).await?.try_collect().await?;
+        Ok(chunks.concat())
+    }
+
+    #[tokio::test]
+    async fn local_storage_should_stream_and_read_ranges() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("crablink-{}", uuid::Uuid::new_v4()));
+        let storage = LocalStorage::new(&dir);
+        let chunks = ["hello ", "local ", "storage"].map(|c| Ok(Bytes::from(c)));
+        let size = storage.put("tmp/a.txt", &mut stream::iter(chunks)).await?;
+        assert_eq!(size, 19);
+
+        storage.rename("tmp/a.txt", "1/abc/def/a.txt").await?;
+        assert_eq!(storage.head("tmp/a.txt").await?, None);
+        assert_eq!(storage.head("1/abc/def/a.txt").await?, Some(19));
+        assert_eq!(
+            read(&storage, "1/abc/def/a.txt", None).await?,
+            b"hello local storage"
+        );
+        assert_eq!(
+            read(&storage, "1/abc/def/a.txt", Some(6..11)).await?,
+            b"local"
+        );
+        assert!(storage
+            .presigned_url("1/abc/def/a.txt", Duration::from_secs(60))
+            .await?
+            .is_none());
+        assert!(storage
+            .presigned_upload_url("tmp/b.txt", 6, Duration::from_secs(60))
+            .await?
+            .is_none());
+
+        storage.delete("1/abc/def/a.txt").await?;
+        assert!(matches!(
+            storage.get("1/abc/def/a.txt", None).await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(storage.head("../etc/passwd").await.is_err());
+        fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+}
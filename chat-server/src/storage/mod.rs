@@ -0,0 +1,122 @@
+mod local;
+mod s3;
+
+use std::{fmt::Debug, ops::Range, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::{stream::BoxStream, Stream};
+
+use crate::{config::StorageConfig, AppError};
+
+pub(crate) use local::LocalStorage;
+#[cfg(test)]
+pub(crate) use s3::test_s3;
+pub(crate) use s3::S3Storage;
+
+/// A stream of file content, uploads are written as they arrive.
+pub(crate) type ByteStream<'a> = dyn Stream<Item = Result<Bytes, AppError>> + Send + Unpin + 'a;
+
+/// Where uploaded files are kept. Keys are relative paths like `1/0a1/b2c/3d4.png`.
+#[async_trait]
+pub(crate) trait Storage: Debug + Send + Sync {
+    /// Write the stream to `key`, returns the number of bytes written.
+    async fn put(&self, key: &str, body: &mut ByteStream<'_>) -> Result<u64, AppError>;
+
+    /// Read the object or a range of it.
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<BoxStream<'static, Result<Bytes, AppError>>, AppError>;
+
+    /// The size of the object, `None` when it does not exist.
+    async fn head(&self, key: &str) -> Result<Option<u64>, AppError>;
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), AppError>;
+
+    async fn delete(&self, key: &str) -> Result<(), AppError>;
+
+    /// A url to download the object without going through the server, drivers which cannot
+    /// sign urls return `None`.
+    async fn presigned_url(
+        &self,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<Option<String>, AppError>;
+
+    /// A url to upload the object with a `PUT` without going through the server, only a body
+    /// of `size` bytes is accepted. Drivers which cannot sign urls return `None`.
+    async fn presigned_upload_url(
+        &self,
+        key: &str,
+        size: u64,
+        expires_in: Duration,
+    ) -> Result<Option<String>, AppError>;
+}
+
+pub(crate) fn from_config(config: &StorageConfig) -> Result<Arc<dyn Storage>, AppError> {
+    let storage: Arc<dyn Storage> = match config {
+        StorageConfig::Local { base_dir } => Arc::new(LocalStorage::new(base_dir)),
+        StorageConfig::S3(config) => Arc::new(S3Storage::new(config)?),
+    };
+    Ok(storage)
+}
+
+/// The part of a file asked for by a `Range` header.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ByteRange {
+    Full,
+    Partial(Range<u64>),
+    Unsatisfiable,
+}
+
+/// Parse a single `bytes=` range, other and malformed ranges are ignored and the whole file
+/// is sent.
+pub(crate) fn parse_range(header: Option<&str>, size: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(n) => (size.saturating_sub(n), size),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => (start, size),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, (end + 1).min(size)),
+            _ => return ByteRange::Full,
+        },
+    };
+    if start >= size {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_header_should_be_parsed() {
+        assert_eq!(parse_range(None, 10), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=2-4"), 10), ByteRange::Partial(2..5));
+        assert_eq!(parse_range(Some("bytes=2-"), 10), ByteRange::Partial(2..10));
+        assert_eq!(parse_range(Some("bytes=-3"), 10), ByteRange::Partial(7..10));
+        assert_eq!(
+            parse_range(Some("bytes=5-100"), 10),
+            ByteRange::Partial(5..10)
+        );
+        assert_eq!(parse_range(Some("bytes=10-"), 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=4-2"), 10), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-1"), 10), ByteRange::Full);
+    }
+}
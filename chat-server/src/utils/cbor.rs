@@ -0,0 +1,166 @@
+use crate::AppError;
+
+/// Nested values deeper than this are rejected, webauthn structures are shallow.
+const MAX_DEPTH: usize = 16;
+
+/// A decoded CBOR value, only what webauthn authenticators produce is supported: no
+/// indefinite lengths, tags or floats.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CborValue {
+    Int(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<CborValue>),
+    Map(Vec<(CborValue, CborValue)>),
+    Bool(bool),
+    Null,
+}
+
+impl CborValue {
+    /// Decode the value at the start of the bytes, returning how many bytes it used.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), AppError> {
+        let mut decoder = Decoder { bytes, pos: 0 };
+        let value = decoder.value(0)?;
+        Ok((value, decoder.pos))
+    }
+
+    /// The value of a key of a map.
+    pub fn get(&self, key: &CborValue) -> Option<&CborValue> {
+        match self {
+            Self::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i128> {
+        match self {
+            Self::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn value(&mut self, depth: usize) -> Result<CborValue, AppError> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("too deeply nested"));
+        }
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        if major == 7 {
+            return match info {
+                20 => Ok(CborValue::Bool(false)),
+                21 => Ok(CborValue::Bool(true)),
+                22 => Ok(CborValue::Null),
+                _ => Err(invalid("unsupported simple value")),
+            };
+        }
+        let arg = self.argument(info)?;
+        match major {
+            0 => Ok(CborValue::Int(arg as i128)),
+            1 => Ok(CborValue::Int(-1 - arg as i128)),
+            2 => Ok(CborValue::Bytes(self.take(arg)?.to_vec())),
+            3 => {
+                let text = std::str::from_utf8(self.take(arg)?).map_err(|_| invalid("bad text"))?;
+                Ok(CborValue::Text(text.to_string()))
+            }
+            4 => {
+                // every item takes at least a byte, this bounds the allocation
+                let len = self.len(arg)?;
+                let items = (0..len)
+                    .map(|_| self.value(depth + 1))
+                    .collect::<Result<_, _>>()?;
+                Ok(CborValue::Array(items))
+            }
+            5 => {
+                let len = self.len(arg)?;
+                let entries = (0..len)
+                    .map(|_| Ok((self.value(depth + 1)?, self.value(depth + 1)?)))
+                    .collect::<Result<_, AppError>>()?;
+                Ok(CborValue::Map(entries))
+            }
+            _ => Err(invalid("tags are not supported")),
+        }
+    }
+
+    fn argument(&mut self, info: u8) -> Result<u64, AppError> {
+        let size = match info {
+            0..=23 => return Ok(info as u64),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Err(invalid("indefinite lengths are not supported")),
+        };
+        let bytes = self.take(size)?;
+        Ok(bytes.iter().fold(0, |n, b| (n << 8) | *b as u64))
+    }
+
+    fn len(&self, arg: u64) -> Result<usize, AppError> {
+        match usize::try_from(arg) {
+            Ok(len) if len <= self.bytes.len() - self.pos => Ok(len),
+            _ => Err(invalid("truncated")),
+        }
+    }
+
+    fn take(&mut self, len: u64) -> Result<&[u8], AppError> {
+        let len = self.len(len)?;
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+fn invalid(reason: &str) -> AppError {
+    AppError::InvalidInput(format!("invalid cbor: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cbor_should_decode_webauthn_values() {
+        // {"fmt": "none", 1: -7, "x": h'0102', "ok": [true, null]} and a trailing byte
+        let bytes = [
+            0xa4, 0x63, b'f', b'm', b't', 0x64, b'n', b'o', b'n', b'e', 0x01, 0x26, 0x61, b'x',
+            0x42, 0x01, 0x02, 0x62, b'o', b'k', 0x82, 0xf5, 0xf6, 0xff,
+        ];
+        let (value, used) = CborValue::decode(&bytes).unwrap();
+        assert_eq!(used, bytes.len() - 1);
+        let text = |s: &str| CborValue::Text(s.to_string());
+        assert_eq!(value.get(&text("fmt")), Some(&text("none")));
+        assert_eq!(
+            value.get(&CborValue::Int(1)).and_then(|v| v.as_int()),
+            Some(-7)
+        );
+        assert_eq!(
+            value.get(&text("x")).and_then(|v| v.as_bytes()),
+            Some(&[1u8, 2][..])
+        );
+        assert_eq!(
+            value.get(&text("ok")),
+            Some(&CborValue::Array(vec![
+                CborValue::Bool(true),
+                CborValue::Null
+            ]))
+        );
+
+        // a byte string longer than the input
+        assert!(CborValue::decode(&[0x5a, 0xff, 0xff, 0xff, 0xff, 0x00]).is_err());
+        // an array nested too deeply
+        assert!(CborValue::decode(&[0x81; 64]).is_err());
+    }
+}
@@ -1,7 +1,22 @@
+mod cbor;
 mod jwt;
+mod oidc;
+mod outbound;
+mod sign;
 mod test;
+mod webauthn;
 
 pub use jwt::{DecodingKey, EncodingKey};
+pub use oidc::{random_token, OidcClient, OidcIdentity};
+pub use outbound::{check_outbound_url, outbound_client};
+pub use sign::sign;
+pub use webauthn::{
+    decode_b64, encode_b64, new_challenge, parse_client_data, verify_assertion, verify_registration,
+};
 
 #[cfg(test)]
-pub use test::utils::{create_test_pool, parser_response};
+pub use oidc::{code_challenge, OidcProfile};
+#[cfg(test)]
+pub use test::utils::{create_test_pool, load_fixtures, parser_response};
+#[cfg(test)]
+pub use webauthn::test_authenticator::SoftAuthenticator;
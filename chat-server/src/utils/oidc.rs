@@ -0,0 +1,234 @@
+use jwt_simple::prelude::*;
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{config::OidcProviderConfig, AppError};
+
+/// The endpoints of a provider, from its `.well-known/openid-configuration`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcDiscovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// What the provider tells about the user in its id token.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OidcProfile {
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// A user authenticated by a provider, the subject is its stable id there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OidcIdentity {
+    pub subject: String,
+    pub profile: OidcProfile,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+/// The authorization code flow with PKCE against one provider.
+pub struct OidcClient<'a> {
+    config: &'a OidcProviderConfig,
+    http: &'a Client,
+}
+
+impl<'a> OidcClient<'a> {
+    pub fn new(config: &'a OidcProviderConfig, http: &'a Client) -> Self {
+        Self { config, http }
+    }
+
+    pub async fn discover(&self) -> Result<OidcDiscovery, AppError> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let discovery: OidcDiscovery = get_json(self.http.get(url)).await?;
+        // the issuer of the tokens must be the configured one
+        if discovery.issuer != self.config.issuer {
+            return Err(AppError::UpstreamError(format!(
+                "unexpected issuer {}",
+                discovery.issuer
+            )));
+        }
+        Ok(discovery)
+    }
+
+    /// Where to send the user to sign in at the provider.
+    pub fn authorize_url(
+        &self,
+        discovery: &OidcDiscovery,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<Url, AppError> {
+        let mut url = Url::parse(&discovery.authorization_endpoint)
+            .map_err(|e| AppError::UpstreamError(format!("invalid authorization url: {}", e)))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", "openid email profile")
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", &code_challenge(code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url)
+    }
+
+    /// Exchange the code the provider redirected back with, the id token it returns is
+    /// verified with the keys of the provider.
+    pub async fn exchange(
+        &self,
+        discovery: &OidcDiscovery,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<OidcIdentity, AppError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let token: TokenResponse =
+            get_json(self.http.post(&discovery.token_endpoint).form(&form)).await?;
+        let jwks: JwkSet = get_json(self.http.get(&discovery.jwks_uri)).await?;
+        self.verify_id_token(&token.id_token, &jwks, &discovery.issuer, nonce)
+    }
+
+    fn verify_id_token(
+        &self,
+        id_token: &str,
+        jwks: &JwkSet,
+        issuer: &str,
+        nonce: &str,
+    ) -> Result<OidcIdentity, AppError> {
+        let metadata = Token::decode_metadata(id_token)?;
+        let jwk = jwks
+            .keys
+            .iter()
+            .find(|k| metadata.key_id().is_none() || k.kid.as_deref() == metadata.key_id())
+            .ok_or_else(|| AppError::UpstreamError("unknown id token key".to_string()))?;
+        let opts = VerificationOptions {
+            allowed_issuers: Some(HashSet::from_strings(&[issuer])),
+            allowed_audiences: Some(HashSet::from_strings(&[&self.config.client_id])),
+            required_nonce: Some(nonce.to_string()),
+            ..Default::default()
+        };
+        let claims = match (metadata.algorithm(), jwk.kty.as_str()) {
+            ("RS256", "RSA") => {
+                let key = RS256PublicKey::from_components(
+                    &decode_b64(jwk.n.as_deref())?,
+                    &decode_b64(jwk.e.as_deref())?,
+                )?;
+                key.verify_token::<OidcProfile>(id_token, Some(opts))?
+            }
+            ("ES256", "EC") if jwk.crv.as_deref() == Some("P-256") => {
+                // an uncompressed sec1 point
+                let mut point = vec![0x04];
+                point.extend(decode_b64(jwk.x.as_deref())?);
+                point.extend(decode_b64(jwk.y.as_deref())?);
+                let key = ES256PublicKey::from_bytes(&point)?;
+                key.verify_token::<OidcProfile>(id_token, Some(opts))?
+            }
+            (alg, _) => {
+                return Err(AppError::UpstreamError(format!(
+                    "unsupported id token algorithm {}",
+                    alg
+                )))
+            }
+        };
+        let subject = claims
+            .subject
+            .ok_or_else(|| AppError::UpstreamError("id token without subject".to_string()))?;
+        Ok(OidcIdentity {
+            subject,
+            profile: claims.custom,
+        })
+    }
+}
+
+/// A random string for the state, the nonce and the code verifier of a login.
+pub fn random_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// The S256 challenge of a PKCE code verifier.
+pub fn code_challenge(code_verifier: &str) -> String {
+    let digest = Sha256::digest(code_verifier.as_bytes());
+    Base64UrlSafeNoPadding::encode_to_string(digest).expect("base64 encoding never fails")
+}
+
+fn decode_b64(value: Option<&str>) -> Result<Vec<u8>, AppError> {
+    value
+        .and_then(|v| Base64UrlSafeNoPadding::decode_to_vec(v, None).ok())
+        .ok_or_else(|| AppError::UpstreamError("invalid key of the provider".to_string()))
+}
+
+async fn get_json<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T, AppError> {
+    let upstream = |e: reqwest::Error| AppError::UpstreamError(e.to_string());
+    let res = request.send().await.map_err(upstream)?;
+    let status = res.status();
+    let body = res.bytes().await.map_err(upstream)?;
+    if !status.is_success() {
+        return Err(AppError::UpstreamError(format!(
+            "identity provider returned {}: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        )));
+    }
+    serde_json::from_slice(&body).map_err(|e| AppError::UpstreamError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_challenge_should_follow_rfc7636() {
+        // the example of appendix B
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        assert_eq!(
+            code_challenge(verifier),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, Client, Url,
+};
+use tokio::net::lookup_host;
+
+use crate::AppError;
+
+/// Check a url the server calls on behalf of users, like webhooks and slash commands. Only http(s) urls are
+/// accepted and, unless private hosts are allowed, hosts resolving to loopback, private or
+/// link-local addresses are rejected so users cannot reach the network of the server. A host
+/// which does not resolve yet passes, `outbound_client` only connects to public addresses.
+pub async fn check_outbound_url(url: &str, allow_private: bool) -> Result<Url, AppError> {
+    let invalid = || AppError::InvalidInput(format!("invalid url: {}", url));
+    let parsed = Url::parse(url).map_err(|_| invalid())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(invalid());
+    }
+    let host = parsed.host_str().ok_or_else(invalid)?;
+    // ipv6 hosts keep their brackets
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<IpAddr> = match host.parse() {
+        _ if allow_private => vec![],
+        Ok(ip) => vec![ip],
+        Err(_) => match lookup_host((host, 0)).await {
+            Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+            Err(_) => vec![],
+        },
+    };
+    if let Some(ip) = addrs.into_iter().find(|ip| !is_public(*ip)) {
+        return Err(AppError::InvalidInput(format!(
+            "{} points to the private address {}",
+            url, ip
+        )));
+    }
+    Ok(parsed)
+}
+
+/// A client for urls checked with `check_outbound_url`. Redirects are not followed and, unless
+/// private hosts are allowed, names only resolve to public addresses, so a host pointed to
+/// another address after the check is still refused.
+pub fn outbound_client(timeout: Duration, allow_private: bool) -> reqwest::Result<Client> {
+    let builder = Client::builder()
+        .timeout(timeout)
+        .redirect(redirect::Policy::none());
+    match allow_private {
+        true => builder.build(),
+        false => builder.dns_resolver(Arc::new(PublicResolver)).build(),
+    }
+}
+
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // "this network" and the shared address space of carrier-grade NAT
+                || a == 0
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn outbound_url_should_be_public() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/hook",
+            "http://192.168.0.1/hook",
+            "http://100.64.0.1/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            let ret = check_outbound_url(url, false).await;
+            assert!(matches!(ret, Err(AppError::InvalidInput(_))), "{}", url);
+        }
+        assert!(check_outbound_url("ftp://example.com/hook", false)
+            .await
+            .is_err());
+        assert!(check_outbound_url("https://93.184.215.14/hook", false)
+            .await
+            .is_ok());
+        assert!(check_outbound_url("http://127.0.0.1/hook", true)
+            .await
+            .is_ok());
+    }
+}
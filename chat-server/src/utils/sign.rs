@@ -0,0 +1,13 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Hex encoded HMAC-SHA256 of `{timestamp}.{body}`, receivers recompute it with the shared
+/// secret and should reject old timestamps.
+pub fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
@@ -30,9 +30,18 @@ pub mod utils {
         let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
         let tdb = TestPg::new(database_url, Path::new("../migrations"));
         let pool = tdb.get_pool().await;
+        load_fixtures(&pool).await?;
         Ok(TestDb { _tdb: tdb, pool })
     }
 
+    // 测试数据: 用户 alice/bob/charlie/dave, 聊天 general/bob-charlie 以及一些消息
+    pub async fn load_fixtures(pool: &Pool<Postgres>) -> Result<()> {
+        sqlx::raw_sql(include_str!("../../fixtures/test.sql"))
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
     // for<'de> 就像是告诉编译器："别担心，这个类型可以处理任何生命周期的输入"
     pub async fn parser_response<T>(res: Response<Body>) -> Result<T>
     where
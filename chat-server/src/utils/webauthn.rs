@@ -0,0 +1,386 @@
+use jwt_simple::reexports::ct_codecs::{Base64UrlSafeNoPadding, Decoder, Encoder};
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{config::WebauthnConfig, AppError};
+
+use super::cbor::CborValue;
+
+/// The user touched the authenticator.
+const FLAG_USER_PRESENT: u8 = 0x01;
+/// The authenticator checked who the user is, e.g. with a fingerprint or a pin.
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+/// COSE ids of an EC2 key on P-256 used with ES256.
+const COSE_KTY_EC2: i128 = 2;
+const COSE_ALG_ES256: i128 = -7;
+const COSE_CRV_P256: i128 = 1;
+
+/// What the browser tells about the ceremony, signed with the authenticator data.
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ty: String,
+    challenge: String,
+    origin: String,
+}
+
+/// The authenticator data of a registration or an authentication.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: Vec<u8>,
+    pub flags: u8,
+    pub sign_count: u32,
+    /// the id and the public key of a new credential, only in registrations
+    pub credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// A credential created by an authenticator, the public key is a SEC1 encoded P-256 point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewPasskey {
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+impl AuthenticatorData {
+    pub fn parse(bytes: &[u8]) -> Result<Self, AppError> {
+        if bytes.len() < 37 {
+            return Err(invalid("authenticator data is too short"));
+        }
+        let flags = bytes[32];
+        let sign_count = u32::from_be_bytes(bytes[33..37].try_into().expect("4 bytes"));
+        let mut credential = None;
+        if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+            // aaguid, then the length of the credential id
+            let rest = bytes.get(37 + 16..).ok_or_else(|| invalid("truncated"))?;
+            let (len, rest) = rest
+                .split_at_checked(2)
+                .ok_or_else(|| invalid("truncated"))?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            let (id, rest) = rest
+                .split_at_checked(len)
+                .ok_or_else(|| invalid("truncated"))?;
+            let (key, _) = CborValue::decode(rest)?;
+            credential = Some((id.to_vec(), cose_to_sec1(&key)?));
+        }
+        Ok(Self {
+            rp_id_hash: bytes[..32].to_vec(),
+            flags,
+            sign_count,
+            credential,
+        })
+    }
+
+    /// The data is for our relying party and the user was there, verified if required.
+    fn check(&self, config: &WebauthnConfig, user_verification: bool) -> Result<(), AppError> {
+        if self.rp_id_hash != Sha256::digest(config.rp_id.as_bytes()).as_slice() {
+            return Err(denied("the passkey is for another site"));
+        }
+        if self.flags & FLAG_USER_PRESENT == 0 {
+            return Err(denied("the user was not present"));
+        }
+        if user_verification && self.flags & FLAG_USER_VERIFIED == 0 {
+            return Err(denied("the user was not verified"));
+        }
+        Ok(())
+    }
+}
+
+/// Check the client data of a ceremony of the type, returning its challenge.
+pub fn parse_client_data(
+    client_data_json: &[u8],
+    ty: &str,
+    config: &WebauthnConfig,
+) -> Result<String, AppError> {
+    let data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|e| invalid(&format!("client data: {}", e)))?;
+    if data.ty != ty {
+        return Err(invalid(&format!("expected a {} ceremony", ty)));
+    }
+    if data.origin != config.origin {
+        return Err(denied(&format!("unexpected origin {}", data.origin)));
+    }
+    Ok(data.challenge)
+}
+
+/// Verify the attestation object of a registration. Attestation statements are not checked,
+/// we ask for none and trust the key the browser hands over.
+pub fn verify_registration(
+    attestation_object: &[u8],
+    config: &WebauthnConfig,
+) -> Result<NewPasskey, AppError> {
+    let (attestation, _) = CborValue::decode(attestation_object)?;
+    let auth_data = attestation
+        .get(&CborValue::Text("authData".to_string()))
+        .and_then(CborValue::as_bytes)
+        .ok_or_else(|| invalid("no authenticator data"))?;
+    let auth_data = AuthenticatorData::parse(auth_data)?;
+    auth_data.check(config, false)?;
+    let (credential_id, public_key) = auth_data
+        .credential
+        .ok_or_else(|| invalid("no credential"))?;
+    Ok(NewPasskey {
+        credential_id,
+        public_key,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+/// Verify the signature of an authentication, returning the new sign count.
+pub fn verify_assertion(
+    public_key: &[u8],
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+    user_verification: bool,
+    config: &WebauthnConfig,
+) -> Result<u32, AppError> {
+    let auth_data = AuthenticatorData::parse(authenticator_data)?;
+    auth_data.check(config, user_verification)?;
+    let key = VerifyingKey::from_sec1_bytes(public_key).map_err(|_| invalid("bad public key"))?;
+    let signature = Signature::from_der(signature).map_err(|_| invalid("bad signature"))?;
+    let mut signed = authenticator_data.to_vec();
+    signed.extend(Sha256::digest(client_data_json));
+    key.verify(&signed, &signature)
+        .map_err(|_| denied("invalid signature"))?;
+    Ok(auth_data.sign_count)
+}
+
+/// A random challenge of 32 bytes.
+pub fn new_challenge() -> String {
+    let mut bytes = Uuid::new_v4().into_bytes().to_vec();
+    bytes.extend(Uuid::new_v4().into_bytes());
+    encode_b64(&bytes)
+}
+
+pub fn encode_b64(bytes: &[u8]) -> String {
+    Base64UrlSafeNoPadding::encode_to_string(bytes).expect("base64 encoding never fails")
+}
+
+pub fn decode_b64(value: &str) -> Result<Vec<u8>, AppError> {
+    Base64UrlSafeNoPadding::decode_to_vec(value, None).map_err(|_| invalid("bad base64url"))
+}
+
+fn cose_to_sec1(key: &CborValue) -> Result<Vec<u8>, AppError> {
+    let int = |k: i128| key.get(&CborValue::Int(k)).and_then(CborValue::as_int);
+    let bytes = |k: i128| key.get(&CborValue::Int(k)).and_then(CborValue::as_bytes);
+    if int(1) != Some(COSE_KTY_EC2)
+        || int(3) != Some(COSE_ALG_ES256)
+        || int(-1) != Some(COSE_CRV_P256)
+    {
+        return Err(invalid("only ES256 passkeys are supported"));
+    }
+    let (Some(x), Some(y)) = (bytes(-2), bytes(-3)) else {
+        return Err(invalid("bad public key"));
+    };
+    let mut point = vec![0x04];
+    point.extend(x);
+    point.extend(y);
+    VerifyingKey::from_sec1_bytes(&point).map_err(|_| invalid("bad public key"))?;
+    Ok(point)
+}
+
+fn invalid(reason: &str) -> AppError {
+    AppError::InvalidInput(format!("invalid passkey: {}", reason))
+}
+
+fn denied(reason: &str) -> AppError {
+    AppError::PermissionDenied(format!("passkey rejected: {}", reason))
+}
+
+/// An authenticator in memory, it answers ceremonies like a browser would.
+#[cfg(test)]
+pub mod test_authenticator {
+    use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    pub struct SoftAuthenticator {
+        key: SigningKey,
+        pub credential_id: Vec<u8>,
+        pub sign_count: u32,
+        rp_id: String,
+        origin: String,
+    }
+
+    impl SoftAuthenticator {
+        pub fn new(config: &WebauthnConfig) -> Self {
+            let seed = Sha256::digest(Uuid::new_v4().as_bytes());
+            Self {
+                key: SigningKey::from_slice(&seed).expect("a valid scalar"),
+                credential_id: Uuid::new_v4().as_bytes().to_vec(),
+                sign_count: 0,
+                rp_id: config.rp_id.clone(),
+                origin: config.origin.clone(),
+            }
+        }
+
+        /// The `PublicKeyCredential` of `navigator.credentials.create()`, as json.
+        pub fn register(&self, challenge: &str) -> Value {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let mut cose = vec![0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21];
+            cose.extend(cbor_bytes(point.x().unwrap()));
+            cose.push(0x22);
+            cose.extend(cbor_bytes(point.y().unwrap()));
+
+            let mut auth_data = self.auth_data(
+                FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED_CREDENTIAL,
+                self.sign_count,
+            );
+            auth_data.extend([0; 16]);
+            auth_data.extend((self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend(&self.credential_id);
+            auth_data.extend(cose);
+
+            // {"fmt": "none", "attStmt": {}, "authData": ...}
+            let mut attestation = vec![0xa3, 0x63];
+            attestation.extend(b"fmt");
+            attestation.push(0x64);
+            attestation.extend(b"none");
+            attestation.push(0x67);
+            attestation.extend(b"attStmt");
+            attestation.push(0xa0);
+            attestation.push(0x68);
+            attestation.extend(b"authData");
+            attestation.extend(cbor_bytes(&auth_data));
+
+            json!({
+                "id": encode_b64(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "clientDataJSON": self.client_data("webauthn.create", challenge),
+                    "attestationObject": encode_b64(&attestation),
+                },
+            })
+        }
+
+        /// The `PublicKeyCredential` of `navigator.credentials.get()`, as json.
+        pub fn authenticate(&mut self, challenge: &str, user_verified: bool) -> Value {
+            self.sign_count += 1;
+            let flags = match user_verified {
+                true => FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+                false => FLAG_USER_PRESENT,
+            };
+            let auth_data = self.auth_data(flags, self.sign_count);
+            let client_data = self.client_data("webauthn.get", challenge);
+            let mut signed = auth_data.clone();
+            signed.extend(Sha256::digest(decode_b64(&client_data).unwrap()));
+            let signature: Signature = self.key.sign(&signed);
+            json!({
+                "id": encode_b64(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "clientDataJSON": client_data,
+                    "authenticatorData": encode_b64(&auth_data),
+                    "signature": encode_b64(signature.to_der().as_bytes()),
+                },
+            })
+        }
+
+        fn auth_data(&self, flags: u8, sign_count: u32) -> Vec<u8> {
+            let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend(sign_count.to_be_bytes());
+            data
+        }
+
+        fn client_data(&self, ty: &str, challenge: &str) -> String {
+            let data = json!({ "type": ty, "challenge": challenge, "origin": self.origin });
+            encode_b64(data.to_string().as_bytes())
+        }
+    }
+
+    fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+        let mut out = match bytes.len() {
+            len @ 0..=23 => vec![0x40 | len as u8],
+            len @ 24..=255 => vec![0x58, len as u8],
+            len => {
+                let mut out = vec![0x59];
+                out.extend((len as u16).to_be_bytes());
+                out
+            }
+        };
+        out.extend(bytes);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test_authenticator::SoftAuthenticator, *};
+
+    fn config() -> WebauthnConfig {
+        WebauthnConfig {
+            rp_id: "localhost".to_string(),
+            rp_name: "Crablink".to_string(),
+            origin: "http://localhost:7070".to_string(),
+        }
+    }
+
+    fn field(value: &serde_json::Value, name: &str) -> Vec<u8> {
+        decode_b64(value["response"][name].as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn registration_and_assertion_should_verify() {
+        let config = config();
+        let mut authenticator = SoftAuthenticator::new(&config);
+        let credential = authenticator.register("c1");
+        let client_data = field(&credential, "clientDataJSON");
+        assert_eq!(
+            parse_client_data(&client_data, "webauthn.create", &config).unwrap(),
+            "c1"
+        );
+        let passkey =
+            verify_registration(&field(&credential, "attestationObject"), &config).unwrap();
+        assert_eq!(passkey.credential_id, authenticator.credential_id);
+
+        let assertion = authenticator.authenticate("c2", false);
+        let auth_data = field(&assertion, "authenticatorData");
+        let client_data = field(&assertion, "clientDataJSON");
+        let signature = field(&assertion, "signature");
+        let verify = |uv: bool, client_data: &[u8], config: &WebauthnConfig| {
+            verify_assertion(
+                &passkey.public_key,
+                &auth_data,
+                client_data,
+                &signature,
+                uv,
+                config,
+            )
+        };
+        assert_eq!(verify(false, &client_data, &config).unwrap(), 1);
+        assert!(matches!(
+            verify(true, &client_data, &config),
+            Err(AppError::PermissionDenied(_))
+        ));
+        // the signature covers the client data
+        let mut tampered = client_data.clone();
+        tampered.push(b' ');
+        assert!(matches!(
+            verify(false, &tampered, &config),
+            Err(AppError::PermissionDenied(_))
+        ));
+        let other = WebauthnConfig {
+            rp_id: "evil.com".to_string(),
+            ..config.clone()
+        };
+        assert!(matches!(
+            verify(false, &client_data, &other),
+            Err(AppError::PermissionDenied(_))
+        ));
+        assert!(parse_client_data(&client_data, "webauthn.get", &other).is_ok());
+        let other = WebauthnConfig {
+            origin: "https://evil.com".to_string(),
+            ..config
+        };
+        assert!(matches!(
+            parse_client_data(&client_data, "webauthn.get", &other),
+            Err(AppError::PermissionDenied(_))
+        ));
+    }
+}
@@ -0,0 +1,7 @@
+mod retention;
+mod schedule;
+mod webhook;
+
+pub(crate) use retention::spawn_retention_worker;
+pub(crate) use schedule::spawn_schedule_worker;
+pub(crate) use webhook::spawn_webhook_worker;
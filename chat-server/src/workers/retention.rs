@@ -0,0 +1,47 @@
+use std::{sync::Arc, time::Duration};
+
+use sqlx::PgPool;
+use tokio::time;
+use tracing::{info, warn};
+
+use crate::{
+    models::{ChatFile, Message},
+    storage::Storage,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Small batches keep the locks short, other writes wait at most for one of them.
+const BATCH_SIZE: i64 = 500;
+const BATCH_PAUSE: Duration = Duration::from_millis(100);
+
+/// Purge messages older than the retention of their chat, and the uploads to presigned urls
+/// which expired.
+pub(crate) fn spawn_retention_worker(pool: PgPool, storage: Arc<dyn Storage>) {
+    tokio::spawn(async move {
+        let mut interval = time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let mut purged = 0;
+            loop {
+                match Message::purge_expired(BATCH_SIZE, storage.as_ref(), &pool).await {
+                    Ok(0) => break,
+                    Ok(n) => purged += n,
+                    Err(e) => {
+                        warn!("failed to purge expired messages: {}", e);
+                        break;
+                    }
+                }
+                time::sleep(BATCH_PAUSE).await;
+            }
+            if purged > 0 {
+                info!("purged {} expired messages", purged);
+            }
+            match ChatFile::sweep_uploads(BATCH_SIZE, storage.as_ref(), &pool).await {
+                Ok(0) => {}
+                Ok(n) => info!("removed {} expired uploads", n),
+                Err(e) => warn!("failed to remove expired uploads: {}", e),
+            }
+        }
+    });
+}
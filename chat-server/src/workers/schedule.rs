@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tokio::time;
+use tracing::{info, warn};
+
+use crate::models::ScheduledMessage;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const BATCH_SIZE: i64 = 100;
+
+/// Deliver scheduled messages once they are due.
+pub(crate) fn spawn_schedule_worker(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut interval = time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match ScheduledMessage::deliver_due(BATCH_SIZE, &pool).await {
+                Ok(0) => {}
+                Ok(n) => info!("delivered {} scheduled messages", n),
+                Err(e) => warn!("failed to deliver scheduled messages: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,252 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use reqwest::Client;
+use sqlx::PgPool;
+use tokio::{task::JoinSet, time};
+use tracing::{info, warn};
+
+use crate::{
+    models::{DueDelivery, WebhookDelivery},
+    utils::{check_outbound_url, outbound_client, sign},
+    AppError,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const BATCH_SIZE: i64 = 50;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Send the queued webhook deliveries, failed ones are retried with a backoff.
+pub(crate) fn spawn_webhook_worker(pool: PgPool, allow_private: bool) {
+    tokio::spawn(async move {
+        let client =
+            outbound_client(REQUEST_TIMEOUT, allow_private).expect("webhook client should build");
+        let mut interval = time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match deliver_due(&client, allow_private, &pool).await {
+                Ok(0) => {}
+                Ok(n) => info!("delivered {} webhook events", n),
+                Err(e) => warn!("failed to deliver webhook events: {}", e),
+            }
+        }
+    });
+}
+
+/// Send a batch of due deliveries concurrently, returns how many of them succeeded.
+pub(crate) async fn deliver_due(
+    client: &Client,
+    allow_private: bool,
+    pool: &PgPool,
+) -> Result<usize, AppError> {
+    let due = WebhookDelivery::claim_due(BATCH_SIZE, pool).await?;
+    let mut tasks = JoinSet::new();
+    for delivery in due {
+        let client = client.clone();
+        let pool = pool.clone();
+        tasks.spawn(async move { deliver(&client, &delivery, allow_private, &pool).await });
+    }
+
+    let mut delivered = 0;
+    while let Some(ret) = tasks.join_next().await {
+        match ret {
+            Ok(Ok(true)) => delivered += 1,
+            Ok(Ok(false)) => {}
+            Ok(Err(e)) => warn!("failed to record webhook delivery: {}", e),
+            Err(e) => warn!("webhook delivery task failed: {}", e),
+        }
+    }
+    Ok(delivered)
+}
+
+async fn deliver(
+    client: &Client,
+    delivery: &DueDelivery,
+    allow_private: bool,
+    pool: &PgPool,
+) -> Result<bool, AppError> {
+    // the host may point somewhere else since the webhook was created
+    if let Err(e) = check_outbound_url(&delivery.url, allow_private).await {
+        WebhookDelivery::mark_failed(delivery, None, &e.to_string(), pool).await?;
+        warn!("webhook delivery {} refused: {}", delivery.id, e);
+        return Ok(false);
+    }
+    let body = delivery.payload.to_string();
+    let timestamp = Utc::now().timestamp().to_string();
+    let signature = sign(&delivery.secret, &timestamp, &body);
+    let ret = client
+        .post(&delivery.url)
+        .header("content-type", "application/json")
+        .header("x-webhook-id", delivery.id)
+        .header("x-webhook-event", &delivery.event)
+        .header("x-webhook-timestamp", &timestamp)
+        .header("x-webhook-signature", format!("sha256={}", signature))
+        .body(body)
+        .send()
+        .await;
+
+    let (status, error) = match ret {
+        Ok(res) if res.status().is_success() => {
+            WebhookDelivery::mark_delivered(delivery.id, res.status().as_u16(), pool).await?;
+            return Ok(true);
+        }
+        Ok(res) => (
+            Some(res.status().as_u16()),
+            format!("unexpected status {}", res.status()),
+        ),
+        Err(e) => (None, e.to_string()),
+    };
+    let status = WebhookDelivery::mark_failed(delivery, status, &error, pool).await?;
+    warn!(
+        "webhook delivery {} failed ({:?}): {}",
+        delivery.id, status, error
+    );
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    use anyhow::Result;
+    use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        models::{
+            CreateMessage, CreateWebhook, ListMessages, Message, Webhook, WebhookDeadLetter,
+            WebhookDeliveryStatus,
+        },
+        utils::create_test_pool,
+    };
+
+    #[derive(Default)]
+    struct Receiver {
+        /// how many requests to answer with an error before accepting them
+        failures: AtomicUsize,
+        received: Mutex<Vec<(HeaderMap, String)>>,
+    }
+
+    async fn receive(
+        State(receiver): State<Arc<Receiver>>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        receiver.received.lock().unwrap().push((headers, body));
+        match receiver
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        {
+            Ok(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Err(_) => StatusCode::NO_CONTENT,
+        }
+    }
+
+    async fn start_receiver(failures: usize) -> Result<(String, Arc<Receiver>)> {
+        let receiver = Arc::new(Receiver {
+            failures: AtomicUsize::new(failures),
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(receiver.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/hook", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok((url, receiver))
+    }
+
+    #[tokio::test]
+    async fn webhook_should_receive_signed_events_after_retry() -> Result<()> {
+        let db = create_test_pool().await?;
+        let (url, receiver) = start_receiver(1).await?;
+        let input = CreateWebhook {
+            url,
+            events: vec![],
+        };
+        let webhook = Webhook::create(1, &input, 1, true, &db).await?;
+        let input = CreateMessage {
+            content: "ship it".to_string(),
+            ..Default::default()
+        };
+        let message = Message::create(&input, 1, 2, &db).await?;
+
+        let client = Client::new();
+        assert_eq!(deliver_due(&client, true, &db).await?, 0);
+        let log = WebhookDelivery::list(1, webhook.id, &ListMessages::default(), 1, &db).await?;
+        assert_eq!(log[0].status, WebhookDeliveryStatus::Pending);
+        assert_eq!(log[0].attempts, 1);
+        assert_eq!(log[0].response_status, Some(500));
+        assert!(log[0].next_attempt_at > Utc::now());
+
+        // nothing is due until the backoff passed
+        assert_eq!(deliver_due(&client, true, &db).await?, 0);
+        sqlx::query("UPDATE webhook_deliveries SET next_attempt_at = now()")
+            .execute(&*db)
+            .await?;
+        assert_eq!(deliver_due(&client, true, &db).await?, 1);
+        let log = WebhookDelivery::list(1, webhook.id, &ListMessages::default(), 1, &db).await?;
+        assert_eq!(log[0].status, WebhookDeliveryStatus::Delivered);
+        assert_eq!(log[0].attempts, 2);
+
+        let received = receiver.received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let (headers, body) = &received[1];
+        let header = |name: &str| headers[name].to_str().unwrap().to_string();
+        assert_eq!(header("x-webhook-event"), "message.created");
+        let signature = sign(&webhook.secret, &header("x-webhook-timestamp"), body);
+        assert_eq!(
+            header("x-webhook-signature"),
+            format!("sha256={}", signature)
+        );
+        let payload: serde_json::Value = serde_json::from_str(body)?;
+        assert_eq!(payload["chat_id"], 1);
+        assert_eq!(payload["data"]["id"], message.id);
+        assert_eq!(payload["data"]["content"], "ship it");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failing_webhook_should_end_in_dead_letters() -> Result<()> {
+        let db = create_test_pool().await?;
+        let (url, receiver) = start_receiver(usize::MAX).await?;
+        let input = CreateWebhook {
+            url,
+            events: vec!["message.deleted".to_string()],
+        };
+        let webhook = Webhook::create(1, &input, 1, true, &db).await?;
+        Message::delete(2, 2, &db).await?;
+        // the last of 8 attempts
+        sqlx::query("UPDATE webhook_deliveries SET attempts = 7")
+            .execute(&*db)
+            .await?;
+
+        let client = Client::new();
+        assert_eq!(deliver_due(&client, true, &db).await?, 0);
+        assert_eq!(receiver.received.lock().unwrap().len(), 1);
+        let dead_letters = WebhookDeadLetter::list(1, webhook.id, 1, &db).await?;
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].event, "message.deleted");
+        assert_eq!(dead_letters[0].attempts, 8);
+
+        let delivery = WebhookDeadLetter::retry(1, webhook.id, dead_letters[0].id, 1, &db).await?;
+        assert_eq!(delivery.status, WebhookDeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 0);
+        assert!(WebhookDeadLetter::list(1, webhook.id, 1, &db)
+            .await?
+            .is_empty());
+
+        // private hosts are refused when sending too, without a request
+        assert_eq!(deliver_due(&client, false, &db).await?, 0);
+        assert_eq!(receiver.received.lock().unwrap().len(), 1);
+        let log = WebhookDelivery::list(1, webhook.id, &ListMessages::default(), 1, &db).await?;
+        assert_eq!(log[0].attempts, 1);
+        assert!(log[0].error.as_deref().unwrap().contains("private address"));
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+-- chat admins, they can delete any message in the chat
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS admins bigint[] NOT NULL DEFAULT '{}';
+
+-- messages can be edited and soft deleted
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS updated_at timestamptz;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS deleted_at timestamptz;
+
+-- previous versions of a message, appended before every edit or deletion
+CREATE TABLE IF NOT EXISTS message_edits(
+  id bigserial PRIMARY KEY,
+  message_id bigint NOT NULL REFERENCES messages(id),
+  content text NOT NULL,
+  images text[] NOT NULL DEFAULT '{}',
+  edited_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for message_edits for message_id
+CREATE INDEX IF NOT EXISTS message_edits_message_id_index ON message_edits(message_id, created_at DESC);
+
+-- notify real-time consumers when a message is created, edited or deleted
+-- only ids are sent, the payload of pg_notify is limited to 8000 bytes
+CREATE OR REPLACE FUNCTION message_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  op text;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    op := 'created';
+  ELSIF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+    op := 'deleted';
+  ELSE
+    op := 'edited';
+  END IF;
+  PERFORM
+    pg_notify('chat_message_changed', json_build_object('op', op, 'id', NEW.id, 'chat_id', NEW.chat_id, 'sender_id', NEW.sender_id)::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_changed_trigger
+  AFTER INSERT OR UPDATE OF content, images, deleted_at ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_changed();
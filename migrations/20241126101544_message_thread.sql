@@ -0,0 +1,36 @@
+-- replies of a thread point to the top level message which started it
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS parent_id bigint REFERENCES messages(id);
+
+-- create index for messages for parent_id, used to list replies and count them
+CREATE INDEX IF NOT EXISTS parent_id_index ON messages(parent_id, id DESC)
+WHERE
+  parent_id IS NOT NULL;
+
+-- users following a thread
+CREATE TABLE IF NOT EXISTS thread_followers(
+  message_id bigint NOT NULL REFERENCES messages(id),
+  user_id bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (message_id, user_id)
+);
+
+-- real-time consumers need the thread to notify its followers
+CREATE OR REPLACE FUNCTION message_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  op text;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    op := 'created';
+  ELSIF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+    op := 'deleted';
+  ELSE
+    op := 'edited';
+  END IF;
+  PERFORM
+    pg_notify('chat_message_changed', json_build_object('op', op, 'id', NEW.id, 'chat_id', NEW.chat_id, 'sender_id', NEW.sender_id, 'parent_id', NEW.parent_id)::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,8 @@
+-- read position of every chat member
+CREATE TABLE IF NOT EXISTS chat_reads(
+  chat_id bigint NOT NULL REFERENCES chats(id),
+  user_id bigint NOT NULL REFERENCES users(id),
+  last_read_message_id bigint NOT NULL REFERENCES messages(id),
+  updated_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, user_id)
+);
@@ -0,0 +1,14 @@
+-- create workspace table, every user and chat belongs to one workspace
+CREATE TABLE IF NOT EXISTS workspaces(
+  id bigserial PRIMARY KEY,
+  name varchar(32) NOT NULL UNIQUE,
+  owner_id bigint REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- existing users and chats go to the default workspace
+INSERT INTO workspaces(name)
+  VALUES ('default');
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS ws_id bigint NOT NULL DEFAULT 1 REFERENCES workspaces(id);
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS ws_id bigint NOT NULL DEFAULT 1 REFERENCES workspaces(id);
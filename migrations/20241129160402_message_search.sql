@@ -0,0 +1,32 @@
+-- text search configuration used to index and search the messages of a workspace
+ALTER TABLE workspaces ADD COLUMN IF NOT EXISTS search_config regconfig NOT NULL DEFAULT 'simple';
+
+-- the search configuration is copied into every message, a generated column
+-- can only use the columns of its own row
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS ts_config regconfig NOT NULL DEFAULT 'simple';
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS content_tsv tsvector GENERATED ALWAYS AS (to_tsvector(ts_config, content)) STORED;
+
+-- create gin index for messages for content_tsv
+CREATE INDEX IF NOT EXISTS content_tsv_index ON messages USING GIN (content_tsv);
+
+-- use the search configuration of the workspace when a message is inserted
+CREATE OR REPLACE FUNCTION message_ts_config()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  SELECT
+    w.search_config INTO NEW.ts_config
+  FROM
+    chats c
+    JOIN workspaces w ON w.id = c.ws_id
+  WHERE
+    c.id = NEW.chat_id;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_ts_config_trigger
+  BEFORE INSERT ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_ts_config();
@@ -0,0 +1,17 @@
+-- create mention kind type: user, here, channel
+CREATE TYPE mention_kind AS ENUM(
+  'user',
+  'here',
+  'channel'
+);
+
+-- users mentioned in a message, @here and @channel are expanded to the chat members
+CREATE TABLE IF NOT EXISTS message_mentions(
+  message_id bigint NOT NULL REFERENCES messages(id),
+  user_id bigint NOT NULL REFERENCES users(id),
+  kind mention_kind NOT NULL,
+  PRIMARY KEY (message_id, user_id)
+);
+
+-- create index for message_mentions for user_id, used by the mention inbox
+CREATE INDEX IF NOT EXISTS message_mentions_user_id_index ON message_mentions(user_id, message_id DESC);
@@ -0,0 +1,13 @@
+-- profile fields of users
+ALTER TABLE users ADD COLUMN IF NOT EXISTS display_name varchar(64);
+-- reference to an uploaded file
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar varchar(256);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS title varchar(64);
+-- IANA time zone name, e.g. Asia/Shanghai
+ALTER TABLE users ADD COLUMN IF NOT EXISTS timezone varchar(64);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS status_text varchar(128);
+
+-- a new email only replaces the current one after it is verified
+ALTER TABLE users ADD COLUMN IF NOT EXISTS pending_email varchar(64);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_token varchar(64);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_token_expires_at timestamptz;
@@ -0,0 +1,63 @@
+-- how many messages can be pinned in a chat
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS pin_limit int NOT NULL DEFAULT 50;
+
+-- messages pinned by chat admins
+CREATE TABLE IF NOT EXISTS chat_pins(
+  chat_id bigint NOT NULL REFERENCES chats(id),
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  pinned_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, message_id)
+);
+
+-- messages saved by users into their private list
+CREATE TABLE IF NOT EXISTS saved_messages(
+  user_id bigint NOT NULL REFERENCES users(id),
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, message_id)
+);
+
+-- create index for saved_messages for message_id, used to drop entries of deleted messages
+CREATE INDEX IF NOT EXISTS saved_messages_message_id_index ON saved_messages(message_id);
+
+-- deleted messages leave the pins and saved lists
+CREATE OR REPLACE FUNCTION message_deleted_unpin()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+    DELETE FROM chat_pins
+    WHERE message_id = NEW.id;
+    DELETE FROM saved_messages
+    WHERE message_id = NEW.id;
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_deleted_unpin_trigger
+  AFTER UPDATE OF deleted_at ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_deleted_unpin();
+
+-- users removed from a chat lose the messages they saved from it
+CREATE OR REPLACE FUNCTION chat_members_unsave()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  DELETE FROM saved_messages s USING messages m
+  WHERE s.message_id = m.id
+    AND m.chat_id = NEW.id
+    AND s.user_id = ANY (OLD.members)
+    AND NOT s.user_id = ANY (NEW.members);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_members_unsave_trigger
+  AFTER UPDATE OF members ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_members_unsave();
@@ -0,0 +1,21 @@
+-- messages waiting to be sent, the worker moves them to messages when they are due
+CREATE TABLE IF NOT EXISTS scheduled_messages(
+  id bigserial PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id),
+  sender_id bigint NOT NULL REFERENCES users(id),
+  parent_id bigint REFERENCES messages(id),
+  content text NOT NULL,
+  images text[] NOT NULL DEFAULT '{}',
+  send_at timestamptz NOT NULL,
+  -- why the delivery failed, failed messages are not retried
+  error text,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for scheduled_messages for send_at, used by the worker to find due messages
+CREATE INDEX IF NOT EXISTS send_at_index ON scheduled_messages(send_at)
+WHERE
+  error IS NULL;
+
+-- create index for scheduled_messages for sender_id
+CREATE INDEX IF NOT EXISTS scheduled_sender_id_index ON scheduled_messages(sender_id, send_at);
@@ -0,0 +1,15 @@
+-- unsent messages of users, one per chat and one per thread
+CREATE TABLE IF NOT EXISTS message_drafts(
+  id bigserial PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id),
+  chat_id bigint NOT NULL REFERENCES chats(id),
+  -- the thread the draft replies to
+  parent_id bigint REFERENCES messages(id) ON DELETE CASCADE,
+  content text NOT NULL,
+  -- supplied by clients, the highest version wins
+  version bigint NOT NULL,
+  updated_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create unique index for message_drafts for user_id, chat_id and the thread
+CREATE UNIQUE INDEX IF NOT EXISTS message_drafts_user_chat_index ON message_drafts(user_id, chat_id, COALESCE(parent_id, 0));
@@ -0,0 +1,137 @@
+-- urls registered by chat admins to receive chat events
+CREATE TABLE IF NOT EXISTS chat_webhooks(
+  id bigserial PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id),
+  url text NOT NULL,
+  -- key of the HMAC-SHA256 signature of the payloads
+  secret text NOT NULL,
+  -- events the webhook subscribed to, empty for all of them
+  events text[] NOT NULL DEFAULT '{}',
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for chat_webhooks for chat_id
+CREATE INDEX IF NOT EXISTS chat_webhooks_chat_id_index ON chat_webhooks(chat_id);
+
+CREATE TYPE webhook_delivery_status AS ENUM(
+  'pending',
+  'delivered',
+  'dead'
+);
+
+-- every event sent to a webhook, kept as the delivery log
+CREATE TABLE IF NOT EXISTS webhook_deliveries(
+  id bigserial PRIMARY KEY,
+  webhook_id bigint NOT NULL REFERENCES chat_webhooks(id) ON DELETE CASCADE,
+  event text NOT NULL,
+  payload jsonb NOT NULL,
+  status webhook_delivery_status NOT NULL DEFAULT 'pending',
+  attempts int NOT NULL DEFAULT 0,
+  next_attempt_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  -- outcome of the last attempt
+  response_status int,
+  error text,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  delivered_at timestamptz
+);
+
+-- create index for webhook_deliveries for next_attempt_at, used by the worker to find due deliveries
+CREATE INDEX IF NOT EXISTS webhook_deliveries_next_attempt_at_index ON webhook_deliveries(next_attempt_at)
+WHERE
+  status = 'pending';
+
+-- create index for webhook_deliveries for webhook_id, used by the delivery log
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id_index ON webhook_deliveries(webhook_id, id DESC);
+
+-- deliveries which ran out of attempts, until an admin retries them
+CREATE TABLE IF NOT EXISTS webhook_dead_letters(
+  id bigserial PRIMARY KEY,
+  delivery_id bigint NOT NULL UNIQUE REFERENCES webhook_deliveries(id) ON DELETE CASCADE,
+  webhook_id bigint NOT NULL REFERENCES chat_webhooks(id) ON DELETE CASCADE,
+  event text NOT NULL,
+  payload jsonb NOT NULL,
+  attempts int NOT NULL,
+  error text,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- queue an event for the webhooks of a chat which subscribed to it
+CREATE OR REPLACE FUNCTION enqueue_webhook(chat bigint, ev text, data jsonb)
+  RETURNS void
+  AS $$
+BEGIN
+  INSERT INTO webhook_deliveries(webhook_id, event, payload)
+  SELECT
+    w.id,
+    ev,
+    jsonb_build_object('event', ev, 'chat_id', chat, 'data', data, 'created_at', now())
+  FROM
+    chat_webhooks w
+  WHERE
+    w.chat_id = chat
+    AND (cardinality(w.events) = 0
+      OR ev = ANY (w.events));
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION message_webhook()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  ev text;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    ev := 'message.created';
+  ELSIF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+    ev := 'message.deleted';
+  ELSIF NEW.deleted_at IS NULL AND (NEW.content IS DISTINCT FROM OLD.content OR NEW.images IS DISTINCT FROM OLD.images) THEN
+    ev := 'message.edited';
+  ELSE
+    RETURN NEW;
+  END IF;
+  PERFORM
+    enqueue_webhook(NEW.chat_id, ev, jsonb_build_object('id', NEW.id, 'chat_id', NEW.chat_id, 'sender_id', NEW.sender_id, 'parent_id', NEW.parent_id, 'content', NEW.content, 'images', NEW.images, 'created_at', NEW.created_at, 'updated_at', NEW.updated_at, 'deleted_at', NEW.deleted_at));
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_webhook_trigger
+  AFTER INSERT OR UPDATE OF content, images, deleted_at ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_webhook();
+
+CREATE OR REPLACE FUNCTION chat_members_webhook()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  added bigint[];
+  removed bigint[];
+BEGIN
+  added := ARRAY (
+    SELECT
+      unnest(NEW.members)
+    EXCEPT
+    SELECT
+      unnest(OLD.members));
+  removed := ARRAY (
+    SELECT
+      unnest(OLD.members)
+    EXCEPT
+    SELECT
+      unnest(NEW.members));
+  IF cardinality(added) > 0 OR cardinality(removed) > 0 THEN
+    PERFORM
+      enqueue_webhook(NEW.id, 'chat.members_changed', jsonb_build_object('members', NEW.members, 'added', added, 'removed', removed));
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_members_webhook_trigger
+  AFTER UPDATE OF members ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_members_webhook();
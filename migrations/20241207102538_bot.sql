@@ -0,0 +1,36 @@
+-- bots are users which cannot sign in with a password, they use api tokens instead
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_bot boolean NOT NULL DEFAULT FALSE;
+
+-- the user who created the bot and manages its tokens
+ALTER TABLE users ADD COLUMN IF NOT EXISTS bot_owner_id bigint REFERENCES users(id);
+
+ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL;
+
+-- long-lived api tokens of bots
+CREATE TABLE IF NOT EXISTS bot_tokens(
+  id bigserial PRIMARY KEY,
+  bot_id bigint NOT NULL REFERENCES users(id),
+  name varchar(64) NOT NULL,
+  -- sha256 of the token, the token itself is only shown when it is issued
+  token_hash char(64) NOT NULL UNIQUE,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  last_used_at timestamptz
+);
+
+-- create index for bot_tokens for bot_id
+CREATE INDEX IF NOT EXISTS bot_tokens_bot_id_index ON bot_tokens(bot_id);
+
+-- urls which let external systems post into a chat as a bot
+CREATE TABLE IF NOT EXISTS incoming_webhooks(
+  id bigserial PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id),
+  bot_id bigint NOT NULL REFERENCES users(id),
+  name varchar(64) NOT NULL,
+  -- sha256 of the token in the url
+  token_hash char(64) NOT NULL UNIQUE,
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for incoming_webhooks for chat_id
+CREATE INDEX IF NOT EXISTS incoming_webhooks_chat_id_index ON incoming_webhooks(chat_id);
@@ -0,0 +1,19 @@
+-- what the chat is about, set with /topic
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS topic varchar(250);
+
+-- commands of a workspace forwarded to the http endpoint of a bot
+CREATE TABLE IF NOT EXISTS slash_commands(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  -- without the leading slash
+  name varchar(32) NOT NULL,
+  description text NOT NULL DEFAULT '',
+  -- the bot replying to the command, it has to be a member of the chat
+  bot_id bigint NOT NULL REFERENCES users(id),
+  url text NOT NULL,
+  -- key of the `x-command-signature` header
+  secret text NOT NULL,
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (ws_id, name)
+);
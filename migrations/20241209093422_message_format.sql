@@ -0,0 +1,109 @@
+-- how the content of a message is written, markdown is sanitized by the server
+CREATE TYPE message_format AS ENUM(
+  'plain',
+  'markdown'
+);
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS format message_format NOT NULL DEFAULT 'plain';
+-- canonical plain text rendering of the content, used by search and notifications
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS content_text text;
+-- typed attachments: file, image, code and link
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS attachments jsonb NOT NULL DEFAULT '[]';
+
+ALTER TABLE message_edits ADD COLUMN IF NOT EXISTS format message_format NOT NULL DEFAULT 'plain';
+ALTER TABLE message_edits ADD COLUMN IF NOT EXISTS attachments jsonb NOT NULL DEFAULT '[]';
+
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS format message_format NOT NULL DEFAULT 'plain';
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS attachments jsonb NOT NULL DEFAULT '[]';
+
+-- existing messages are plain text and their images become image attachments
+UPDATE
+  messages
+SET
+  content_text = content,
+  attachments = COALESCE((
+    SELECT
+      jsonb_agg(jsonb_build_object('type', 'image', 'url', u) ORDER BY i)
+    FROM unnest(images) WITH ORDINALITY AS t(u, i)), '[]');
+
+UPDATE
+  message_edits
+SET
+  attachments = COALESCE((
+    SELECT
+      jsonb_agg(jsonb_build_object('type', 'image', 'url', u) ORDER BY i)
+    FROM unnest(images) WITH ORDINALITY AS t(u, i)), '[]');
+
+UPDATE
+  scheduled_messages
+SET
+  attachments = COALESCE((
+    SELECT
+      jsonb_agg(jsonb_build_object('type', 'image', 'url', u) ORDER BY i)
+    FROM unnest(images) WITH ORDINALITY AS t(u, i)), '[]');
+
+ALTER TABLE messages ALTER COLUMN content_text SET NOT NULL;
+
+-- plain messages are their own plain text, markdown is rendered by the server
+CREATE OR REPLACE FUNCTION message_content_text()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF NEW.format = 'plain' OR NEW.content_text IS NULL THEN
+    NEW.content_text := NEW.content;
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_content_text_trigger
+  BEFORE INSERT OR UPDATE OF content, format, content_text ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_content_text();
+
+-- index the plain text, markdown syntax should not be searchable
+ALTER TABLE messages DROP COLUMN IF EXISTS content_tsv;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS content_tsv tsvector GENERATED ALWAYS AS (to_tsvector(ts_config, content_text)) STORED;
+
+CREATE INDEX IF NOT EXISTS content_tsv_index ON messages USING GIN (content_tsv);
+
+-- the triggers watching images are moved to attachments
+DROP TRIGGER IF EXISTS message_changed_trigger ON messages;
+DROP TRIGGER IF EXISTS message_webhook_trigger ON messages;
+
+CREATE OR REPLACE FUNCTION message_webhook()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  ev text;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    ev := 'message.created';
+  ELSIF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+    ev := 'message.deleted';
+  ELSIF NEW.deleted_at IS NULL AND (NEW.content IS DISTINCT FROM OLD.content OR NEW.attachments IS DISTINCT FROM OLD.attachments) THEN
+    ev := 'message.edited';
+  ELSE
+    RETURN NEW;
+  END IF;
+  PERFORM
+    enqueue_webhook(NEW.chat_id, ev, jsonb_build_object('id', NEW.id, 'chat_id', NEW.chat_id, 'sender_id', NEW.sender_id, 'parent_id', NEW.parent_id, 'format', NEW.format, 'content', NEW.content, 'content_text', NEW.content_text, 'attachments', NEW.attachments, 'created_at', NEW.created_at, 'updated_at', NEW.updated_at, 'deleted_at', NEW.deleted_at));
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_changed_trigger
+  AFTER INSERT OR UPDATE OF content, attachments, deleted_at ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_changed();
+
+CREATE TRIGGER message_webhook_trigger
+  AFTER INSERT OR UPDATE OF content, attachments, deleted_at ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_webhook();
+
+ALTER TABLE messages DROP COLUMN IF EXISTS images;
+ALTER TABLE message_edits DROP COLUMN IF EXISTS images;
+ALTER TABLE scheduled_messages DROP COLUMN IF EXISTS images;
@@ -0,0 +1,22 @@
+-- uploaded files, they are stored under the sha256 of their content
+CREATE TABLE IF NOT EXISTS files(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  owner_id bigint NOT NULL REFERENCES users(id),
+  hash char(64) NOT NULL,
+  ext varchar(16) NOT NULL,
+  -- the file name given by the uploader
+  name varchar(255) NOT NULL,
+  mime varchar(128) NOT NULL,
+  size bigint NOT NULL,
+  -- dimensions and placeholder of images, EXIF metadata is stripped before storing them
+  width int,
+  height int,
+  blurhash varchar(64),
+  -- thumbnail sizes generated for the image, smaller images have none
+  thumbnails text[] NOT NULL DEFAULT '{}',
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- the same user uploading the same content gets the same file
+CREATE UNIQUE INDEX IF NOT EXISTS files_owner_hash_index ON files(ws_id, hash, ext, owner_id);
@@ -0,0 +1,57 @@
+-- storage quotas in bytes, the defaults of the config are used when they are null
+ALTER TABLE users ADD COLUMN IF NOT EXISTS storage_quota bigint;
+ALTER TABLE workspaces ADD COLUMN IF NOT EXISTS storage_quota bigint;
+
+-- the size of the files each user uploaded, kept up to date by a trigger on files
+CREATE TABLE IF NOT EXISTS storage_usage(
+  user_id bigint PRIMARY KEY REFERENCES users(id),
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  files bigint NOT NULL DEFAULT 0,
+  bytes bigint NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS storage_usage_ws_id_index ON storage_usage(ws_id);
+
+INSERT INTO storage_usage(user_id, ws_id, files, bytes)
+SELECT
+  owner_id,
+  ws_id,
+  count(*),
+  sum(size)
+FROM
+  files
+GROUP BY
+  owner_id,
+  ws_id
+ON CONFLICT (user_id)
+  DO NOTHING;
+
+-- uploading the same content again does not insert a file, so it is counted once
+CREATE OR REPLACE FUNCTION file_usage_changed()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    INSERT INTO storage_usage(user_id, ws_id, files, bytes)
+      VALUES (NEW.owner_id, NEW.ws_id, 1, NEW.size)
+    ON CONFLICT (user_id)
+      DO UPDATE SET
+        files = storage_usage.files + 1, bytes = storage_usage.bytes + NEW.size;
+    RETURN NEW;
+  END IF;
+  UPDATE
+    storage_usage
+  SET
+    files = files - 1,
+    bytes = bytes - OLD.size
+  WHERE
+    user_id = OLD.owner_id;
+  RETURN OLD;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER file_usage_changed_trigger
+  AFTER INSERT OR DELETE ON files
+  FOR EACH ROW
+  EXECUTE FUNCTION file_usage_changed();
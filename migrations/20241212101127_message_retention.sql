@@ -0,0 +1,69 @@
+-- how many days messages are kept, null keeps them forever
+-- chats without their own setting use the one of their workspace
+ALTER TABLE workspaces ADD COLUMN IF NOT EXISTS retention_days int;
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS retention_days int;
+
+-- expired messages are copied to archived_messages instead of only being deleted
+ALTER TABLE workspaces ADD COLUMN IF NOT EXISTS retention_archive boolean NOT NULL DEFAULT FALSE;
+
+-- messages of a chat or sent by a user are not purged while a hold is active
+CREATE TABLE IF NOT EXISTS legal_holds(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  chat_id bigint REFERENCES chats(id),
+  user_id bigint REFERENCES users(id),
+  reason text NOT NULL,
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  -- released holds are kept as a record
+  released_at timestamptz,
+  CHECK ((chat_id IS NULL) <> (user_id IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS legal_holds_chat_id_index ON legal_holds(chat_id)
+WHERE
+  released_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS legal_holds_user_id_index ON legal_holds(user_id)
+WHERE
+  released_at IS NULL;
+
+-- expired messages of workspaces archiving them, with their reactions
+CREATE TABLE IF NOT EXISTS archived_messages(
+  -- the id of the message
+  id bigint PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id),
+  sender_id bigint NOT NULL REFERENCES users(id),
+  message jsonb NOT NULL,
+  reactions jsonb NOT NULL DEFAULT '[]',
+  created_at timestamptz NOT NULL,
+  archived_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- what belongs to a message goes with it
+ALTER TABLE message_edits
+  DROP CONSTRAINT IF EXISTS message_edits_message_id_fkey,
+  ADD CONSTRAINT message_edits_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+ALTER TABLE thread_followers
+  DROP CONSTRAINT IF EXISTS thread_followers_message_id_fkey,
+  ADD CONSTRAINT thread_followers_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+ALTER TABLE message_reactions
+  DROP CONSTRAINT IF EXISTS message_reactions_message_id_fkey,
+  ADD CONSTRAINT message_reactions_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+ALTER TABLE message_mentions
+  DROP CONSTRAINT IF EXISTS message_mentions_message_id_fkey,
+  ADD CONSTRAINT message_mentions_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+ALTER TABLE scheduled_messages
+  DROP CONSTRAINT IF EXISTS scheduled_messages_parent_id_fkey,
+  ADD CONSTRAINT scheduled_messages_parent_id_fkey FOREIGN KEY (parent_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+-- ids only grow, the read position stays valid after the message is purged
+ALTER TABLE chat_reads
+  DROP CONSTRAINT IF EXISTS chat_reads_last_read_message_id_fkey;
+
+-- find the messages still referencing an uploaded file
+CREATE INDEX IF NOT EXISTS messages_attachments_index ON messages USING gin(attachments jsonb_path_ops);
@@ -0,0 +1,40 @@
+-- security relevant actions, rows are never changed or deleted
+CREATE TABLE IF NOT EXISTS audit_events(
+  id bigserial PRIMARY KEY,
+  -- null when the workspace is unknown, e.g. a failed signin with an unknown email
+  ws_id bigint REFERENCES workspaces(id),
+  actor_id bigint REFERENCES users(id),
+  -- e.g. `user.signin` or `chat.member_remove`
+  action varchar(64) NOT NULL,
+  target_type varchar(32),
+  target_id bigint,
+  metadata jsonb NOT NULL DEFAULT '{}',
+  ip varchar(45),
+  user_agent text,
+  -- the x-request-id of the request, to find its logs
+  request_id varchar(64),
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_ws_id_index ON audit_events(ws_id, id DESC);
+
+CREATE INDEX IF NOT EXISTS audit_events_actor_id_index ON audit_events(actor_id, id DESC);
+
+CREATE OR REPLACE FUNCTION audit_events_append_only()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only_trigger
+  BEFORE UPDATE OR DELETE ON audit_events
+  FOR EACH ROW
+  EXECUTE FUNCTION audit_events_append_only();
+
+CREATE TRIGGER audit_events_no_truncate_trigger
+  BEFORE TRUNCATE ON audit_events
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION audit_events_append_only();
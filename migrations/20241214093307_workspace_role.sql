@@ -0,0 +1,18 @@
+-- what a user can do in its workspace, guests only see the chats they were invited to
+CREATE TYPE workspace_role AS ENUM(
+  'owner',
+  'admin',
+  'member',
+  'guest'
+);
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role workspace_role NOT NULL DEFAULT 'member';
+
+UPDATE
+  users
+SET
+  role = 'owner'
+FROM
+  workspaces w
+WHERE
+  w.owner_id = users.id;
@@ -0,0 +1,57 @@
+-- blocked users cannot message or mention the user who blocked them, nor see its presence
+CREATE TABLE IF NOT EXISTS user_blocks(
+  user_id bigint NOT NULL REFERENCES users(id),
+  blocked_id bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, blocked_id),
+  CHECK (user_id <> blocked_id)
+);
+
+CREATE INDEX IF NOT EXISTS user_blocks_blocked_id_index ON user_blocks(blocked_id);
+
+-- suspended users cannot sign in or use their tokens until then
+ALTER TABLE users ADD COLUMN IF NOT EXISTS suspended_until timestamptz;
+
+CREATE TYPE moderation_action AS ENUM(
+  'dismiss',
+  'delete_message',
+  'warn',
+  'suspend'
+);
+
+-- reported messages waiting for an admin, resolved ones are kept
+CREATE TABLE IF NOT EXISTS message_reports(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  -- no foreign key, the report stays when the message is purged
+  message_id bigint NOT NULL,
+  chat_id bigint NOT NULL REFERENCES chats(id),
+  sender_id bigint NOT NULL REFERENCES users(id),
+  -- the content when it was reported, the message may be edited or deleted since
+  content text NOT NULL,
+  reporter_id bigint NOT NULL REFERENCES users(id),
+  reason text NOT NULL,
+  action moderation_action,
+  note text,
+  resolved_by bigint REFERENCES users(id),
+  resolved_at timestamptz,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (message_id, reporter_id)
+);
+
+CREATE INDEX IF NOT EXISTS message_reports_open_index ON message_reports(ws_id, id)
+WHERE
+  resolved_at IS NULL;
+
+-- warnings sent to users by admins, e.g. after a report
+CREATE TABLE IF NOT EXISTS user_warnings(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  user_id bigint NOT NULL REFERENCES users(id),
+  report_id bigint REFERENCES message_reports(id),
+  reason text NOT NULL,
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS user_warnings_user_id_index ON user_warnings(user_id, id DESC);
@@ -0,0 +1,5 @@
+-- filters run on messages before they are stored, see ContentFilterConfig
+ALTER TABLE workspaces ADD COLUMN IF NOT EXISTS content_filters jsonb NOT NULL DEFAULT '{}';
+
+-- messages flagged by a filter are reported without a reporter
+ALTER TABLE message_reports ALTER COLUMN reporter_id DROP NOT NULL;
@@ -0,0 +1,19 @@
+-- accounts of identity providers linked to users, by the stable id of the provider
+CREATE TABLE IF NOT EXISTS user_identities(
+  provider varchar(64) NOT NULL,
+  subject varchar(255) NOT NULL,
+  user_id bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS user_identities_user_id_index ON user_identities(user_id);
+
+-- logins started at a provider and not finished yet, each one can be finished once
+CREATE TABLE IF NOT EXISTS oidc_logins(
+  state varchar(64) PRIMARY KEY,
+  provider varchar(64) NOT NULL,
+  code_verifier varchar(128) NOT NULL,
+  nonce varchar(64) NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,32 @@
+-- passkeys of users, the public key is a SEC1 encoded P-256 point
+CREATE TABLE IF NOT EXISTS webauthn_credentials(
+  id bigserial PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id),
+  -- base64url, as browsers send it
+  credential_id text NOT NULL UNIQUE,
+  public_key bytea NOT NULL,
+  -- authenticators increase it on every use, a lower value means a cloned authenticator
+  sign_count bigint NOT NULL DEFAULT 0,
+  name varchar(64) NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_used_at timestamptz
+);
+
+CREATE INDEX IF NOT EXISTS webauthn_credentials_user_id_index ON webauthn_credentials(user_id);
+
+CREATE TYPE webauthn_ceremony AS ENUM(
+  'registration',
+  'authentication'
+);
+
+-- challenges of ceremonies in progress, each one can be answered once
+CREATE TABLE IF NOT EXISTS webauthn_challenges(
+  challenge varchar(64) PRIMARY KEY,
+  ceremony webauthn_ceremony NOT NULL,
+  -- the user registering a passkey or who gave its password, none for passwordless sign ins
+  user_id bigint REFERENCES users(id),
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- users who confirm their password with a passkey
+ALTER TABLE users ADD COLUMN IF NOT EXISTS passkey_required boolean NOT NULL DEFAULT false;
@@ -0,0 +1,19 @@
+-- uploads sent straight to the storage with a presigned url, a file is registered when the
+-- client completes them
+CREATE TABLE IF NOT EXISTS file_uploads(
+  -- where the client puts the file, e.g. tmp/0193...
+  key varchar(64) PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  owner_id bigint NOT NULL REFERENCES users(id),
+  -- the file name given by the uploader
+  name varchar(255) NOT NULL,
+  -- the size signed into the url, the storage refuses other sizes
+  size bigint NOT NULL,
+  -- the row is kept until it expires so the object of a url used again is removed too
+  completed_at timestamptz,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS file_uploads_owner_id_index ON file_uploads(owner_id);
+
+CREATE INDEX IF NOT EXISTS file_uploads_created_at_index ON file_uploads(created_at);